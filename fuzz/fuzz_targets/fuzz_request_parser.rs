@@ -1,12 +1,7 @@
 #![no_main]
 use libfuzzer_sys::fuzz_target;
 
-use dray::protocol::request::Request;
-use std::convert::TryFrom;
-
 fuzz_target!(|data: &[u8]| {
     // The result is acceptable as long as a panic does not result
-    match Request::try_from(data) {
-        _ => ()
-    };
+    let _ = dray::parse_request(data);
 });
@@ -0,0 +1,82 @@
+use std::fmt::{Display, Formatter, Result};
+use std::net::SocketAddr;
+
+/// An authentication attempt, logged as a single line of `key=value` fields so
+/// it can be audited.
+///
+/// # Warning
+/// Only the fingerprint of a key is recorded, never the key itself.
+pub struct AuthEvent<'a> {
+    pub user: &'a str,
+    pub peer_addr: Option<SocketAddr>,
+    pub method: &'a str,
+    pub outcome: AuthOutcome,
+    pub fingerprint: &'a str,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AuthOutcome {
+    Accepted,
+    Rejected,
+}
+
+impl Display for AuthEvent<'_> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result {
+        let peer_addr = match self.peer_addr {
+            Some(peer_addr) => peer_addr.ip().to_string(),
+            None => String::from("unknown"),
+        };
+
+        write!(
+            formatter,
+            "auth_event user={:?} peer_addr={} method={} outcome={} fingerprint={}",
+            self.user, peer_addr, self.method, self.outcome, self.fingerprint
+        )
+    }
+}
+
+impl Display for AuthOutcome {
+    fn fmt(&self, formatter: &mut Formatter) -> Result {
+        match self {
+            AuthOutcome::Accepted => write!(formatter, "accepted"),
+            AuthOutcome::Rejected => write!(formatter, "rejected"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display_formats_rejected_auth_event() {
+        let auth_event = AuthEvent {
+            user: "test",
+            peer_addr: Some("127.0.0.1:2222".parse().unwrap()),
+            method: "publickey",
+            outcome: AuthOutcome::Rejected,
+            fingerprint: "SHA256:fingerprint",
+        };
+
+        assert_eq!(
+            "auth_event user=\"test\" peer_addr=127.0.0.1 method=publickey outcome=rejected fingerprint=SHA256:fingerprint",
+            auth_event.to_string()
+        );
+    }
+
+    #[test]
+    fn test_display_formats_auth_event_with_unknown_peer_addr() {
+        let auth_event = AuthEvent {
+            user: "test",
+            peer_addr: None,
+            method: "publickey",
+            outcome: AuthOutcome::Accepted,
+            fingerprint: "SHA256:fingerprint",
+        };
+
+        assert_eq!(
+            "auth_event user=\"test\" peer_addr=unknown method=publickey outcome=accepted fingerprint=SHA256:fingerprint",
+            auth_event.to_string()
+        );
+    }
+}
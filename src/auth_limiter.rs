@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Throttles sources that repeatedly fail to authenticate, to slow down
+/// brute-force attacks.
+///
+/// Each IP address has a bucket of `max_failures` tokens that refills over the
+/// refill window. A failed authentication takes a token, and an address with an
+/// empty bucket is throttled until a token is refilled, so legitimate clients
+/// can reconnect after backing off.
+pub struct AuthLimiter {
+    max_failures: f64,
+    refill_per_second: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl AuthLimiter {
+    pub fn new(max_failures: u32, refill_window: Duration) -> AuthLimiter {
+        AuthLimiter {
+            max_failures: f64::from(max_failures),
+            refill_per_second: f64::from(max_failures) / refill_window.as_secs_f64(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_throttled(&self, ip_addr: IpAddr) -> bool {
+        self.is_throttled_at(ip_addr, Instant::now())
+    }
+
+    pub fn record_failure(&self, ip_addr: IpAddr) {
+        self.record_failure_at(ip_addr, Instant::now())
+    }
+
+    fn is_throttled_at(&self, ip_addr: IpAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let tokens = match buckets.get_mut(&ip_addr) {
+            Some(bucket) => self.refill(bucket, now),
+            None => return false,
+        };
+
+        // Full buckets are forgotten, so the map only holds recent failures.
+        if tokens >= self.max_failures {
+            buckets.remove(&ip_addr);
+        }
+
+        tokens < 1.0
+    }
+
+    fn record_failure_at(&self, ip_addr: IpAddr, now: Instant) {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let bucket = buckets.entry(ip_addr).or_insert(Bucket {
+            tokens: self.max_failures,
+            updated: now,
+        });
+
+        self.refill(bucket, now);
+        bucket.tokens = (bucket.tokens - 1.0).max(0.0);
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_second).min(self.max_failures);
+        bucket.updated = now;
+        bucket.tokens
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_repeated_failures_throttle_ip_addr() {
+        let auth_limiter = AuthLimiter::new(3, Duration::from_secs(60));
+        let ip_addr: IpAddr = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            auth_limiter.record_failure_at(ip_addr, now);
+            assert!(!auth_limiter.is_throttled_at(ip_addr, now));
+        }
+
+        auth_limiter.record_failure_at(ip_addr, now);
+        assert!(auth_limiter.is_throttled_at(ip_addr, now));
+    }
+
+    #[test]
+    fn test_failures_do_not_throttle_other_ip_addrs() {
+        let auth_limiter = AuthLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+
+        auth_limiter.record_failure_at("192.0.2.1".parse().unwrap(), now);
+
+        assert!(!auth_limiter.is_throttled_at("192.0.2.2".parse().unwrap(), now));
+    }
+
+    #[test]
+    fn test_throttled_ip_addr_is_allowed_after_backoff() {
+        let auth_limiter = AuthLimiter::new(3, Duration::from_secs(60));
+        let ip_addr: IpAddr = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            auth_limiter.record_failure_at(ip_addr, now);
+        }
+
+        assert!(auth_limiter.is_throttled_at(ip_addr, now + Duration::from_secs(10)));
+        assert!(!auth_limiter.is_throttled_at(ip_addr, now + Duration::from_secs(30)));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::config::DrayConfig;
+
+use anyhow::{anyhow, Result};
+use log::LevelFilter;
+use std::path::PathBuf;
+
+pub const USAGE: &str =
+    "Usage: dray [--config-file <path>] [--host <host:port>] [--log-level <level>]";
+
+/// The settings that can be given on the command line. They take precedence
+/// over the environment, which takes precedence over the config file.
+#[derive(Debug, Default, PartialEq)]
+pub struct CliArgs {
+    /// A TOML or YAML config file, which is loaded by `DrayConfig::from_file`.
+    pub config_file: Option<PathBuf>,
+    pub host: Option<String>,
+    pub log_level: Option<LevelFilter>,
+}
+
+impl CliArgs {
+    /// Parses the arguments after the program name. Values follow their flag
+    /// either as the next argument or after an `=`, such as `--host=[::]:2222`.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<CliArgs> {
+        let mut cli_args = CliArgs::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_owned(), value.to_owned()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing value for {}", arg))?;
+                    (arg, value)
+                }
+            };
+
+            match flag.as_str() {
+                "--config-file" => cli_args.config_file = Some(PathBuf::from(value)),
+                "--host" => cli_args.host = Some(value),
+                "--log-level" => {
+                    cli_args.log_level = Some(
+                        value
+                            .parse()
+                            .map_err(|_| anyhow!("Invalid log level {}", value))?,
+                    )
+                }
+                _ => return Err(anyhow!("Unknown argument {}", flag)),
+            }
+        }
+
+        Ok(cli_args)
+    }
+
+    /// Loads the config from the environment and the config file, then applies
+    /// the command line settings.
+    pub fn load_config(&self) -> Result<DrayConfig> {
+        let dray_config = match &self.config_file {
+            Some(config_file) => DrayConfig::from_file(config_file)?,
+            None => DrayConfig::new()?,
+        };
+
+        let dray_config = self.apply(dray_config);
+        dray_config.validate()?;
+
+        Ok(dray_config)
+    }
+
+    /// Overrides the config with the settings given on the command line.
+    pub fn apply(&self, mut dray_config: DrayConfig) -> DrayConfig {
+        if let Some(host) = &self.host {
+            dray_config.host = host.clone();
+        }
+
+        dray_config
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_reads_flags() {
+        let cli_args = CliArgs::parse(
+            vec![
+                "--config-file",
+                "dray.toml",
+                "--host=[::]:2222",
+                "--log-level",
+                "debug",
+            ]
+            .into_iter()
+            .map(String::from),
+        )
+        .unwrap();
+
+        assert_eq!(
+            CliArgs {
+                config_file: Some(PathBuf::from("dray.toml")),
+                host: Some(String::from("[::]:2222")),
+                log_level: Some(LevelFilter::Debug),
+            },
+            cli_args
+        );
+    }
+
+    #[test]
+    fn test_parse_without_args() {
+        assert_eq!(
+            CliArgs::default(),
+            CliArgs::parse(Vec::<String>::new()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_argument() {
+        assert!(CliArgs::parse(vec![String::from("--port"), String::from("22")]).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_missing_value() {
+        assert!(CliArgs::parse(vec![String::from("--host")]).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_log_level() {
+        assert!(CliArgs::parse(vec![String::from("--log-level=loud")]).is_err());
+    }
+
+    #[test]
+    fn test_apply_overrides_host() {
+        let dray_config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+            ])
+            .unwrap();
+
+        let cli_args = CliArgs::parse(vec![String::from("--host=0.0.0.0:22")]).unwrap();
+        let dray_config = cli_args.apply(dray_config);
+
+        assert_eq!("0.0.0.0:22", dray_config.host);
+        assert_eq!("bucket", dray_config.s3.bucket);
+    }
+}
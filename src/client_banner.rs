@@ -0,0 +1,147 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The longest identification line the SSH protocol allows, including the
+/// trailing CR LF.
+const MAX_BANNER_LENGTH: usize = 255;
+
+/// Passes a connection through to the SSH server while recording the client's
+/// identification line, such as `SSH-2.0-OpenSSH_8.9`, which names the client
+/// software. The SSH library does not expose it to handlers.
+pub struct BannerCapturingStream<S> {
+    stream: S,
+    banner: Arc<OnceLock<String>>,
+    line: Vec<u8>,
+}
+
+impl<S> BannerCapturingStream<S> {
+    pub fn new(stream: S, banner: Arc<OnceLock<String>>) -> BannerCapturingStream<S> {
+        BannerCapturingStream {
+            stream,
+            banner,
+            line: Vec::new(),
+        }
+    }
+
+    /// Collects lines until one starts with `SSH-`. Servers may receive other
+    /// lines first, which are skipped.
+    fn capture(&mut self, data: &[u8]) {
+        for byte in data {
+            if self.banner.get().is_some() {
+                return;
+            }
+
+            if *byte != b'\n' {
+                if self.line.len() < MAX_BANNER_LENGTH {
+                    self.line.push(*byte);
+                }
+                continue;
+            }
+
+            let line = String::from_utf8_lossy(&self.line)
+                .trim_end_matches('\r')
+                .to_owned();
+            self.line.clear();
+
+            if line.starts_with("SSH-") {
+                let _ = self.banner.set(line);
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for BannerCapturingStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+
+        match Pin::new(&mut this.stream).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                this.capture(&buf.filled()[filled..]);
+                Poll::Ready(Ok(()))
+            }
+            poll => poll,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for BannerCapturingStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, data)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_read_captures_client_banner() {
+        let banner = Arc::new(OnceLock::new());
+        let mut stream = BannerCapturingStream::new(
+            &b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3\r\n\x00\x00\x01\x2c"[..],
+            banner.clone(),
+        );
+
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).await.unwrap();
+
+        assert_eq!(
+            b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3\r\n\x00\x00\x01\x2c".to_vec(),
+            data
+        );
+        assert_eq!(
+            Some("SSH-2.0-OpenSSH_8.9p1 Ubuntu-3"),
+            banner.get().map(String::as_str)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_captures_banner_split_across_reads() {
+        let banner = Arc::new(OnceLock::new());
+        let mut stream = BannerCapturingStream::new(
+            &b"hello\r\nSSH-2.0-PuTTY_Release_0.78\r\n"[..],
+            banner.clone(),
+        );
+
+        let mut chunk = [0; 4];
+        while stream.read(&mut chunk).await.unwrap() > 0 {}
+
+        assert_eq!(
+            Some("SSH-2.0-PuTTY_Release_0.78"),
+            banner.get().map(String::as_str)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_without_banner_captures_nothing() {
+        let banner = Arc::new(OnceLock::new());
+        let mut stream = BannerCapturingStream::new(&b"GET / HTTP/1.1"[..], banner.clone());
+
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).await.unwrap();
+
+        assert_eq!(None, banner.get());
+    }
+}
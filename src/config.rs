@@ -1,27 +1,401 @@
+use std::collections::BTreeMap;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::Path;
+use std::time::Duration;
+use std::{env, fs};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde::Deserialize;
+use thrussh::server::Config;
+use thrussh::Preferred;
 use thrussh_keys::key;
 
+pub use crate::sftp_session::{
+    DirEntriesPolicy, ErrorVerbosity, MissingFilePolicy, NoopRequest, RenamePolicy,
+};
 pub use crate::storage::s3::S3Config;
 
+/// The largest SSH packet payload that thrussh accepts.
+const MAX_PACKET_SIZE: u32 = 65535;
+
+/// How much longer than the idle timeout thrussh waits for data before
+/// dropping a connection, so idle clients are told why they are disconnected.
+const CONNECTION_TIMEOUT_MARGIN: Duration = Duration::from_secs(60);
+
+/// The shortest time thrussh waits for data before dropping a connection,
+/// which is thrussh's own default.
+const MIN_CONNECTION_TIMEOUT: Duration = Duration::from_secs(600);
+
 #[derive(Deserialize, Debug)]
 pub struct DrayConfig {
     pub host: String,
 
     ssh_key_paths: String,
 
+    #[serde(default = "get_default_log_auth_attempts")]
+    pub log_auth_attempts: bool,
+
+    /// Logs the IP address and port of each new connection.
+    #[serde(default = "get_default_log_connections")]
+    pub log_connections: bool,
+
+    /// The failed authentications allowed from an IP address before its
+    /// connections are refused. Throttling is disabled if unset.
+    pub max_auth_failures: Option<u32>,
+
+    /// The seconds for a throttled IP address to regain all of its allowed
+    /// authentication failures.
+    #[serde(default = "get_default_auth_failure_window")]
+    pub auth_failure_window: u64,
+
+    /// The seconds each user's authorized keys are cached before being reloaded
+    /// from storage, or 0 to load them on every authentication attempt. Sending
+    /// SIGHUP clears the cache immediately.
+    #[serde(default = "get_default_authorized_keys_cache_ttl")]
+    pub authorized_keys_cache_ttl: u64,
+
+    /// The rejected authentication attempts allowed on a connection, which is
+    /// passed to thrussh as its `max_auth_attempts`.
+    #[serde(default = "get_default_max_auth_tries")]
+    pub max_auth_tries: usize,
+
+    /// The seconds a client has to authenticate before it is disconnected, or 0
+    /// to wait indefinitely.
+    #[serde(default = "get_default_login_grace_time")]
+    pub login_grace_time: u64,
+
+    /// The seconds an SFTP session may go without sending a request before it
+    /// is disconnected, or 0 to wait indefinitely.
+    #[serde(default)]
+    pub idle_timeout: u64,
+
+    /// Text added to the reason given to clients disconnected by the server,
+    /// such as for an idle timeout, like where to ask for help. The reason is
+    /// sent on the SFTP channel's stderr, which clients such as OpenSSH print.
+    #[serde(default)]
+    pub disconnect_message: Option<String>,
+
+    /// Creates a marker for each user's home directory on login if the home is
+    /// empty, for clients that fail when their home directory does not exist.
+    #[serde(default)]
+    pub auto_create_home: bool,
+
+    /// The most bytes a client may read with a single request.
+    #[serde(default = "get_default_max_data_length")]
+    pub max_read_length: u32,
+
+    /// The most bytes a client is told it may write with a single request.
+    #[serde(default = "get_default_max_data_length")]
+    pub max_write_length: u32,
+
+    /// The most bytes of an incomplete SFTP packet held for a channel while the
+    /// rest of it arrives. Clients whose partial packet grows past it are
+    /// disconnected, so it must be larger than the largest request clients send.
+    #[serde(default = "get_default_max_packet_buffer")]
+    pub max_packet_buffer: usize,
+
+    /// The most bytes of a file name or long name sent in a name response, such
+    /// as a directory listing entry. Requests whose response has a longer name
+    /// fail instead of sending an oversized packet.
+    #[serde(default = "get_default_max_name_length")]
+    pub max_name_length: u32,
+
+    /// The most entries a single directory listing returns, which bounds the
+    /// memory used to list huge prefixes. 0 means no limit.
+    #[serde(default)]
+    pub max_dir_entries: usize,
+
+    /// Whether a listing with more than the maximum entries is cut short with
+    /// `truncate` or answered with a `fail` status.
+    #[serde(default)]
+    pub on_max_dir_entries: DirEntriesPolicy,
+
+    /// Whether directory listings start with `.` and `..` entries. Some
+    /// clients require them, while others show them as regular entries.
+    #[serde(default = "get_default_readdir_dot_entries")]
+    pub readdir_dot_entries: bool,
+
+    /// Whether stat responses include each file's extended attributes, which
+    /// are S3 object tags. Reading them costs an extra S3 request per stat, so
+    /// they are left out unless enabled. Directory listings never include them.
+    #[serde(default)]
+    pub stat_extended_attributes: bool,
+
+    /// Whether opening a directory with nothing stored under it fails with
+    /// SSH_FX_NO_SUCH_FILE instead of listing no entries. S3 has no empty
+    /// directories, so directories that were created but not yet written to
+    /// cannot be opened either.
+    #[serde(default)]
+    pub opendir_require_exists: bool,
+
+    /// Whether clients asking for an SFTP version other than 3 are refused
+    /// rather than negotiated down to version 3.
+    #[serde(default)]
+    pub strict_version_3: bool,
+
+    /// Whether opening a missing file for writing without SSH_FXF_CREAT is
+    /// answered with a `fail` status or `create`s the file anyway.
+    #[serde(default)]
+    pub on_write_missing_file: MissingFilePolicy,
+
+    /// Whether renaming onto an existing file will `overwrite` it or `fail`,
+    /// for clients whose SFTP version cannot say which they want.
+    #[serde(default)]
+    pub on_rename_existing_file: RenamePolicy,
+
+    /// Unimplemented requests to answer with OK for client compatibility, such
+    /// as `setstat,fsetstat`.
+    #[serde(default)]
+    pub noop_requests: Vec<NoopRequest>,
+
+    /// Additional subsystem names to start SFTP for, such as
+    /// `sftp@vendor.example`, for clients that do not request `sftp`.
+    #[serde(default)]
+    pub sftp_subsystem_aliases: Vec<String>,
+
+    /// Whether unexpected errors are sent to clients as a `generic` message or
+    /// with `detailed` backend information. Details are always logged.
+    #[serde(default)]
+    pub error_verbosity: ErrorVerbosity,
+
+    /// The host key algorithms to advertise in order of preference, such as
+    /// `ssh-ed25519,rsa-sha2-512`. The thrussh defaults are used if empty.
+    #[serde(default)]
+    pub host_key_algorithms: Vec<HostKeyAlgorithm>,
+
+    /// The key exchange algorithms to allow in order of preference, such as
+    /// `curve25519-sha256@libssh.org`. The thrussh defaults are used if empty.
+    #[serde(default)]
+    pub kex_algorithms: Vec<String>,
+
+    /// The ciphers to allow in order of preference, such as
+    /// `chacha20-poly1305@openssh.com`. The thrussh defaults are used if empty.
+    #[serde(default)]
+    pub ciphers: Vec<String>,
+
+    /// The initial SSH channel window in bytes, which is how much data a client
+    /// may send before the server grows the window again. Larger windows keep
+    /// uploads from stalling on high-latency links.
+    ///
+    /// Downloads are bounded by the client's window instead, which only the
+    /// client can set. Responses that do not fit in it wait until the client
+    /// grows it, then are sent without waiting for further requests.
+    #[serde(default = "get_default_window_size")]
+    pub window_size: u32,
+
+    /// The largest SSH packet payload in bytes, at most 65535. Responses are
+    /// handed to the SSH session in pieces of this size, so large responses
+    /// are not copied into one buffer.
+    #[serde(default = "get_default_max_packet_size")]
+    pub max_packet_size: u32,
+
+    /// Whether multipart uploads left unfinished by crashed sessions are
+    /// aborted at startup to reclaim their storage.
+    #[serde(default)]
+    pub cleanup_orphaned_uploads: bool,
+
+    /// How many seconds old an unfinished upload must be before startup
+    /// cleanup aborts it. Keep this longer than any upload takes, since other
+    /// servers sharing the bucket may still be writing recent ones.
+    #[serde(default = "get_default_orphaned_upload_age")]
+    pub orphaned_upload_age: u64,
+
+    /// Whether a malformed request is answered with a `respond` bad message
+    /// status or ends the connection with `disconnect`.
+    #[serde(default)]
+    pub on_bad_message: BadMessagePolicy,
+
+    /// Logs a breakdown of every SFTP packet at debug level for troubleshooting
+    /// clients.
+    #[serde(default)]
+    pub debug_dump: bool,
+
+    /// The most bytes of each packet shown by the debug dump.
+    #[serde(default = "get_default_debug_dump_max_bytes")]
+    pub debug_dump_max_bytes: usize,
+
     #[serde(flatten)]
     pub s3: S3Config,
 }
 
 impl DrayConfig {
     pub fn new() -> Result<DrayConfig> {
-        let dray_config = envy::prefixed("DRAY_").from_env::<DrayConfig>()?;
+        DrayConfig::from_vars(env::vars())
+    }
+
+    fn from_vars<I: IntoIterator<Item = (String, String)>>(vars: I) -> Result<DrayConfig> {
+        let dray_config = envy::prefixed("DRAY_").from_iter::<_, DrayConfig>(vars)?;
+        dray_config.validate()?;
+
         Ok(dray_config)
     }
 
+    /// Loads the config from a TOML or YAML file, picked by its extension. The
+    /// file's keys are the names of the environment variables without the
+    /// `DRAY_` prefix, such as `host` or `s3_bucket`, and `DRAY_*` environment
+    /// variables override the values in the file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<DrayConfig> {
+        DrayConfig::from_file_with_vars(path.as_ref(), env::vars())
+    }
+
+    fn from_file_with_vars<I: IntoIterator<Item = (String, String)>>(
+        path: &Path,
+        vars: I,
+    ) -> Result<DrayConfig> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| anyhow!("Failed to read config file {}: {}", path.display(), error))?;
+
+        let file_values: BTreeMap<String, FileValue> =
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some("toml") => toml::from_str(&contents)?,
+                Some("yaml") | Some("yml") => serde_yaml::from_str(&contents)?,
+                _ => {
+                    return Err(anyhow!(
+                        "Config file {} must have a .toml, .yaml, or .yml extension",
+                        path.display()
+                    ))
+                }
+            };
+
+        let mut merged_vars: BTreeMap<String, String> = file_values
+            .into_iter()
+            .map(|(key, value)| (format!("DRAY_{}", key.to_uppercase()), value.to_var()))
+            .collect();
+        merged_vars.extend(vars.into_iter().filter(|(key, _)| key.starts_with("DRAY_")));
+
+        let dray_config = envy::prefixed("DRAY_").from_iter::<_, DrayConfig>(merged_vars)?;
+        dray_config.validate()?;
+
+        Ok(dray_config)
+    }
+
+    /// Checks the settings that would otherwise only fail once the server
+    /// starts, so a bad config is reported when it is loaded.
+    pub fn validate(&self) -> Result<()> {
+        self.get_listen_addr()?;
+
+        if self.ssh_key_paths.trim().is_empty() {
+            return Err(anyhow!("At least one SSH key path must be set"));
+        }
+
+        if self.s3.bucket.is_empty() {
+            return Err(anyhow!("The S3 bucket must be set"));
+        }
+
+        if !(1..=MAX_PACKET_SIZE).contains(&self.max_packet_size) {
+            return Err(anyhow!(
+                "The max packet size must be between 1 and {} bytes",
+                MAX_PACKET_SIZE
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Parses the host into the address to listen on. IP addresses are parsed
+    /// directly, so IPv6 addresses are written in brackets, such as `[::]:2222`.
+    /// Otherwise, the host is resolved, such as `localhost:2222`.
+    pub fn get_listen_addr(&self) -> Result<SocketAddr> {
+        if let Ok(listen_addr) = self.host.parse::<SocketAddr>() {
+            return Ok(listen_addr);
+        }
+
+        if self.host.matches(':').count() > 1 {
+            return Err(anyhow!(
+                "Host {} must use brackets around an IPv6 address, such as [::]:2222",
+                self.host
+            ));
+        }
+
+        self.host
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("Host {} did not resolve to an address", self.host))
+    }
+
+    /// Builds the SSH server configuration from the host keys and algorithm
+    /// preferences.
+    pub fn get_ssh_config(&self) -> Result<Config> {
+        let keys = self.get_ssh_keys()?;
+        let mut preferred = Preferred::default();
+
+        if !self.host_key_algorithms.is_empty() {
+            let host_key_algorithms: Vec<key::Name> = self
+                .host_key_algorithms
+                .iter()
+                .map(|host_key_algorithm| host_key_algorithm.name())
+                .collect();
+
+            let has_key = host_key_algorithms.iter().any(|host_key_algorithm| {
+                keys.iter().any(|key| key.name() == host_key_algorithm.0)
+            });
+
+            if !has_key {
+                return Err(anyhow!(
+                    "None of the host key algorithms {:?} have a loaded SSH key",
+                    self.host_key_algorithms
+                ));
+            }
+
+            // thrussh only accepts static preferences, and the configuration is
+            // built once on startup.
+            preferred.key = Box::leak(host_key_algorithms.into_boxed_slice());
+        }
+
+        preferred.kex = select_algorithms(
+            "key exchange algorithm",
+            &self.kex_algorithms,
+            preferred.kex,
+        )?;
+        preferred.cipher = select_algorithms("cipher", &self.ciphers, preferred.cipher)?;
+
+        Ok(Config {
+            keys,
+            preferred,
+            max_auth_attempts: self.max_auth_tries,
+            window_size: self.window_size,
+            maximum_packet_size: self.max_packet_size,
+            connection_timeout: Some(
+                self.get_idle_timeout()
+                    .map_or(MIN_CONNECTION_TIMEOUT, |idle_timeout| {
+                        MIN_CONNECTION_TIMEOUT.max(idle_timeout + CONNECTION_TIMEOUT_MARGIN)
+                    }),
+            ),
+            ..Default::default()
+        })
+    }
+
+    /// Gets how long each user's authorized keys are cached.
+    pub fn get_authorized_keys_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.authorized_keys_cache_ttl)
+    }
+
+    /// Gets how long a client has to authenticate, if there is a limit.
+    pub fn get_login_grace_time(&self) -> Option<Duration> {
+        match self.login_grace_time {
+            0 => None,
+            login_grace_time => Some(Duration::from_secs(login_grace_time)),
+        }
+    }
+
+    /// Gets how long an SFTP session may be idle, if there is a limit.
+    pub fn get_idle_timeout(&self) -> Option<Duration> {
+        match self.idle_timeout {
+            0 => None,
+            idle_timeout => Some(Duration::from_secs(idle_timeout)),
+        }
+    }
+
+    /// Checks if a subsystem request is for SFTP, either by its standard name or
+    /// a configured alias.
+    pub fn is_sftp_subsystem(&self, name: &str) -> bool {
+        name == "sftp"
+            || self
+                .sftp_subsystem_aliases
+                .iter()
+                .any(|alias| alias == name)
+    }
+
     pub fn get_ssh_keys(&self) -> Result<Vec<key::KeyPair>> {
         let keys: Result<Vec<key::KeyPair>, _> = self
             .ssh_key_paths
@@ -35,6 +409,152 @@ impl DrayConfig {
     }
 }
 
+/// How the server handles a request that cannot be parsed.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BadMessagePolicy {
+    #[default]
+    Respond,
+    Disconnect,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum HostKeyAlgorithm {
+    #[serde(rename = "ssh-ed25519")]
+    Ed25519,
+    #[serde(rename = "rsa-sha2-256")]
+    RsaSha2_256,
+    #[serde(rename = "rsa-sha2-512")]
+    RsaSha2_512,
+}
+
+impl HostKeyAlgorithm {
+    fn name(&self) -> key::Name {
+        match self {
+            HostKeyAlgorithm::Ed25519 => key::ED25519,
+            HostKeyAlgorithm::RsaSha2_256 => key::RSA_SHA2_256,
+            HostKeyAlgorithm::RsaSha2_512 => key::RSA_SHA2_512,
+        }
+    }
+}
+
+/// Selects the configured algorithms from those supported by thrussh, keeping
+/// the configured order. Unknown algorithms are rejected, so a typo cannot
+/// silently weaken the allowed algorithms.
+fn select_algorithms<N: AsRef<str> + Copy>(
+    algorithm_type: &str,
+    configured: &[String],
+    supported: &'static [N],
+) -> Result<&'static [N]> {
+    if configured.is_empty() {
+        return Ok(supported);
+    }
+
+    let selected: Result<Vec<N>> = configured
+        .iter()
+        .map(|name| {
+            supported
+                .iter()
+                .find(|supported_name| supported_name.as_ref() == name.trim())
+                .copied()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Unknown {} {}, supported values are {:?}",
+                        algorithm_type,
+                        name,
+                        supported.iter().map(AsRef::as_ref).collect::<Vec<&str>>()
+                    )
+                })
+        })
+        .collect();
+
+    Ok(Box::leak(selected?.into_boxed_slice()))
+}
+
+/// A value from a config file, which is turned into the string its environment
+/// variable would hold so both sources are deserialized the same way.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FileValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<FileValue>),
+}
+
+impl FileValue {
+    fn to_var(&self) -> String {
+        match self {
+            FileValue::Bool(value) => value.to_string(),
+            FileValue::Integer(value) => value.to_string(),
+            FileValue::Float(value) => value.to_string(),
+            FileValue::String(value) => value.clone(),
+            FileValue::List(values) => values
+                .iter()
+                .map(FileValue::to_var)
+                .collect::<Vec<String>>()
+                .join(","),
+        }
+    }
+}
+
+fn get_default_log_auth_attempts() -> bool {
+    true
+}
+
+fn get_default_log_connections() -> bool {
+    true
+}
+
+fn get_default_readdir_dot_entries() -> bool {
+    true
+}
+
+fn get_default_max_data_length() -> u32 {
+    255 * 1024
+}
+
+fn get_default_max_packet_buffer() -> usize {
+    1024 * 1024
+}
+
+fn get_default_max_name_length() -> u32 {
+    8192
+}
+
+fn get_default_auth_failure_window() -> u64 {
+    300
+}
+
+fn get_default_authorized_keys_cache_ttl() -> u64 {
+    5
+}
+
+fn get_default_max_auth_tries() -> usize {
+    6
+}
+
+fn get_default_login_grace_time() -> u64 {
+    120
+}
+
+fn get_default_window_size() -> u32 {
+    2 * 1024 * 1024
+}
+
+fn get_default_max_packet_size() -> u32 {
+    32 * 1024
+}
+
+fn get_default_orphaned_upload_age() -> u64 {
+    24 * 60 * 60
+}
+
+fn get_default_debug_dump_max_bytes() -> usize {
+    64
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -50,7 +570,7 @@ mod test {
     #[test]
     fn test_get_ssh_keys_with_multiple_keys() {
         let temp_key = create_temp_key();
-        let config = create_config(vec![temp_key.clone(), temp_key].join(","));
+        let config = create_config([temp_key.clone(), temp_key].join(","));
 
         assert_eq!(2, config.get_ssh_keys().unwrap().len())
     }
@@ -63,18 +583,704 @@ mod test {
         config.get_ssh_keys().unwrap();
     }
 
+    #[test]
+    fn test_from_file_loads_toml() {
+        let config_path = create_temp_config(
+            "toml",
+            "host = \"localhost:2222\"\n\
+             ssh_key_paths = \"key\"\n\
+             s3_bucket = \"bucket\"\n\
+             max_dir_entries = 1000\n\
+             noop_requests = [\"setstat\", \"fsetstat\"]\n",
+        );
+
+        let config = DrayConfig::from_file_with_vars(&config_path, Vec::new()).unwrap();
+
+        assert_eq!("localhost:2222", config.host);
+        assert_eq!("bucket", config.s3.bucket);
+        assert_eq!(1000, config.max_dir_entries);
+        assert_eq!(
+            vec![NoopRequest::Setstat, NoopRequest::Fsetstat],
+            config.noop_requests
+        );
+    }
+
+    #[test]
+    fn test_from_file_loads_yaml() {
+        let config_path = create_temp_config(
+            "yaml",
+            "host: localhost:2222\nssh_key_paths: key\ns3_bucket: bucket\nlog_connections: false\n",
+        );
+
+        let config = DrayConfig::from_file_with_vars(&config_path, Vec::new()).unwrap();
+
+        assert_eq!("bucket", config.s3.bucket);
+        assert!(!config.log_connections);
+    }
+
+    #[test]
+    fn test_from_file_env_var_overrides_file_value() {
+        let config_path = create_temp_config(
+            "toml",
+            "host = \"localhost:2222\"\nssh_key_paths = \"key\"\ns3_bucket = \"bucket\"\n",
+        );
+
+        let config = DrayConfig::from_file_with_vars(
+            &config_path,
+            vec![
+                (String::from("DRAY_S3_BUCKET"), String::from("other-bucket")),
+                (String::from("HOME"), String::from("/root")),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!("other-bucket", config.s3.bucket);
+        assert_eq!("localhost:2222", config.host);
+    }
+
+    #[test]
+    fn test_from_vars_loads_valid_config() {
+        let config = DrayConfig::from_vars(vec![
+            (String::from("DRAY_HOST"), String::from("localhost:2222")),
+            (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+            (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+        ])
+        .unwrap();
+
+        assert_eq!("bucket", config.s3.bucket);
+    }
+
+    #[test]
+    fn test_from_vars_validates_config() {
+        assert!(DrayConfig::from_vars(vec![
+            (String::from("DRAY_HOST"), String::from("localhost:2222")),
+            (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+            (String::from("DRAY_S3_BUCKET"), String::from("")),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_from_file_rejects_unknown_extension() {
+        let config_path = create_temp_config("ini", "host = localhost:2222\n");
+
+        assert!(DrayConfig::from_file_with_vars(&config_path, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_from_file_validates_merged_config() {
+        let config_path = create_temp_config(
+            "toml",
+            "host = \"localhost:2222\"\nssh_key_paths = \"key\"\ns3_bucket = \"bucket\"\n",
+        );
+
+        assert!(DrayConfig::from_file_with_vars(
+            &config_path,
+            vec![(String::from("DRAY_HOST"), String::from("::1:2222"))],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_noop_requests() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (
+                    String::from("DRAY_NOOP_REQUESTS"),
+                    String::from("setstat,fsetstat"),
+                ),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            vec![NoopRequest::Setstat, NoopRequest::Fsetstat],
+            config.noop_requests
+        );
+    }
+
+    #[test]
+    fn test_is_sftp_subsystem_accepts_configured_alias() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (
+                    String::from("DRAY_SFTP_SUBSYSTEM_ALIASES"),
+                    String::from("sftp@vendor.example,vendor-sftp"),
+                ),
+            ])
+            .unwrap();
+
+        assert!(config.is_sftp_subsystem("sftp"));
+        assert!(config.is_sftp_subsystem("sftp@vendor.example"));
+        assert!(config.is_sftp_subsystem("vendor-sftp"));
+        assert!(!config.is_sftp_subsystem("unknown"));
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_error_verbosity() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (
+                    String::from("DRAY_ERROR_VERBOSITY"),
+                    String::from("detailed"),
+                ),
+            ])
+            .unwrap();
+
+        assert_eq!(ErrorVerbosity::Detailed, config.error_verbosity);
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_on_rename_existing_file() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (
+                    String::from("DRAY_ON_RENAME_EXISTING_FILE"),
+                    String::from("fail"),
+                ),
+            ])
+            .unwrap();
+
+        assert_eq!(RenamePolicy::Fail, config.on_rename_existing_file);
+    }
+
+    #[test]
+    fn test_get_ssh_config_uses_auth_limits() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), create_temp_key()),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_MAX_AUTH_TRIES"), String::from("3")),
+                (String::from("DRAY_LOGIN_GRACE_TIME"), String::from("30")),
+            ])
+            .unwrap();
+
+        assert_eq!(3, config.get_ssh_config().unwrap().max_auth_attempts);
+        assert_eq!(Some(Duration::from_secs(30)), config.get_login_grace_time());
+    }
+
+    #[test]
+    fn test_get_ssh_config_uses_window_size() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), create_temp_key()),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_WINDOW_SIZE"), String::from("16777216")),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            16 * 1024 * 1024,
+            config.get_ssh_config().unwrap().window_size
+        );
+    }
+
+    #[test]
+    fn test_get_ssh_config_uses_max_packet_size() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), create_temp_key()),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_MAX_PACKET_SIZE"), String::from("16384")),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            16 * 1024,
+            config.get_ssh_config().unwrap().maximum_packet_size
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_max_packet_size_over_ssh_limit() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_MAX_PACKET_SIZE"), String::from("65536")),
+            ])
+            .unwrap();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_idle_timeout_and_disconnect_message() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_IDLE_TIMEOUT"), String::from("300")),
+                (
+                    String::from("DRAY_DISCONNECT_MESSAGE"),
+                    String::from("Contact support@example.com."),
+                ),
+            ])
+            .unwrap();
+
+        assert_eq!(Some(Duration::from_secs(300)), config.get_idle_timeout());
+        assert_eq!(
+            Some("Contact support@example.com."),
+            config.disconnect_message.as_deref()
+        );
+    }
+
+    #[test]
+    fn test_get_ssh_config_connection_timeout_outlasts_idle_timeout() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), create_temp_key()),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_IDLE_TIMEOUT"), String::from("3600")),
+            ])
+            .unwrap();
+
+        let connection_timeout = config.get_ssh_config().unwrap().connection_timeout.unwrap();
+
+        assert!(connection_timeout > Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_get_ssh_config_keeps_default_connection_timeout_as_floor() {
+        let config = create_config(create_temp_key());
+
+        assert_eq!(
+            Some(Duration::from_secs(600)),
+            config.get_ssh_config().unwrap().connection_timeout
+        );
+
+        let config = DrayConfig {
+            idle_timeout: 300,
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(
+            Some(Duration::from_secs(600)),
+            config.get_ssh_config().unwrap().connection_timeout
+        );
+    }
+
+    #[test]
+    fn test_get_idle_timeout_is_unlimited_by_default() {
+        assert_eq!(None, create_config(String::from("")).get_idle_timeout());
+    }
+
+    #[test]
+    fn test_get_login_grace_time_is_unlimited_when_zero() {
+        let config = DrayConfig {
+            login_grace_time: 0,
+            ..create_config(String::from(""))
+        };
+
+        assert_eq!(None, config.get_login_grace_time());
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_max_dir_entries() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_MAX_DIR_ENTRIES"), String::from("1000")),
+                (
+                    String::from("DRAY_ON_MAX_DIR_ENTRIES"),
+                    String::from("fail"),
+                ),
+            ])
+            .unwrap();
+
+        assert_eq!(1000, config.max_dir_entries);
+        assert_eq!(DirEntriesPolicy::Fail, config.on_max_dir_entries);
+    }
+
+    #[test]
+    fn test_dray_config_readdir_dot_entries_defaults_to_true() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+            ])
+            .unwrap();
+
+        assert!(config.readdir_dot_entries);
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_readdir_dot_entries() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (
+                    String::from("DRAY_READDIR_DOT_ENTRIES"),
+                    String::from("false"),
+                ),
+            ])
+            .unwrap();
+
+        assert!(!config.readdir_dot_entries);
+    }
+
+    #[test]
+    fn test_dray_config_stat_extended_attributes_defaults_to_false() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+            ])
+            .unwrap();
+
+        assert!(!config.stat_extended_attributes);
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_opendir_require_exists() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (
+                    String::from("DRAY_OPENDIR_REQUIRE_EXISTS"),
+                    String::from("true"),
+                ),
+            ])
+            .unwrap();
+
+        assert!(config.opendir_require_exists);
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_strict_version_3() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_STRICT_VERSION_3"), String::from("true")),
+            ])
+            .unwrap();
+
+        assert!(config.strict_version_3);
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_orphaned_upload_cleanup() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (
+                    String::from("DRAY_CLEANUP_ORPHANED_UPLOADS"),
+                    String::from("true"),
+                ),
+                (
+                    String::from("DRAY_ORPHANED_UPLOAD_AGE"),
+                    String::from("3600"),
+                ),
+            ])
+            .unwrap();
+
+        assert!(config.cleanup_orphaned_uploads);
+        assert_eq!(3600, config.orphaned_upload_age);
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_on_bad_message() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (
+                    String::from("DRAY_ON_BAD_MESSAGE"),
+                    String::from("disconnect"),
+                ),
+            ])
+            .unwrap();
+
+        assert_eq!(BadMessagePolicy::Disconnect, config.on_bad_message);
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_auth_throttling() {
+        let config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("localhost:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_MAX_AUTH_FAILURES"), String::from("5")),
+            ])
+            .unwrap();
+
+        assert_eq!(Some(5), config.max_auth_failures);
+        assert_eq!(300, config.auth_failure_window);
+    }
+
+    #[test]
+    fn test_get_ssh_config_prefers_host_key_algorithms_in_configured_order() {
+        let config = DrayConfig {
+            host_key_algorithms: vec![HostKeyAlgorithm::RsaSha2_512, HostKeyAlgorithm::Ed25519],
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(
+            &[key::RSA_SHA2_512, key::ED25519],
+            config.get_ssh_config().unwrap().preferred.key
+        );
+    }
+
+    #[test]
+    fn test_get_ssh_config_without_host_key_algorithms_uses_defaults() {
+        let config = create_config(create_temp_key());
+
+        assert_eq!(
+            Preferred::default().key,
+            config.get_ssh_config().unwrap().preferred.key
+        );
+    }
+
+    #[test]
+    fn test_get_ssh_config_rejects_host_key_algorithms_without_loaded_key() {
+        let config = DrayConfig {
+            host_key_algorithms: vec![HostKeyAlgorithm::RsaSha2_256],
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.get_ssh_config().is_err());
+    }
+
+    #[test]
+    fn test_get_ssh_config_prefers_configured_ciphers() {
+        let config = DrayConfig {
+            ciphers: vec![String::from("chacha20-poly1305@openssh.com")],
+            ..create_config(create_temp_key())
+        };
+
+        let ciphers: Vec<&str> = config
+            .get_ssh_config()
+            .unwrap()
+            .preferred
+            .cipher
+            .iter()
+            .map(AsRef::as_ref)
+            .collect();
+
+        assert_eq!(vec!["chacha20-poly1305@openssh.com"], ciphers);
+    }
+
+    #[test]
+    fn test_get_ssh_config_prefers_configured_kex_algorithms() {
+        let config = DrayConfig {
+            kex_algorithms: vec![String::from("curve25519-sha256@libssh.org")],
+            ..create_config(create_temp_key())
+        };
+
+        let kex_algorithms: Vec<&str> = config
+            .get_ssh_config()
+            .unwrap()
+            .preferred
+            .kex
+            .iter()
+            .map(AsRef::as_ref)
+            .collect();
+
+        assert_eq!(vec!["curve25519-sha256@libssh.org"], kex_algorithms);
+    }
+
+    #[test]
+    fn test_get_ssh_config_rejects_unknown_cipher() {
+        let config = DrayConfig {
+            ciphers: vec![String::from("aes128-cbc")],
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.get_ssh_config().is_err());
+    }
+
+    #[test]
+    fn test_get_ssh_config_rejects_unknown_kex_algorithm() {
+        let config = DrayConfig {
+            kex_algorithms: vec![String::from("diffie-hellman-group1-sha1")],
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.get_ssh_config().is_err());
+    }
+
+    #[test]
+    fn test_dray_config_deserializes_host_key_algorithms() {
+        let config = envy::prefixed("DRAY_")
+            .from_iter::<_, DrayConfig>(vec![
+                (String::from("DRAY_HOST"), String::from("0.0.0.0:2222")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::from("key")),
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (
+                    String::from("DRAY_HOST_KEY_ALGORITHMS"),
+                    String::from("ssh-ed25519,rsa-sha2-512"),
+                ),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            vec![HostKeyAlgorithm::Ed25519, HostKeyAlgorithm::RsaSha2_512],
+            config.host_key_algorithms
+        );
+    }
+
+    #[test]
+    fn test_get_listen_addr_with_ipv4_address() {
+        let config = create_config_with_host("127.0.0.1:2222");
+
+        assert_eq!(
+            SocketAddr::from(([127, 0, 0, 1], 2222)),
+            config.get_listen_addr().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_listen_addr_with_ipv6_address() {
+        let config = create_config_with_host("[2001:db8::1]:22");
+
+        assert_eq!(
+            SocketAddr::from(([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1], 22)),
+            config.get_listen_addr().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_listen_addr_with_bracketed_unspecified_ipv6_address() {
+        let config = create_config_with_host("[::]:2222");
+
+        assert_eq!(
+            SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], 2222)),
+            config.get_listen_addr().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_listen_addr_with_hostname() {
+        let config = create_config_with_host("localhost:2222");
+
+        let listen_addr = config.get_listen_addr().unwrap();
+
+        assert!(listen_addr.ip().is_loopback());
+        assert_eq!(2222, listen_addr.port());
+    }
+
+    #[test]
+    fn test_get_listen_addr_with_unbracketed_ipv6_address() {
+        let config = create_config_with_host("::1:2222");
+
+        assert!(config.get_listen_addr().is_err());
+    }
+
+    fn create_config_with_host(host: &str) -> DrayConfig {
+        DrayConfig {
+            host: String::from(host),
+            ..create_config(String::from(""))
+        }
+    }
+
     fn create_config(key_paths: String) -> DrayConfig {
         DrayConfig {
             host: String::from(""),
             ssh_key_paths: key_paths,
+            log_auth_attempts: true,
+            log_connections: true,
+            max_auth_failures: None,
+            auth_failure_window: 300,
+            authorized_keys_cache_ttl: 5,
+            max_auth_tries: 6,
+            login_grace_time: 120,
+            idle_timeout: 0,
+            disconnect_message: None,
+            auto_create_home: false,
+            max_read_length: 255 * 1024,
+            max_write_length: 255 * 1024,
+            max_packet_buffer: 1024 * 1024,
+            max_name_length: 8192,
+            max_dir_entries: 0,
+            on_max_dir_entries: DirEntriesPolicy::Truncate,
+            readdir_dot_entries: true,
+            stat_extended_attributes: false,
+            opendir_require_exists: false,
+            strict_version_3: false,
+            on_write_missing_file: MissingFilePolicy::Fail,
+            on_rename_existing_file: RenamePolicy::Overwrite,
+            noop_requests: Vec::new(),
+            sftp_subsystem_aliases: Vec::new(),
+            error_verbosity: ErrorVerbosity::Generic,
+            on_bad_message: BadMessagePolicy::Respond,
+            host_key_algorithms: Vec::new(),
+            kex_algorithms: Vec::new(),
+            ciphers: Vec::new(),
+            window_size: 2 * 1024 * 1024,
+            max_packet_size: 32 * 1024,
+            cleanup_orphaned_uploads: false,
+            orphaned_upload_age: 24 * 60 * 60,
+            debug_dump: false,
+            debug_dump_max_bytes: 64,
             s3: S3Config {
                 endpoint_name: None,
                 endpoint_region: String::from("us-east-1"),
+                region: None,
                 bucket: String::from("bucket"),
+                bucket_prefixes: Vec::new(),
+                user_profiles: Vec::new(),
+                storage_class: None,
+                max_connections: None,
+                connect_timeout: None,
+                part_size: 10 * 1024 * 1024,
+                list_page_size: None,
+                max_write_buffer: None,
+                public_read_permissions: false,
+                rmdir_require_empty: false,
+                preserve_mtime: false,
+                list_metadata: false,
+                decompress_gzip: false,
+                case_insensitive_keys: false,
+                presigned_url_expiry: 900,
+                read_after_write_retries: 0,
+                delete_retries: 3,
+                read_coalesce_window: 256 * 1024,
+                follow_redirects: false,
+                uploader_metadata: None,
+                snapshot_dir: None,
+                write_spool_dir: None,
             },
         }
     }
 
+    fn create_temp_config(extension: &str, contents: &str) -> std::path::PathBuf {
+        let config_path =
+            env::temp_dir().join(format!("dray-{}.{}", uuid::Uuid::new_v4(), extension));
+        fs::write(&config_path, contents).unwrap();
+        config_path
+    }
+
     fn create_temp_key() -> String {
         let temp_file = env::temp_dir().join("id_ed25519");
 
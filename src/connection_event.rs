@@ -0,0 +1,65 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt::{Display, Formatter, Result};
+use std::net::SocketAddr;
+
+/// A new client connection, logged as a single line of `key=value` fields with
+/// the peer's IP address and port kept apart, so log pipelines can look up the
+/// address without parsing it.
+pub struct ConnectionEvent {
+    pub peer_addr: SocketAddr,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl ConnectionEvent {
+    pub fn new(peer_addr: SocketAddr) -> ConnectionEvent {
+        ConnectionEvent {
+            peer_addr,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+impl Display for ConnectionEvent {
+    fn fmt(&self, formatter: &mut Formatter) -> Result {
+        write!(
+            formatter,
+            "connection_event peer_ip={} peer_port={} timestamp={}",
+            self.peer_addr.ip(),
+            self.peer_addr.port(),
+            self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_display_formats_connection_event() {
+        let connection_event = ConnectionEvent {
+            peer_addr: "127.0.0.1:50022".parse().unwrap(),
+            timestamp: Utc.timestamp(1608671341, 0),
+        };
+
+        assert_eq!(
+            "connection_event peer_ip=127.0.0.1 peer_port=50022 timestamp=2020-12-22T21:09:01.000Z",
+            connection_event.to_string()
+        );
+    }
+
+    #[test]
+    fn test_display_formats_ipv6_connection_event() {
+        let connection_event = ConnectionEvent {
+            peer_addr: "[::1]:50022".parse().unwrap(),
+            timestamp: Utc.timestamp(1608671341, 0),
+        };
+
+        assert_eq!(
+            "connection_event peer_ip=::1 peer_port=50022 timestamp=2020-12-22T21:09:01.000Z",
+            connection_event.to_string()
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::protocol::{request::Request, response::Response};
+
+/// Formats SFTP packets for diagnosing client interoperability issues. Packet
+/// bytes are truncated to a cap, and file data is reported by length, so file
+/// contents are never logged beyond the cap.
+#[derive(Debug, Clone, Copy)]
+pub struct DebugDump {
+    max_bytes: usize,
+}
+
+impl DebugDump {
+    pub fn new(max_bytes: usize) -> DebugDump {
+        DebugDump { max_bytes }
+    }
+
+    pub fn format_request(&self, request: &Request, request_bytes: &[u8]) -> String {
+        let fields = match request {
+            Request::Write(write) => format!(
+                "id={} handle={:?} offset={} data_length={}",
+                write.id,
+                write.handle,
+                write.offset,
+                write.data.len()
+            ),
+            _ => format!("{:?}", request),
+        };
+
+        format!(
+            "sftp_request opcode={} fields={} bytes={}",
+            get_request_opcode_name(request),
+            fields,
+            self.format_bytes(request_bytes)
+        )
+    }
+
+    pub fn format_invalid_request(&self, request_bytes: &[u8]) -> String {
+        format!(
+            "sftp_request opcode=invalid bytes={}",
+            self.format_bytes(request_bytes)
+        )
+    }
+
+    pub fn format_response(&self, response: &Response, response_bytes: &[u8]) -> String {
+        let fields = match response {
+            Response::Data(data) => format!("id={} data_length={}", data.id, data.data.len()),
+            _ => format!("{:?}", response),
+        };
+
+        format!(
+            "sftp_response opcode={} fields={} bytes={}",
+            get_response_opcode_name(response),
+            fields,
+            self.format_bytes(response_bytes)
+        )
+    }
+
+    fn format_bytes(&self, bytes: &[u8]) -> String {
+        if bytes.len() <= self.max_bytes {
+            return hex::encode(bytes);
+        }
+
+        format!(
+            "{}... ({} bytes)",
+            hex::encode(&bytes[..self.max_bytes]),
+            bytes.len()
+        )
+    }
+}
+
+fn get_request_opcode_name(request: &Request) -> &'static str {
+    match request {
+        Request::Init(_) => "SSH_FXP_INIT",
+        Request::Open(_) => "SSH_FXP_OPEN",
+        Request::Close(_) => "SSH_FXP_CLOSE",
+        Request::Read(_) => "SSH_FXP_READ",
+        Request::Write(_) => "SSH_FXP_WRITE",
+        Request::Lstat(_) => "SSH_FXP_LSTAT",
+        Request::Fstat(_) => "SSH_FXP_FSTAT",
+        Request::Setstat(_) => "SSH_FXP_SETSTAT",
+        Request::Fsetstat(_) => "SSH_FXP_FSETSTAT",
+        Request::Opendir(_) => "SSH_FXP_OPENDIR",
+        Request::Readdir(_) => "SSH_FXP_READDIR",
+        Request::Remove(_) => "SSH_FXP_REMOVE",
+        Request::Mkdir(_) => "SSH_FXP_MKDIR",
+        Request::Rmdir(_) => "SSH_FXP_RMDIR",
+        Request::Realpath(_) => "SSH_FXP_REALPATH",
+        Request::Stat(_) => "SSH_FXP_STAT",
+        Request::Rename(_) => "SSH_FXP_RENAME",
+        Request::Readlink(_) => "SSH_FXP_READLINK",
+        Request::Symlink(_) => "SSH_FXP_SYMLINK",
+        Request::Extended(_) => "SSH_FXP_EXTENDED",
+    }
+}
+
+fn get_response_opcode_name(response: &Response) -> &'static str {
+    match response {
+        Response::Version(_) => "SSH_FXP_VERSION",
+        Response::Status(_) => "SSH_FXP_STATUS",
+        Response::Handle(_) => "SSH_FXP_HANDLE",
+        Response::Data(_) => "SSH_FXP_DATA",
+        Response::DataStream(_) => "SSH_FXP_DATA",
+        Response::Name(_) => "SSH_FXP_NAME",
+        Response::Attrs(_) => "SSH_FXP_ATTRS",
+        Response::Limits(_) => "SSH_FXP_EXTENDED_REPLY",
+        Response::PresignedUrl(_) => "SSH_FXP_EXTENDED_REPLY",
+        Response::SpaceAvailable(_) => "SSH_FXP_EXTENDED_REPLY",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::protocol::{request, response};
+
+    use bytes::Bytes;
+
+    #[test]
+    fn test_format_request_includes_opcode_name() {
+        let debug_dump = DebugDump::new(64);
+
+        let request = Request::Realpath(request::path::Path {
+            id: 1,
+            path: String::from("."),
+        });
+
+        let line = debug_dump.format_request(&request, &[0x10]);
+
+        assert!(line.contains("opcode=SSH_FXP_REALPATH"));
+        assert!(line.contains("bytes=10"));
+    }
+
+    #[test]
+    fn test_format_request_omits_write_data() {
+        let debug_dump = DebugDump::new(64);
+
+        let request = Request::Write(request::write::Write {
+            id: 1,
+            handle: String::from("handle"),
+            offset: 0,
+            data: Bytes::from("secret"),
+        });
+
+        let line = debug_dump.format_request(&request, &[]);
+
+        assert!(line.contains("data_length=6"));
+        assert!(!line.contains("secret"));
+    }
+
+    #[test]
+    fn test_format_response_truncates_bytes_to_cap() {
+        let debug_dump = DebugDump::new(2);
+
+        let response = Response::Data(response::data::Data {
+            id: 1,
+            data: vec![0xAB; 8],
+        });
+
+        let line = debug_dump.format_response(&response, &[0xAB; 8]);
+
+        assert!(line.contains("opcode=SSH_FXP_DATA"));
+        assert!(line.contains("bytes=abab... (8 bytes)"));
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::Result;
+
+/// The most early data that will be held, which is enough for the INIT packet and
+/// a few requests that eager clients send without waiting for the subsystem.
+const MAX_EARLY_DATA_LENGTH: usize = 256 * 1024;
+
+/// Holds data that a client sends before the SFTP subsystem has been started, so
+/// it can be processed once the subsystem is running instead of being treated
+/// as SFTP data on a channel that has not agreed to speak SFTP.
+pub struct EarlyDataBuffer {
+    started: bool,
+    pending: Vec<u8>,
+}
+
+impl EarlyDataBuffer {
+    pub fn new() -> EarlyDataBuffer {
+        EarlyDataBuffer {
+            started: false,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns data that should be processed now, or `None` if the data was
+    /// buffered until the subsystem starts. An error is returned if a client
+    /// sends more early data than will be buffered.
+    pub fn push(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.started {
+            return Ok(Some(data.to_vec()));
+        }
+
+        if self.pending.len() + data.len() > MAX_EARLY_DATA_LENGTH {
+            return Err(anyhow::anyhow!(
+                "Received more than {} bytes before the SFTP subsystem started.",
+                MAX_EARLY_DATA_LENGTH
+            ));
+        }
+
+        self.pending.extend_from_slice(data);
+        Ok(None)
+    }
+
+    /// Marks the subsystem as started and returns any data that was buffered.
+    pub fn start(&mut self) -> Vec<u8> {
+        self.started = true;
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_push_before_start_buffers_data() {
+        let mut early_data_buffer = EarlyDataBuffer::new();
+
+        assert_eq!(None, early_data_buffer.push(&[0x01]).unwrap());
+        assert_eq!(None, early_data_buffer.push(&[0x02]).unwrap());
+
+        assert_eq!(vec![0x01, 0x02], early_data_buffer.start());
+    }
+
+    #[test]
+    fn test_push_after_start_returns_data() {
+        let mut early_data_buffer = EarlyDataBuffer::new();
+
+        assert!(early_data_buffer.start().is_empty());
+        assert_eq!(Some(vec![0x01]), early_data_buffer.push(&[0x01]).unwrap());
+    }
+
+    #[test]
+    fn test_start_clears_buffered_data() {
+        let mut early_data_buffer = EarlyDataBuffer::new();
+
+        early_data_buffer.push(&[0x01]).unwrap();
+        early_data_buffer.start();
+
+        assert!(early_data_buffer.start().is_empty());
+    }
+
+    #[test]
+    fn test_push_before_start_rejects_excess_data() {
+        let mut early_data_buffer = EarlyDataBuffer::new();
+
+        assert!(early_data_buffer
+            .push(&vec![0x00; MAX_EARLY_DATA_LENGTH + 1])
+            .is_err());
+    }
+}
@@ -1,23 +1,89 @@
+use crate::protocol::response::status::StatusCode;
+
 use std::error::Error as StdError;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::fmt::Result;
 
 #[derive(Debug, PartialEq)]
+#[allow(dead_code, clippy::enum_variant_names)]
 pub enum Error {
     BadMessage,
+    /// A string in the request is not valid UTF-8, which is reported to the
+    /// client as a bad message but logged separately from truncated packets.
+    InvalidUtf8,
     Unimplemented,
     ServerError,
+    PermissionDenied,
+    NoSuchFile,
+    Eof,
+    QuotaExceeded,
+}
+
+impl Error {
+    /// Returns the SSH_FX_* status code sent to the client for the error.
+    ///
+    /// SFTP version 3 has no SSH_FX_QUOTA_EXCEEDED, so exceeding a quota is
+    /// reported as a failure.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::BadMessage => StatusCode::BadMessage,
+            Error::InvalidUtf8 => StatusCode::BadMessage,
+            Error::Unimplemented => StatusCode::OperationUnsupported,
+            Error::ServerError => StatusCode::Failure,
+            Error::PermissionDenied => StatusCode::PermissionDenied,
+            Error::NoSuchFile => StatusCode::NoSuchFile,
+            Error::Eof => StatusCode::Eof,
+            Error::QuotaExceeded => StatusCode::Failure,
+        }
+    }
 }
 
 impl Display for Error {
-    fn fmt(&self, _formatter: &mut Formatter) -> Result {
-        todo!()
+    fn fmt(&self, formatter: &mut Formatter) -> Result {
+        let message = match self {
+            Error::BadMessage => "Bad message.",
+            Error::InvalidUtf8 => "Invalid UTF-8 string.",
+            Error::Unimplemented => "Operation unsupported.",
+            Error::ServerError => "Failure.",
+            Error::PermissionDenied => "Permission denied.",
+            Error::NoSuchFile => "No such file.",
+            Error::Eof => "End of file.",
+            Error::QuotaExceeded => "Quota exceeded.",
+        };
+
+        write!(formatter, "{}", message)
     }
 }
 
-impl StdError for Error {
-    fn description(&self) -> &str {
-        todo!()
+impl StdError for Error {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status_code_maps_each_error() {
+        assert_eq!(StatusCode::BadMessage, Error::BadMessage.status_code());
+        assert_eq!(StatusCode::BadMessage, Error::InvalidUtf8.status_code());
+        assert_eq!(
+            StatusCode::OperationUnsupported,
+            Error::Unimplemented.status_code()
+        );
+        assert_eq!(StatusCode::Failure, Error::ServerError.status_code());
+        assert_eq!(
+            StatusCode::PermissionDenied,
+            Error::PermissionDenied.status_code()
+        );
+        assert_eq!(StatusCode::NoSuchFile, Error::NoSuchFile.status_code());
+        assert_eq!(StatusCode::Eof, Error::Eof.status_code());
+        assert_eq!(StatusCode::Failure, Error::QuotaExceeded.status_code());
+    }
+
+    #[test]
+    fn test_display_formats_error_message() {
+        assert_eq!("Permission denied.", Error::PermissionDenied.to_string());
+        assert_eq!("No such file.", Error::NoSuchFile.to_string());
+        assert_eq!("Quota exceeded.", Error::QuotaExceeded.to_string());
     }
 }
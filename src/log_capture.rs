@@ -0,0 +1,64 @@
+use std::sync::{Mutex, Once};
+use std::thread::{self, ThreadId};
+
+static INIT: Once = Once::new();
+
+static CAPTURE_LOGGER: CaptureLogger = CaptureLogger {
+    records: Mutex::new(Vec::new()),
+};
+
+/// Collects the messages logged on the current thread from when it is created,
+/// so tests running in parallel only see their own messages. Tests must log on
+/// their own thread, as the default `#[tokio::test]` runtime does.
+pub struct LogCapture {
+    thread_id: ThreadId,
+}
+
+impl LogCapture {
+    pub fn start() -> LogCapture {
+        INIT.call_once(|| {
+            log::set_logger(&CAPTURE_LOGGER).unwrap();
+            log::set_max_level(log::LevelFilter::Info);
+        });
+
+        let thread_id = thread::current().id();
+
+        CAPTURE_LOGGER
+            .records
+            .lock()
+            .unwrap()
+            .retain(|(id, _)| *id != thread_id);
+
+        LogCapture { thread_id }
+    }
+
+    pub fn messages(&self) -> Vec<String> {
+        CAPTURE_LOGGER
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| *id == self.thread_id)
+            .map(|(_, message)| message.clone())
+            .collect()
+    }
+}
+
+struct CaptureLogger {
+    records: Mutex<Vec<(ThreadId, String)>>,
+}
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.records
+            .lock()
+            .unwrap()
+            .push((thread::current().id(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
@@ -1,31 +1,48 @@
+use anyhow::Error;
 use dotenv::dotenv;
-use log::{info, LevelFilter};
+use log::{error, info, LevelFilter};
+use std::process;
 use std::time::Duration;
 use tokio::runtime::Runtime;
 use tokio::signal;
 
-use dray::{config::DrayConfig, DraySshServer};
+use dray::{cli::CliArgs, DraySshServer};
+
+fn main() -> Result<(), Error> {
+    let cli_args = match CliArgs::parse(std::env::args().skip(1)) {
+        Ok(cli_args) => cli_args,
+        Err(error) => {
+            eprintln!("{}\n{}", error, dray::cli::USAGE);
+            process::exit(2);
+        }
+    };
 
-fn main() {
     dotenv().ok();
 
     env_logger::Builder::new()
-        .filter_level(LevelFilter::Info)
+        .filter_level(cli_args.log_level.unwrap_or(LevelFilter::Info))
         .init();
 
     info!("Starting Dray");
 
     let runtime = Runtime::new().unwrap();
 
-    let dray_config = DrayConfig::new().unwrap();
-    let dray_server = DraySshServer::new(dray_config);
+    let dray_config = cli_args.load_config().unwrap();
+    let dray_server = runtime.block_on(DraySshServer::new(dray_config))?;
 
     runtime.block_on(dray_server.health_check()).unwrap();
+
+    if let Err(error) = runtime.block_on(dray_server.cleanup_orphaned_uploads()) {
+        error!("Failed to clean up orphaned uploads: {}", error);
+    }
+
     runtime.spawn(dray_server.run_server());
 
     runtime.block_on(signal::ctrl_c()).unwrap();
 
     info!("Received SIGINT - Shutting Down Dray");
 
-    runtime.shutdown_timeout(Duration::from_secs(10))
+    runtime.shutdown_timeout(Duration::from_secs(10));
+
+    Ok(())
 }
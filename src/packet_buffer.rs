@@ -0,0 +1,128 @@
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+
+/// The length of the u32 length prefix at the start of every SFTP packet.
+const PACKET_LENGTH_LENGTH: usize = 4;
+
+/// Reassembles SFTP packets from SSH channel data. SSH splits channel data into
+/// messages no larger than the peer's maximum packet size, so a single SFTP
+/// packet, such as a 32 KiB write, can arrive across several messages.
+pub struct PacketBuffer {
+    pending: BytesMut,
+    max_pending_length: usize,
+}
+
+impl PacketBuffer {
+    pub fn new(max_pending_length: usize) -> PacketBuffer {
+        PacketBuffer {
+            pending: BytesMut::new(),
+            max_pending_length,
+        }
+    }
+
+    /// Buffers the data and returns every packet that is now complete. A trailing
+    /// partial packet is held until the rest of it arrives. An error is returned
+    /// if the partial packet grows past the most bytes that will be held, such
+    /// as for a client that sends a huge length prefix.
+    pub fn push(&mut self, data: &[u8]) -> Result<Bytes> {
+        self.pending.extend_from_slice(data);
+
+        let mut complete_length = 0;
+
+        while self.pending.len() - complete_length >= PACKET_LENGTH_LENGTH {
+            let length_prefix =
+                &self.pending[complete_length..complete_length + PACKET_LENGTH_LENGTH];
+            let packet_length = PACKET_LENGTH_LENGTH
+                + u32::from_be_bytes([
+                    length_prefix[0],
+                    length_prefix[1],
+                    length_prefix[2],
+                    length_prefix[3],
+                ]) as usize;
+
+            if self.pending.len() - complete_length < packet_length {
+                break;
+            }
+
+            complete_length += packet_length;
+        }
+
+        let packets = self.pending.split_to(complete_length).freeze();
+
+        if self.pending.len() > self.max_pending_length {
+            return Err(anyhow::anyhow!(
+                "Received a partial SFTP packet of more than {} bytes.",
+                self.max_pending_length
+            ));
+        }
+
+        Ok(packets)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MAX_PENDING_LENGTH: usize = 1024;
+
+    #[test]
+    fn test_push_returns_complete_packets() {
+        let mut packet_buffer = PacketBuffer::new(MAX_PENDING_LENGTH);
+
+        assert_eq!(
+            Bytes::from(vec![0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00]),
+            packet_buffer
+                .push(&[0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00])
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_push_holds_partial_packet_until_complete() {
+        let mut packet_buffer = PacketBuffer::new(MAX_PENDING_LENGTH);
+
+        assert!(packet_buffer.push(&[0x00, 0x00]).unwrap().is_empty());
+        assert!(packet_buffer.push(&[0x00, 0x02, 0x01]).unwrap().is_empty());
+        assert_eq!(
+            Bytes::from(vec![0x00, 0x00, 0x00, 0x02, 0x01, 0x02]),
+            packet_buffer.push(&[0x02]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_push_holds_trailing_partial_packet() {
+        let mut packet_buffer = PacketBuffer::new(MAX_PENDING_LENGTH);
+
+        assert_eq!(
+            Bytes::from(vec![0x00, 0x00, 0x00, 0x01, 0x01]),
+            packet_buffer
+                .push(&[0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x02, 0x01])
+                .unwrap()
+        );
+        assert_eq!(
+            Bytes::from(vec![0x00, 0x00, 0x00, 0x02, 0x01, 0x02]),
+            packet_buffer.push(&[0x02]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_push_rejects_partial_packet_over_max_length() {
+        let mut packet_buffer = PacketBuffer::new(4);
+
+        assert!(packet_buffer.push(&[0x00, 0x10, 0x00, 0x00]).is_ok());
+        assert!(packet_buffer.push(&[0x01]).is_err());
+    }
+
+    #[test]
+    fn test_push_allows_complete_packets_over_max_length() {
+        let mut packet_buffer = PacketBuffer::new(4);
+
+        assert_eq!(
+            Bytes::from(vec![0x00, 0x00, 0x00, 0x02, 0x01, 0x02]),
+            packet_buffer
+                .push(&[0x00, 0x00, 0x00, 0x02, 0x01, 0x02])
+                .unwrap()
+        );
+    }
+}
@@ -2,13 +2,31 @@ use crate::error::Error;
 use crate::try_buf::TryBuf;
 
 use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::BTreeMap;
 use std::convert::From;
 use std::convert::TryFrom;
+use std::convert::TryInto;
 
 const SIZE: u32 = 0x00000001;
 const UIDGID: u32 = 0x00000002;
 const PERMISSIONS: u32 = 0x00000004;
 const ACMODTIME: u32 = 0x00000008;
+const EXTENDED: u32 = 0x80000000;
+
+// Attribute flags from version 4 onwards, which replace UIDGID and ACMODTIME.
+const V4_ACCESSTIME: u32 = 0x00000008;
+const V4_CREATETIME: u32 = 0x00000010;
+const V4_MODIFYTIME: u32 = 0x00000020;
+const V4_ACL: u32 = 0x00000040;
+const V4_OWNERGROUP: u32 = 0x00000080;
+const V4_SUBSECOND_TIMES: u32 = 0x00000100;
+const V5_BITS: u32 = 0x00000200;
+const V6_ALLOCATION_SIZE: u32 = 0x00000400;
+const V6_TEXT_HINT: u32 = 0x00000800;
+const V6_MIME_TYPE: u32 = 0x00001000;
+const V6_LINK_COUNT: u32 = 0x00002000;
+const V6_UNTRANSLATED_NAME: u32 = 0x00004000;
+const V6_CTIME: u32 = 0x00008000;
 
 #[derive(Debug, Default, PartialEq)]
 pub struct FileAttributes {
@@ -18,6 +36,7 @@ pub struct FileAttributes {
     pub permissions: Option<u32>,
     pub atime: Option<u32>,
     pub mtime: Option<u32>,
+    pub extended: BTreeMap<String, String>,
 }
 
 impl FileAttributes {
@@ -27,6 +46,106 @@ impl FileAttributes {
             None => false,
         }
     }
+
+    /// Parses attributes in the format of the negotiated protocol version.
+    /// Attributes from version 4 onwards start with the file type and carry
+    /// fields that have no version 3 equivalent, which are skipped.
+    pub fn try_from_version(
+        file_attributes_bytes: &mut Bytes,
+        version: u32,
+    ) -> Result<FileAttributes, Error> {
+        if version < 4 {
+            return FileAttributes::try_from(file_attributes_bytes);
+        }
+
+        let attributes = file_attributes_bytes.try_get_u32()?;
+        file_attributes_bytes.try_get_u8()?; // type
+
+        let mut file_attributes = FileAttributes::default();
+
+        if attributes & SIZE != 0 {
+            file_attributes.size = Some(file_attributes_bytes.try_get_u64()?);
+        }
+
+        if version >= 6 && attributes & V6_ALLOCATION_SIZE != 0 {
+            file_attributes_bytes.try_get_u64()?;
+        }
+
+        if attributes & V4_OWNERGROUP != 0 {
+            file_attributes_bytes.try_get_string()?; // owner
+            file_attributes_bytes.try_get_string()?; // group
+        }
+
+        if attributes & PERMISSIONS != 0 {
+            file_attributes.permissions = Some(file_attributes_bytes.try_get_u32()?);
+        }
+
+        let has_subseconds = attributes & V4_SUBSECOND_TIMES != 0;
+
+        if attributes & V4_ACCESSTIME != 0 {
+            file_attributes.atime = Some(try_get_time(file_attributes_bytes, has_subseconds)?);
+        }
+
+        if attributes & V4_CREATETIME != 0 {
+            try_get_time(file_attributes_bytes, has_subseconds)?;
+        }
+
+        if attributes & V4_MODIFYTIME != 0 {
+            file_attributes.mtime = Some(try_get_time(file_attributes_bytes, has_subseconds)?);
+        }
+
+        if version >= 6 && attributes & V6_CTIME != 0 {
+            try_get_time(file_attributes_bytes, has_subseconds)?;
+        }
+
+        if attributes & V4_ACL != 0 {
+            file_attributes_bytes.try_get_string()?;
+        }
+
+        if version >= 5 && attributes & V5_BITS != 0 {
+            file_attributes_bytes.try_get_u32()?; // attrib-bits
+
+            if version >= 6 {
+                file_attributes_bytes.try_get_u32()?; // attrib-bits-valid
+            }
+        }
+
+        if version >= 6 {
+            if attributes & V6_TEXT_HINT != 0 {
+                file_attributes_bytes.try_get_u8()?;
+            }
+
+            if attributes & V6_MIME_TYPE != 0 {
+                file_attributes_bytes.try_get_string()?;
+            }
+
+            if attributes & V6_LINK_COUNT != 0 {
+                file_attributes_bytes.try_get_u32()?;
+            }
+
+            if attributes & V6_UNTRANSLATED_NAME != 0 {
+                file_attributes_bytes.try_get_string()?;
+            }
+        }
+
+        if attributes & EXTENDED != 0 {
+            file_attributes.extended = try_get_extended(file_attributes_bytes)?;
+        }
+
+        Ok(file_attributes)
+    }
+}
+
+/// Reads a version 4+ time in seconds since the epoch, which is truncated to the
+/// 32 bits used by version 3.
+fn try_get_time(time_bytes: &mut Bytes, has_subseconds: bool) -> Result<u32, Error> {
+    let seconds = time_bytes.try_get_i64()?;
+
+    if has_subseconds {
+        time_bytes.try_get_u32()?; // nanoseconds
+    }
+
+    Ok(seconds as u32)
 }
 
 impl TryFrom<&mut Bytes> for FileAttributes {
@@ -66,10 +185,30 @@ impl TryFrom<&mut Bytes> for FileAttributes {
             } else {
                 None
             },
+            extended: if attributes & EXTENDED != 0 {
+                try_get_extended(file_attributes_bytes)?
+            } else {
+                BTreeMap::new()
+            },
         })
     }
 }
 
+fn try_get_extended(extended_bytes: &mut Bytes) -> Result<BTreeMap<String, String>, Error> {
+    let extended_count = extended_bytes.try_get_u32()?;
+
+    let mut extended = BTreeMap::new();
+
+    for _ in 0..extended_count {
+        let extended_type = extended_bytes.try_get_string()?;
+        let extended_data = extended_bytes.try_get_string()?;
+
+        extended.insert(extended_type, extended_data);
+    }
+
+    Ok(extended)
+}
+
 impl From<&FileAttributes> for Bytes {
     fn from(file_attributes: &FileAttributes) -> Self {
         let mut attributes: u32 = 0;
@@ -90,6 +229,10 @@ impl From<&FileAttributes> for Bytes {
             attributes |= ACMODTIME;
         }
 
+        if !file_attributes.extended.is_empty() {
+            attributes |= EXTENDED;
+        }
+
         let mut attribute_bytes = BytesMut::new();
 
         attribute_bytes.put_u32(attributes);
@@ -112,6 +255,17 @@ impl From<&FileAttributes> for Bytes {
             attribute_bytes.put_u32(file_attributes.mtime.unwrap_or(0));
         }
 
+        if !file_attributes.extended.is_empty() {
+            attribute_bytes.put_u32(file_attributes.extended.len().try_into().unwrap());
+
+            for (extended_type, extended_data) in &file_attributes.extended {
+                attribute_bytes.put_u32(extended_type.len().try_into().unwrap());
+                attribute_bytes.put_slice(extended_type.as_bytes());
+                attribute_bytes.put_u32(extended_data.len().try_into().unwrap());
+                attribute_bytes.put_slice(extended_data.as_bytes());
+            }
+        }
+
         attribute_bytes.freeze()
     }
 }
@@ -136,6 +290,7 @@ mod tests {
                 permissions: None,
                 atime: None,
                 mtime: None,
+                extended: BTreeMap::new(),
             },
             file_attributes
         )
@@ -150,6 +305,7 @@ mod tests {
             permissions: Some(777),
             atime: Some(1608671340),
             mtime: Some(1608671341),
+            extended: BTreeMap::new(),
         };
 
         let mut file_attributes_bytes = Bytes::from(&file_attributes);
@@ -172,6 +328,7 @@ mod tests {
             permissions: None,
             atime: None,
             mtime: None,
+            extended: BTreeMap::new(),
         };
 
         let mut file_attributes_bytes = Bytes::from(&file_attributes);
@@ -202,7 +359,8 @@ mod tests {
                 gid: Some(200),
                 permissions: Some(777),
                 atime: Some(1608671340),
-                mtime: Some(1608671341)
+                mtime: Some(1608671341),
+                extended: BTreeMap::new(),
             },
             file_attributes
         );
@@ -230,12 +388,58 @@ mod tests {
                 gid: None,
                 permissions: None,
                 atime: None,
-                mtime: None
+                mtime: None,
+                extended: BTreeMap::new(),
             },
             file_attributes
         );
     }
 
+    #[test]
+    fn test_from_file_attributes_creates_vector_with_extended_fields() {
+        let file_attributes = FileAttributes {
+            extended: create_extended(),
+            ..Default::default()
+        };
+
+        let mut file_attributes_bytes = Bytes::from(&file_attributes);
+
+        assert_eq!(0x80000000, file_attributes_bytes.get_u32());
+        assert_eq!(1, file_attributes_bytes.get_u32()); // extended count
+        assert_eq!(3, file_attributes_bytes.get_u32()); // extended type length
+        assert_eq!(b"env", &file_attributes_bytes.copy_to_bytes(3)[..]);
+        assert_eq!(4, file_attributes_bytes.get_u32()); // extended data length
+        assert_eq!(b"prod", &file_attributes_bytes.copy_to_bytes(4)[..]);
+        assert_eq!(0, file_attributes_bytes.remaining());
+    }
+
+    #[test]
+    fn test_try_from_vector_creates_file_attributes_with_extended_fields() {
+        let file_attributes = FileAttributes {
+            size: Some(1000),
+            extended: create_extended(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            file_attributes,
+            FileAttributes::try_from(&mut Bytes::from(&file_attributes)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_try_from_vector_returns_error_with_missing_extended_data() {
+        let mut file_attributes_bytes = BytesMut::new();
+
+        file_attributes_bytes.put_u32(0x80000000);
+        file_attributes_bytes.put_u32(1); // extended count
+
+        assert_eq!(
+            Error::BadMessage,
+            FileAttributes::try_from(&mut file_attributes_bytes.freeze()).unwrap_err()
+        );
+    }
+
     #[test]
     fn test_try_from_vector_returns_error_with_missing_data() {
         let mut file_attributes_bytes = BytesMut::new();
@@ -248,6 +452,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_try_from_version_parses_v6_attributes() {
+        let mut file_attributes_bytes = BytesMut::new();
+        file_attributes_bytes.put_u32(
+            SIZE | V4_OWNERGROUP
+                | PERMISSIONS
+                | V4_MODIFYTIME
+                | V4_SUBSECOND_TIMES
+                | V5_BITS
+                | EXTENDED,
+        );
+        file_attributes_bytes.put_u8(1); // regular file type
+        file_attributes_bytes.put_u64(1000); // size
+        file_attributes_bytes.put_u32(4); // owner length
+        file_attributes_bytes.put_slice(b"user"); // owner
+        file_attributes_bytes.put_u32(5); // group length
+        file_attributes_bytes.put_slice(b"group"); // group
+        file_attributes_bytes.put_u32(0o644); // permissions
+        file_attributes_bytes.put_i64(1608671341); // mtime seconds
+        file_attributes_bytes.put_u32(500); // mtime nanoseconds
+        file_attributes_bytes.put_u32(0); // attrib-bits
+        file_attributes_bytes.put_u32(0); // attrib-bits-valid
+        file_attributes_bytes.put_u32(0); // extended count
+
+        assert_eq!(
+            Ok(FileAttributes {
+                size: Some(1000),
+                permissions: Some(0o644),
+                mtime: Some(1608671341),
+                ..Default::default()
+            }),
+            FileAttributes::try_from_version(&mut file_attributes_bytes.freeze(), 6)
+        );
+    }
+
+    #[test]
+    fn test_try_from_version_returns_error_with_missing_v4_type() {
+        let mut file_attributes_bytes = BytesMut::new();
+        file_attributes_bytes.put_u32(0);
+
+        assert_eq!(
+            Err(Error::BadMessage),
+            FileAttributes::try_from_version(&mut file_attributes_bytes.freeze(), 4)
+        );
+    }
+
     #[test]
     fn test_is_dir_returns_true_with_directory_permission_bit_set() {
         assert!(FileAttributes {
@@ -274,4 +524,10 @@ mod tests {
         }
         .is_dir());
     }
+
+    fn create_extended() -> BTreeMap<String, String> {
+        let mut extended = BTreeMap::new();
+        extended.insert(String::from("env"), String::from("prod"));
+        extended
+    }
 }
@@ -0,0 +1,366 @@
+use crate::error::Error;
+use crate::protocol::request::MAX_PATH_LENGTH;
+use crate::try_buf::TryBuf;
+
+use bytes::Bytes;
+use std::convert::TryFrom;
+
+pub const READDIR_RESUME: &str = "readdir-resume@dray";
+pub const FSYNC: &str = "fsync@openssh.com";
+pub const OPEN_VERSION: &str = "open-version@dray";
+pub const LIMITS: &str = "limits@openssh.com";
+pub const PRESIGN_DOWNLOAD: &str = "presign-download@dray";
+pub const SPACE_AVAILABLE: &str = "space-available";
+pub const COPY_DATA: &str = "copy-data";
+pub const HOME_DIRECTORY: &str = "home-directory";
+
+#[derive(Debug, PartialEq)]
+pub struct Extended {
+    pub id: u32,
+    pub extended_request: ExtendedRequest,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExtendedRequest {
+    ReaddirResume(ReaddirResume),
+    Fsync(Fsync),
+    OpenVersion(OpenVersion),
+    Limits,
+    PresignDownload(PresignDownload),
+    SpaceAvailable(SpaceAvailable),
+    CopyData(CopyData),
+    HomeDirectory(HomeDirectory),
+    Unsupported(String),
+}
+
+/// Continues a directory listing that was interrupted, such as by a dropped
+/// connection, using the handle of the interrupted listing.
+#[derive(Debug, PartialEq)]
+pub struct ReaddirResume {
+    pub path: String,
+    pub handle: String,
+}
+
+/// Asks for the data written to an open handle to be persisted.
+#[derive(Debug, PartialEq)]
+pub struct Fsync {
+    pub handle: String,
+}
+
+/// Opens a prior version of a file in a versioned bucket for reading.
+#[derive(Debug, PartialEq)]
+pub struct OpenVersion {
+    pub path: String,
+    pub version_id: String,
+}
+
+/// Asks for a time-limited URL that downloads a file directly from the storage
+/// backend, so large downloads bypass the server.
+#[derive(Debug, PartialEq)]
+pub struct PresignDownload {
+    pub path: String,
+}
+
+/// Asks how much space is available to the user, such as for a client to
+/// display the remaining quota.
+#[derive(Debug, PartialEq)]
+pub struct SpaceAvailable {
+    pub path: String,
+}
+
+/// Copies data from one open handle to another on the server, so the data
+/// does not pass through the client. A read length of 0 copies until the end of
+/// the file.
+#[derive(Debug, PartialEq)]
+pub struct CopyData {
+    pub read_handle: String,
+    pub read_offset: u64,
+    pub read_length: u64,
+    pub write_handle: String,
+    pub write_offset: u64,
+}
+
+/// Asks for the home directory of a user, or of the current user if the name
+/// is empty, for clients that do not resolve `.` with realpath to find it.
+#[derive(Debug, PartialEq)]
+pub struct HomeDirectory {
+    pub username: String,
+}
+
+impl TryFrom<&mut Bytes> for Extended {
+    type Error = Error;
+
+    fn try_from(extended_bytes: &mut Bytes) -> Result<Self, Self::Error> {
+        let id = extended_bytes.try_get_u32()?;
+        let extended_request = extended_bytes.try_get_string()?;
+
+        let extended_request = match extended_request.as_str() {
+            READDIR_RESUME => ExtendedRequest::ReaddirResume(ReaddirResume {
+                path: extended_bytes.try_get_string_max(MAX_PATH_LENGTH)?,
+                handle: extended_bytes.try_get_string()?,
+            }),
+            FSYNC => ExtendedRequest::Fsync(Fsync {
+                handle: extended_bytes.try_get_string()?,
+            }),
+            OPEN_VERSION => ExtendedRequest::OpenVersion(OpenVersion {
+                path: extended_bytes.try_get_string_max(MAX_PATH_LENGTH)?,
+                version_id: extended_bytes.try_get_string()?,
+            }),
+            LIMITS => ExtendedRequest::Limits,
+            PRESIGN_DOWNLOAD => ExtendedRequest::PresignDownload(PresignDownload {
+                path: extended_bytes.try_get_string_max(MAX_PATH_LENGTH)?,
+            }),
+            SPACE_AVAILABLE => ExtendedRequest::SpaceAvailable(SpaceAvailable {
+                path: extended_bytes.try_get_string_max(MAX_PATH_LENGTH)?,
+            }),
+            COPY_DATA => ExtendedRequest::CopyData(CopyData {
+                read_handle: extended_bytes.try_get_string()?,
+                read_offset: extended_bytes.try_get_u64()?,
+                read_length: extended_bytes.try_get_u64()?,
+                write_handle: extended_bytes.try_get_string()?,
+                write_offset: extended_bytes.try_get_u64()?,
+            }),
+            HOME_DIRECTORY => ExtendedRequest::HomeDirectory(HomeDirectory {
+                username: extended_bytes.try_get_string()?,
+            }),
+            _ => ExtendedRequest::Unsupported(extended_request),
+        };
+
+        Ok(Extended {
+            id,
+            extended_request,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    use crate::try_buf::TryBufMut;
+
+    use bytes::{BufMut, BytesMut};
+
+    #[test]
+    fn test_parse_readdir_resume() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01);
+        extended_bytes.try_put_str(READDIR_RESUME).unwrap(); // extended request
+        extended_bytes.try_put_str("/path").unwrap(); // path
+        extended_bytes.try_put_str("handle").unwrap(); // handle
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::ReaddirResume(ReaddirResume {
+                    path: String::from("/path"),
+                    handle: String::from("handle"),
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_readdir_resume_with_invalid_handle() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01);
+        extended_bytes.try_put_str(READDIR_RESUME).unwrap(); // extended request
+        extended_bytes.try_put_str("/path").unwrap(); // path
+        extended_bytes.put_u32(1); // invalid handle length
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Err(Error::BadMessage)
+        );
+    }
+
+    #[test]
+    fn test_parse_fsync() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01);
+        extended_bytes.try_put_str(FSYNC).unwrap(); // extended request
+        extended_bytes.try_put_str("handle").unwrap(); // handle
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::Fsync(Fsync {
+                    handle: String::from("handle"),
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_open_version() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01);
+        extended_bytes.try_put_str(OPEN_VERSION).unwrap(); // extended request
+        extended_bytes.try_put_str("/path").unwrap(); // path
+        extended_bytes.try_put_str("version").unwrap(); // version id
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::OpenVersion(OpenVersion {
+                    path: String::from("/path"),
+                    version_id: String::from("version"),
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01);
+        extended_bytes.try_put_str(LIMITS).unwrap(); // extended request
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::Limits,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_presign_download() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01);
+        extended_bytes.try_put_str(PRESIGN_DOWNLOAD).unwrap(); // extended request
+        extended_bytes.try_put_str("/path").unwrap(); // path
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::PresignDownload(PresignDownload {
+                    path: String::from("/path"),
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_space_available() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01);
+        extended_bytes.try_put_str(SPACE_AVAILABLE).unwrap(); // extended request
+        extended_bytes.try_put_str("/path").unwrap(); // path
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::SpaceAvailable(SpaceAvailable {
+                    path: String::from("/path"),
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_home_directory() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01);
+        extended_bytes.try_put_str(HOME_DIRECTORY).unwrap(); // extended request
+        extended_bytes.try_put_str("test").unwrap(); // username
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::HomeDirectory(HomeDirectory {
+                    username: String::from("test"),
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_copy_data() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01);
+        extended_bytes.try_put_str(COPY_DATA).unwrap(); // extended request
+        extended_bytes.try_put_str("read").unwrap(); // read handle
+        extended_bytes.put_u64(0x02); // read offset
+        extended_bytes.put_u64(0x03); // read length
+        extended_bytes.try_put_str("write").unwrap(); // write handle
+        extended_bytes.put_u64(0x04); // write offset
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::CopyData(CopyData {
+                    read_handle: String::from("read"),
+                    read_offset: 0x02,
+                    read_length: 0x03,
+                    write_handle: String::from("write"),
+                    write_offset: 0x04,
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_copy_data_with_missing_write_offset() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01);
+        extended_bytes.try_put_str(COPY_DATA).unwrap(); // extended request
+        extended_bytes.try_put_str("read").unwrap(); // read handle
+        extended_bytes.put_u64(0x00); // read offset
+        extended_bytes.put_u64(0x00); // read length
+        extended_bytes.try_put_str("write").unwrap(); // write handle
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Err(Error::BadMessage)
+        );
+    }
+
+    #[test]
+    fn test_parse_unsupported_extended_request() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01);
+        extended_bytes.try_put_str("unknown@example.com").unwrap(); // extended request
+        extended_bytes.put_u32(0xFF); // request specific data
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::Unsupported(String::from("unknown@example.com")),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_extended_with_invalid_id() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u8(0x01);
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Err(Error::BadMessage)
+        );
+    }
+}
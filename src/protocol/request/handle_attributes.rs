@@ -36,6 +36,7 @@ mod test {
     use crate::try_buf::TryBufMut;
 
     use bytes::{BufMut, BytesMut};
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_parse_handle_attributes() {
@@ -45,7 +46,7 @@ mod test {
         handle_attributes_bytes.try_put_str("handle").unwrap(); // handle
 
         let file_attributes = get_file_attributes();
-        handle_attributes_bytes.put_slice(&mut Bytes::from(&file_attributes)); // file attributes
+        handle_attributes_bytes.put_slice(&Bytes::from(&file_attributes)); // file attributes
 
         assert_eq!(
             HandleAttributes::try_from(&mut handle_attributes_bytes.freeze()),
@@ -105,6 +106,7 @@ mod test {
             permissions: None,
             atime: None,
             mtime: None,
+            extended: BTreeMap::new(),
         }
     }
 }
@@ -6,7 +6,7 @@ use std::convert::TryFrom;
 
 #[derive(Debug, PartialEq)]
 pub struct Init {
-    pub version: u8,
+    pub version: u32,
 }
 
 impl TryFrom<&mut Bytes> for Init {
@@ -14,7 +14,7 @@ impl TryFrom<&mut Bytes> for Init {
 
     fn try_from(init_bytes: &mut Bytes) -> Result<Self, Self::Error> {
         Ok(Init {
-            version: init_bytes.try_get_u8()?,
+            version: init_bytes.try_get_u32()?,
         })
     }
 }
@@ -29,7 +29,7 @@ mod tests {
     fn test_parse_init_message() {
         let mut init_bytes = BytesMut::new();
 
-        init_bytes.put_u8(0x03);
+        init_bytes.put_u32(0x03);
 
         assert_eq!(
             Init::try_from(&mut init_bytes.freeze()),
@@ -1,13 +1,11 @@
 use std::convert::TryFrom;
 
-use bytes::Bytes;
-use log::debug;
-use log::log_enabled;
-use log::Level::Debug;
+use bytes::{Buf, Bytes};
 
 use crate::error::Error;
 use crate::try_buf::TryBuf;
 
+pub mod extended;
 pub mod handle;
 pub mod handle_attributes;
 pub mod init;
@@ -19,7 +17,12 @@ pub mod rename;
 pub mod symlink;
 pub mod write;
 
-const DATA_TYPE_LENGTH: u32 = 1;
+const DATA_LENGTH_LENGTH: usize = 4;
+const DATA_TYPE_LENGTH: usize = 1;
+
+/// The longest path in bytes accepted in a request, matching the usual Linux
+/// `PATH_MAX`.
+pub const MAX_PATH_LENGTH: u32 = 4096;
 
 #[derive(Debug, PartialEq)]
 pub enum Request {
@@ -42,45 +45,222 @@ pub enum Request {
     Rename(rename::Rename),
     Readlink(path::Path),
     Symlink(symlink::Symlink),
+    Extended(extended::Extended),
+}
+
+impl Request {
+    /// Returns the id to echo in the response, or `None` for `SSH_FXP_INIT`,
+    /// which is answered with the version instead.
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            Request::Init(_) => None,
+            Request::Open(open) => Some(open.id),
+            Request::Close(handle) | Request::Readdir(handle) => Some(handle.id),
+            Request::Read(read) => Some(read.id),
+            Request::Write(write) => Some(write.id),
+            Request::Lstat(path)
+            | Request::Fstat(path)
+            | Request::Opendir(path)
+            | Request::Remove(path)
+            | Request::Rmdir(path)
+            | Request::Realpath(path)
+            | Request::Stat(path)
+            | Request::Readlink(path) => Some(path.id),
+            Request::Setstat(path_attributes) | Request::Mkdir(path_attributes) => {
+                Some(path_attributes.id)
+            }
+            Request::Fsetstat(handle_attributes) => Some(handle_attributes.id),
+            Request::Rename(rename) => Some(rename.id),
+            Request::Symlink(symlink) => Some(symlink.id),
+            Request::Extended(extended) => Some(extended.id),
+        }
+    }
+
+    /// Returns the lowercase name of the request type, such as `open`, which
+    /// labels the work done for it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Request::Init(_) => "init",
+            Request::Open(_) => "open",
+            Request::Close(_) => "close",
+            Request::Read(_) => "read",
+            Request::Write(_) => "write",
+            Request::Lstat(_) => "lstat",
+            Request::Fstat(_) => "fstat",
+            Request::Setstat(_) => "setstat",
+            Request::Fsetstat(_) => "fsetstat",
+            Request::Opendir(_) => "opendir",
+            Request::Readdir(_) => "readdir",
+            Request::Remove(_) => "remove",
+            Request::Mkdir(_) => "mkdir",
+            Request::Rmdir(_) => "rmdir",
+            Request::Realpath(_) => "realpath",
+            Request::Stat(_) => "stat",
+            Request::Rename(_) => "rename",
+            Request::Readlink(_) => "readlink",
+            Request::Symlink(_) => "symlink",
+            Request::Extended(_) => "extended",
+        }
+    }
+
+    /// Parses the next request in the format of the negotiated protocol
+    /// version.
+    pub fn try_from_version(request_bytes: &mut Bytes, version: u32) -> Result<Request, Error> {
+        REQUEST_PARSERS.parse(request_bytes, version)
+    }
+}
+
+/// Parses the payload that follows a request's type byte, in the format of the
+/// negotiated protocol version.
+pub type RequestParser = fn(&mut Bytes, u32) -> Result<Request, Error>;
+
+/// Routes each request type to the parser for its payload, so a new request
+/// type is supported by registering its parser.
+#[derive(Clone, Copy)]
+pub struct RequestParsers {
+    parsers: [Option<RequestParser>; 256],
+}
+
+static REQUEST_PARSERS: RequestParsers = RequestParsers::standard();
+
+impl RequestParsers {
+    pub const fn new() -> RequestParsers {
+        RequestParsers {
+            parsers: [None; 256],
+        }
+    }
+
+    /// Creates the parsers for every request type the server supports.
+    pub const fn standard() -> RequestParsers {
+        RequestParsers::new()
+            .with_parser(1, |data_payload, _| {
+                Ok(Request::Init(init::Init::try_from(data_payload)?))
+            })
+            .with_parser(3, |data_payload, version| {
+                Ok(Request::Open(open::Open::try_from_version(
+                    data_payload,
+                    version,
+                )?))
+            })
+            .with_parser(4, |data_payload, _| {
+                Ok(Request::Close(handle::Handle::try_from(data_payload)?))
+            })
+            .with_parser(5, |data_payload, _| {
+                Ok(Request::Read(read::Read::try_from(data_payload)?))
+            })
+            .with_parser(6, |data_payload, _| {
+                Ok(Request::Write(write::Write::try_from(data_payload)?))
+            })
+            .with_parser(7, |data_payload, _| {
+                Ok(Request::Lstat(path::Path::try_from(data_payload)?))
+            })
+            .with_parser(8, |data_payload, _| {
+                Ok(Request::Fstat(path::Path::try_from(data_payload)?))
+            })
+            .with_parser(9, |data_payload, _| {
+                Ok(Request::Setstat(path_attributes::PathAttributes::try_from(
+                    data_payload,
+                )?))
+            })
+            .with_parser(10, |data_payload, _| {
+                Ok(Request::Fsetstat(
+                    handle_attributes::HandleAttributes::try_from(data_payload)?,
+                ))
+            })
+            .with_parser(11, |data_payload, _| {
+                Ok(Request::Opendir(path::Path::try_from(data_payload)?))
+            })
+            .with_parser(12, |data_payload, _| {
+                Ok(Request::Readdir(handle::Handle::try_from(data_payload)?))
+            })
+            .with_parser(13, |data_payload, _| {
+                Ok(Request::Remove(path::Path::try_from(data_payload)?))
+            })
+            .with_parser(14, |data_payload, _| {
+                Ok(Request::Mkdir(path_attributes::PathAttributes::try_from(
+                    data_payload,
+                )?))
+            })
+            .with_parser(15, |data_payload, _| {
+                Ok(Request::Rmdir(path::Path::try_from(data_payload)?))
+            })
+            .with_parser(16, |data_payload, _| {
+                Ok(Request::Realpath(path::Path::try_from(data_payload)?))
+            })
+            .with_parser(17, |data_payload, _| {
+                Ok(Request::Stat(path::Path::try_from(data_payload)?))
+            })
+            .with_parser(18, |data_payload, version| {
+                Ok(Request::Rename(rename::Rename::try_from_version(
+                    data_payload,
+                    version,
+                )?))
+            })
+            .with_parser(19, |data_payload, _| {
+                Ok(Request::Readlink(path::Path::try_from(data_payload)?))
+            })
+            .with_parser(20, |data_payload, _| {
+                Ok(Request::Symlink(symlink::Symlink::try_from(data_payload)?))
+            })
+            .with_parser(200, |data_payload, _| {
+                Ok(Request::Extended(extended::Extended::try_from(
+                    data_payload,
+                )?))
+            })
+    }
+
+    /// Registers the parser for a request type, replacing any parser already
+    /// registered for it.
+    pub const fn with_parser(mut self, data_type: u8, parser: RequestParser) -> RequestParsers {
+        self.parsers[data_type as usize] = Some(parser);
+        self
+    }
+
+    /// Parses the next request with the parser registered for its type.
+    /// Requests of unregistered types are rejected as bad messages.
+    pub fn parse(&self, request_bytes: &mut Bytes, version: u32) -> Result<Request, Error> {
+        let data_payload = &mut try_get_packet(request_bytes)?;
+
+        let data_type = data_payload.peek_u8()?;
+        data_payload.advance(DATA_TYPE_LENGTH);
+
+        match self.parsers[data_type as usize] {
+            Some(parser) => parser(data_payload, version),
+            None => Err(Error::BadMessage),
+        }
+    }
 }
 
 impl TryFrom<&mut Bytes> for Request {
     type Error = Error;
 
     fn try_from(request_bytes: &mut Bytes) -> Result<Self, Self::Error> {
-        if log_enabled!(Debug) {
-            debug!("Request bytes: {}", hex::encode(&request_bytes));
-        }
+        Request::try_from_version(request_bytes, 3)
+    }
+}
+
+/// Parses a single request from untrusted bytes. Malformed input results in
+/// `Error::BadMessage` or `Error::InvalidUtf8` rather than a panic, so this is
+/// the entrypoint used for fuzzing.
+pub fn parse_request(data: &[u8]) -> Result<Request, Error> {
+    Request::try_from(data)
+}
 
-        let data_length = request_bytes.try_get_u32()?;
-        let data_type = request_bytes.try_get_u8()?;
-        let data_payload = &mut request_bytes.try_get_bytes(data_length - DATA_TYPE_LENGTH)?;
-
-        let message = match data_type {
-            1 => Request::Init(init::Init::try_from(data_payload)?),
-            3 => Request::Open(open::Open::try_from(data_payload)?),
-            4 => Request::Close(handle::Handle::try_from(data_payload)?),
-            5 => Request::Read(read::Read::try_from(data_payload)?),
-            6 => Request::Write(write::Write::try_from(data_payload)?),
-            7 => Request::Lstat(path::Path::try_from(data_payload)?),
-            8 => Request::Fstat(path::Path::try_from(data_payload)?),
-            9 => Request::Setstat(path_attributes::PathAttributes::try_from(data_payload)?),
-            10 => Request::Fsetstat(handle_attributes::HandleAttributes::try_from(data_payload)?),
-            11 => Request::Opendir(path::Path::try_from(data_payload)?),
-            12 => Request::Readdir(handle::Handle::try_from(data_payload)?),
-            13 => Request::Remove(path::Path::try_from(data_payload)?),
-            14 => Request::Mkdir(path_attributes::PathAttributes::try_from(data_payload)?),
-            15 => Request::Rmdir(path::Path::try_from(data_payload)?),
-            16 => Request::Realpath(path::Path::try_from(data_payload)?),
-            17 => Request::Stat(path::Path::try_from(data_payload)?),
-            18 => Request::Rename(rename::Rename::try_from(data_payload)?),
-            19 => Request::Readlink(path::Path::try_from(data_payload)?),
-            20 => Request::Symlink(symlink::Symlink::try_from(data_payload)?),
-            _ => return Err(Error::BadMessage),
-        };
-
-        Ok(message)
+/// Splits the next packet from the buffer, excluding the length prefix. The
+/// length covers the type and payload, so it must be at least one byte and
+/// must not exceed the bytes available.
+fn try_get_packet(request_bytes: &mut Bytes) -> Result<Bytes, Error> {
+    let data_length = request_bytes.peek_u32()? as usize;
+
+    if data_length < DATA_TYPE_LENGTH
+        || request_bytes.remaining() - DATA_LENGTH_LENGTH < data_length
+    {
+        return Err(Error::BadMessage);
     }
+
+    request_bytes.advance(DATA_LENGTH_LENGTH);
+
+    Ok(request_bytes.split_to(data_length))
 }
 
 impl TryFrom<&[u8]> for Request {
@@ -93,6 +273,7 @@ impl TryFrom<&[u8]> for Request {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use std::convert::TryInto;
 
     use bytes::{BufMut, BytesMut};
@@ -120,10 +301,76 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_framed_message_leaves_following_bytes() {
+        let mut init_payload = BytesMut::new();
+        init_payload.put_u32(3); // Protocol Version 3
+
+        let mut message = BytesMut::new();
+        message.put_slice(&build_message(1, init_payload));
+        message.put_u8(0xFF);
+
+        let mut message = message.freeze();
+
+        assert_eq!(
+            Request::try_from(&mut message),
+            Ok(Request::Init(init::Init { version: 0x03 }))
+        );
+        assert_eq!(&[0xFF], &message[..]);
+    }
+
+    #[test]
+    fn test_parse_message_with_length_exceeding_available_bytes() {
+        let mut message = BytesMut::new();
+        message.put_u32(3); // type and a two byte payload
+        message.put_u8(1);
+        message.put_u8(3);
+
+        assert_eq!(
+            Request::try_from(&mut message.freeze()),
+            Err(Error::BadMessage)
+        );
+    }
+
+    #[test]
+    fn test_parse_message_with_zero_length() {
+        let mut message = BytesMut::new();
+        message.put_u32(0);
+        message.put_u8(1);
+
+        assert_eq!(
+            Request::try_from(&mut message.freeze()),
+            Err(Error::BadMessage)
+        );
+    }
+
+    #[test]
+    fn test_parse_routes_registered_request_type() {
+        let request_parsers = RequestParsers::new().with_parser(21, |data_payload, _| {
+            Ok(Request::Stat(path::Path::try_from(data_payload)?))
+        });
+
+        let mut stat_payload = BytesMut::new();
+        stat_payload.put_u32(0x01); // id
+        stat_payload.try_put_str("/file").unwrap(); // path
+
+        assert_eq!(
+            request_parsers.parse(&mut build_message(21, stat_payload.clone()), 3),
+            Ok(Request::Stat(path::Path {
+                id: 0x01,
+                path: String::from("/file"),
+            }))
+        );
+        assert_eq!(
+            request_parsers.parse(&mut build_message(17, stat_payload), 3),
+            Err(Error::BadMessage)
+        );
+    }
+
     #[test]
     fn test_parse_init_message() {
         let mut init_payload = BytesMut::new();
-        init_payload.put_u8(3); // Protocol Version 3
+        init_payload.put_u32(3); // Protocol Version 3
 
         assert_eq!(
             Request::try_from(&mut build_message(1, init_payload)),
@@ -157,6 +404,7 @@ mod tests {
                     create_new_only: false,
                     append: false,
                     truncate: false,
+                    text: false,
                 },
             })),
         )
@@ -488,6 +736,7 @@ mod tests {
                 id: 1,
                 old_path: String::from("oldpath"),
                 new_path: String::from("newpath"),
+                overwrite: None,
             }))
         );
     }
@@ -541,6 +790,170 @@ mod tests {
         assert_invalid_message(20);
     }
 
+    #[test]
+    fn test_parse_extended_message() {
+        let mut extended_payload = BytesMut::new();
+
+        extended_payload.put_u32(1);
+        extended_payload.try_put_str("readdir-resume@dray").unwrap();
+        extended_payload.try_put_str("path").unwrap();
+        extended_payload.try_put_str("handle").unwrap();
+
+        assert_eq!(
+            Request::try_from(&mut build_message(200, extended_payload)),
+            Ok(Request::Extended(extended::Extended {
+                id: 1,
+                extended_request: extended::ExtendedRequest::ReaddirResume(
+                    extended::ReaddirResume {
+                        path: String::from("path"),
+                        handle: String::from("handle"),
+                    }
+                ),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_extended_message() {
+        assert_invalid_message(200);
+    }
+
+    #[test]
+    fn test_parse_request_with_seed_messages() {
+        for seed_message in build_seed_messages() {
+            assert!(parse_request(&seed_message).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_parse_request_with_truncated_seed_messages() {
+        for seed_message in build_seed_messages() {
+            for len in 0..seed_message.len() {
+                let _ = parse_request(&seed_message[..len]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_request_with_corrupted_seed_messages() {
+        for seed_message in build_seed_messages() {
+            for index in 0..seed_message.len() {
+                let mut corrupted_message = seed_message.to_vec();
+                corrupted_message[index] = 0xFF;
+
+                let _ = parse_request(&corrupted_message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_id_returns_write_request_id() {
+        let request = Request::Write(write::Write {
+            id: 0x07,
+            handle: String::from("handle"),
+            offset: 0,
+            data: Bytes::from_static(b"data"),
+        });
+
+        assert_eq!(Some(0x07), request.id());
+    }
+
+    #[test]
+    fn test_id_returns_handle_request_id() {
+        let request = Request::Close(handle::Handle {
+            id: 0x08,
+            handle: String::from("handle"),
+        });
+
+        assert_eq!(Some(0x08), request.id());
+    }
+
+    #[test]
+    fn test_id_returns_none_for_init_request() {
+        let request = Request::Init(init::Init { version: 3 });
+
+        assert_eq!(None, request.id());
+    }
+
+    #[test]
+    fn test_id_returns_parsed_id_for_every_request_except_init() {
+        for seed_message in build_seed_messages() {
+            let request = parse_request(&seed_message).unwrap();
+
+            match request {
+                Request::Init(_) => assert_eq!(None, request.id()),
+                _ => assert_eq!(Some(1), request.id()),
+            }
+        }
+    }
+
+    fn build_seed_messages() -> Vec<Bytes> {
+        let mut id = BytesMut::new();
+        id.put_u32(1); // Id
+
+        let mut handle = id.clone();
+        handle.try_put_str("handle").unwrap();
+
+        let mut path = id.clone();
+        path.try_put_str("/path").unwrap();
+
+        let mut path_attributes = path.clone();
+        path_attributes.put_slice(&Bytes::from(&FileAttributes {
+            size: Some(1),
+            extended: BTreeMap::from([(String::from("env"), String::from("prod"))]),
+            ..Default::default()
+        }));
+
+        let mut handle_attributes = handle.clone();
+        handle_attributes.put_slice(&Bytes::from(&get_file_attrs()));
+
+        let mut init = BytesMut::new();
+        init.put_u32(3); // Protocol Version 3
+
+        let mut open = path.clone();
+        open.put_u32(0x00000003); // Read and Write Flags
+        open.put_slice(&Bytes::from(&get_file_attrs()));
+
+        let mut read = handle.clone();
+        read.put_u64(0); // Offset
+        read.put_u32(1024); // Length
+
+        let mut write = handle.clone();
+        write.put_u64(0); // Offset
+        write.try_put_str("data").unwrap();
+
+        let mut two_paths = path.clone();
+        two_paths.try_put_str("/other").unwrap();
+
+        let mut readdir_resume = id.clone();
+        readdir_resume.try_put_str("readdir-resume@dray").unwrap();
+        readdir_resume.try_put_str("/path").unwrap();
+        readdir_resume.try_put_str("handle").unwrap();
+
+        vec![
+            build_message(1, init),
+            build_message(3, open),
+            build_message(4, handle.clone()),
+            build_message(5, read),
+            build_message(6, write),
+            build_message(7, path.clone()),
+            build_message(8, path.clone()),
+            build_message(9, path_attributes.clone()),
+            build_message(10, handle_attributes),
+            build_message(11, path.clone()),
+            build_message(12, handle),
+            build_message(13, path.clone()),
+            build_message(14, path_attributes),
+            build_message(15, path.clone()),
+            build_message(16, path.clone()),
+            build_message(17, path.clone()),
+            build_message(18, two_paths.clone()),
+            build_message(19, path),
+            build_message(20, two_paths),
+            build_message(200, readdir_resume),
+        ]
+    }
+
     fn assert_invalid_message(message_type: u8) {
         let payload = BytesMut::new();
 
@@ -571,6 +984,7 @@ mod tests {
             permissions: None,
             atime: None,
             mtime: None,
+            extended: BTreeMap::new(),
         }
     }
 }
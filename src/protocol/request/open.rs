@@ -1,5 +1,6 @@
 use crate::error::Error;
 use crate::protocol::file_attributes::FileAttributes;
+use crate::protocol::request::MAX_PATH_LENGTH;
 use crate::try_buf::TryBuf;
 
 use bytes::Bytes;
@@ -11,6 +12,21 @@ const APPEND: u32 = 0x00000004;
 const CREAT: u32 = 0x00000008;
 const TRUNC: u32 = 0x00000010;
 const EXCL: u32 = 0x00000020;
+// The pflag that version 4 added for text mode.
+const TEXT: u32 = 0x00000040;
+
+// The desired-access and flags fields that replace pflags from version 5.
+const ACE4_READ_DATA: u32 = 0x00000001;
+const ACE4_WRITE_DATA: u32 = 0x00000002;
+const ACE4_APPEND_DATA: u32 = 0x00000004;
+const SSH_FXF_ACCESS_DISPOSITION: u32 = 0x00000007;
+const SSH_FXF_CREATE_NEW: u32 = 0x00000000;
+const SSH_FXF_CREATE_TRUNCATE: u32 = 0x00000001;
+const SSH_FXF_OPEN_OR_CREATE: u32 = 0x00000003;
+const SSH_FXF_TRUNCATE_EXISTING: u32 = 0x00000004;
+const SSH_FXF_APPEND_DATA: u32 = 0x00000008;
+const SSH_FXF_APPEND_DATA_ATOMIC: u32 = 0x00000010;
+const SSH_FXF_TEXT_MODE: u32 = 0x00000020;
 
 #[derive(Debug, PartialEq)]
 pub struct Open {
@@ -24,11 +40,22 @@ impl TryFrom<&mut Bytes> for Open {
     type Error = Error;
 
     fn try_from(open_bytes: &mut Bytes) -> Result<Self, Self::Error> {
+        Open::try_from_version(open_bytes, 3)
+    }
+}
+
+impl Open {
+    /// Parses an open request in the format of the negotiated protocol version,
+    /// since version 5 replaced pflags with desired-access and flags.
+    pub fn try_from_version(open_bytes: &mut Bytes, version: u32) -> Result<Open, Error> {
         let id = open_bytes.try_get_u32()?;
-        let filename = open_bytes.try_get_string()?;
+        let filename = open_bytes.try_get_string_max(MAX_PATH_LENGTH)?;
 
-        let open_options = OpenOptions::try_from(&mut *open_bytes)?;
-        let file_attributes = FileAttributes::try_from(&mut *open_bytes)?;
+        let open_options = match version {
+            0..=4 => OpenOptions::try_from_pflags(&mut *open_bytes, version)?,
+            _ => OpenOptions::try_from_access(&mut *open_bytes)?,
+        };
+        let file_attributes = FileAttributes::try_from_version(&mut *open_bytes, version)?;
 
         Ok(Open {
             id,
@@ -47,12 +74,24 @@ pub struct OpenOptions {
     pub create_new_only: bool,
     pub append: bool,
     pub truncate: bool,
+    /// Whether the client asked for text mode, from version 4, in which the
+    /// server may translate line endings. Files are stored as sent, so the
+    /// hint is only recorded.
+    pub text: bool,
 }
 
 impl TryFrom<&mut Bytes> for OpenOptions {
     type Error = Error;
 
     fn try_from(open_options_bytes: &mut Bytes) -> Result<Self, Self::Error> {
+        OpenOptions::try_from_pflags(open_options_bytes, 3)
+    }
+}
+
+impl OpenOptions {
+    /// Parses the pflags of versions before 5, of which version 4 added text
+    /// mode.
+    fn try_from_pflags(open_options_bytes: &mut Bytes, version: u32) -> Result<OpenOptions, Error> {
         let file_attributes = open_options_bytes.try_get_u32()?;
 
         Ok(OpenOptions {
@@ -62,6 +101,36 @@ impl TryFrom<&mut Bytes> for OpenOptions {
             create_new_only: file_attributes & EXCL == EXCL,
             append: file_attributes & APPEND == APPEND,
             truncate: file_attributes & TRUNC == TRUNC,
+            text: version >= 4 && file_attributes & TEXT == TEXT,
+        })
+    }
+
+    /// Parses the version 5+ desired-access and flags fields into the same
+    /// options as the version 3 pflags.
+    fn try_from_access(open_options_bytes: &mut Bytes) -> Result<OpenOptions, Error> {
+        let desired_access = open_options_bytes.try_get_u32()?;
+        let flags = open_options_bytes.try_get_u32()?;
+
+        let disposition = flags & SSH_FXF_ACCESS_DISPOSITION;
+
+        if disposition > SSH_FXF_TRUNCATE_EXISTING {
+            return Err(Error::BadMessage);
+        }
+
+        Ok(OpenOptions {
+            read: desired_access & ACE4_READ_DATA != 0,
+            write: desired_access & (ACE4_WRITE_DATA | ACE4_APPEND_DATA) != 0,
+            create: matches!(
+                disposition,
+                SSH_FXF_CREATE_NEW | SSH_FXF_CREATE_TRUNCATE | SSH_FXF_OPEN_OR_CREATE
+            ),
+            create_new_only: disposition == SSH_FXF_CREATE_NEW,
+            append: flags & (SSH_FXF_APPEND_DATA | SSH_FXF_APPEND_DATA_ATOMIC) != 0,
+            truncate: matches!(
+                disposition,
+                SSH_FXF_CREATE_TRUNCATE | SSH_FXF_TRUNCATE_EXISTING
+            ),
+            text: flags & SSH_FXF_TEXT_MODE != 0,
         })
     }
 }
@@ -73,6 +142,9 @@ mod tests {
     use crate::try_buf::TryBufMut;
 
     use bytes::{BufMut, BytesMut};
+    use std::collections::BTreeMap;
+
+    const PERMISSIONS: u32 = 0x00000004;
     use std::convert::TryInto;
 
     #[test]
@@ -88,7 +160,7 @@ mod tests {
             ..get_file_attributes()
         };
 
-        open_bytes.put_slice(&mut Bytes::from(&file_attributes)); // file attributes
+        open_bytes.put_slice(&Bytes::from(&file_attributes)); // file attributes
 
         assert_eq!(
             Open::try_from(&mut open_bytes.freeze()),
@@ -283,6 +355,146 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_v5_open_matches_v3_open() {
+        let mut v3_open_bytes = BytesMut::new();
+        v3_open_bytes.put_u32(0x01); // id
+        v3_open_bytes.try_put_str("/file/path").unwrap(); // filename
+        v3_open_bytes.put_u32(READ | WRITE | CREAT | TRUNC); // pflags
+        v3_open_bytes.put_u32(PERMISSIONS); // attribute flags
+        v3_open_bytes.put_u32(0o644); // permissions
+
+        let mut v5_open_bytes = BytesMut::new();
+        v5_open_bytes.put_u32(0x01); // id
+        v5_open_bytes.try_put_str("/file/path").unwrap(); // filename
+        v5_open_bytes.put_u32(ACE4_READ_DATA | ACE4_WRITE_DATA); // desired-access
+        v5_open_bytes.put_u32(SSH_FXF_CREATE_TRUNCATE); // flags
+        v5_open_bytes.put_u32(PERMISSIONS); // attribute flags
+        v5_open_bytes.put_u8(1); // regular file type
+        v5_open_bytes.put_u32(0o644); // permissions
+
+        let v3_open = Open::try_from_version(&mut v3_open_bytes.freeze(), 3).unwrap();
+        let v5_open = Open::try_from_version(&mut v5_open_bytes.freeze(), 5).unwrap();
+
+        assert_eq!(
+            OpenOptions {
+                read: true,
+                write: true,
+                create: true,
+                truncate: true,
+                ..get_open_options()
+            },
+            v5_open.open_options
+        );
+        assert_eq!(v3_open, v5_open);
+    }
+
+    #[test]
+    fn test_parse_open_with_text_mode() {
+        let mut v3_open_bytes = BytesMut::new();
+        v3_open_bytes.put_u32(0x01); // id
+        v3_open_bytes.try_put_str("/file/path").unwrap(); // filename
+        v3_open_bytes.put_u32(READ | TEXT); // pflags
+        v3_open_bytes.put_u32(0); // attribute flags
+
+        let mut v4_open_bytes = BytesMut::new();
+        v4_open_bytes.put_u32(0x01); // id
+        v4_open_bytes.try_put_str("/file/path").unwrap(); // filename
+        v4_open_bytes.put_u32(READ | TEXT); // pflags
+        v4_open_bytes.put_u32(0); // attribute flags
+        v4_open_bytes.put_u8(1); // regular file type
+
+        let mut v5_open_bytes = BytesMut::new();
+        v5_open_bytes.put_u32(0x01); // id
+        v5_open_bytes.try_put_str("/file/path").unwrap(); // filename
+        v5_open_bytes.put_u32(ACE4_READ_DATA); // desired-access
+        v5_open_bytes.put_u32(0x00000002 | SSH_FXF_TEXT_MODE); // open existing, text mode
+        v5_open_bytes.put_u32(0); // attribute flags
+        v5_open_bytes.put_u8(1); // regular file type
+
+        let v3_open = Open::try_from_version(&mut v3_open_bytes.freeze(), 3).unwrap();
+        let v4_open = Open::try_from_version(&mut v4_open_bytes.freeze(), 4).unwrap();
+        let v5_open = Open::try_from_version(&mut v5_open_bytes.freeze(), 5).unwrap();
+
+        let text_open_options = OpenOptions {
+            read: true,
+            text: true,
+            ..get_open_options()
+        };
+
+        // Version 3 has no text mode, so the flag is not read as one.
+        assert!(!v3_open.open_options.text);
+        assert_eq!(text_open_options, v4_open.open_options);
+        assert_eq!(text_open_options, v5_open.open_options);
+    }
+
+    #[test]
+    fn test_parse_v5_open_with_access_dispositions() {
+        let cases = [
+            (
+                ACE4_WRITE_DATA,
+                SSH_FXF_CREATE_NEW,
+                OpenOptions {
+                    write: true,
+                    create: true,
+                    create_new_only: true,
+                    ..get_open_options()
+                },
+            ),
+            (
+                ACE4_READ_DATA,
+                0x00000002, // SSH_FXF_OPEN_EXISTING
+                OpenOptions {
+                    read: true,
+                    ..get_open_options()
+                },
+            ),
+            (
+                ACE4_APPEND_DATA,
+                SSH_FXF_OPEN_OR_CREATE | SSH_FXF_APPEND_DATA,
+                OpenOptions {
+                    write: true,
+                    create: true,
+                    append: true,
+                    ..get_open_options()
+                },
+            ),
+        ];
+
+        for (desired_access, flags, open_options) in cases {
+            let mut open_bytes = BytesMut::new();
+            open_bytes.put_u32(0x01); // id
+            open_bytes.try_put_str("/file/path").unwrap(); // filename
+            open_bytes.put_u32(desired_access);
+            open_bytes.put_u32(flags);
+            open_bytes.put_u32(0); // attribute flags
+            open_bytes.put_u8(1); // regular file type
+
+            assert_eq!(
+                open_options,
+                Open::try_from_version(&mut open_bytes.freeze(), 5)
+                    .unwrap()
+                    .open_options
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_v5_open_with_invalid_disposition() {
+        let mut open_bytes = BytesMut::new();
+        open_bytes.put_u32(0x01); // id
+        open_bytes.try_put_str("/file/path").unwrap(); // filename
+        open_bytes.put_u32(ACE4_READ_DATA); // desired-access
+        open_bytes.put_u32(0x00000005); // unknown disposition
+        open_bytes.put_u32(0); // attribute flags
+        open_bytes.put_u8(1); // regular file type
+
+        assert_eq!(
+            Open::try_from_version(&mut open_bytes.freeze(), 5),
+            Err(Error::BadMessage)
+        );
+    }
+
     fn get_file_attributes() -> FileAttributes {
         FileAttributes {
             size: None,
@@ -291,6 +503,7 @@ mod tests {
             permissions: None,
             atime: None,
             mtime: None,
+            extended: BTreeMap::new(),
         }
     }
 
@@ -302,6 +515,7 @@ mod tests {
             create_new_only: false,
             append: false,
             truncate: false,
+            text: false,
         }
     }
 }
@@ -1,4 +1,5 @@
 use crate::error::Error;
+use crate::protocol::request::MAX_PATH_LENGTH;
 use crate::try_buf::TryBuf;
 
 use bytes::Bytes;
@@ -10,33 +11,33 @@ pub struct Path {
     pub path: String,
 }
 
-impl Path {
-    pub fn to_normalized_path(&self) -> String {
-        let mut normalized_components: Vec<&str> = vec![];
-        let mut components_to_skip: usize = 0;
-
-        for path_component in self.path.rsplit('/') {
-            match path_component {
-                "" => {}
-                "." => {}
-                ".." => components_to_skip += 1,
-                _ => {
-                    if components_to_skip > 0 {
-                        components_to_skip -= 1;
-                    } else {
-                        normalized_components.push(path_component);
-                    }
+/// Normalizes an absolute or relative path into an absolute path without `.`,
+/// `..`, or redundant slashes.
+pub fn normalize_path(path: &str) -> String {
+    let mut normalized_components: Vec<&str> = vec![];
+    let mut components_to_skip: usize = 0;
+
+    for path_component in path.rsplit('/') {
+        match path_component {
+            "" => {}
+            "." => {}
+            ".." => components_to_skip += 1,
+            _ => {
+                if components_to_skip > 0 {
+                    components_to_skip -= 1;
+                } else {
+                    normalized_components.push(path_component);
                 }
             }
         }
+    }
 
-        if !normalized_components.is_empty() {
-            normalized_components.push("");
-            normalized_components.reverse();
-            normalized_components.join("/")
-        } else {
-            "/".to_owned()
-        }
+    if !normalized_components.is_empty() {
+        normalized_components.push("");
+        normalized_components.reverse();
+        normalized_components.join("/")
+    } else {
+        "/".to_owned()
     }
 }
 
@@ -45,7 +46,7 @@ impl TryFrom<&mut Bytes> for Path {
 
     fn try_from(path_bytes: &mut Bytes) -> Result<Self, Self::Error> {
         let id = path_bytes.try_get_u32()?;
-        let path = path_bytes.try_get_string()?;
+        let path = path_bytes.try_get_string_max(MAX_PATH_LENGTH)?;
 
         Ok(Path { id, path })
     }
@@ -102,58 +103,83 @@ mod test {
     }
 
     #[test]
-    fn test_normalize_path_skips_normalized_path() {
-        let path = create_path("/sample/path");
+    fn test_parse_path_with_invalid_utf8_filename() {
+        let mut path_bytes = BytesMut::new();
+
+        path_bytes.put_u32(0x01); // id
+        path_bytes.put_u32(0x02); // filename length
+        path_bytes.put_slice(&[0xC3, 0x28]); // invalid UTF-8 filename
 
-        assert_eq!("/sample/path", path.to_normalized_path());
+        assert_eq!(
+            Path::try_from(&mut path_bytes.freeze()),
+            Err(Error::InvalidUtf8)
+        );
     }
 
     #[test]
-    fn test_normalize_path_converts_relative_path() {
-        let path = create_path("sample/path");
+    fn test_parse_path_at_max_length() {
+        let mut path_bytes = BytesMut::new();
+        let path = "a".repeat(MAX_PATH_LENGTH as usize);
 
-        assert_eq!("/sample/path", path.to_normalized_path());
+        path_bytes.put_u32(0x01); // id
+        path_bytes.try_put_str(&path).unwrap(); // filename
+
+        assert_eq!(
+            Path::try_from(&mut path_bytes.freeze()),
+            Ok(Path { id: 0x01, path })
+        );
     }
 
     #[test]
-    fn test_normalize_path_strips_trailing_slash() {
-        let path = create_path("/sample/path/");
+    fn test_parse_path_over_max_length() {
+        let mut path_bytes = BytesMut::new();
 
-        assert_eq!("/sample/path", path.to_normalized_path());
+        path_bytes.put_u32(0x01); // id
+        path_bytes
+            .try_put_str(&"a".repeat(MAX_PATH_LENGTH as usize + 1))
+            .unwrap(); // filename
+
+        assert_eq!(
+            Path::try_from(&mut path_bytes.freeze()),
+            Err(Error::BadMessage)
+        );
     }
 
     #[test]
-    fn test_normalize_path_handles_single_dot() {
-        let path = create_path("/sample/./path");
-
-        assert_eq!("/sample/path", path.to_normalized_path());
+    fn test_normalize_path_skips_normalized_path() {
+        assert_eq!("/sample/path", normalize_path("/sample/path"));
     }
 
     #[test]
-    fn test_normalize_path_pops_component_with_double_dot() {
-        let path = create_path("/sample/../path");
-
-        assert_eq!("/path", path.to_normalized_path());
+    fn test_normalize_path_converts_relative_path() {
+        assert_eq!("/sample/path", normalize_path("sample/path"));
     }
 
     #[test]
-    fn test_normalize_returns_root_with_no_components_remaining() {
-        let path = create_path("/../..");
+    fn test_normalize_path_strips_trailing_slash() {
+        assert_eq!("/sample/path", normalize_path("/sample/path/"));
+    }
 
-        assert_eq!("/", path.to_normalized_path());
+    #[test]
+    fn test_normalize_path_handles_single_dot() {
+        assert_eq!("/sample/path", normalize_path("/sample/./path"));
     }
 
     #[test]
-    fn test_normalize_strips_extra_slashes() {
-        let path = create_path("//////sample///////path////");
+    fn test_normalize_path_pops_component_with_double_dot() {
+        assert_eq!("/path", normalize_path("/sample/../path"));
+    }
 
-        assert_eq!("/sample/path", path.to_normalized_path());
+    #[test]
+    fn test_normalize_returns_root_with_no_components_remaining() {
+        assert_eq!("/", normalize_path("/../.."));
     }
 
-    fn create_path(path: &str) -> Path {
-        Path {
-            id: 1,
-            path: path.to_owned(),
-        }
+    #[test]
+    fn test_normalize_strips_extra_slashes() {
+        assert_eq!(
+            "/sample/path",
+            normalize_path("//////sample///////path////")
+        );
     }
 }
@@ -1,5 +1,6 @@
 use crate::error::Error;
 use crate::protocol::file_attributes::FileAttributes;
+use crate::protocol::request::MAX_PATH_LENGTH;
 use crate::try_buf::TryBuf;
 
 use bytes::Bytes;
@@ -17,7 +18,7 @@ impl TryFrom<&mut Bytes> for PathAttributes {
 
     fn try_from(path_attributes_bytes: &mut Bytes) -> Result<Self, Self::Error> {
         let id = path_attributes_bytes.try_get_u32()?;
-        let path = path_attributes_bytes.try_get_string()?;
+        let path = path_attributes_bytes.try_get_string_max(MAX_PATH_LENGTH)?;
         let file_attributes = FileAttributes::try_from(path_attributes_bytes)?;
 
         Ok(PathAttributes {
@@ -36,6 +37,7 @@ mod test {
     use crate::try_buf::TryBufMut;
 
     use bytes::{BufMut, BytesMut};
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_parse_path_attributes() {
@@ -45,7 +47,7 @@ mod test {
         path_attributes_bytes.try_put_str("/file/path").unwrap(); // filename
 
         let file_attributes = get_file_attributes();
-        path_attributes_bytes.put_slice(&mut Bytes::from(&file_attributes)); // file attributes
+        path_attributes_bytes.put_slice(&Bytes::from(&file_attributes)); // file attributes
 
         assert_eq!(
             PathAttributes::try_from(&mut path_attributes_bytes.freeze()),
@@ -105,6 +107,7 @@ mod test {
             permissions: None,
             atime: None,
             mtime: None,
+            extended: BTreeMap::new(),
         }
     }
 }
@@ -1,28 +1,49 @@
 use crate::error::Error;
+use crate::protocol::request::MAX_PATH_LENGTH;
 use crate::try_buf::TryBuf;
 
 use bytes::Bytes;
 use std::convert::TryFrom;
 
+// The rename flag, from version 5, that allows replacing an existing file.
+const SSH_FXF_RENAME_OVERWRITE: u32 = 0x00000001;
+
 #[derive(Debug, PartialEq)]
 pub struct Rename {
     pub id: u32,
     pub old_path: String,
     pub new_path: String,
+    /// Whether the client allows replacing an existing new path. Clients
+    /// before version 5 cannot say, so it is left to the server's policy.
+    pub overwrite: Option<bool>,
 }
 
 impl TryFrom<&mut Bytes> for Rename {
     type Error = Error;
 
     fn try_from(rename_bytes: &mut Bytes) -> Result<Self, Self::Error> {
+        Rename::try_from_version(rename_bytes, 3)
+    }
+}
+
+impl Rename {
+    /// Parses a rename request in the format of the negotiated protocol
+    /// version, since version 5 added flags after the paths.
+    pub fn try_from_version(rename_bytes: &mut Bytes, version: u32) -> Result<Rename, Error> {
         let id = rename_bytes.try_get_u32()?;
-        let old_path = rename_bytes.try_get_string()?;
-        let new_path = rename_bytes.try_get_string()?;
+        let old_path = rename_bytes.try_get_string_max(MAX_PATH_LENGTH)?;
+        let new_path = rename_bytes.try_get_string_max(MAX_PATH_LENGTH)?;
+
+        let overwrite = match version {
+            0..=4 => None,
+            _ => Some(rename_bytes.try_get_u32()? & SSH_FXF_RENAME_OVERWRITE != 0),
+        };
 
         Ok(Rename {
             id,
             old_path,
             new_path,
+            overwrite,
         })
     }
 }
@@ -50,10 +71,53 @@ mod test {
                 id: 0x01,
                 old_path: String::from("/oldpath"),
                 new_path: String::from("/newpath"),
+                overwrite: None,
             })
         );
     }
 
+    #[test]
+    fn test_parse_rename_with_overwrite_flag() {
+        let mut overwrite_bytes = BytesMut::new();
+        overwrite_bytes.put_u32(0x01);
+        overwrite_bytes.try_put_str("/oldpath").unwrap(); // old path
+        overwrite_bytes.try_put_str("/newpath").unwrap(); // new path
+        overwrite_bytes.put_u32(SSH_FXF_RENAME_OVERWRITE); // flags
+
+        let mut no_overwrite_bytes = BytesMut::new();
+        no_overwrite_bytes.put_u32(0x01);
+        no_overwrite_bytes.try_put_str("/oldpath").unwrap(); // old path
+        no_overwrite_bytes.try_put_str("/newpath").unwrap(); // new path
+        no_overwrite_bytes.put_u32(0); // flags
+
+        assert_eq!(
+            Some(true),
+            Rename::try_from_version(&mut overwrite_bytes.freeze(), 5)
+                .unwrap()
+                .overwrite
+        );
+        assert_eq!(
+            Some(false),
+            Rename::try_from_version(&mut no_overwrite_bytes.freeze(), 5)
+                .unwrap()
+                .overwrite
+        );
+    }
+
+    #[test]
+    fn test_parse_rename_with_missing_flags() {
+        let mut rename_bytes = BytesMut::new();
+
+        rename_bytes.put_u32(0x01);
+        rename_bytes.try_put_str("/oldpath").unwrap(); // old path
+        rename_bytes.try_put_str("/newpath").unwrap(); // new path
+
+        assert_eq!(
+            Rename::try_from_version(&mut rename_bytes.freeze(), 5),
+            Err(Error::BadMessage)
+        );
+    }
+
     #[test]
     fn test_parse_rename_with_invalid_id() {
         let mut rename_bytes = BytesMut::new();
@@ -1,4 +1,5 @@
 use crate::error::Error;
+use crate::protocol::request::MAX_PATH_LENGTH;
 use crate::try_buf::TryBuf;
 
 use bytes::Bytes;
@@ -16,8 +17,8 @@ impl TryFrom<&mut Bytes> for Symlink {
 
     fn try_from(symlink_bytes: &mut Bytes) -> Result<Self, Self::Error> {
         let id = symlink_bytes.try_get_u32()?;
-        let link_path = symlink_bytes.try_get_string()?;
-        let target_path = symlink_bytes.try_get_string()?;
+        let link_path = symlink_bytes.try_get_string_max(MAX_PATH_LENGTH)?;
+        let target_path = symlink_bytes.try_get_string_max(MAX_PATH_LENGTH)?;
 
         Ok(Symlink {
             id,
@@ -24,6 +24,7 @@ mod test {
     use super::*;
 
     use bytes::Buf;
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_from_creates_attrs_bytes() {
@@ -36,6 +37,7 @@ mod test {
                 permissions: Some(777),
                 atime: Some(300),
                 mtime: Some(400),
+                extended: BTreeMap::new(),
             },
         };
 
@@ -43,6 +45,6 @@ mod test {
 
         assert_eq!(0x01, attrs_bytes.get_u32());
         assert_eq!(0x0F, attrs_bytes.get_u32()); // check attributes bitmask
-        assert_eq!(true, attrs_bytes.has_remaining());
+        assert!(attrs_bytes.has_remaining());
     }
 }
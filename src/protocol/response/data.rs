@@ -1,4 +1,5 @@
 use bytes::{BufMut, Bytes, BytesMut};
+use futures::stream::BoxStream;
 use std::convert::From;
 use std::convert::TryInto;
 use std::fmt::Debug;
@@ -30,6 +31,41 @@ impl Debug for Data {
     }
 }
 
+/// Data that is sent as its chunks are read rather than buffered whole. Only
+/// the id and length are serialized, and the chunks follow them on the channel.
+pub struct DataStream {
+    pub id: u32,
+    pub len: u32,
+    pub chunks: BoxStream<'static, anyhow::Result<Bytes>>,
+}
+
+impl From<&DataStream> for Bytes {
+    fn from(data_stream: &DataStream) -> Self {
+        let mut data_bytes = BytesMut::new();
+
+        data_bytes.put_u32(data_stream.id);
+        data_bytes.put_u32(data_stream.len);
+
+        data_bytes.freeze()
+    }
+}
+
+/// Streams cannot be compared, so streams of the same length are equal.
+impl PartialEq for DataStream {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.len == other.len
+    }
+}
+
+impl Debug for DataStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataStream")
+            .field("id", &self.id)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -60,4 +96,23 @@ mod test {
 
         assert_eq!("Data { id: 1, len: 2 }", format!("{:?}", data));
     }
+
+    #[test]
+    fn test_from_creates_data_stream_bytes_without_chunks() {
+        let data_stream = DataStream {
+            id: 0x01,
+            len: 0x10000,
+            chunks: Box::pin(futures::stream::empty()),
+        };
+
+        let data_bytes = &mut Bytes::from(&data_stream);
+
+        assert_eq!(0x01, data_bytes.get_u32());
+        assert_eq!(0x10000, data_bytes.get_u32());
+        assert!(data_bytes.is_empty());
+        assert_eq!(
+            "DataStream { id: 1, len: 65536 }",
+            format!("{:?}", data_stream)
+        );
+    }
 }
@@ -0,0 +1,54 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::convert::From;
+
+/// The reply to a `limits@openssh.com` request, which tells clients how large
+/// their requests may be so they can pipeline reads and writes efficiently. A
+/// limit of 0 means there is no limit.
+#[derive(Debug, PartialEq)]
+pub struct Limits {
+    pub id: u32,
+    pub max_packet_length: u64,
+    pub max_read_length: u64,
+    pub max_write_length: u64,
+    pub max_open_handles: u64,
+}
+
+impl From<&Limits> for Bytes {
+    fn from(limits: &Limits) -> Self {
+        let mut limits_bytes = BytesMut::new();
+
+        limits_bytes.put_u32(limits.id);
+        limits_bytes.put_u64(limits.max_packet_length);
+        limits_bytes.put_u64(limits.max_read_length);
+        limits_bytes.put_u64(limits.max_write_length);
+        limits_bytes.put_u64(limits.max_open_handles);
+
+        limits_bytes.freeze()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Buf;
+
+    #[test]
+    fn test_from_creates_limits_bytes() {
+        let limits = Limits {
+            id: 0x01,
+            max_packet_length: 0x02,
+            max_read_length: 0x03,
+            max_write_length: 0x04,
+            max_open_handles: 0x05,
+        };
+
+        let limits_bytes = &mut Bytes::from(&limits);
+
+        assert_eq!(0x01, limits_bytes.get_u32());
+        assert_eq!(0x02, limits_bytes.get_u64());
+        assert_eq!(0x03, limits_bytes.get_u64());
+        assert_eq!(0x04, limits_bytes.get_u64());
+        assert_eq!(0x05, limits_bytes.get_u64());
+    }
+}
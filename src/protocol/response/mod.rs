@@ -1,17 +1,17 @@
 pub mod attrs;
 pub mod data;
 pub mod handle;
+pub mod limits;
 pub mod name;
+pub mod presigned_url;
+pub mod space_available;
 pub mod status;
 pub mod version;
 
-use bytes::{Buf, BufMut, Bytes, BytesMut};
-use log::debug;
-use log::log_enabled;
-use log::Level::Debug;
-use std::convert::TryFrom;
+use crate::error::Error;
+use crate::try_buf::TryBufMut;
 
-const DATA_TYPE_LENGTH: u32 = 1;
+use bytes::{BufMut, Bytes, BytesMut};
 
 #[derive(Debug, PartialEq)]
 pub enum Response {
@@ -19,44 +19,64 @@ pub enum Response {
     Status(status::Status),
     Handle(handle::Handle),
     Data(data::Data),
+    DataStream(data::DataStream),
     Name(name::Name),
     Attrs(attrs::Attrs),
+    Limits(limits::Limits),
+    PresignedUrl(presigned_url::PresignedUrl),
+    SpaceAvailable(space_available::SpaceAvailable),
 }
 
-impl From<&Response> for Bytes {
-    fn from(response: &Response) -> Self {
-        let data_type: u8 = match response {
+impl Response {
+    /// Serializes the response, failing if a name response has a file name or
+    /// long name longer than `max_name_length` bytes.
+    pub fn try_to_bytes(&self, max_name_length: u32) -> Result<Bytes, Error> {
+        let data_type: u8 = match self {
             Response::Version(_) => 2,
             Response::Status(_) => 101,
             Response::Handle(_) => 102,
             Response::Data(_) => 103,
+            Response::DataStream(_) => 103,
             Response::Name(_) => 104,
             Response::Attrs(_) => 105,
+            Response::Limits(_) => 201,         // SSH_FXP_EXTENDED_REPLY
+            Response::PresignedUrl(_) => 201,   // SSH_FXP_EXTENDED_REPLY
+            Response::SpaceAvailable(_) => 201, // SSH_FXP_EXTENDED_REPLY
         };
 
-        let data_payload: Bytes = match response {
+        let data_payload: Bytes = match self {
             Response::Version(version) => version.into(),
             Response::Status(status) => status.into(),
             Response::Handle(handle) => handle.into(),
             Response::Data(data) => data.into(),
-            Response::Name(name) => name.into(),
+            Response::DataStream(data_stream) => data_stream.into(),
+            Response::Name(name) => name.try_to_bytes(max_name_length)?,
             Response::Attrs(attrs) => attrs.into(),
+            Response::Limits(limits) => limits.into(),
+            Response::PresignedUrl(presigned_url) => presigned_url.into(),
+            Response::SpaceAvailable(space_available) => space_available.into(),
         };
 
-        let data_length = DATA_TYPE_LENGTH + u32::try_from(data_payload.remaining()).unwrap();
+        // The chunks of a data stream are sent after the payload, but they are
+        // part of the same packet.
+        let streamed_len = match self {
+            Response::DataStream(data_stream) => data_stream.len as usize,
+            _ => 0,
+        };
 
         let mut response_bytes = BytesMut::new();
-        response_bytes.put_u32(data_length);
-        response_bytes.put_u8(data_type);
+        response_bytes
+            .try_put_sftp_header(data_type, data_payload.len() + streamed_len)
+            .unwrap();
         response_bytes.put_slice(&data_payload);
 
-        let response_bytes = response_bytes.freeze();
-
-        if log_enabled!(Debug) {
-            debug!("Response bytes: {}", hex::encode(&response_bytes));
-        }
+        Ok(response_bytes.freeze())
+    }
+}
 
-        response_bytes
+impl From<&Response> for Bytes {
+    fn from(response: &Response) -> Self {
+        response.try_to_bytes(u32::MAX).unwrap()
     }
 }
 
@@ -68,10 +88,14 @@ mod test {
     use super::*;
 
     use bytes::Buf;
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_from_creates_version_bytes() {
-        let version = Response::Version(version::Version { version: 0x01 });
+        let version = Response::Version(version::Version {
+            version: 0x01,
+            extensions: Vec::new(),
+        });
 
         let version_bytes = &mut Bytes::from(&version);
 
@@ -80,6 +104,43 @@ mod test {
         assert_eq!(0x01, version_bytes.get_u32());
     }
 
+    #[test]
+    fn test_from_creates_limits_bytes() {
+        let limits = Response::Limits(limits::Limits {
+            id: 0x01,
+            max_packet_length: 0x02,
+            max_read_length: 0x03,
+            max_write_length: 0x04,
+            max_open_handles: 0x05,
+        });
+
+        let limits_bytes = &mut Bytes::from(&limits);
+
+        assert_eq!(37, limits_bytes.get_u32());
+        assert_eq!(201, limits_bytes.get_u8());
+        assert_eq!(0x01, limits_bytes.get_u32());
+        assert_eq!(0x02, limits_bytes.get_u64());
+        assert_eq!(0x03, limits_bytes.get_u64());
+        assert_eq!(0x04, limits_bytes.get_u64());
+        assert_eq!(0x05, limits_bytes.get_u64());
+    }
+
+    #[test]
+    fn test_from_creates_presigned_url_bytes() {
+        let presigned_url = Response::PresignedUrl(presigned_url::PresignedUrl {
+            id: 0x01,
+            url: String::from("url"),
+        });
+
+        let presigned_url_bytes = &mut Bytes::from(&presigned_url);
+
+        assert_eq!(12, presigned_url_bytes.get_u32());
+        assert_eq!(201, presigned_url_bytes.get_u8());
+        assert_eq!(0x01, presigned_url_bytes.get_u32());
+        assert_eq!(0x03, presigned_url_bytes.get_u32()); // url length
+        assert_eq!(b"url", &presigned_url_bytes.copy_to_bytes(3)[..]); // url
+    }
+
     #[test]
     fn test_from_creates_status_bytes() {
         let status = Response::Status(status::Status {
@@ -103,6 +164,19 @@ mod test {
         ) // OK bytes
     }
 
+    #[test]
+    fn test_from_prefixes_status_with_packet_length() {
+        let status = Response::Status(status::Status {
+            id: 0x01,
+            status_code: status::StatusCode::Failure,
+            error_message: String::from("Failure."),
+        });
+
+        let status_bytes = &mut Bytes::from(&status);
+
+        assert_eq!(status_bytes.len() - 4, status_bytes.get_u32() as usize);
+    }
+
     #[test]
     fn test_from_creates_handle_bytes() {
         let handle = Response::Handle(handle::Handle {
@@ -138,6 +212,23 @@ mod test {
         assert_eq!(&[0x02, 0x03], &data_bytes.copy_to_bytes(2)[..]); // data
     }
 
+    #[test]
+    fn test_from_creates_data_stream_header_with_streamed_length() {
+        let data_stream = Response::DataStream(data::DataStream {
+            id: 0x01,
+            len: 0x02,
+            chunks: Box::pin(futures::stream::empty()),
+        });
+
+        let data_bytes = &mut Bytes::from(&data_stream);
+
+        assert_eq!(11, data_bytes.get_u32());
+        assert_eq!(103, data_bytes.get_u8());
+        assert_eq!(0x01, data_bytes.get_u32());
+        assert_eq!(0x02, data_bytes.get_u32()); // data length
+        assert!(data_bytes.is_empty()); // the data follows as chunks
+    }
+
     #[test]
     fn test_from_creates_name_bytes() {
         let file_attributes = get_file_attributes();
@@ -147,7 +238,7 @@ mod test {
             id: 0x01,
             files: vec![name::File {
                 file_name: String::from("file"),
-                file_attributes: file_attributes,
+                file_attributes,
             }],
         });
 
@@ -192,6 +283,7 @@ mod test {
             permissions: None,
             atime: None,
             mtime: None,
+            extended: BTreeMap::new(),
         }
     }
 }
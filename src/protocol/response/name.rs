@@ -1,30 +1,75 @@
+use crate::error::Error;
 use crate::protocol::file_attributes::FileAttributes;
+use crate::protocol::response::Response;
+use crate::try_buf::TryBufMut;
 
 use bytes::{BufMut, Bytes, BytesMut};
 use chrono::DateTime;
 use chrono::NaiveDateTime;
 use chrono::Utc;
 use std::convert::From;
-use std::convert::TryInto;
+use std::convert::TryFrom;
 
+/// An SSH_FXP_NAME response, which is serialized as the id and a `u32` count,
+/// then the file name, long name and ATTRS of each entry.
 #[derive(Debug, PartialEq)]
 pub struct Name {
     pub id: u32,
     pub files: Vec<File>,
 }
 
+impl Name {
+    /// Serializes the response, failing if any file name or long name is longer
+    /// than `max_name_length` bytes.
+    pub fn try_to_bytes(&self, max_name_length: u32) -> Result<Bytes, Error> {
+        let mut name_bytes = BytesMut::new();
+
+        name_bytes.put_u32(self.id);
+        name_bytes.put_u32(u32::try_from(self.files.len()).unwrap());
+
+        for file in &self.files {
+            name_bytes.put_slice(&file.try_to_bytes(max_name_length)?);
+        }
+
+        Ok(name_bytes.freeze())
+    }
+}
+
 impl From<&Name> for Bytes {
     fn from(name: &Name) -> Self {
-        let mut name_bytes = BytesMut::new();
+        name.try_to_bytes(u32::MAX).unwrap()
+    }
+}
 
-        name_bytes.put_u32(name.id);
-        name_bytes.put_u32(name.files.len().try_into().unwrap());
+/// Builds an SSH_FXP_NAME response one entry at a time.
+pub struct NameResponse {
+    id: u32,
+    files: Vec<File>,
+}
 
-        for file in &name.files {
-            name_bytes.put_slice(&Bytes::from(file));
+impl NameResponse {
+    pub fn new(id: u32) -> NameResponse {
+        NameResponse {
+            id,
+            files: Vec::new(),
         }
+    }
+
+    pub fn with_file(mut self, file: File) -> NameResponse {
+        self.files.push(file);
+        self
+    }
 
-        name_bytes.freeze()
+    pub fn with_files(mut self, files: Vec<File>) -> NameResponse {
+        self.files.extend(files);
+        self
+    }
+
+    pub fn build(self) -> Response {
+        Response::Name(Name {
+            id: self.id,
+            files: self.files,
+        })
     }
 }
 
@@ -81,21 +126,24 @@ impl File {
     }
 }
 
-impl From<&File> for Bytes {
-    fn from(item: &File) -> Self {
+impl File {
+    /// Serializes the entry, failing if its file name or long name is longer
+    /// than `max_name_length` bytes.
+    pub fn try_to_bytes(&self, max_name_length: u32) -> Result<Bytes, Error> {
         let mut file_bytes = BytesMut::new();
 
-        let file_name_bytes = item.file_name.as_bytes();
-        file_bytes.put_u32(file_name_bytes.len().try_into().unwrap());
-        file_bytes.put_slice(file_name_bytes);
+        file_bytes.try_put_string_max(&self.file_name, max_name_length)?;
+        file_bytes.try_put_string_max(&self.get_long_name(), max_name_length)?;
 
-        let long_name = item.get_long_name();
-        file_bytes.put_u32(long_name.len().try_into().unwrap());
-        file_bytes.put_slice(long_name.as_bytes());
+        file_bytes.put_slice(&Bytes::from(&self.file_attributes));
 
-        file_bytes.put_slice(&Bytes::from(&item.file_attributes));
+        Ok(file_bytes.freeze())
+    }
+}
 
-        file_bytes.freeze()
+impl From<&File> for Bytes {
+    fn from(item: &File) -> Self {
+        item.try_to_bytes(u32::MAX).unwrap()
     }
 }
 
@@ -103,7 +151,129 @@ impl From<&File> for Bytes {
 mod test {
     use super::*;
 
+    use crate::try_buf::TryBuf;
+
     use bytes::Buf;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_name_response_builds_name_with_files_in_order() {
+        let response = NameResponse::new(0x01)
+            .with_file(File {
+                file_name: String::from("first"),
+                file_attributes: FileAttributes::default(),
+            })
+            .with_files(vec![File {
+                file_name: String::from("second"),
+                file_attributes: FileAttributes::default(),
+            }])
+            .build();
+
+        assert_eq!(
+            Response::Name(Name {
+                id: 0x01,
+                files: vec![
+                    File {
+                        file_name: String::from("first"),
+                        file_attributes: FileAttributes::default(),
+                    },
+                    File {
+                        file_name: String::from("second"),
+                        file_attributes: FileAttributes::default(),
+                    },
+                ],
+            }),
+            response
+        );
+    }
+
+    #[test]
+    fn test_from_creates_name_bytes_that_parse_back_into_entries() {
+        let files = vec![
+            File {
+                file_name: String::from("file.txt"),
+                file_attributes: FileAttributes {
+                    size: Some(1000),
+                    permissions: Some(0o100644),
+                    mtime: Some(1000000000),
+                    atime: Some(1000000000),
+                    ..Default::default()
+                },
+            },
+            File {
+                file_name: String::from("dir"),
+                file_attributes: FileAttributes {
+                    permissions: Some(0o40777),
+                    ..Default::default()
+                },
+            },
+        ];
+        let long_names: Vec<String> = files.iter().map(File::get_long_name).collect();
+        let name = Name { id: 0x01, files };
+
+        let name_bytes = &mut Bytes::from(&name);
+
+        assert_eq!(0x01, name_bytes.try_get_u32().unwrap()); // id
+        let count = name_bytes.try_get_u32().unwrap();
+        let mut entries = Vec::new();
+
+        for _ in 0..count {
+            let file_name = name_bytes.try_get_string().unwrap();
+            let long_name = name_bytes.try_get_string().unwrap();
+            let file_attributes: FileAttributes = name_bytes.try_into().unwrap();
+            entries.push((file_name, long_name, file_attributes));
+        }
+
+        assert!(!name_bytes.has_remaining());
+        assert_eq!(
+            name.files
+                .into_iter()
+                .zip(long_names)
+                .map(|(file, long_name)| (file.file_name, long_name, file.file_attributes))
+                .collect::<Vec<_>>(),
+            entries
+        );
+    }
+
+    #[test]
+    fn test_try_to_bytes_writes_names_within_max_length() {
+        let name = Name {
+            id: 0x01,
+            files: vec![File {
+                file_name: String::from("file"),
+                file_attributes: FileAttributes::default(),
+            }],
+        };
+
+        // The long name is the longest string, at 41 bytes.
+        assert_eq!(Ok(Bytes::from(&name)), name.try_to_bytes(41));
+    }
+
+    #[test]
+    fn test_try_to_bytes_rejects_long_name_over_max_length() {
+        let name = Name {
+            id: 0x01,
+            files: vec![File {
+                file_name: String::from("file"),
+                file_attributes: FileAttributes::default(),
+            }],
+        };
+
+        assert_eq!(Err(Error::BadMessage), name.try_to_bytes(40));
+    }
+
+    #[test]
+    fn test_try_to_bytes_rejects_file_name_over_max_length() {
+        let name = Name {
+            id: 0x01,
+            files: vec![File {
+                file_name: "a".repeat(100),
+                file_attributes: FileAttributes::default(),
+            }],
+        };
+
+        assert_eq!(Err(Error::BadMessage), name.try_to_bytes(99));
+    }
 
     #[test]
     fn test_get_long_name_creates_long_name_with_missing_fields() {
@@ -268,6 +438,6 @@ mod test {
             long_name.as_bytes(),
             &file_bytes.copy_to_bytes(long_name.len())[..]
         );
-        assert_eq!(true, file_bytes.has_remaining()); // has file attributes
+        assert!(file_bytes.has_remaining()); // has file attributes
     }
 }
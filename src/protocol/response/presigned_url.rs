@@ -0,0 +1,48 @@
+use crate::try_buf::TryBufMut;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use std::convert::From;
+
+/// The reply to a `presign-download@dray` request, which holds a URL that
+/// downloads the file without going through the server until it expires.
+#[derive(Debug, PartialEq)]
+pub struct PresignedUrl {
+    pub id: u32,
+    pub url: String,
+}
+
+impl From<&PresignedUrl> for Bytes {
+    fn from(presigned_url: &PresignedUrl) -> Self {
+        let mut presigned_url_bytes = BytesMut::new();
+
+        presigned_url_bytes.put_u32(presigned_url.id);
+        presigned_url_bytes.try_put_str(&presigned_url.url).unwrap();
+
+        presigned_url_bytes.freeze()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Buf;
+
+    #[test]
+    fn test_from_creates_presigned_url_bytes() {
+        let presigned_url = PresignedUrl {
+            id: 0x01,
+            url: String::from("https://example.com"),
+        };
+
+        let presigned_url_bytes = &mut Bytes::from(&presigned_url);
+
+        assert_eq!(0x01, presigned_url_bytes.get_u32());
+        assert_eq!(19, presigned_url_bytes.get_u32()); // url length
+        assert_eq!(
+            b"https://example.com",
+            &presigned_url_bytes.copy_to_bytes(19)[..]
+        ); // url
+        assert!(!presigned_url_bytes.has_remaining());
+    }
+}
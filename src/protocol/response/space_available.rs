@@ -0,0 +1,59 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::convert::From;
+
+/// The reply to a `space-available` request. Object storage has no fixed
+/// capacity, so the device fields report `u64::MAX`, as do the user fields
+/// when the user has no quota.
+#[derive(Debug, PartialEq)]
+pub struct SpaceAvailable {
+    pub id: u32,
+    pub bytes_on_device: u64,
+    pub unused_bytes_on_device: u64,
+    pub bytes_available_to_user: u64,
+    pub unused_bytes_available_to_user: u64,
+    pub bytes_per_allocation_unit: u32,
+}
+
+impl From<&SpaceAvailable> for Bytes {
+    fn from(space_available: &SpaceAvailable) -> Self {
+        let mut space_available_bytes = BytesMut::new();
+
+        space_available_bytes.put_u32(space_available.id);
+        space_available_bytes.put_u64(space_available.bytes_on_device);
+        space_available_bytes.put_u64(space_available.unused_bytes_on_device);
+        space_available_bytes.put_u64(space_available.bytes_available_to_user);
+        space_available_bytes.put_u64(space_available.unused_bytes_available_to_user);
+        space_available_bytes.put_u32(space_available.bytes_per_allocation_unit);
+
+        space_available_bytes.freeze()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Buf;
+
+    #[test]
+    fn test_from_creates_space_available_bytes() {
+        let space_available = SpaceAvailable {
+            id: 0x01,
+            bytes_on_device: 0x02,
+            unused_bytes_on_device: 0x03,
+            bytes_available_to_user: 0x04,
+            unused_bytes_available_to_user: 0x05,
+            bytes_per_allocation_unit: 0x06,
+        };
+
+        let space_available_bytes = &mut Bytes::from(&space_available);
+
+        assert_eq!(0x01, space_available_bytes.get_u32());
+        assert_eq!(0x02, space_available_bytes.get_u64());
+        assert_eq!(0x03, space_available_bytes.get_u64());
+        assert_eq!(0x04, space_available_bytes.get_u64());
+        assert_eq!(0x05, space_available_bytes.get_u64());
+        assert_eq!(0x06, space_available_bytes.get_u32());
+        assert!(!space_available_bytes.has_remaining());
+    }
+}
@@ -1,9 +1,14 @@
+use crate::try_buf::TryBufMut;
+
 use bytes::{BufMut, Bytes, BytesMut};
 use std::convert::From;
 
 #[derive(Debug, PartialEq)]
 pub struct Version {
     pub version: u32,
+    /// The names and versions of the extensions the server supports, which
+    /// clients check before sending extended requests.
+    pub extensions: Vec<(String, String)>,
 }
 
 impl From<&Version> for Bytes {
@@ -12,6 +17,11 @@ impl From<&Version> for Bytes {
 
         status_bytes.put_u32(status.version);
 
+        for (name, version) in &status.extensions {
+            status_bytes.try_put_str(name).unwrap();
+            status_bytes.try_put_str(version).unwrap();
+        }
+
         status_bytes.freeze()
     }
 }
@@ -24,10 +34,30 @@ mod test {
 
     #[test]
     fn test_from_creates_version_bytes() {
-        let version = Version { version: 0x03 };
+        let version = Version {
+            version: 0x03,
+            extensions: Vec::new(),
+        };
+
+        let version_bytes = &mut Bytes::from(&version);
+
+        assert_eq!(0x03, version_bytes.get_u32());
+        assert!(!version_bytes.has_remaining());
+    }
+
+    #[test]
+    fn test_from_creates_version_bytes_with_extensions() {
+        let version = Version {
+            version: 0x03,
+            extensions: vec![(String::from("ext@example.com"), String::from("1"))],
+        };
 
         let version_bytes = &mut Bytes::from(&version);
 
         assert_eq!(0x03, version_bytes.get_u32());
+        assert_eq!(15, version_bytes.get_u32()); // name length
+        assert_eq!(b"ext@example.com", &version_bytes.copy_to_bytes(15)[..]); // name
+        assert_eq!(1, version_bytes.get_u32()); // version length
+        assert_eq!(b"1", &version_bytes.copy_to_bytes(1)[..]); // version
     }
 }
@@ -1,39 +1,416 @@
+use crate::error::Error;
 use crate::protocol::{
     file_attributes::FileAttributes,
     request::{self, Request},
     response::{self, Response},
 };
-use crate::storage::Storage;
+use crate::storage::{backend_calls, permissions::UserPermissions, DirPage, ReadData, Storage};
+use crate::transfer_summary::TransferSummary;
 use anyhow::Result;
+use bytes::Bytes;
 use log::error;
 use log::info;
-use std::{sync::Arc, time::Duration};
+use log::warn;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
 
 pub struct SftpSession {
     object_storage: Arc<dyn Storage>,
     user: String,
+    working_dir: String,
+    permissions: UserPermissions,
+    noop_requests: Vec<NoopRequest>,
+    error_verbosity: ErrorVerbosity,
+    limits: Limits,
+    max_name_length: u32,
+    visible_prefixes: Option<Vec<String>>,
+    quota: Option<u64>,
+    max_dir_entries: usize,
+    dir_entries_policy: DirEntriesPolicy,
+    missing_file_policy: MissingFilePolicy,
+    rename_policy: RenamePolicy,
+    dot_entries: bool,
+    extended_attributes: bool,
+    require_existing_dirs: bool,
+    strict_version: bool,
+    client_banner: Option<String>,
+    is_initialized: AtomicBool,
+    version: AtomicU32,
+    handle_modes: Mutex<HashMap<String, HandleMode>>,
+    dir_paths: Mutex<HashMap<String, String>>,
+    dir_entry_counts: Mutex<HashMap<String, usize>>,
+    finished_dirs: Mutex<HashSet<String>>,
+    pending_dot_entries: Mutex<HashSet<String>>,
+    transfers: Mutex<HashMap<String, Transfer>>,
+}
+
+/// Counts the data moved through an open file handle, which is summarized in
+/// the logs when the handle is closed.
+struct Transfer {
+    path: String,
+    bytes_read: u64,
+    bytes_written: u64,
+    started: Instant,
+    /// The client's text mode hint, kept with the handle for any later line
+    /// ending translation. Data is currently stored unchanged.
+    text: bool,
+}
+
+impl Transfer {
+    fn new(path: String, text: bool) -> Transfer {
+        Transfer {
+            path,
+            bytes_read: 0,
+            bytes_written: 0,
+            started: Instant::now(),
+            text,
+        }
+    }
+
+    fn summarize(self) -> TransferSummary {
+        TransferSummary {
+            path: self.path,
+            bytes_read: self.bytes_read,
+            bytes_written: self.bytes_written,
+            duration: self.started.elapsed(),
+            text: self.text,
+        }
+    }
+}
+
+/// The newest protocol version the server supports. Clients that offer a newer
+/// version fall back to this one.
+const SFTP_VERSION: u32 = 3;
+
+/// The access a file handle was opened with, based on the SSH_FXP_OPEN pflags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HandleMode {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl HandleMode {
+    fn from_open_options(open_options: &request::open::OpenOptions) -> HandleMode {
+        let is_write = open_options.create
+            || open_options.write
+            || open_options.append
+            || open_options.truncate;
+
+        match (open_options.read, is_write) {
+            (true, true) => HandleMode::ReadWrite,
+            (false, true) => HandleMode::Write,
+            _ => HandleMode::Read,
+        }
+    }
+
+    fn is_readable(&self) -> bool {
+        *self != HandleMode::Write
+    }
+
+    fn is_writable(&self) -> bool {
+        *self != HandleMode::Read
+    }
+}
+
+/// An unimplemented request that is answered with `SSH_FX_OK` instead of
+/// `SSH_FX_OP_UNSUPPORTED`, for clients that always send the request and
+/// disconnect when it is unsupported.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NoopRequest {
+    Setstat,
+    Fsetstat,
+    Symlink,
+}
+
+/// How much detail about unexpected errors is sent to clients. Errors from the
+/// storage backend can contain bucket names and keys, so the details are always
+/// logged but only sent to clients in `detailed` mode.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorVerbosity {
+    #[default]
+    Generic,
+    Detailed,
+}
+
+/// How a directory listing with more entries than the maximum is handled. A
+/// `truncate`d listing ends early and logs a warning, while `fail` answers the
+/// readdir that passes the maximum with SSH_FX_FAILURE.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DirEntriesPolicy {
+    #[default]
+    Truncate,
+    Fail,
+}
+
+/// How an open for writing without SSH_FXF_CREAT of a file that does not exist
+/// is handled. `fail` answers with SSH_FX_NO_SUCH_FILE, as the flags ask, while
+/// `create` creates the file anyway for clients that leave out the flag.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingFilePolicy {
+    #[default]
+    Fail,
+    Create,
+}
+
+/// How a rename onto a path that already exists is handled when the client
+/// does not say. Clients before SFTP version 5 cannot, and the version 3 draft
+/// leaves it to the server. `overwrite` replaces the existing file, while
+/// `fail` answers with SSH_FX_FAILURE. Version 5 clients pick with
+/// SSH_FXF_RENAME_OVERWRITE instead.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RenamePolicy {
+    #[default]
+    Overwrite,
+    Fail,
+}
+
+/// The most data a client may read or write with a single request, which is
+/// advertised to clients that send `limits@openssh.com`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub max_read_length: u32,
+    pub max_write_length: u32,
+}
+
+/// The room left in each packet for the SFTP header and request fields, such as
+/// the handle and offset of a write, besides the data.
+const PACKET_OVERHEAD_LENGTH: u32 = 1024;
+
+impl Limits {
+    fn max_packet_length(&self) -> u32 {
+        self.max_read_length.max(self.max_write_length) + PACKET_OVERHEAD_LENGTH
+    }
+}
+
+impl Default for Limits {
+    /// Defaults to the limits of the OpenSSH SFTP server.
+    fn default() -> Self {
+        Limits {
+            max_read_length: 255 * 1024,
+            max_write_length: 255 * 1024,
+        }
+    }
 }
 
 impl SftpSession {
     pub fn new(object_storage: Arc<dyn Storage>, user: String) -> Self {
+        let working_dir = object_storage.get_home(&user);
+
         SftpSession {
             object_storage,
             user,
+            working_dir,
+            permissions: UserPermissions::read_write(),
+            noop_requests: Vec::new(),
+            error_verbosity: ErrorVerbosity::default(),
+            limits: Limits::default(),
+            max_name_length: u32::MAX,
+            visible_prefixes: None,
+            quota: None,
+            max_dir_entries: 0,
+            dir_entries_policy: DirEntriesPolicy::default(),
+            missing_file_policy: MissingFilePolicy::default(),
+            rename_policy: RenamePolicy::default(),
+            require_existing_dirs: false,
+            strict_version: false,
+            dot_entries: false,
+            extended_attributes: false,
+            client_banner: None,
+            is_initialized: AtomicBool::new(false),
+            version: AtomicU32::new(SFTP_VERSION),
+            handle_modes: Mutex::new(HashMap::new()),
+            dir_paths: Mutex::new(HashMap::new()),
+            dir_entry_counts: Mutex::new(HashMap::new()),
+            finished_dirs: Mutex::new(HashSet::new()),
+            pending_dot_entries: Mutex::new(HashSet::new()),
+            transfers: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Returns whether the client has sent SSH_FXP_INIT. The channel should be
+    /// closed if a client sends any other request first, since no protocol
+    /// version has been negotiated.
+    pub fn is_initialized(&self) -> bool {
+        self.is_initialized.load(Ordering::SeqCst)
+    }
+
+    /// Returns the protocol version negotiated by SSH_FXP_INIT, which decides
+    /// how requests are parsed.
+    pub fn version(&self) -> u32 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    pub fn with_permissions(mut self, permissions: UserPermissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    pub fn with_noop_requests(mut self, noop_requests: Vec<NoopRequest>) -> Self {
+        self.noop_requests = noop_requests;
+        self
+    }
+
+    pub fn with_error_verbosity(mut self, error_verbosity: ErrorVerbosity) -> Self {
+        self.error_verbosity = error_verbosity;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Fails requests whose name response has a file name or long name longer
+    /// than `max_name_length` bytes, rather than sending an oversized packet.
+    pub fn with_max_name_length(mut self, max_name_length: u32) -> Self {
+        self.max_name_length = max_name_length;
+        self
+    }
+
+    /// Hides every path outside of the user's home and the visible prefixes.
+    /// The directories above a visible prefix can still be listed to reach it.
+    pub fn with_visible_prefixes(mut self, visible_prefixes: Option<Vec<String>>) -> Self {
+        self.visible_prefixes = visible_prefixes.map(|mut visible_prefixes| {
+            visible_prefixes.push(self.working_dir.clone());
+            visible_prefixes
+        });
+        self
+    }
+
+    /// Sets the most bytes the user may store under their home, which is
+    /// reported to clients that ask how much space is available.
+    pub fn with_quota(mut self, quota: Option<u64>) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Caps the entries returned from a directory handle, which bounds the
+    /// memory a listing of a huge prefix takes. A maximum of 0 means no limit.
+    pub fn with_max_dir_entries(
+        mut self,
+        max_dir_entries: usize,
+        dir_entries_policy: DirEntriesPolicy,
+    ) -> Self {
+        self.max_dir_entries = max_dir_entries;
+        self.dir_entries_policy = dir_entries_policy;
+        self
+    }
+
+    /// Starts each directory listing with `.` and `..` entries, which some
+    /// clients require to treat the listing as a directory.
+    pub fn with_dot_entries(mut self, dot_entries: bool) -> Self {
+        self.dot_entries = dot_entries;
+        self
+    }
+
+    /// Adds each file's extended attributes to stat and fstat responses. They
+    /// are read from storage separately from the rest of the metadata.
+    pub fn with_extended_attributes(mut self, extended_attributes: bool) -> Self {
+        self.extended_attributes = extended_attributes;
+        self
+    }
+
+    /// Answers an opendir of a directory with nothing stored under it with
+    /// SSH_FX_NO_SUCH_FILE, rather than a handle that lists no entries.
+    pub fn with_require_existing_dirs(mut self, require_existing_dirs: bool) -> Self {
+        self.require_existing_dirs = require_existing_dirs;
+        self
+    }
+
+    /// Refuses clients that ask for a version other than the supported one,
+    /// instead of negotiating down to it.
+    pub fn with_strict_version(mut self, strict_version: bool) -> Self {
+        self.strict_version = strict_version;
+        self
+    }
+
+    /// Records the client's SSH identification line, such as
+    /// `SSH-2.0-OpenSSH_8.9`, which is logged with the negotiated SFTP version.
+    pub fn with_client_banner(mut self, client_banner: Option<String>) -> Self {
+        self.client_banner = client_banner;
+        self
+    }
+
+    pub fn with_missing_file_policy(mut self, missing_file_policy: MissingFilePolicy) -> Self {
+        self.missing_file_policy = missing_file_policy;
+        self
+    }
+
+    pub fn with_rename_policy(mut self, rename_policy: RenamePolicy) -> Self {
+        self.rename_policy = rename_policy;
+        self
+    }
+
+    /// Handles a request that is never cancelled.
+    #[cfg(test)]
     pub async fn handle_request(&self, request: Request) -> Response {
+        self.handle_cancellable_request(request, &CancellationToken::new())
+            .await
+    }
+
+    /// Handles a request unless the token is cancelled first, such as when the
+    /// client disconnects, in which case the storage operation is dropped.
+    pub async fn handle_cancellable_request(
+        &self,
+        request: Request,
+        cancellation_token: &CancellationToken,
+    ) -> Response {
         info!("Received request: {:?}", request);
 
-        let response = match request {
+        if !self.is_initialized() && !matches!(request, Request::Init(_)) {
+            let response = SftpSession::build_uninitialized_response();
+            info!("Sending response: {:?}", response);
+            return response;
+        }
+
+        let id = request.id().unwrap_or(0);
+        let operation = request.name();
+
+        let response = tokio::select! {
+            biased;
+            _ = cancellation_token.cancelled() => {
+                info!("Cancelled request after the channel closed");
+                return SftpSession::build_cancelled_response();
+            }
+            response = backend_calls::with_operation(operation, self.dispatch_request(request)) => response,
+        };
+
+        let response = match response {
+            Ok(response) => response,
+            Err(error) => {
+                error!("{}", SftpSession::format_error_log(&error));
+                self.build_internal_error_response(id, &error)
+            }
+        };
+
+        info!("Sending response: {:?}", response);
+        response
+    }
+
+    async fn dispatch_request(&self, request: Request) -> Result<Response> {
+        match request {
             Request::Init(init_request) => self.handle_init_request(init_request),
             Request::Open(open_request) => self.handle_open_request(open_request).await,
             Request::Close(close_request) => self.handle_close_request(close_request).await,
             Request::Read(read_request) => self.handle_read_request(read_request).await,
             Request::Write(write_request) => self.handle_write_request(write_request).await,
-            Request::Lstat(lstat_request) => self.handle_lstat_request(lstat_request),
-            Request::Fstat(fstat_request) => self.handle_fstat_request(fstat_request),
-            Request::Setstat(setstat_request) => self.handle_setstat_request(setstat_request),
+            Request::Lstat(lstat_request) => self.handle_lstat_request(lstat_request).await,
+            Request::Fstat(fstat_request) => self.handle_fstat_request(fstat_request).await,
+            Request::Setstat(setstat_request) => self.handle_setstat_request(setstat_request).await,
             Request::Fsetstat(fsetstat_request) => self.handle_fsetstat_request(fsetstat_request),
             Request::Opendir(opendir_request) => self.handle_opendir_request(opendir_request).await,
             Request::Readdir(readdir_request) => self.handle_readdir_request(readdir_request).await,
@@ -43,41 +420,120 @@ impl SftpSession {
             Request::Realpath(realpath_request) => self.handle_realpath_request(realpath_request),
             Request::Stat(stat_request) => self.handle_stat_request(stat_request).await,
             Request::Rename(rename_request) => self.handle_rename_request(rename_request).await,
-            Request::Readlink(readlink_request) => self.handle_readlink_request(readlink_request),
+            Request::Readlink(readlink_request) => {
+                self.handle_readlink_request(readlink_request).await
+            }
             Request::Symlink(symlink_request) => self.handle_symlink_request(symlink_request),
-        };
-
-        let response = match response {
-            Ok(response) => response,
-            Err(error) => {
-                error!("Received error while processing request: {}", error);
-
-                // TODO: Move error handling into individual handlers to get the id right
-                Response::Status(response::status::Status {
-                    id: 0,
-                    status_code: response::status::StatusCode::BadMessage,
-                    error_message: String::from("Internal server error."),
-                })
+            Request::Extended(extended_request) => {
+                self.handle_extended_request(extended_request).await
             }
-        };
-
-        info!("Sending response: {:?}", response);
-        response
+        }
     }
 
-    fn handle_init_request(&self, _init_request: request::init::Init) -> Result<Response> {
-        Ok(Response::Version(response::version::Version { version: 3 }))
+    fn handle_init_request(&self, init_request: request::init::Init) -> Result<Response> {
+        // The session is left uninitialized, so the channel is closed after
+        // the refusal is sent.
+        if self.strict_version && init_request.version != SFTP_VERSION {
+            info!(
+                "Refusing sftp_init version={} client={:?}",
+                init_request.version,
+                self.client_banner.as_deref().unwrap_or("unknown")
+            );
+            return Ok(Response::Status(response::status::Status {
+                id: 0,
+                status_code: response::status::StatusCode::OperationUnsupported,
+                error_message: format!(
+                    "SFTP version {} is not supported. Only version {} is allowed.",
+                    init_request.version, SFTP_VERSION
+                ),
+            }));
+        }
+
+        let version = init_request.version.min(SFTP_VERSION);
+
+        self.version.store(version, Ordering::SeqCst);
+        self.is_initialized.store(true, Ordering::SeqCst);
+        info!(
+            "sftp_init version={} client={:?}",
+            version,
+            self.client_banner.as_deref().unwrap_or("unknown")
+        );
+        Ok(Response::Version(response::version::Version {
+            version,
+            extensions: vec![
+                (String::from(request::extended::LIMITS), String::from("1")),
+                (
+                    String::from(request::extended::PRESIGN_DOWNLOAD),
+                    String::from("1"),
+                ),
+                (
+                    String::from(request::extended::SPACE_AVAILABLE),
+                    String::from("1"),
+                ),
+                (
+                    String::from(request::extended::COPY_DATA),
+                    String::from("1"),
+                ),
+                (
+                    String::from(request::extended::HOME_DIRECTORY),
+                    String::from("1"),
+                ),
+            ],
+        }))
     }
 
     async fn handle_open_request(&self, open_request: request::open::Open) -> Result<Response> {
-        let handle = if open_request.open_options.create {
-            self.object_storage
-                .open_write_handle(open_request.filename)
-                .await?
-        } else if open_request.open_options.read {
+        let handle_mode = HandleMode::from_open_options(&open_request.open_options);
+
+        if (handle_mode.is_writable() && !self.permissions.write)
+            || (handle_mode.is_readable() && !self.permissions.read)
+        {
+            return Ok(SftpSession::build_permission_denied_response(
+                open_request.id,
+            ));
+        }
+
+        let path = self.resolve_path(&open_request.filename);
+
+        if !self.is_path_visible(&path) {
+            return Ok(SftpSession::build_permission_denied_response(
+                open_request.id,
+            ));
+        }
+
+        let open_options = &open_request.open_options;
+
+        if open_options.write
+            && !open_options.create
+            && self.missing_file_policy == MissingFilePolicy::Fail
+        {
+            match self.object_storage.file_exists(path.clone()).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return SftpSession::build_storage_error_response(
+                        open_request.id,
+                        Error::NoSuchFile.into(),
+                    )
+                }
+                Err(error) => {
+                    return SftpSession::build_storage_error_response(open_request.id, error)
+                }
+            }
+        }
+
+        let handle = if open_options.create || open_options.write {
+            // A file opened without SSH_FXF_TRUNC is being rewritten rather than
+            // replaced, so its metadata is carried over to the new contents.
             self.object_storage
-                .open_read_handle(open_request.filename)
-                .await?
+                .open_write_handle(
+                    path.clone(),
+                    open_request.file_attributes,
+                    !open_options.truncate,
+                    &self.user,
+                )
+                .await
+        } else if open_options.read {
+            self.object_storage.open_read_handle(path.clone()).await
         } else {
             return Ok(Response::Status(response::status::Status {
                 id: open_request.id,
@@ -86,6 +542,20 @@ impl SftpSession {
             }));
         };
 
+        let handle = match handle {
+            Ok(handle) => handle,
+            Err(error) => return SftpSession::build_storage_error_response(open_request.id, error),
+        };
+
+        self.handle_modes
+            .lock()
+            .unwrap()
+            .insert(handle.clone(), handle_mode);
+        self.transfers.lock().unwrap().insert(
+            handle.clone(),
+            Transfer::new(path, open_request.open_options.text),
+        );
+
         Ok(Response::Handle(response::handle::Handle {
             id: open_request.id,
             handle,
@@ -96,9 +566,37 @@ impl SftpSession {
         &self,
         close_request: request::handle::Handle,
     ) -> Result<Response> {
-        self.object_storage
+        self.handle_modes
+            .lock()
+            .unwrap()
+            .remove(&close_request.handle);
+        self.dir_paths.lock().unwrap().remove(&close_request.handle);
+        self.dir_entry_counts
+            .lock()
+            .unwrap()
+            .remove(&close_request.handle);
+        self.finished_dirs
+            .lock()
+            .unwrap()
+            .remove(&close_request.handle);
+        self.pending_dot_entries
+            .lock()
+            .unwrap()
+            .remove(&close_request.handle);
+
+        let transfer = self.transfers.lock().unwrap().remove(&close_request.handle);
+
+        if let Some(transfer) = transfer {
+            info!("{}", transfer.summarize());
+        }
+
+        if let Err(error) = self
+            .object_storage
             .close_handle(&close_request.handle)
-            .await?;
+            .await
+        {
+            return SftpSession::build_storage_error_response(close_request.id, error);
+        }
 
         Ok(Response::Status(response::status::Status {
             id: close_request.id,
@@ -108,10 +606,45 @@ impl SftpSession {
     }
 
     async fn handle_read_request(&self, read_request: request::read::Read) -> Result<Response> {
-        let data = self
+        if !self.permissions.read
+            || !self.is_handle_mode_allowed(&read_request.handle, HandleMode::is_readable)
+        {
+            return Ok(SftpSession::build_permission_denied_response(
+                read_request.id,
+            ));
+        }
+        let len = read_request.len.min(self.limits.max_read_length);
+        let mut data = match self
             .object_storage
-            .read_data(&read_request.handle, read_request.len)
-            .await?;
+            .read_data_at(&read_request.handle, read_request.offset, len)
+            .await
+        {
+            Ok(ReadData::Buffered(data)) => data,
+            Ok(ReadData::Streamed {
+                len: streamed_len,
+                chunks,
+            }) => {
+                if let Some(transfer) = self.transfers.lock().unwrap().get_mut(&read_request.handle)
+                {
+                    transfer.bytes_read += streamed_len as u64;
+                }
+
+                return Ok(Response::DataStream(response::data::DataStream {
+                    id: read_request.id,
+                    len: streamed_len,
+                    chunks,
+                }));
+            }
+            Err(error) => return SftpSession::build_storage_error_response(read_request.id, error),
+        };
+
+        // Clients treat the requested length as a hard maximum, so never pass on
+        // more than was asked for even if a backend range fetch overreads.
+        data.truncate(len as usize);
+
+        if let Some(transfer) = self.transfers.lock().unwrap().get_mut(&read_request.handle) {
+            transfer.bytes_read += data.len() as u64;
+        }
 
         if data.is_empty() {
             Ok(Response::Status(response::status::Status {
@@ -128,9 +661,31 @@ impl SftpSession {
     }
 
     async fn handle_write_request(&self, write_request: request::write::Write) -> Result<Response> {
-        self.object_storage
+        if !self.permissions.write
+            || !self.is_handle_mode_allowed(&write_request.handle, HandleMode::is_writable)
+        {
+            return Ok(SftpSession::build_permission_denied_response(
+                write_request.id,
+            ));
+        }
+        let data_len = write_request.data.len() as u64;
+
+        if let Err(error) = self
+            .object_storage
             .write_data(&write_request.handle, write_request.data)
-            .await?;
+            .await
+        {
+            return SftpSession::build_storage_error_response(write_request.id, error);
+        }
+
+        if let Some(transfer) = self
+            .transfers
+            .lock()
+            .unwrap()
+            .get_mut(&write_request.handle)
+        {
+            transfer.bytes_written += data_len;
+        }
 
         // TODO: This is a hack to prevent Filezilla from running out of request ids.
         // Refactor the handle manager to lock the entire handle manager, so only
@@ -144,40 +699,197 @@ impl SftpSession {
         }))
     }
 
-    fn handle_lstat_request(&self, lstat_request: request::path::Path) -> Result<Response> {
-        Ok(SftpSession::build_not_supported_response(lstat_request.id))
+    /// Storage has no symbolic links, so lstat is the same as stat. Clients such
+    /// as OpenSSH sftp use lstat to expand the paths given to get.
+    async fn handle_lstat_request(&self, lstat_request: request::path::Path) -> Result<Response> {
+        self.handle_stat_request(lstat_request).await
+    }
+
+    /// Answers fstat with the metadata of the path the handle was opened for.
+    /// A file still being written is not stored yet, so its size is the number
+    /// of bytes written through the handle so far.
+    async fn handle_fstat_request(&self, fstat_request: request::path::Path) -> Result<Response> {
+        let handle = &fstat_request.path;
+
+        if self.dir_paths.lock().unwrap().contains_key(handle) {
+            return Ok(Response::Attrs(response::attrs::Attrs {
+                id: fstat_request.id,
+                file_attributes: SftpSession::build_dir_attributes(),
+            }));
+        }
+
+        let transfer = self
+            .transfers
+            .lock()
+            .unwrap()
+            .get(handle)
+            .map(|transfer| (transfer.path.clone(), transfer.bytes_written));
+
+        let (path, bytes_written) = match transfer {
+            Some(transfer) => transfer,
+            None => {
+                return SftpSession::build_storage_error_response(
+                    fstat_request.id,
+                    anyhow::Error::new(Error::ServerError).context("Unknown handle."),
+                )
+            }
+        };
+
+        let mut file_attributes = self
+            .object_storage
+            .get_file_metadata(path.clone())
+            .await?
+            .file_attributes;
+
+        if file_attributes.is_dir() {
+            file_attributes = FileAttributes {
+                permissions: Some(0o100777),
+                size: Some(bytes_written),
+                ..SftpSession::build_dir_attributes()
+            };
+        } else {
+            self.add_extended_attributes(path, &mut file_attributes)
+                .await?;
+        }
+
+        Ok(Response::Attrs(response::attrs::Attrs {
+            id: fstat_request.id,
+            file_attributes,
+        }))
     }
 
-    fn handle_fstat_request(&self, fstat_request: request::path::Path) -> Result<Response> {
-        Ok(SftpSession::build_not_supported_response(fstat_request.id))
+    /// Adds a file's extended attributes to its stat attributes, if enabled.
+    async fn add_extended_attributes(
+        &self,
+        path: String,
+        file_attributes: &mut FileAttributes,
+    ) -> Result<()> {
+        if self.extended_attributes {
+            file_attributes
+                .extended
+                .extend(self.object_storage.get_extended_attributes(path).await?);
+        }
+
+        Ok(())
     }
 
-    fn handle_setstat_request(
+    async fn handle_setstat_request(
         &self,
         setstat_request: request::path_attributes::PathAttributes,
     ) -> Result<Response> {
-        Ok(SftpSession::build_not_supported_response(
-            setstat_request.id,
-        ))
+        if !self.permissions.write {
+            return Ok(SftpSession::build_permission_denied_response(
+                setstat_request.id,
+            ));
+        }
+
+        let path = self.resolve_path(&setstat_request.path);
+
+        if !self.is_path_visible(&path) {
+            return Ok(SftpSession::build_permission_denied_response(
+                setstat_request.id,
+            ));
+        }
+
+        let mut is_applied = false;
+
+        if let Some(permissions) = setstat_request.file_attributes.permissions {
+            match self
+                .object_storage
+                .set_permissions(path.clone(), permissions)
+                .await
+            {
+                Ok(()) => is_applied = true,
+                Err(error) if error.downcast_ref::<Error>() == Some(&Error::Unimplemented) => {}
+                Err(error) => {
+                    return SftpSession::build_storage_error_response(setstat_request.id, error)
+                }
+            }
+        }
+
+        if let Some(mtime) = setstat_request.file_attributes.mtime {
+            match self.object_storage.set_mtime(path.clone(), mtime).await {
+                Ok(()) => is_applied = true,
+                Err(error) if error.downcast_ref::<Error>() == Some(&Error::Unimplemented) => {}
+                Err(error) => {
+                    return SftpSession::build_storage_error_response(setstat_request.id, error)
+                }
+            }
+        }
+
+        if !setstat_request.file_attributes.extended.is_empty() {
+            self.object_storage
+                .set_extended_attributes(path, setstat_request.file_attributes.extended)
+                .await?;
+            is_applied = true;
+        }
+
+        if !is_applied {
+            return Ok(self.build_unimplemented_response(NoopRequest::Setstat, setstat_request.id));
+        }
+
+        Ok(Response::Status(response::status::Status {
+            id: setstat_request.id,
+            status_code: response::status::StatusCode::Ok,
+            error_message: String::from("Successfully set file attributes."),
+        }))
     }
 
     fn handle_fsetstat_request(
         &self,
         fsetstat_request: request::handle_attributes::HandleAttributes,
     ) -> Result<Response> {
-        Ok(SftpSession::build_not_supported_response(
-            fsetstat_request.id,
-        ))
+        if !self.permissions.write {
+            return Ok(SftpSession::build_permission_denied_response(
+                fsetstat_request.id,
+            ));
+        }
+        Ok(self.build_unimplemented_response(NoopRequest::Fsetstat, fsetstat_request.id))
     }
 
     async fn handle_opendir_request(
         &self,
         opendir_request: request::path::Path,
     ) -> Result<Response> {
-        let handle = self
-            .object_storage
-            .open_dir_handle(opendir_request.path)
-            .await?;
+        if !self.permissions.read {
+            return Ok(SftpSession::build_permission_denied_response(
+                opendir_request.id,
+            ));
+        }
+        let path = self.resolve_path(&opendir_request.path);
+
+        if !self.is_path_traversable(&path) {
+            return Ok(SftpSession::build_permission_denied_response(
+                opendir_request.id,
+            ));
+        }
+
+        // The root and working directories are always listable, like they are
+        // always reported as directories, even if nothing is stored under them.
+        if self.require_existing_dirs && path != "/" && path != self.working_dir {
+            match self.object_storage.dir_exists(path.clone()).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return SftpSession::build_storage_error_response(
+                        opendir_request.id,
+                        Error::NoSuchFile.into(),
+                    )
+                }
+                Err(error) => {
+                    return SftpSession::build_storage_error_response(opendir_request.id, error)
+                }
+            }
+        }
+
+        let handle = self.object_storage.open_dir_handle(path.clone()).await?;
+        self.dir_paths.lock().unwrap().insert(handle.clone(), path);
+
+        if self.dot_entries {
+            self.pending_dot_entries
+                .lock()
+                .unwrap()
+                .insert(handle.clone());
+        }
 
         Ok(Response::Handle(response::handle::Handle {
             id: opendir_request.id,
@@ -189,10 +901,86 @@ impl SftpSession {
         &self,
         readdir_request: request::handle::Handle,
     ) -> Result<Response> {
-        let files = self
-            .object_storage
-            .read_dir(&readdir_request.handle)
-            .await?;
+        if !self.permissions.read {
+            return Ok(SftpSession::build_permission_denied_response(
+                readdir_request.id,
+            ));
+        }
+        // The listing is known to be over once a page reports no more pages, so
+        // the backend is not asked for an empty page to find the end.
+        if self
+            .finished_dirs
+            .lock()
+            .unwrap()
+            .contains(&readdir_request.handle)
+        {
+            return Ok(Response::Status(response::status::Status {
+                id: readdir_request.id,
+                status_code: response::status::StatusCode::Eof,
+                error_message: String::from("End of file."),
+            }));
+        }
+
+        let dir_page = self.read_visible_dir(&readdir_request.handle).await?;
+
+        // Keys such as `dir/./file` list as dot entries, which would be taken
+        // for the directory itself or its parent.
+        let mut files: Vec<response::name::File> = dir_page
+            .files
+            .into_iter()
+            .filter(|file| file.file_name != "." && file.file_name != "..")
+            .collect();
+
+        if !dir_page.has_more {
+            self.finished_dirs
+                .lock()
+                .unwrap()
+                .insert(readdir_request.handle.clone());
+        }
+
+        if self.max_dir_entries > 0 {
+            let mut dir_entry_counts = self.dir_entry_counts.lock().unwrap();
+            let dir_entry_count = dir_entry_counts
+                .entry(readdir_request.handle.clone())
+                .or_insert(0);
+
+            if *dir_entry_count + files.len() > self.max_dir_entries {
+                match self.dir_entries_policy {
+                    DirEntriesPolicy::Truncate => {
+                        // Later pages of a truncated listing are dropped too, so
+                        // only the first truncation is logged.
+                        if *dir_entry_count < self.max_dir_entries {
+                            warn!(
+                                "Truncating directory listing at {} entries.",
+                                self.max_dir_entries
+                            );
+                        }
+                        files.truncate(self.max_dir_entries - *dir_entry_count);
+                    }
+                    DirEntriesPolicy::Fail => {
+                        return Ok(Response::Status(response::status::Status {
+                            id: readdir_request.id,
+                            status_code: response::status::StatusCode::Failure,
+                            error_message: format!(
+                                "Directory has more than {} entries.",
+                                self.max_dir_entries
+                            ),
+                        }));
+                    }
+                }
+            }
+
+            *dir_entry_count += files.len();
+        }
+
+        if self
+            .pending_dot_entries
+            .lock()
+            .unwrap()
+            .remove(&readdir_request.handle)
+        {
+            files.splice(0..0, SftpSession::build_dot_entries());
+        }
 
         match files.is_empty() {
             true => Ok(Response::Status(response::status::Status {
@@ -200,15 +988,27 @@ impl SftpSession {
                 status_code: response::status::StatusCode::Eof,
                 error_message: String::from("End of file."),
             })),
-            false => Ok(Response::Name(response::name::Name {
-                id: readdir_request.id,
-                files,
-            })),
+            false => Ok(response::name::NameResponse::new(readdir_request.id)
+                .with_files(files)
+                .build()),
         }
     }
 
     async fn handle_remove_request(&self, remove_request: request::path::Path) -> Result<Response> {
-        self.object_storage.remove_file(remove_request.path).await?;
+        if !self.permissions.write {
+            return Ok(SftpSession::build_permission_denied_response(
+                remove_request.id,
+            ));
+        }
+        let path = self.resolve_path(&remove_request.path);
+
+        if !self.is_path_visible(&path) {
+            return Ok(SftpSession::build_permission_denied_response(
+                remove_request.id,
+            ));
+        }
+
+        self.object_storage.remove_file(path).await?;
 
         Ok(Response::Status(response::status::Status {
             id: remove_request.id,
@@ -221,7 +1021,20 @@ impl SftpSession {
         &self,
         mkdir_request: request::path_attributes::PathAttributes,
     ) -> Result<Response> {
-        self.object_storage.create_dir(mkdir_request.path).await?;
+        if !self.permissions.write {
+            return Ok(SftpSession::build_permission_denied_response(
+                mkdir_request.id,
+            ));
+        }
+        let path = self.resolve_path(&mkdir_request.path);
+
+        if !self.is_path_visible(&path) {
+            return Ok(SftpSession::build_permission_denied_response(
+                mkdir_request.id,
+            ));
+        }
+
+        self.object_storage.create_dir(path).await?;
 
         Ok(Response::Status(response::status::Status {
             id: mkdir_request.id,
@@ -231,7 +1044,22 @@ impl SftpSession {
     }
 
     async fn handle_rmdir_request(&self, rmdir_request: request::path::Path) -> Result<Response> {
-        self.object_storage.remove_dir(rmdir_request.path).await?;
+        if !self.permissions.write {
+            return Ok(SftpSession::build_permission_denied_response(
+                rmdir_request.id,
+            ));
+        }
+        let path = self.resolve_path(&rmdir_request.path);
+
+        if !self.is_path_visible(&path) {
+            return Ok(SftpSession::build_permission_denied_response(
+                rmdir_request.id,
+            ));
+        }
+
+        if let Err(error) = self.object_storage.remove_dir(path).await {
+            return SftpSession::build_storage_error_response(rmdir_request.id, error);
+        }
 
         Ok(Response::Status(response::status::Status {
             id: rmdir_request.id,
@@ -241,34 +1069,46 @@ impl SftpSession {
     }
 
     fn handle_realpath_request(&self, realpath_request: request::path::Path) -> Result<Response> {
-        let path = if realpath_request.path == "." {
-            self.object_storage.get_home(&self.user)
-        } else {
-            realpath_request.to_normalized_path()
-        };
+        let path = self.resolve_path(&realpath_request.path);
 
-        Ok(Response::Name(response::name::Name {
-            id: realpath_request.id,
-            files: vec![response::name::File {
+        Ok(response::name::NameResponse::new(realpath_request.id)
+            .with_file(response::name::File {
                 file_name: path,
-                file_attributes: FileAttributes {
-                    permissions: Some(0o40777),
-                    size: None,
-                    uid: None,
-                    gid: None,
-                    atime: None,
-                    mtime: None,
-                },
-            }],
-        }))
+                file_attributes: SftpSession::build_dir_attributes(),
+            })
+            .build())
     }
 
     async fn handle_stat_request(&self, stat_request: request::path::Path) -> Result<Response> {
-        let file_attributes = self
-            .object_storage
-            .get_file_metadata(stat_request.path.clone())
-            .await?
-            .file_attributes;
+        let path = self.resolve_path(&stat_request.path);
+
+        if !self.is_path_traversable(&path) {
+            return Ok(SftpSession::build_permission_denied_response(
+                stat_request.id,
+            ));
+        }
+
+        // Clients such as WinSCP probe the root and working directories on connect,
+        // so these are always reported as directories without querying storage.
+        // A trailing slash names the prefix rather than an object with the same
+        // name, so it is always a directory too.
+        let file_attributes =
+            if path == "/" || path == self.working_dir || stat_request.path.ends_with('/') {
+                SftpSession::build_dir_attributes()
+            } else {
+                let mut file_attributes = self
+                    .object_storage
+                    .get_file_metadata(path.clone())
+                    .await?
+                    .file_attributes;
+
+                if !file_attributes.is_dir() {
+                    self.add_extended_attributes(path, &mut file_attributes)
+                        .await?;
+                }
+
+                file_attributes
+            };
 
         Ok(Response::Attrs(response::attrs::Attrs {
             id: stat_request.id,
@@ -280,30 +1120,495 @@ impl SftpSession {
         &self,
         rename_request: request::rename::Rename,
     ) -> Result<Response> {
-        self.object_storage
-            .rename(rename_request.old_path, rename_request.new_path)
-            .await?;
+        if !self.permissions.write {
+            return Ok(SftpSession::build_permission_denied_response(
+                rename_request.id,
+            ));
+        }
+        let old_path = self.resolve_path(&rename_request.old_path);
+        let new_path = self.resolve_path(&rename_request.new_path);
 
-        Ok(Response::Status(response::status::Status {
-            id: rename_request.id,
-            status_code: response::status::StatusCode::Ok,
-            error_message: String::from("File renamed."),
-        }))
-    }
+        if !self.is_path_visible(&old_path) || !self.is_path_visible(&new_path) {
+            return Ok(SftpSession::build_permission_denied_response(
+                rename_request.id,
+            ));
+        }
+
+        let overwrite = rename_request
+            .overwrite
+            .unwrap_or(self.rename_policy == RenamePolicy::Overwrite);
+
+        if !overwrite {
+            match self.object_storage.file_exists(new_path.clone()).await {
+                Ok(false) => {}
+                Ok(true) => {
+                    return Ok(Response::Status(response::status::Status {
+                        id: rename_request.id,
+                        status_code: response::status::StatusCode::Failure,
+                        error_message: String::from("File already exists."),
+                    }))
+                }
+                Err(error) => {
+                    return SftpSession::build_storage_error_response(rename_request.id, error)
+                }
+            }
+        }
+
+        self.object_storage.rename(old_path, new_path).await?;
+
+        Ok(Response::Status(response::status::Status {
+            id: rename_request.id,
+            status_code: response::status::StatusCode::Ok,
+            error_message: String::from("File renamed."),
+        }))
+    }
+
+    async fn handle_readlink_request(
+        &self,
+        readlink_request: request::path::Path,
+    ) -> Result<Response> {
+        if !self.permissions.read {
+            return Ok(SftpSession::build_permission_denied_response(
+                readlink_request.id,
+            ));
+        }
+        let path = self.resolve_path(&readlink_request.path);
+
+        if !self.is_path_visible(&path) {
+            return Ok(SftpSession::build_permission_denied_response(
+                readlink_request.id,
+            ));
+        }
+
+        let target = match self.object_storage.read_link(path).await {
+            Ok(target) => target,
+            Err(error) => {
+                return SftpSession::build_storage_error_response(readlink_request.id, error)
+            }
+        };
 
-    fn handle_readlink_request(&self, readlink_request: request::path::Path) -> Result<Response> {
-        Ok(SftpSession::build_not_supported_response(
-            readlink_request.id,
-        ))
+        Ok(response::name::NameResponse::new(readlink_request.id)
+            .with_file(response::name::File {
+                file_name: target,
+                file_attributes: FileAttributes::default(),
+            })
+            .build())
     }
 
     fn handle_symlink_request(
         &self,
         symlink_request: request::symlink::Symlink,
     ) -> Result<Response> {
-        Ok(SftpSession::build_not_supported_response(
-            symlink_request.id,
-        ))
+        if !self.permissions.write {
+            return Ok(SftpSession::build_permission_denied_response(
+                symlink_request.id,
+            ));
+        }
+        Ok(self.build_unimplemented_response(NoopRequest::Symlink, symlink_request.id))
+    }
+
+    async fn handle_extended_request(
+        &self,
+        extended_request: request::extended::Extended,
+    ) -> Result<Response> {
+        match extended_request.extended_request {
+            request::extended::ExtendedRequest::ReaddirResume(readdir_resume) => {
+                if !self.permissions.read {
+                    return Ok(SftpSession::build_permission_denied_response(
+                        extended_request.id,
+                    ));
+                }
+
+                let path = self.resolve_path(&readdir_resume.path);
+
+                if !self.is_path_traversable(&path) {
+                    return Ok(SftpSession::build_permission_denied_response(
+                        extended_request.id,
+                    ));
+                }
+
+                let handle = self
+                    .object_storage
+                    .resume_dir_handle(path.clone(), &readdir_resume.handle)
+                    .await?;
+                self.dir_paths.lock().unwrap().insert(handle.clone(), path);
+
+                Ok(Response::Handle(response::handle::Handle {
+                    id: extended_request.id,
+                    handle,
+                }))
+            }
+            request::extended::ExtendedRequest::Fsync(fsync) => {
+                if let Err(error) = self.object_storage.flush_handle(&fsync.handle).await {
+                    return SftpSession::build_storage_error_response(extended_request.id, error);
+                }
+
+                Ok(Response::Status(response::status::Status {
+                    id: extended_request.id,
+                    status_code: response::status::StatusCode::Ok,
+                    error_message: String::from("File synced."),
+                }))
+            }
+            request::extended::ExtendedRequest::OpenVersion(open_version) => {
+                if !self.permissions.read {
+                    return Ok(SftpSession::build_permission_denied_response(
+                        extended_request.id,
+                    ));
+                }
+
+                let path = self.resolve_path(&open_version.path);
+
+                if !self.is_path_visible(&path) {
+                    return Ok(SftpSession::build_permission_denied_response(
+                        extended_request.id,
+                    ));
+                }
+
+                let handle = match self
+                    .object_storage
+                    .open_version_read_handle(path, open_version.version_id)
+                    .await
+                {
+                    Ok(handle) => handle,
+                    Err(error) => {
+                        return SftpSession::build_storage_error_response(
+                            extended_request.id,
+                            error,
+                        )
+                    }
+                };
+
+                Ok(Response::Handle(response::handle::Handle {
+                    id: extended_request.id,
+                    handle,
+                }))
+            }
+            request::extended::ExtendedRequest::Limits => {
+                Ok(Response::Limits(response::limits::Limits {
+                    id: extended_request.id,
+                    max_packet_length: self.limits.max_packet_length().into(),
+                    max_read_length: self.limits.max_read_length.into(),
+                    max_write_length: self.limits.max_write_length.into(),
+                    max_open_handles: 0,
+                }))
+            }
+            request::extended::ExtendedRequest::PresignDownload(presign_download) => {
+                if !self.permissions.read {
+                    return Ok(SftpSession::build_permission_denied_response(
+                        extended_request.id,
+                    ));
+                }
+
+                let path = self.resolve_path(&presign_download.path);
+
+                if !self.is_path_visible(&path) {
+                    return Ok(SftpSession::build_permission_denied_response(
+                        extended_request.id,
+                    ));
+                }
+
+                match self.object_storage.presign_download(path).await {
+                    Ok(url) => Ok(Response::PresignedUrl(
+                        response::presigned_url::PresignedUrl {
+                            id: extended_request.id,
+                            url,
+                        },
+                    )),
+                    Err(error) => {
+                        SftpSession::build_storage_error_response(extended_request.id, error)
+                    }
+                }
+            }
+            request::extended::ExtendedRequest::SpaceAvailable(space_available) => {
+                let path = self.resolve_path(&space_available.path);
+
+                if !self.is_path_traversable(&path) {
+                    return Ok(SftpSession::build_permission_denied_response(
+                        extended_request.id,
+                    ));
+                }
+
+                let (bytes_available_to_user, unused_bytes_available_to_user) = match self.quota {
+                    Some(quota) => {
+                        let used_bytes = self
+                            .object_storage
+                            .get_used_bytes(self.working_dir.clone())
+                            .await?;
+
+                        (quota, quota.saturating_sub(used_bytes))
+                    }
+                    None => (u64::MAX, u64::MAX),
+                };
+
+                Ok(Response::SpaceAvailable(
+                    response::space_available::SpaceAvailable {
+                        id: extended_request.id,
+                        bytes_on_device: u64::MAX,
+                        unused_bytes_on_device: u64::MAX,
+                        bytes_available_to_user,
+                        unused_bytes_available_to_user,
+                        bytes_per_allocation_unit: 1,
+                    },
+                ))
+            }
+            request::extended::ExtendedRequest::HomeDirectory(home_directory) => {
+                // Users are isolated from each other, so only their own home
+                // directory is revealed.
+                if !home_directory.username.is_empty() && home_directory.username != self.user {
+                    return Ok(SftpSession::build_permission_denied_response(
+                        extended_request.id,
+                    ));
+                }
+
+                Ok(response::name::NameResponse::new(extended_request.id)
+                    .with_file(response::name::File {
+                        file_name: self.object_storage.get_home(&self.user),
+                        file_attributes: SftpSession::build_dir_attributes(),
+                    })
+                    .build())
+            }
+            request::extended::ExtendedRequest::CopyData(copy_data) => {
+                self.handle_copy_data_request(extended_request.id, copy_data)
+                    .await
+            }
+            request::extended::ExtendedRequest::Unsupported(_) => Ok(
+                SftpSession::build_not_supported_response(extended_request.id),
+            ),
+        }
+    }
+
+    /// Copies a file on the storage backend when the whole file is copied into
+    /// a new file, and otherwise reads and writes the data on the server. Like
+    /// reads and writes, handles are read and written in order, so a copy must
+    /// start where each handle left off.
+    async fn handle_copy_data_request(
+        &self,
+        id: u32,
+        copy_data: request::extended::CopyData,
+    ) -> Result<Response> {
+        if !self.permissions.read
+            || !self.permissions.write
+            || !self.is_handle_mode_allowed(&copy_data.read_handle, HandleMode::is_readable)
+            || !self.is_handle_mode_allowed(&copy_data.write_handle, HandleMode::is_writable)
+        {
+            return Ok(SftpSession::build_permission_denied_response(id));
+        }
+
+        let (read_position, write_position) = {
+            let transfers = self.transfers.lock().unwrap();
+            let position = |handle: &str, bytes: fn(&Transfer) -> u64| {
+                transfers.get(handle).map(bytes).unwrap_or_default()
+            };
+
+            (
+                position(&copy_data.read_handle, |transfer| transfer.bytes_read),
+                position(&copy_data.write_handle, |transfer| transfer.bytes_written),
+            )
+        };
+
+        if copy_data.read_offset != read_position || copy_data.write_offset != write_position {
+            return Ok(Response::Status(response::status::Status {
+                id,
+                status_code: response::status::StatusCode::Failure,
+                error_message: String::from("Copies must continue where each handle left off."),
+            }));
+        }
+
+        let is_whole_file = copy_data.read_length == 0 && read_position == 0 && write_position == 0;
+
+        let copied_bytes = match is_whole_file {
+            true => match self
+                .object_storage
+                .copy_data(&copy_data.read_handle, &copy_data.write_handle)
+                .await
+            {
+                Ok(copied_bytes) => Some(copied_bytes),
+                Err(error) if error.downcast_ref::<Error>() == Some(&Error::Unimplemented) => None,
+                Err(error) => return SftpSession::build_storage_error_response(id, error),
+            },
+            false => None,
+        };
+
+        let copied_bytes = match copied_bytes {
+            Some(copied_bytes) => copied_bytes,
+            None => match self.copy_data_through_server(&copy_data).await {
+                Ok(copied_bytes) => copied_bytes,
+                Err(error) => return SftpSession::build_storage_error_response(id, error),
+            },
+        };
+
+        let mut transfers = self.transfers.lock().unwrap();
+
+        if let Some(transfer) = transfers.get_mut(&copy_data.read_handle) {
+            transfer.bytes_read += copied_bytes;
+        }
+
+        if let Some(transfer) = transfers.get_mut(&copy_data.write_handle) {
+            transfer.bytes_written += copied_bytes;
+        }
+
+        Ok(Response::Status(response::status::Status {
+            id,
+            status_code: response::status::StatusCode::Ok,
+            error_message: String::from(""),
+        }))
+    }
+
+    /// Reads up to the requested length, or until the end of the file for a
+    /// length of 0, and writes the data as it is read.
+    async fn copy_data_through_server(
+        &self,
+        copy_data: &request::extended::CopyData,
+    ) -> Result<u64> {
+        let mut remaining = match copy_data.read_length {
+            0 => u64::MAX,
+            read_length => read_length,
+        };
+        let mut copied_bytes = 0;
+
+        while remaining > 0 {
+            let len = remaining.min(self.limits.max_read_length.into()) as u32;
+            let mut data = self
+                .object_storage
+                .read_data(&copy_data.read_handle, len)
+                .await?;
+            data.truncate(len as usize);
+
+            if data.is_empty() {
+                break;
+            }
+
+            remaining -= data.len() as u64;
+            copied_bytes += data.len() as u64;
+
+            self.object_storage
+                .write_data(&copy_data.write_handle, Bytes::from(data))
+                .await?;
+        }
+
+        Ok(copied_bytes)
+    }
+
+    /// Resolves a client-supplied path against the session's working directory,
+    /// which starts at the user's home directory.
+    fn resolve_path(&self, path: &str) -> String {
+        match path.starts_with('/') {
+            true => request::path::normalize_path(path),
+            false => request::path::normalize_path(&format!("{}/{}", self.working_dir, path)),
+        }
+    }
+
+    /// Checks if a path is in the user's home or a visible prefix.
+    fn is_path_visible(&self, path: &str) -> bool {
+        match &self.visible_prefixes {
+            Some(visible_prefixes) => visible_prefixes
+                .iter()
+                .any(|visible_prefix| is_path_within(path, visible_prefix)),
+            None => true,
+        }
+    }
+
+    /// Checks if a path is visible or is a directory above a visible prefix,
+    /// which clients need to stat and list to reach the prefix.
+    fn is_path_traversable(&self, path: &str) -> bool {
+        match &self.visible_prefixes {
+            Some(visible_prefixes) => visible_prefixes.iter().any(|visible_prefix| {
+                is_path_within(path, visible_prefix) || is_path_within(visible_prefix, path)
+            }),
+            None => true,
+        }
+    }
+
+    /// Reads the next page of a directory listing, leaving out hidden entries.
+    /// Pages with only hidden entries are skipped, since an empty page would
+    /// end the listing.
+    async fn read_visible_dir(&self, handle: &str) -> Result<DirPage> {
+        let dir_path = self.dir_paths.lock().unwrap().get(handle).cloned();
+
+        let dir_path = match (&self.visible_prefixes, dir_path) {
+            (Some(_), Some(dir_path)) => dir_path,
+            _ => return self.object_storage.read_dir(handle).await,
+        };
+
+        loop {
+            let dir_page = self.object_storage.read_dir(handle).await?;
+
+            let files: Vec<response::name::File> = dir_page
+                .files
+                .into_iter()
+                .filter(|file| {
+                    self.is_path_traversable(&request::path::normalize_path(&format!(
+                        "{}/{}",
+                        dir_path, file.file_name
+                    )))
+                })
+                .collect();
+
+            if !files.is_empty() || !dir_page.has_more {
+                return Ok(DirPage {
+                    files,
+                    has_more: dir_page.has_more,
+                });
+            }
+        }
+    }
+
+    /// Checks the mode a handle was opened with. Handles that were not opened
+    /// as files are left for storage to reject.
+    fn is_handle_mode_allowed(&self, handle: &str, is_allowed: fn(&HandleMode) -> bool) -> bool {
+        match self.handle_modes.lock().unwrap().get(handle) {
+            Some(handle_mode) => is_allowed(handle_mode),
+            None => true,
+        }
+    }
+
+    fn build_dot_entries() -> Vec<response::name::File> {
+        [".", ".."]
+            .iter()
+            .map(|file_name| response::name::File {
+                file_name: String::from(*file_name),
+                file_attributes: SftpSession::build_dir_attributes(),
+            })
+            .collect()
+    }
+
+    fn build_dir_attributes() -> FileAttributes {
+        FileAttributes {
+            permissions: Some(0o40777),
+            size: None,
+            uid: None,
+            gid: None,
+            atime: None,
+            mtime: None,
+            extended: BTreeMap::new(),
+        }
+    }
+
+    /// Serializes a response to send on the channel. A name response with a
+    /// name longer than the maximum name length is replaced with a failure.
+    pub fn serialize_response(&self, response: Response) -> (Response, Bytes) {
+        match response.try_to_bytes(self.max_name_length) {
+            Ok(response_bytes) => (response, response_bytes),
+            Err(_) => {
+                let id = match &response {
+                    Response::Name(name) => name.id,
+                    _ => 0,
+                };
+                info!(
+                    "Failing response with a name longer than {} bytes",
+                    self.max_name_length
+                );
+
+                let response = Response::Status(response::status::Status {
+                    id,
+                    status_code: response::status::StatusCode::Failure,
+                    error_message: String::from("A file name is too long to send."),
+                });
+                let response_bytes = Bytes::from(&response);
+
+                (response, response_bytes)
+            }
+        }
     }
 
     pub fn build_invalid_request_message_response() -> Response {
@@ -314,6 +1619,74 @@ impl SftpSession {
         })
     }
 
+    fn build_uninitialized_response() -> Response {
+        Response::Status(response::status::Status {
+            id: 0,
+            status_code: response::status::StatusCode::BadMessage,
+            error_message: String::from("SSH_FXP_INIT must be sent before other requests."),
+        })
+    }
+
+    /// Builds the response for a request cancelled because its channel closed,
+    /// which is only logged since the client can no longer receive it.
+    fn build_cancelled_response() -> Response {
+        Response::Status(response::status::Status {
+            id: 0,
+            status_code: response::status::StatusCode::ConnectionLost,
+            error_message: String::from("Request cancelled."),
+        })
+    }
+
+    fn build_unimplemented_response(&self, request: NoopRequest, id: u32) -> Response {
+        match self.noop_requests.contains(&request) {
+            true => Response::Status(response::status::Status {
+                id,
+                status_code: response::status::StatusCode::Ok,
+                error_message: String::from("Ignored unimplemented request."),
+            }),
+            false => SftpSession::build_not_supported_response(id),
+        }
+    }
+
+    /// Builds the status for a storage error the client is meant to see. A
+    /// summary that storage adds as context, such as how many objects could not
+    /// be deleted, is sent in place of the error's own message.
+    fn build_storage_error_response(id: u32, error: anyhow::Error) -> Result<Response> {
+        match error.downcast_ref::<Error>() {
+            Some(status_error) => Ok(Response::Status(response::status::Status {
+                id,
+                status_code: status_error.status_code(),
+                error_message: error.to_string(),
+            })),
+            None => Err(error),
+        }
+    }
+
+    fn format_error_log(error: &anyhow::Error) -> String {
+        format!("Received error while processing request: {:#}", error)
+    }
+
+    fn build_internal_error_response(&self, id: u32, error: &anyhow::Error) -> Response {
+        let error_message = match self.error_verbosity {
+            ErrorVerbosity::Generic => String::from("Internal server error."),
+            ErrorVerbosity::Detailed => format!("{:#}", error),
+        };
+
+        Response::Status(response::status::Status {
+            id,
+            status_code: response::status::StatusCode::BadMessage,
+            error_message,
+        })
+    }
+
+    fn build_permission_denied_response(id: u32) -> Response {
+        Response::Status(response::status::Status {
+            id,
+            status_code: response::status::StatusCode::PermissionDenied,
+            error_message: String::from("Permission denied."),
+        })
+    }
+
     fn build_not_supported_response(id: u32) -> Response {
         Response::Status(response::status::Status {
             id,
@@ -322,3 +1695,2428 @@ impl SftpSession {
         })
     }
 }
+
+impl Drop for SftpSession {
+    /// Logs the backend calls made during the session, if storage counts them.
+    fn drop(&mut self) {
+        if let Some(backend_calls) = self.object_storage.backend_calls() {
+            if !backend_calls.is_empty() {
+                info!("{}", backend_calls);
+            }
+        }
+    }
+}
+
+/// Checks if a path is a directory or anything beneath it.
+fn is_path_within(path: &str, dir: &str) -> bool {
+    dir == "/" || path == dir || path.starts_with(&format!("{}/", dir))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::log_capture::LogCapture;
+    use crate::storage::memory::MemoryStorage;
+    use crate::storage::VERSION_ID_ATTRIBUTE;
+
+    use bytes::{Buf, Bytes};
+    use futures::TryStreamExt;
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_returns_eof_for_empty_dir() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        let handle = open_dir(&sftp_session, "/home/test").await;
+
+        assert_eq!(
+            build_eof_response(2),
+            sftp_session
+                .handle_request(Request::Readdir(request::handle::Handle {
+                    id: 2,
+                    handle: handle.clone(),
+                }))
+                .await
+        );
+
+        assert_eq!(
+            build_eof_response(3),
+            sftp_session
+                .handle_request(Request::Readdir(request::handle::Handle { id: 3, handle }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_opendir_request_fails_for_missing_dir_when_required() {
+        let sftp_session =
+            create_sftp_session(Arc::new(MemoryStorage::new())).with_require_existing_dirs(true);
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::NoSuchFile,
+                error_message: String::from("No such file."),
+            }),
+            sftp_session
+                .handle_request(Request::Opendir(request::path::Path {
+                    id: 1,
+                    path: String::from("/home/test/missing"),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_opendir_request_opens_existing_and_working_dirs_when_required() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/dir/file.txt", b"data");
+        let sftp_session = create_sftp_session(object_storage).with_require_existing_dirs(true);
+
+        for path in ["/home/test/dir", "/home/test", "/"] {
+            match sftp_session
+                .handle_request(Request::Opendir(request::path::Path {
+                    id: 1,
+                    path: String::from(path),
+                }))
+                .await
+            {
+                Response::Handle(_) => {}
+                response => panic!("Unexpected response for {}: {:?}", path, response),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_returns_files_then_eof() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage);
+
+        let handle = open_dir(&sftp_session, "/home/test").await;
+
+        match sftp_session
+            .handle_request(Request::Readdir(request::handle::Handle {
+                id: 2,
+                handle: handle.clone(),
+            }))
+            .await
+        {
+            Response::Name(name) => assert_eq!("file.txt", name.files[0].file_name),
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        assert_eq!(
+            build_eof_response(3),
+            sftp_session
+                .handle_request(Request::Readdir(request::handle::Handle { id: 3, handle }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_setstat_request_round_trips_extended_attributes() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage).with_extended_attributes(true);
+
+        let mut extended = BTreeMap::new();
+        extended.insert(String::from("env"), String::from("prod"));
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::Ok,
+                error_message: String::from("Successfully set file attributes."),
+            }),
+            sftp_session
+                .handle_request(Request::Setstat(request::path_attributes::PathAttributes {
+                    id: 1,
+                    path: String::from("/home/test/file.txt"),
+                    file_attributes: FileAttributes {
+                        extended: extended.clone(),
+                        ..Default::default()
+                    },
+                }))
+                .await
+        );
+
+        match sftp_session
+            .handle_request(Request::Stat(request::path::Path {
+                id: 2,
+                path: String::from("/home/test/file.txt"),
+            }))
+            .await
+        {
+            Response::Attrs(attrs) => assert_eq!(extended, attrs.file_attributes.extended),
+            response => panic!("Unexpected response: {:?}", response),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_handle_stat_request_leaves_out_extended_attributes_by_default() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let mut extended = BTreeMap::new();
+        extended.insert(String::from("env"), String::from("prod"));
+        object_storage
+            .set_extended_attributes(String::from("/home/test/file.txt"), extended)
+            .await
+            .unwrap();
+
+        let sftp_session = create_sftp_session(object_storage);
+
+        match sftp_session
+            .handle_request(Request::Stat(request::path::Path {
+                id: 1,
+                path: String::from("/home/test/file.txt"),
+            }))
+            .await
+        {
+            Response::Attrs(attrs) => assert!(attrs.file_attributes.extended.is_empty()),
+            response => panic!("Unexpected response: {:?}", response),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_handle_fstat_request_returns_attributes_of_read_handle() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let mut extended = BTreeMap::new();
+        extended.insert(String::from("env"), String::from("prod"));
+        object_storage
+            .set_extended_attributes(String::from("/home/test/file.txt"), extended.clone())
+            .await
+            .unwrap();
+
+        let sftp_session = create_sftp_session(object_storage).with_extended_attributes(true);
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(build_read_open_request("file.txt")))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        match sftp_session
+            .handle_request(Request::Fstat(request::path::Path {
+                id: 2,
+                path: handle,
+            }))
+            .await
+        {
+            Response::Attrs(attrs) => {
+                assert_eq!(Some(4), attrs.file_attributes.size);
+                assert!(!attrs.file_attributes.is_dir());
+                assert_eq!(extended, attrs.file_attributes.extended);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_handle_fstat_request_returns_bytes_written_to_write_handle() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(build_write_open_request("file.txt")))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        sftp_session
+            .handle_request(Request::Write(request::write::Write {
+                id: 2,
+                handle: handle.clone(),
+                offset: 0,
+                data: bytes::Bytes::from("data"),
+            }))
+            .await;
+
+        match sftp_session
+            .handle_request(Request::Fstat(request::path::Path {
+                id: 3,
+                path: handle,
+            }))
+            .await
+        {
+            Response::Attrs(attrs) => {
+                assert_eq!(Some(4), attrs.file_attributes.size);
+                assert!(!attrs.file_attributes.is_dir());
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_handle_fstat_request_returns_dir_attributes_for_dir_handle() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        let handle = open_dir(&sftp_session, "/home/test").await;
+
+        match sftp_session
+            .handle_request(Request::Fstat(request::path::Path {
+                id: 2,
+                path: handle,
+            }))
+            .await
+        {
+            Response::Attrs(attrs) => assert!(attrs.file_attributes.is_dir()),
+            response => panic!("Unexpected response: {:?}", response),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_handle_fstat_request_fails_for_unknown_handle() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        match sftp_session
+            .handle_request(Request::Fstat(request::path::Path {
+                id: 1,
+                path: String::from("unknown"),
+            }))
+            .await
+        {
+            Response::Status(status) => {
+                assert_eq!(response::status::StatusCode::Failure, status.status_code)
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_handle_setstat_request_without_extended_attributes_is_unsupported() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        assert_eq!(
+            SftpSession::build_not_supported_response(1),
+            sftp_session
+                .handle_request(Request::Setstat(request::path_attributes::PathAttributes {
+                    id: 1,
+                    path: String::from("/home/test/file.txt"),
+                    file_attributes: FileAttributes {
+                        permissions: Some(0o644),
+                        ..Default::default()
+                    },
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_setstat_request_without_extended_attributes_is_noop_when_configured() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()))
+            .with_noop_requests(vec![NoopRequest::Setstat]);
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::Ok,
+                error_message: String::from("Ignored unimplemented request."),
+            }),
+            sftp_session
+                .handle_request(Request::Setstat(request::path_attributes::PathAttributes {
+                    id: 1,
+                    path: String::from("/home/test/file.txt"),
+                    file_attributes: FileAttributes {
+                        permissions: Some(0o644),
+                        ..Default::default()
+                    },
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_symlink_request_is_unsupported_when_other_requests_are_noop() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()))
+            .with_noop_requests(vec![NoopRequest::Setstat]);
+
+        assert_eq!(
+            SftpSession::build_not_supported_response(1),
+            sftp_session
+                .handle_request(Request::Symlink(request::symlink::Symlink {
+                    id: 1,
+                    link_path: String::from("link"),
+                    target_path: String::from("target"),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_readlink_request_without_link_support() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::OperationUnsupported,
+                error_message: String::from("Operation unsupported."),
+            }),
+            sftp_session
+                .handle_request(Request::Readlink(request::path::Path {
+                    id: 1,
+                    path: String::from("file.txt"),
+                }))
+                .await
+        );
+    }
+
+    #[test]
+    fn test_serialize_response_fails_name_response_over_max_name_length() {
+        let sftp_session =
+            create_sftp_session(Arc::new(MemoryStorage::new())).with_max_name_length(100);
+
+        let (response, response_bytes) = sftp_session.serialize_response(
+            response::name::NameResponse::new(1)
+                .with_file(response::name::File {
+                    file_name: "a".repeat(101),
+                    file_attributes: FileAttributes::default(),
+                })
+                .build(),
+        );
+
+        match &response {
+            Response::Status(status) => {
+                assert_eq!(1, status.id);
+                assert_eq!(response::status::StatusCode::Failure, status.status_code);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        };
+        assert_eq!(Bytes::from(&response), response_bytes);
+    }
+
+    #[test]
+    fn test_serialize_response_sends_name_response_within_max_name_length() {
+        let sftp_session =
+            create_sftp_session(Arc::new(MemoryStorage::new())).with_max_name_length(100);
+
+        let name_response = response::name::NameResponse::new(1)
+            .with_file(response::name::File {
+                file_name: String::from("file.txt"),
+                file_attributes: FileAttributes::default(),
+            })
+            .build();
+        let expected_bytes = Bytes::from(&name_response);
+
+        let (response, response_bytes) = sftp_session.serialize_response(name_response);
+
+        assert!(matches!(response, Response::Name(_)));
+        assert_eq!(expected_bytes, response_bytes);
+    }
+
+    #[tokio::test]
+    async fn test_handle_realpath_request_resolves_working_dir_to_home() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        match sftp_session
+            .handle_request(Request::Realpath(request::path::Path {
+                id: 1,
+                path: String::from("."),
+            }))
+            .await
+        {
+            Response::Name(name) => assert_eq!("/home/test", name.files[0].file_name),
+            response => panic!("Unexpected response: {:?}", response),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_handle_realpath_request_resolves_relative_path_under_home() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        match sftp_session
+            .handle_request(Request::Realpath(request::path::Path {
+                id: 1,
+                path: String::from("dir/../file.txt"),
+            }))
+            .await
+        {
+            Response::Name(name) => assert_eq!("/home/test/file.txt", name.files[0].file_name),
+            response => panic!("Unexpected response: {:?}", response),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_handle_realpath_request_resolves_trailing_slash_identically() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        for path in &["dir", "dir/", "/home/test/dir/"] {
+            match sftp_session
+                .handle_request(Request::Realpath(request::path::Path {
+                    id: 1,
+                    path: String::from(*path),
+                }))
+                .await
+            {
+                Response::Name(name) => assert_eq!("/home/test/dir", name.files[0].file_name),
+                response => panic!("Unexpected response: {:?}", response),
+            };
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_stat_request_returns_directory_for_trailing_slash() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/dir", b"data");
+        object_storage.put_file("/home/test/dir/file.txt", b"data");
+        let sftp_session = create_sftp_session(object_storage);
+
+        assert_eq!(
+            Response::Attrs(response::attrs::Attrs {
+                id: 1,
+                file_attributes: SftpSession::build_dir_attributes(),
+            }),
+            sftp_session
+                .handle_request(Request::Stat(request::path::Path {
+                    id: 1,
+                    path: String::from("dir/"),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_resolves_relative_path_under_home() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(request::open::Open {
+                id: 1,
+                filename: String::from("file.txt"),
+                file_attributes: FileAttributes::default(),
+                open_options: request::open::OpenOptions {
+                    read: false,
+                    write: true,
+                    create: true,
+                    create_new_only: false,
+                    append: false,
+                    truncate: true,
+                    text: false,
+                },
+            }))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        sftp_session
+            .handle_request(Request::Close(request::handle::Handle { id: 2, handle }))
+            .await;
+
+        assert!(object_storage.get_file("/home/test/file.txt").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_handle_stat_request_returns_directory_for_working_dir() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        assert_eq!(
+            Response::Attrs(response::attrs::Attrs {
+                id: 1,
+                file_attributes: SftpSession::build_dir_attributes(),
+            }),
+            sftp_session
+                .handle_request(Request::Stat(request::path::Path {
+                    id: 1,
+                    path: String::from("."),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_lstat_request_returns_file_attributes() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+        let sftp_session = create_sftp_session(object_storage);
+
+        let response = sftp_session
+            .handle_request(Request::Lstat(request::path::Path {
+                id: 1,
+                path: String::from("file.txt"),
+            }))
+            .await;
+
+        match response {
+            Response::Attrs(attrs) => {
+                assert_eq!(1, attrs.id);
+                assert_eq!(Some(4), attrs.file_attributes.size);
+            }
+            _ => panic!("Expected attrs, got {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_stat_request_returns_directory_for_root() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        assert_eq!(
+            Response::Attrs(response::attrs::Attrs {
+                id: 1,
+                file_attributes: SftpSession::build_dir_attributes(),
+            }),
+            sftp_session
+                .handle_request(Request::Stat(request::path::Path {
+                    id: 1,
+                    path: String::from("/"),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_readdir_resume_request_resumes_dropped_listing() {
+        let object_storage = Arc::new(MemoryStorage::new().with_page_size(1));
+        object_storage.put_file("/home/test/a.txt", b"data");
+        object_storage.put_file("/home/test/b.txt", b"data");
+        object_storage.put_file("/home/test/c.txt", b"data");
+
+        let dropped_handle = {
+            let sftp_session = create_sftp_session(object_storage.clone());
+            let handle = open_dir(&sftp_session, "/home/test").await;
+
+            assert_eq!(
+                vec!["a.txt"],
+                read_dir_file_names(&sftp_session, &handle).await
+            );
+
+            handle
+        };
+
+        let sftp_session = create_sftp_session(object_storage);
+
+        let handle = match sftp_session
+            .handle_request(Request::Extended(request::extended::Extended {
+                id: 2,
+                extended_request: request::extended::ExtendedRequest::ReaddirResume(
+                    request::extended::ReaddirResume {
+                        path: String::from("."),
+                        handle: dropped_handle.clone(),
+                    },
+                ),
+            }))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        assert_ne!(dropped_handle, handle);
+        assert_eq!(
+            vec!["b.txt"],
+            read_dir_file_names(&sftp_session, &handle).await
+        );
+        assert_eq!(
+            vec!["c.txt"],
+            read_dir_file_names(&sftp_session, &handle).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_close_request_frees_dir_handle() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/dir/file.txt", b"data");
+        let sftp_session = create_sftp_session(object_storage);
+
+        let handle = open_dir(&sftp_session, "dir").await;
+        assert_eq!(
+            vec![String::from("file.txt")],
+            read_dir_file_names(&sftp_session, &handle).await
+        );
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 2,
+                status_code: response::status::StatusCode::Ok,
+                error_message: String::from(""),
+            }),
+            sftp_session
+                .handle_request(Request::Close(request::handle::Handle {
+                    id: 2,
+                    handle: handle.clone(),
+                }))
+                .await
+        );
+
+        assert!(sftp_session.object_storage.read_dir(&handle).await.is_err());
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 2,
+                status_code: response::status::StatusCode::Failure,
+                error_message: String::from("Failure."),
+            }),
+            sftp_session
+                .handle_request(Request::Close(request::handle::Handle {
+                    id: 2,
+                    handle: handle.clone(),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_close_request_with_unknown_handle() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::Failure,
+                error_message: String::from("Failure."),
+            }),
+            sftp_session
+                .handle_request(Request::Close(request::handle::Handle {
+                    id: 1,
+                    handle: String::from("unknown"),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_rmdir_request_removes_files_under_dir() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/dir/file.txt", b"data");
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        let response = sftp_session
+            .handle_request(Request::Rmdir(request::path::Path {
+                id: 1,
+                path: String::from("dir"),
+            }))
+            .await;
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::Ok,
+                error_message: String::from("Successfully removed directory."),
+            }),
+            response
+        );
+        assert_eq!(None, object_storage.get_file("/home/test/dir/file.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_rmdir_request_fails_for_non_empty_dir_when_empty_required() {
+        let object_storage = Arc::new(MemoryStorage::new().with_rmdir_require_empty());
+        object_storage.put_file("/home/test/dir/file.txt", b"data");
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        let response = sftp_session
+            .handle_request(Request::Rmdir(request::path::Path {
+                id: 1,
+                path: String::from("dir"),
+            }))
+            .await;
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::Failure,
+                error_message: String::from("Failure."),
+            }),
+            response
+        );
+        assert_eq!(
+            Some(b"data".to_vec()),
+            object_storage.get_file("/home/test/dir/file.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_with_unsupported_request() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        assert_eq!(
+            SftpSession::build_not_supported_response(2),
+            sftp_session
+                .handle_request(Request::Extended(request::extended::Extended {
+                    id: 2,
+                    extended_request: request::extended::ExtendedRequest::Unsupported(
+                        String::from("unknown@example.com")
+                    ),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_denies_write_for_read_only_user() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()))
+            .with_permissions(UserPermissions::read_only());
+
+        assert_eq!(
+            SftpSession::build_permission_denied_response(1),
+            sftp_session
+                .handle_request(Request::Open(build_write_open_request("file.txt")))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_for_missing_file_returns_no_such_file() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::NoSuchFile,
+                error_message: Error::NoSuchFile.to_string(),
+            }),
+            sftp_session
+                .handle_request(Request::Open(build_read_open_request("file.txt")))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_without_create_for_missing_file_returns_no_such_file() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        let mut open_request = build_write_open_request("file.txt");
+        open_request.open_options.create = false;
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::NoSuchFile,
+                error_message: Error::NoSuchFile.to_string(),
+            }),
+            sftp_session
+                .handle_request(Request::Open(open_request))
+                .await
+        );
+        assert!(object_storage.get_file("/home/test/file.txt").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_without_create_for_existing_file_returns_handle() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage);
+
+        let mut open_request = build_write_open_request("file.txt");
+        open_request.open_options.create = false;
+
+        match sftp_session
+            .handle_request(Request::Open(open_request))
+            .await
+        {
+            Response::Handle(_) => {}
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_open_requests_return_unique_handles() {
+        let object_storage = Arc::new(MemoryStorage::new());
+
+        for i in 0..100 {
+            object_storage.put_file(&format!("/home/test/read{}.txt", i), b"data");
+        }
+
+        let sftp_session = Arc::new(create_sftp_session(object_storage));
+
+        let tasks: Vec<_> = (0..200)
+            .map(|i| {
+                let sftp_session = sftp_session.clone();
+
+                tokio::spawn(async move {
+                    let open_request = match i % 2 {
+                        0 => build_read_open_request(&format!("read{}.txt", i / 2)),
+                        _ => build_write_open_request(&format!("write{}.txt", i / 2)),
+                    };
+
+                    match sftp_session
+                        .handle_request(Request::Open(open_request))
+                        .await
+                    {
+                        Response::Handle(handle) => handle.handle,
+                        response => panic!("Unexpected response: {:?}", response),
+                    }
+                })
+            })
+            .collect();
+
+        let mut handles = HashSet::new();
+
+        for task in tasks {
+            handles.insert(task.await.unwrap());
+        }
+
+        assert_eq!(200, handles.len());
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_without_create_creates_missing_file_with_create_policy() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        let sftp_session = create_sftp_session(object_storage.clone())
+            .with_missing_file_policy(MissingFilePolicy::Create);
+
+        let mut open_request = build_write_open_request("file.txt");
+        open_request.open_options.create = false;
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(open_request))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        sftp_session
+            .handle_request(Request::Close(request::handle::Handle { id: 2, handle }))
+            .await;
+
+        assert!(object_storage.get_file("/home/test/file.txt").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_handle_rename_request_overwrites_existing_file() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/old.txt", b"new data");
+        object_storage.put_file("/home/test/new.txt", b"old data");
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        let response = sftp_session
+            .handle_request(Request::Rename(request::rename::Rename {
+                id: 1,
+                old_path: String::from("old.txt"),
+                new_path: String::from("new.txt"),
+                overwrite: None,
+            }))
+            .await;
+
+        match response {
+            Response::Status(status) => {
+                assert_eq!(response::status::StatusCode::Ok, status.status_code)
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        assert_eq!(
+            Some(b"new data".to_vec()),
+            object_storage.get_file("/home/test/new.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_rename_request_fails_on_existing_file_with_fail_policy() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/old.txt", b"new data");
+        object_storage.put_file("/home/test/new.txt", b"old data");
+        let sftp_session =
+            create_sftp_session(object_storage.clone()).with_rename_policy(RenamePolicy::Fail);
+
+        let response = sftp_session
+            .handle_request(Request::Rename(request::rename::Rename {
+                id: 1,
+                old_path: String::from("old.txt"),
+                new_path: String::from("new.txt"),
+                overwrite: None,
+            }))
+            .await;
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::Failure,
+                error_message: String::from("File already exists."),
+            }),
+            response
+        );
+        assert_eq!(
+            Some(b"old data".to_vec()),
+            object_storage.get_file("/home/test/new.txt")
+        );
+        assert!(object_storage.get_file("/home/test/old.txt").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_handle_rename_request_to_missing_file_with_fail_policy() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/old.txt", b"data");
+        let sftp_session =
+            create_sftp_session(object_storage.clone()).with_rename_policy(RenamePolicy::Fail);
+
+        let response = sftp_session
+            .handle_request(Request::Rename(request::rename::Rename {
+                id: 1,
+                old_path: String::from("old.txt"),
+                new_path: String::from("new.txt"),
+                overwrite: None,
+            }))
+            .await;
+
+        match response {
+            Response::Status(status) => {
+                assert_eq!(response::status::StatusCode::Ok, status.status_code)
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        assert!(object_storage.get_file("/home/test/new.txt").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_handle_rename_request_follows_client_overwrite_flag() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/a.txt", b"a");
+        object_storage.put_file("/home/test/b.txt", b"b");
+        object_storage.put_file("/home/test/c.txt", b"c");
+
+        let fail_session =
+            create_sftp_session(object_storage.clone()).with_rename_policy(RenamePolicy::Fail);
+        let overwrite_session = create_sftp_session(object_storage.clone());
+
+        let overwrite_response = fail_session
+            .handle_request(Request::Rename(request::rename::Rename {
+                id: 1,
+                old_path: String::from("a.txt"),
+                new_path: String::from("b.txt"),
+                overwrite: Some(true),
+            }))
+            .await;
+        let no_overwrite_response = overwrite_session
+            .handle_request(Request::Rename(request::rename::Rename {
+                id: 2,
+                old_path: String::from("b.txt"),
+                new_path: String::from("c.txt"),
+                overwrite: Some(false),
+            }))
+            .await;
+
+        match overwrite_response {
+            Response::Status(status) => {
+                assert_eq!(response::status::StatusCode::Ok, status.status_code)
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        match no_overwrite_response {
+            Response::Status(status) => {
+                assert_eq!(response::status::StatusCode::Failure, status.status_code)
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        assert_eq!(
+            Some(b"a".to_vec()),
+            object_storage.get_file("/home/test/b.txt")
+        );
+        assert_eq!(
+            Some(b"c".to_vec()),
+            object_storage.get_file("/home/test/c.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_remove_request_denies_read_only_user() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+        object_storage.set_user_permissions("test", UserPermissions::read_only());
+
+        let user_permissions = object_storage.get_user_permissions("test").await.unwrap();
+        let sftp_session =
+            create_sftp_session(object_storage.clone()).with_permissions(user_permissions);
+
+        assert_eq!(
+            SftpSession::build_permission_denied_response(1),
+            sftp_session
+                .handle_request(Request::Remove(request::path::Path {
+                    id: 1,
+                    path: String::from("file.txt"),
+                }))
+                .await
+        );
+        assert!(object_storage.get_file("/home/test/file.txt").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_handle_stat_request_reports_mtime_from_open() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        let sftp_session = create_sftp_session(object_storage);
+
+        let mut open_request = build_write_open_request("file.txt");
+        open_request.file_attributes.atime = Some(1608671340);
+        open_request.file_attributes.mtime = Some(1608671341);
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(open_request))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        sftp_session
+            .handle_request(Request::Write(request::write::Write {
+                id: 2,
+                handle: handle.clone(),
+                offset: 0,
+                data: bytes::Bytes::from("data"),
+            }))
+            .await;
+        sftp_session
+            .handle_request(Request::Close(request::handle::Handle { id: 3, handle }))
+            .await;
+
+        match sftp_session
+            .handle_request(Request::Stat(request::path::Path {
+                id: 4,
+                path: String::from("file.txt"),
+            }))
+            .await
+        {
+            Response::Attrs(attrs) => assert_eq!(Some(1608671341), attrs.file_attributes.mtime),
+            response => panic!("Unexpected response: {:?}", response),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_handle_setstat_request_sets_mtime() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+        let sftp_session = create_sftp_session(object_storage);
+
+        sftp_session
+            .handle_request(Request::Setstat(request::path_attributes::PathAttributes {
+                id: 1,
+                path: String::from("file.txt"),
+                file_attributes: FileAttributes {
+                    atime: Some(1608671340),
+                    mtime: Some(1608671341),
+                    ..Default::default()
+                },
+            }))
+            .await;
+
+        match sftp_session
+            .handle_request(Request::Stat(request::path::Path {
+                id: 2,
+                path: String::from("file.txt"),
+            }))
+            .await
+        {
+            Response::Attrs(attrs) => assert_eq!(Some(1608671341), attrs.file_attributes.mtime),
+            response => panic!("Unexpected response: {:?}", response),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_succeeds_for_read_write_user() {
+        let object_storage = Arc::new(MemoryStorage::new());
+
+        let sftp_session = create_sftp_session(object_storage.clone())
+            .with_permissions(UserPermissions::read_write());
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(build_write_open_request("file.txt")))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        sftp_session
+            .handle_request(Request::Write(request::write::Write {
+                id: 2,
+                handle: handle.clone(),
+                offset: 0,
+                data: bytes::Bytes::from("data"),
+            }))
+            .await;
+
+        sftp_session
+            .handle_request(Request::Close(request::handle::Handle { id: 3, handle }))
+            .await;
+
+        assert_eq!(
+            Some(b"data".to_vec()),
+            object_storage.get_file("/home/test/file.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_close_request_logs_transfer_summary() {
+        let object_storage = Arc::new(MemoryStorage::new());
+
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(build_write_open_request("summary.txt")))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        for id in 2..5 {
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id,
+                    handle: handle.clone(),
+                    offset: 0,
+                    data: bytes::Bytes::from(vec![0; 1000]),
+                }))
+                .await;
+        }
+
+        let log_capture = LogCapture::start();
+
+        sftp_session
+            .handle_request(Request::Close(request::handle::Handle { id: 5, handle }))
+            .await;
+
+        let messages = log_capture.messages();
+
+        let summaries = messages
+            .iter()
+            .filter(|message| message.starts_with("transfer_summary"))
+            .collect::<Vec<&String>>();
+
+        assert_eq!(1, summaries.len());
+        assert!(summaries[0].contains("path=\"/home/test/summary.txt\""));
+        assert!(summaries[0].contains(" bytes_read=0 "));
+        assert!(summaries[0].contains(" bytes_written=3000 "));
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_in_text_mode_stores_data_unchanged() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        let mut open_request = build_write_open_request("text.txt");
+        open_request.open_options.text = true;
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(open_request))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        sftp_session
+            .handle_request(Request::Write(request::write::Write {
+                id: 2,
+                handle: handle.clone(),
+                offset: 0,
+                data: bytes::Bytes::from("line\r\nline\n\x00"),
+            }))
+            .await;
+
+        let log_capture = LogCapture::start();
+
+        sftp_session
+            .handle_request(Request::Close(request::handle::Handle { id: 3, handle }))
+            .await;
+
+        assert_eq!(
+            Some(b"line\r\nline\n\x00".to_vec()),
+            object_storage.get_file("/home/test/text.txt")
+        );
+        assert!(log_capture
+            .messages()
+            .iter()
+            .any(|message| message.starts_with("transfer_summary")
+                && message.ends_with(" mode=text")));
+    }
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_lists_dot_entries_first() {
+        let object_storage = Arc::new(MemoryStorage::new().with_page_size(1));
+        object_storage.put_file("/home/test/file0.txt", b"data");
+        object_storage.put_file("/home/test/file1.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage).with_dot_entries(true);
+        let handle = open_dir(&sftp_session, "/home/test").await;
+
+        match sftp_session
+            .handle_request(Request::Readdir(request::handle::Handle {
+                id: 2,
+                handle: handle.clone(),
+            }))
+            .await
+        {
+            Response::Name(name) => {
+                let file_names: Vec<&str> = name
+                    .files
+                    .iter()
+                    .map(|file| file.file_name.as_str())
+                    .collect();
+                assert_eq!(vec![".", "..", "file0.txt"], file_names);
+                assert_eq!(Some(0o40777), name.files[0].file_attributes.permissions);
+                assert_eq!(Some(0o40777), name.files[1].file_attributes.permissions);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        assert_eq!(
+            vec!["file1.txt"],
+            read_dir_file_names(&sftp_session, &handle).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_lists_dot_entries_for_empty_dir() {
+        let sftp_session =
+            create_sftp_session(Arc::new(MemoryStorage::new())).with_dot_entries(true);
+
+        assert_eq!(
+            vec![".", ".."],
+            read_dir_names(&sftp_session, "/home/test").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_omits_dot_entries_without_dot_entries() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+        object_storage.put_file("/home/test/./hidden.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage);
+
+        assert_eq!(
+            vec!["file.txt"],
+            read_dir_names(&sftp_session, "/home/test").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_ends_single_page_listing_without_backend_call() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        assert_eq!(
+            vec!["file.txt"],
+            read_dir_names(&sftp_session, "/home/test").await
+        );
+        assert_eq!(1, object_storage.read_dir_calls());
+    }
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_reads_each_page_once() {
+        let object_storage = Arc::new(MemoryStorage::new().with_page_size(2));
+
+        for i in 0..3 {
+            object_storage.put_file(&format!("/home/test/file{}.txt", i), b"data");
+        }
+
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        assert_eq!(
+            vec!["file0.txt", "file1.txt", "file2.txt"],
+            read_dir_names(&sftp_session, "/home/test").await
+        );
+        assert_eq!(2, object_storage.read_dir_calls());
+    }
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_truncates_at_max_dir_entries() {
+        let object_storage = Arc::new(MemoryStorage::new().with_page_size(2));
+
+        for i in 0..5 {
+            object_storage.put_file(&format!("/home/test/file{}.txt", i), b"data");
+        }
+
+        let sftp_session =
+            create_sftp_session(object_storage).with_max_dir_entries(3, DirEntriesPolicy::Truncate);
+
+        assert_eq!(
+            vec!["file0.txt", "file1.txt", "file2.txt"],
+            read_dir_names(&sftp_session, "/home/test").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_fails_past_max_dir_entries() {
+        let object_storage = Arc::new(MemoryStorage::new().with_page_size(2));
+
+        for i in 0..5 {
+            object_storage.put_file(&format!("/home/test/file{}.txt", i), b"data");
+        }
+
+        let sftp_session =
+            create_sftp_session(object_storage).with_max_dir_entries(3, DirEntriesPolicy::Fail);
+        let handle = open_dir(&sftp_session, "/home/test").await;
+
+        assert_eq!(
+            vec!["file0.txt", "file1.txt"],
+            read_dir_file_names(&sftp_session, &handle).await
+        );
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 2,
+                status_code: response::status::StatusCode::Failure,
+                error_message: String::from("Directory has more than 3 entries."),
+            }),
+            sftp_session
+                .handle_request(Request::Readdir(request::handle::Handle { id: 2, handle }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_lists_only_visible_prefixes() {
+        let object_storage = Arc::new(MemoryStorage::new().with_page_size(1));
+        object_storage.put_file("/home/test/file.txt", b"data");
+        object_storage.put_file("/home/other/file.txt", b"data");
+        object_storage.put_file("/private/file.txt", b"data");
+        object_storage.put_file("/shared/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage)
+            .with_visible_prefixes(Some(vec![String::from("/shared")]));
+
+        assert_eq!(
+            vec!["home", "shared"],
+            read_dir_names(&sftp_session, "/").await
+        );
+        assert_eq!(vec!["test"], read_dir_names(&sftp_session, "/home").await);
+        assert_eq!(
+            vec!["file.txt"],
+            read_dir_names(&sftp_session, "/shared").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_denies_path_outside_visible_prefixes() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+        object_storage.put_file("/home/other/file.txt", b"data");
+        object_storage.put_file("/shared/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage)
+            .with_visible_prefixes(Some(vec![String::from("/shared")]));
+
+        for path in ["/home/test/file.txt", "/shared/file.txt"] {
+            assert!(matches!(
+                sftp_session
+                    .handle_request(Request::Open(build_read_open_request(path)))
+                    .await,
+                Response::Handle(_)
+            ));
+        }
+
+        assert_eq!(
+            SftpSession::build_permission_denied_response(1),
+            sftp_session
+                .handle_request(Request::Open(build_read_open_request(
+                    "/home/other/file.txt"
+                )))
+                .await
+        );
+        assert_eq!(
+            SftpSession::build_permission_denied_response(1),
+            sftp_session
+                .handle_request(Request::Opendir(request::path::Path {
+                    id: 1,
+                    path: String::from("/home/other"),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_denies_user_without_read_permission() {
+        let sftp_session =
+            create_sftp_session(Arc::new(MemoryStorage::new())).with_permissions(UserPermissions {
+                read: false,
+                write: true,
+            });
+
+        assert_eq!(
+            SftpSession::build_permission_denied_response(1),
+            sftp_session
+                .handle_request(Request::Opendir(request::path::Path {
+                    id: 1,
+                    path: String::from("."),
+                }))
+                .await
+        );
+    }
+
+    fn build_presign_download_request(path: &str) -> Request {
+        Request::Extended(request::extended::Extended {
+            id: 1,
+            extended_request: request::extended::ExtendedRequest::PresignDownload(
+                request::extended::PresignDownload {
+                    path: String::from(path),
+                },
+            ),
+        })
+    }
+
+    fn build_space_available_request(path: &str) -> Request {
+        Request::Extended(request::extended::Extended {
+            id: 1,
+            extended_request: request::extended::ExtendedRequest::SpaceAvailable(
+                request::extended::SpaceAvailable {
+                    path: String::from(path),
+                },
+            ),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_copy_data_request_copies_whole_file() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/source.txt", b"0123456789");
+
+        // A small read length makes the copy take several reads.
+        let sftp_session = create_sftp_session(object_storage.clone()).with_limits(Limits {
+            max_read_length: 4,
+            max_write_length: 4,
+        });
+
+        let read_handle = open_file(&sftp_session, build_read_open_request("source.txt")).await;
+        let write_handle = open_file(&sftp_session, build_write_open_request("copy.txt")).await;
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 3,
+                status_code: response::status::StatusCode::Ok,
+                error_message: String::new(),
+            }),
+            sftp_session
+                .handle_request(build_copy_data_request(&read_handle, 0, 0, &write_handle))
+                .await
+        );
+
+        sftp_session
+            .handle_request(Request::Close(request::handle::Handle {
+                id: 4,
+                handle: write_handle,
+            }))
+            .await;
+
+        assert_eq!(
+            object_storage.get_file("/home/test/source.txt"),
+            object_storage.get_file("/home/test/copy.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_copy_data_request_copies_requested_length() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/source.txt", b"0123456789");
+
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        let read_handle = open_file(&sftp_session, build_read_open_request("source.txt")).await;
+        let write_handle = open_file(&sftp_session, build_write_open_request("copy.txt")).await;
+
+        sftp_session
+            .handle_request(build_copy_data_request(&read_handle, 0, 3, &write_handle))
+            .await;
+        sftp_session
+            .handle_request(Request::Close(request::handle::Handle {
+                id: 4,
+                handle: write_handle,
+            }))
+            .await;
+
+        assert_eq!(
+            Some(b"012".to_vec()),
+            object_storage.get_file("/home/test/copy.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_copy_data_request_rejects_offset_past_position() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/source.txt", b"0123456789");
+
+        let sftp_session = create_sftp_session(object_storage);
+
+        let read_handle = open_file(&sftp_session, build_read_open_request("source.txt")).await;
+        let write_handle = open_file(&sftp_session, build_write_open_request("copy.txt")).await;
+
+        match sftp_session
+            .handle_request(build_copy_data_request(&read_handle, 5, 0, &write_handle))
+            .await
+        {
+            Response::Status(status) => {
+                assert_eq!(response::status::StatusCode::Failure, status.status_code)
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_copy_data_request_denies_read_only_user() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/source.txt", b"0123456789");
+
+        let sftp_session =
+            create_sftp_session(object_storage).with_permissions(UserPermissions::read_only());
+
+        let read_handle = open_file(&sftp_session, build_read_open_request("source.txt")).await;
+
+        assert_eq!(
+            SftpSession::build_permission_denied_response(3),
+            sftp_session
+                .handle_request(build_copy_data_request(&read_handle, 0, 0, &read_handle))
+                .await
+        );
+    }
+
+    fn build_write_open_request(filename: &str) -> request::open::Open {
+        request::open::Open {
+            id: 1,
+            filename: String::from(filename),
+            file_attributes: FileAttributes::default(),
+            open_options: request::open::OpenOptions {
+                read: false,
+                write: true,
+                create: true,
+                create_new_only: false,
+                append: false,
+                truncate: true,
+                text: false,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_after_remove_returns_no_such_file() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage);
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(request::open::Open {
+                id: 1,
+                filename: String::from("file.txt"),
+                file_attributes: FileAttributes::default(),
+                open_options: request::open::OpenOptions {
+                    read: true,
+                    write: false,
+                    create: false,
+                    create_new_only: false,
+                    append: false,
+                    truncate: false,
+                    text: false,
+                },
+            }))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        sftp_session
+            .handle_request(Request::Remove(request::path::Path {
+                id: 2,
+                path: String::from("file.txt"),
+            }))
+            .await;
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 3,
+                status_code: response::status::StatusCode::NoSuchFile,
+                error_message: String::from("No such file."),
+            }),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 3,
+                    handle,
+                    offset: 0,
+                    len: 4,
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_fsync_request_flushes_written_data() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(build_write_open_request("file.txt")))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        sftp_session
+            .handle_request(Request::Write(request::write::Write {
+                id: 2,
+                handle: handle.clone(),
+                offset: 0,
+                data: bytes::Bytes::from("data"),
+            }))
+            .await;
+
+        assert_eq!(None, object_storage.get_file("/home/test/file.txt"));
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 3,
+                status_code: response::status::StatusCode::Ok,
+                error_message: String::from("File synced."),
+            }),
+            sftp_session
+                .handle_request(Request::Extended(request::extended::Extended {
+                    id: 3,
+                    extended_request: request::extended::ExtendedRequest::Fsync(
+                        request::extended::Fsync { handle },
+                    ),
+                }))
+                .await
+        );
+
+        assert_eq!(
+            Some(b"data".to_vec()),
+            object_storage.get_file("/home/test/file.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_stat_request_includes_version_id_for_versioned_file() {
+        let object_storage = Arc::new(MemoryStorage::new().with_versioning());
+        object_storage.put_file("/home/test/file.txt", b"first");
+        object_storage.put_file("/home/test/file.txt", b"second");
+
+        let sftp_session = create_sftp_session(object_storage);
+
+        let file_attributes = match sftp_session
+            .handle_request(Request::Stat(request::path::Path {
+                id: 1,
+                path: String::from("file.txt"),
+            }))
+            .await
+        {
+            Response::Attrs(attrs) => attrs.file_attributes,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        assert_eq!(
+            Some(&String::from("2")),
+            file_attributes.extended.get(VERSION_ID_ATTRIBUTE)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_open_version_request_reads_prior_version() {
+        let object_storage = Arc::new(MemoryStorage::new().with_versioning());
+        object_storage.put_file("/home/test/file.txt", b"first");
+        object_storage.put_file("/home/test/file.txt", b"second");
+
+        let sftp_session = create_sftp_session(object_storage);
+
+        let handle = match sftp_session
+            .handle_request(Request::Extended(request::extended::Extended {
+                id: 1,
+                extended_request: request::extended::ExtendedRequest::OpenVersion(
+                    request::extended::OpenVersion {
+                        path: String::from("file.txt"),
+                        version_id: String::from("1"),
+                    },
+                ),
+            }))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        assert_eq!(
+            Response::Data(response::data::Data {
+                id: 2,
+                data: b"first".to_vec(),
+            }),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 2,
+                    handle,
+                    offset: 0,
+                    len: 1024,
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_open_version_request_with_missing_version() {
+        let object_storage = Arc::new(MemoryStorage::new().with_versioning());
+        object_storage.put_file("/home/test/file.txt", b"first");
+
+        let sftp_session = create_sftp_session(object_storage);
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::NoSuchFile,
+                error_message: String::from("No such file."),
+            }),
+            sftp_session
+                .handle_request(Request::Extended(request::extended::Extended {
+                    id: 1,
+                    extended_request: request::extended::ExtendedRequest::OpenVersion(
+                        request::extended::OpenVersion {
+                            path: String::from("file.txt"),
+                            version_id: String::from("2"),
+                        },
+                    ),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_presign_download_request_returns_url() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage);
+
+        assert_eq!(
+            Response::PresignedUrl(response::presigned_url::PresignedUrl {
+                id: 1,
+                url: String::from("memory:///home/test/file.txt"),
+            }),
+            sftp_session
+                .handle_request(build_presign_download_request("file.txt"))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_presign_download_request_for_hidden_path() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/private/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage)
+            .with_visible_prefixes(Some(vec![String::from("/shared")]));
+
+        assert_eq!(
+            SftpSession::build_permission_denied_response(1),
+            sftp_session
+                .handle_request(build_presign_download_request("/private/file.txt"))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_space_available_request_reports_remaining_quota() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", &[0; 300]);
+        object_storage.put_file("/home/test/dir/file.txt", &[0; 200]);
+        object_storage.put_file("/home/other/file.txt", &[0; 400]);
+
+        let sftp_session = create_sftp_session(object_storage).with_quota(Some(1000));
+
+        match sftp_session
+            .handle_request(build_space_available_request("."))
+            .await
+        {
+            Response::SpaceAvailable(space_available) => {
+                assert_eq!(1000, space_available.bytes_available_to_user);
+                assert_eq!(500, space_available.unused_bytes_available_to_user);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_space_available_request_without_quota() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        match sftp_session
+            .handle_request(build_space_available_request("."))
+            .await
+        {
+            Response::SpaceAvailable(space_available) => {
+                assert_eq!(u64::MAX, space_available.bytes_available_to_user);
+                assert_eq!(u64::MAX, space_available.unused_bytes_available_to_user);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_before_init_is_rejected() {
+        let sftp_session = SftpSession::new(Arc::new(MemoryStorage::new()), String::from("test"));
+
+        assert_eq!(
+            SftpSession::build_uninitialized_response(),
+            sftp_session
+                .handle_request(Request::Realpath(request::path::Path {
+                    id: 1,
+                    path: String::from("."),
+                }))
+                .await
+        );
+        assert!(!sftp_session.is_initialized());
+    }
+
+    #[tokio::test]
+    async fn test_handle_cancellable_request_aborts_slow_read_when_cancelled() {
+        let object_storage =
+            Arc::new(MemoryStorage::new().with_read_delay(Duration::from_secs(60)));
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage);
+        let handle = match sftp_session
+            .handle_request(Request::Open(build_read_open_request("file.txt")))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        let cancellation_token = CancellationToken::new();
+        let channel_close = cancellation_token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            channel_close.cancel();
+        });
+
+        let response = tokio::time::timeout(
+            Duration::from_secs(5),
+            sftp_session.handle_cancellable_request(
+                Request::Read(request::read::Read {
+                    id: 2,
+                    handle,
+                    offset: 0,
+                    len: 4,
+                }),
+                &cancellation_token,
+            ),
+        )
+        .await
+        .expect("cancelled read did not abort promptly");
+
+        assert_eq!(SftpSession::build_cancelled_response(), response);
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_limits_request_reports_configured_limits() {
+        let sftp_session =
+            create_sftp_session(Arc::new(MemoryStorage::new())).with_limits(Limits {
+                max_read_length: 32768,
+                max_write_length: 65536,
+            });
+
+        let response = sftp_session
+            .handle_request(Request::Extended(request::extended::Extended {
+                id: 1,
+                extended_request: request::extended::ExtendedRequest::Limits,
+            }))
+            .await;
+
+        let limits_bytes = &mut Bytes::from(&response);
+        assert_eq!(37, limits_bytes.get_u32()); // length
+        assert_eq!(201, limits_bytes.get_u8()); // SSH_FXP_EXTENDED_REPLY
+        assert_eq!(1, limits_bytes.get_u32()); // id
+        assert_eq!(65536 + 1024, limits_bytes.get_u64()); // max packet length
+        assert_eq!(32768, limits_bytes.get_u64()); // max read length
+        assert_eq!(65536, limits_bytes.get_u64()); // max write length
+        assert_eq!(0, limits_bytes.get_u64()); // max open handles
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_home_directory_request_returns_home() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        for username in ["", "test"] {
+            assert_eq!(
+                response::name::NameResponse::new(1)
+                    .with_file(response::name::File {
+                        file_name: String::from("/home/test"),
+                        file_attributes: SftpSession::build_dir_attributes(),
+                    })
+                    .build(),
+                sftp_session
+                    .handle_request(Request::Extended(request::extended::Extended {
+                        id: 1,
+                        extended_request: request::extended::ExtendedRequest::HomeDirectory(
+                            request::extended::HomeDirectory {
+                                username: String::from(username),
+                            },
+                        ),
+                    }))
+                    .await
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_home_directory_request_denies_other_users() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        assert_eq!(
+            SftpSession::build_permission_denied_response(1),
+            sftp_session
+                .handle_request(Request::Extended(request::extended::Extended {
+                    id: 1,
+                    extended_request: request::extended::ExtendedRequest::HomeDirectory(
+                        request::extended::HomeDirectory {
+                            username: String::from("other"),
+                        },
+                    ),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_limits_data_to_max_read_length() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage).with_limits(Limits {
+            max_read_length: 2,
+            max_write_length: 2,
+        });
+        let handle = match sftp_session
+            .handle_request(Request::Open(build_read_open_request("file.txt")))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        assert_eq!(
+            Response::Data(response::data::Data {
+                id: 2,
+                data: b"da".to_vec(),
+            }),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 2,
+                    handle,
+                    offset: 0,
+                    len: 1024,
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_returns_streamed_data() {
+        let object_storage = Arc::new(MemoryStorage::new().with_streamed_reads(2));
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage);
+        let handle = match sftp_session
+            .handle_request(Request::Open(build_read_open_request("file.txt")))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        let data_stream = match sftp_session
+            .handle_request(Request::Read(request::read::Read {
+                id: 2,
+                handle,
+                offset: 0,
+                len: 1024,
+            }))
+            .await
+        {
+            Response::DataStream(data_stream) => data_stream,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        let chunks: Vec<Bytes> = data_stream.chunks.try_collect().await.unwrap();
+
+        assert_eq!(2, data_stream.id);
+        assert_eq!(4, data_stream.len);
+        assert_eq!(vec![Bytes::from("da"), Bytes::from("ta")], chunks);
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_past_end_of_file_returns_tail() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"abcdef");
+
+        let sftp_session = create_sftp_session(object_storage);
+        let handle = match sftp_session
+            .handle_request(Request::Open(build_read_open_request("file.txt")))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        assert_eq!(
+            Response::Data(response::data::Data {
+                id: 2,
+                data: b"abcd".to_vec(),
+            }),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 2,
+                    handle: handle.clone(),
+                    offset: 0,
+                    len: 4,
+                }))
+                .await
+        );
+        assert_eq!(
+            Response::Data(response::data::Data {
+                id: 3,
+                data: b"ef".to_vec(),
+            }),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 3,
+                    handle: handle.clone(),
+                    offset: 4,
+                    len: 10,
+                }))
+                .await
+        );
+        assert_eq!(
+            build_eof_response(4),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 4,
+                    handle,
+                    offset: 6,
+                    len: 10,
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_init_request_initializes_session() {
+        let sftp_session = SftpSession::new(Arc::new(MemoryStorage::new()), String::from("test"));
+
+        assert_eq!(
+            Response::Version(response::version::Version {
+                version: 3,
+                extensions: vec![
+                    (String::from("limits@openssh.com"), String::from("1")),
+                    (String::from("presign-download@dray"), String::from("1")),
+                    (String::from("space-available"), String::from("1")),
+                    (String::from("copy-data"), String::from("1")),
+                    (String::from("home-directory"), String::from("1")),
+                ],
+            }),
+            sftp_session
+                .handle_request(Request::Init(request::init::Init { version: 3 }))
+                .await
+        );
+        assert!(sftp_session.is_initialized());
+    }
+
+    #[tokio::test]
+    async fn test_handle_init_request_logs_client_banner_and_version() {
+        let sftp_session = SftpSession::new(Arc::new(MemoryStorage::new()), String::from("test"))
+            .with_client_banner(Some(String::from("SSH-2.0-OpenSSH_8.9")));
+
+        let log_capture = LogCapture::start();
+
+        sftp_session
+            .handle_request(Request::Init(request::init::Init { version: 3 }))
+            .await;
+
+        assert!(log_capture.messages().contains(&String::from(
+            "sftp_init version=3 client=\"SSH-2.0-OpenSSH_8.9\""
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_handle_init_request_negotiates_supported_version() {
+        let sftp_session = SftpSession::new(Arc::new(MemoryStorage::new()), String::from("test"));
+
+        match sftp_session
+            .handle_request(Request::Init(request::init::Init { version: 6 }))
+            .await
+        {
+            Response::Version(version) => assert_eq!(SFTP_VERSION, version.version),
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        assert_eq!(SFTP_VERSION, sftp_session.version());
+    }
+
+    #[tokio::test]
+    async fn test_handle_init_request_accepts_version_3_in_strict_mode() {
+        let sftp_session = SftpSession::new(Arc::new(MemoryStorage::new()), String::from("test"))
+            .with_strict_version(true);
+
+        match sftp_session
+            .handle_request(Request::Init(request::init::Init { version: 3 }))
+            .await
+        {
+            Response::Version(version) => assert_eq!(3, version.version),
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        assert!(sftp_session.is_initialized());
+    }
+
+    #[tokio::test]
+    async fn test_handle_init_request_refuses_other_versions_in_strict_mode() {
+        let sftp_session = SftpSession::new(Arc::new(MemoryStorage::new()), String::from("test"))
+            .with_strict_version(true);
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 0,
+                status_code: response::status::StatusCode::OperationUnsupported,
+                error_message: String::from(
+                    "SFTP version 6 is not supported. Only version 3 is allowed."
+                ),
+            }),
+            sftp_session
+                .handle_request(Request::Init(request::init::Init { version: 6 }))
+                .await
+        );
+        assert!(!sftp_session.is_initialized());
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_on_read_handle_is_denied() {
+        let object_storage = Arc::new(MemoryStorage::new());
+        object_storage.put_file("/home/test/file.txt", b"data");
+
+        let sftp_session = create_sftp_session(object_storage.clone());
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(build_read_open_request("file.txt")))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        assert_eq!(
+            SftpSession::build_permission_denied_response(2),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 2,
+                    handle,
+                    offset: 0,
+                    data: bytes::Bytes::from("other"),
+                }))
+                .await
+        );
+        assert_eq!(
+            Some(b"data".to_vec()),
+            object_storage.get_file("/home/test/file.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_on_write_handle_is_denied() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+
+        let handle = match sftp_session
+            .handle_request(Request::Open(build_write_open_request("file.txt")))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        assert_eq!(
+            SftpSession::build_permission_denied_response(2),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 2,
+                    handle,
+                    offset: 0,
+                    len: 4,
+                }))
+                .await
+        );
+    }
+
+    #[test]
+    fn test_build_internal_error_response_hides_details_from_client() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()));
+        let error = anyhow::anyhow!("Access denied to bucket secret-bucket.");
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 0x01,
+                status_code: response::status::StatusCode::BadMessage,
+                error_message: String::from("Internal server error."),
+            }),
+            sftp_session.build_internal_error_response(0x01, &error)
+        );
+        assert!(SftpSession::format_error_log(&error).contains("secret-bucket"));
+    }
+
+    #[test]
+    fn test_build_storage_error_response_sends_summary() {
+        let error =
+            anyhow::Error::new(Error::ServerError).context("Failed to delete 1 of 2 objects.");
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 0x01,
+                status_code: response::status::StatusCode::Failure,
+                error_message: String::from("Failed to delete 1 of 2 objects."),
+            }),
+            SftpSession::build_storage_error_response(0x01, error).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_build_internal_error_response_with_detailed_verbosity() {
+        let sftp_session = create_sftp_session(Arc::new(MemoryStorage::new()))
+            .with_error_verbosity(ErrorVerbosity::Detailed);
+        let error = anyhow::anyhow!("Access denied to bucket secret-bucket.");
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 0x01,
+                status_code: response::status::StatusCode::BadMessage,
+                error_message: String::from("Access denied to bucket secret-bucket."),
+            }),
+            sftp_session.build_internal_error_response(0x01, &error)
+        );
+    }
+
+    #[test]
+    fn test_handle_mode_from_open_options() {
+        let mut open_options = build_read_open_request("file.txt").open_options;
+        assert_eq!(
+            HandleMode::Read,
+            HandleMode::from_open_options(&open_options)
+        );
+
+        open_options.write = true;
+        assert_eq!(
+            HandleMode::ReadWrite,
+            HandleMode::from_open_options(&open_options)
+        );
+
+        open_options.read = false;
+        assert_eq!(
+            HandleMode::Write,
+            HandleMode::from_open_options(&open_options)
+        );
+    }
+
+    fn build_read_open_request(filename: &str) -> request::open::Open {
+        request::open::Open {
+            id: 1,
+            filename: String::from(filename),
+            file_attributes: FileAttributes::default(),
+            open_options: request::open::OpenOptions {
+                read: true,
+                write: false,
+                create: false,
+                create_new_only: false,
+                append: false,
+                truncate: false,
+                text: false,
+            },
+        }
+    }
+
+    fn create_sftp_session(object_storage: Arc<MemoryStorage>) -> SftpSession {
+        let sftp_session = SftpSession::new(object_storage, String::from("test"));
+        sftp_session.is_initialized.store(true, Ordering::SeqCst);
+        sftp_session
+    }
+
+    /// Lists every entry of a directory until the end of the listing.
+    async fn read_dir_names(sftp_session: &SftpSession, path: &str) -> Vec<String> {
+        let handle = open_dir(sftp_session, path).await;
+        let mut names = Vec::new();
+
+        loop {
+            match sftp_session
+                .handle_request(Request::Readdir(request::handle::Handle {
+                    id: 2,
+                    handle: handle.clone(),
+                }))
+                .await
+            {
+                Response::Name(name) => {
+                    names.extend(name.files.into_iter().map(|file| file.file_name))
+                }
+                response if response == build_eof_response(2) => return names,
+                response => panic!("Unexpected response: {:?}", response),
+            }
+        }
+    }
+
+    async fn open_file(sftp_session: &SftpSession, open_request: request::open::Open) -> String {
+        match sftp_session
+            .handle_request(Request::Open(open_request))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+
+    fn build_copy_data_request(
+        read_handle: &str,
+        read_offset: u64,
+        read_length: u64,
+        write_handle: &str,
+    ) -> Request {
+        Request::Extended(request::extended::Extended {
+            id: 3,
+            extended_request: request::extended::ExtendedRequest::CopyData(
+                request::extended::CopyData {
+                    read_handle: String::from(read_handle),
+                    read_offset,
+                    read_length,
+                    write_handle: String::from(write_handle),
+                    write_offset: 0,
+                },
+            ),
+        })
+    }
+
+    async fn open_dir(sftp_session: &SftpSession, path: &str) -> String {
+        match sftp_session
+            .handle_request(Request::Opendir(request::path::Path {
+                id: 1,
+                path: String::from(path),
+            }))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+
+    async fn read_dir_file_names(sftp_session: &SftpSession, handle: &str) -> Vec<String> {
+        match sftp_session
+            .handle_request(Request::Readdir(request::handle::Handle {
+                id: 3,
+                handle: String::from(handle),
+            }))
+            .await
+        {
+            Response::Name(name) => name.files.into_iter().map(|file| file.file_name).collect(),
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+
+    fn build_eof_response(id: u32) -> Response {
+        Response::Status(response::status::Status {
+            id,
+            status_code: response::status::StatusCode::Eof,
+            error_message: String::from("End of file."),
+        })
+    }
+}
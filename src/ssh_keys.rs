@@ -1,9 +1,66 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tokio::sync::RwLock;
+
+use crate::storage::Storage;
+
+/// Caches the authorized key fingerprints of each user, so storage is not queried
+/// on every authentication attempt.
+///
+/// The cache is shared between SSH sessions. Entries expire after the TTL, so a
+/// revoked key stops being accepted without an operator step. Clearing the cache
+/// causes the next authentication attempt for each user to reload their keys from
+/// storage right away.
+pub struct AuthorizedKeysCache {
+    ttl: Duration,
+    fingerprints: RwLock<HashMap<String, (Instant, Vec<String>)>>,
+}
+
+impl AuthorizedKeysCache {
+    pub fn new(ttl: Duration) -> AuthorizedKeysCache {
+        AuthorizedKeysCache {
+            ttl,
+            fingerprints: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn get_authorized_keys_fingerprints(
+        &self,
+        object_storage: &dyn Storage,
+        user: &str,
+    ) -> Result<Vec<String>> {
+        if let Some((loaded_at, fingerprints)) = self.fingerprints.read().await.get(user) {
+            if loaded_at.elapsed() < self.ttl {
+                return Ok(fingerprints.clone());
+            }
+        }
+
+        let fingerprints = object_storage
+            .get_authorized_keys_fingerprints(user)
+            .await?;
+
+        let mut cached_fingerprints = self.fingerprints.write().await;
+        cached_fingerprints.retain(|_, (loaded_at, _)| loaded_at.elapsed() < self.ttl);
+
+        if !self.ttl.is_zero() {
+            cached_fingerprints.insert(user.to_owned(), (Instant::now(), fingerprints.clone()));
+        }
+
+        Ok(fingerprints)
+    }
+
+    pub async fn clear(&self) {
+        self.fingerprints.write().await.clear();
+    }
+}
+
 pub fn parse_authorized_keys(authorized_keys: &str) -> Vec<String> {
     authorized_keys
         .lines()
-        .into_iter()
         .filter(|line| !line.is_empty())
-        .map(|line| {
+        .filter_map(|line| {
             let mut pieces = line.split_whitespace();
 
             match (pieces.next(), pieces.next()) {
@@ -12,7 +69,6 @@ pub fn parse_authorized_keys(authorized_keys: &str) -> Vec<String> {
                 _ => None,
             }
         })
-        .flatten()
         .map(|key| key.fingerprint())
         .collect()
 }
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result};
+use std::future::Future;
+use std::sync::Mutex;
+
+/// The operation that backend calls made outside of an SFTP request, such as
+/// the startup health check, are counted under.
+pub const OTHER_OPERATION: &str = "other";
+
+tokio::task_local! {
+    static OPERATION: &'static str;
+}
+
+/// Runs a future with backend calls counted under the given SFTP operation.
+pub async fn with_operation<F: Future>(operation: &'static str, future: F) -> F::Output {
+    OPERATION.scope(operation, future).await
+}
+
+/// Returns the SFTP operation of the request being handled by the current
+/// task.
+pub fn current_operation() -> &'static str {
+    OPERATION
+        .try_with(|operation| *operation)
+        .unwrap_or(OTHER_OPERATION)
+}
+
+/// The kinds of backend API calls, which are priced differently by S3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BackendCall {
+    Get,
+    Put,
+    Head,
+    List,
+    Delete,
+}
+
+impl BackendCall {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BackendCall::Get => "GET",
+            BackendCall::Put => "PUT",
+            BackendCall::Head => "HEAD",
+            BackendCall::List => "LIST",
+            BackendCall::Delete => "DELETE",
+        }
+    }
+}
+
+/// Counts the backend calls made for each SFTP operation type, which are
+/// logged as a single line of `key=value` fields when the session ends so
+/// client behavior can be tied to backend cost.
+#[derive(Debug, Default)]
+pub struct BackendCallCounts {
+    counts: Mutex<BTreeMap<(&'static str, BackendCall), u64>>,
+}
+
+impl BackendCallCounts {
+    pub fn new() -> BackendCallCounts {
+        BackendCallCounts::default()
+    }
+
+    /// Counts a call under the SFTP operation of the current task.
+    pub fn record(&self, call: BackendCall) {
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .entry((current_operation(), call))
+            .or_default() += 1;
+    }
+
+    #[allow(dead_code)]
+    pub fn get(&self, operation: &str, call: BackendCall) -> u64 {
+        self.counts
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|((counted_operation, counted_call), _)| {
+                *counted_operation == operation && *counted_call == call
+            })
+            .map(|(_, count)| *count)
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.lock().unwrap().is_empty()
+    }
+}
+
+impl Display for BackendCallCounts {
+    fn fmt(&self, formatter: &mut Formatter) -> Result {
+        write!(formatter, "backend_calls")?;
+
+        for ((operation, call), count) in self.counts.lock().unwrap().iter() {
+            write!(formatter, " {}.{}={}", operation, call.as_str(), count)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_counts_calls_under_current_operation() {
+        let backend_calls = BackendCallCounts::new();
+
+        with_operation("read", async {
+            backend_calls.record(BackendCall::Get);
+            backend_calls.record(BackendCall::Get);
+        })
+        .await;
+        with_operation("open", async { backend_calls.record(BackendCall::Head) }).await;
+        backend_calls.record(BackendCall::List);
+
+        assert_eq!(2, backend_calls.get("read", BackendCall::Get));
+        assert_eq!(1, backend_calls.get("open", BackendCall::Head));
+        assert_eq!(1, backend_calls.get(OTHER_OPERATION, BackendCall::List));
+        assert_eq!(0, backend_calls.get("open", BackendCall::Get));
+    }
+
+    #[tokio::test]
+    async fn test_display_formats_backend_calls() {
+        let backend_calls = BackendCallCounts::new();
+
+        with_operation("read", async {
+            backend_calls.record(BackendCall::Get);
+            backend_calls.record(BackendCall::Get);
+        })
+        .await;
+        with_operation("open", async {
+            backend_calls.record(BackendCall::Head);
+            backend_calls.record(BackendCall::Get);
+        })
+        .await;
+
+        assert_eq!(
+            "backend_calls open.GET=1 open.HEAD=1 read.GET=2",
+            backend_calls.to_string()
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use chrono::{DateTime, Utc};
+use log::error;
+use rusoto_core::request::{
+    DispatchSignedRequest, DispatchSignedRequestFuture, HttpDispatchError, HttpResponse,
+};
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::ByteStream;
+use std::time::Duration;
+
+/// The error code S3 returns when a request was signed too long before or
+/// after the time S3 received it.
+const REQUEST_TIME_TOO_SKEWED: &[u8] = b"<Code>RequestTimeTooSkewed</Code>";
+
+/// Dispatches S3 requests and turns the rejections caused by a skewed system
+/// clock into an error that says so, instead of a generic access denied.
+///
+/// Requests are signed with the local time before they are dispatched, so they
+/// cannot be retried with a corrected time. The clock has to be fixed instead.
+pub struct ClockSkewDetectingDispatcher<D> {
+    dispatcher: D,
+}
+
+impl<D> ClockSkewDetectingDispatcher<D> {
+    pub fn new(dispatcher: D) -> ClockSkewDetectingDispatcher<D> {
+        ClockSkewDetectingDispatcher { dispatcher }
+    }
+}
+
+impl<D: DispatchSignedRequest + Send + Sync + 'static> DispatchSignedRequest
+    for ClockSkewDetectingDispatcher<D>
+{
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let response = self.dispatcher.dispatch(request, timeout);
+
+        Box::pin(async move {
+            let mut response = response.await?;
+
+            // S3 reports a skewed clock as 403 Forbidden, whose small error body
+            // is buffered to find the code.
+            if response.status != hyper::StatusCode::FORBIDDEN {
+                return Ok(response);
+            }
+
+            let buffered_response = response.buffer().await?;
+
+            if !contains(&buffered_response.body, REQUEST_TIME_TOO_SKEWED) {
+                return Ok(HttpResponse {
+                    status: buffered_response.status,
+                    body: ByteStream::from(buffered_response.body.to_vec()),
+                    headers: buffered_response.headers,
+                });
+            }
+
+            let server_time = buffered_response
+                .headers
+                .get("date")
+                .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+                .map(|date| date.with_timezone(&Utc));
+
+            let message = build_clock_skew_message(Utc::now(), server_time);
+            error!("{}", message);
+
+            Err(HttpDispatchError::new(message))
+        })
+    }
+}
+
+fn build_clock_skew_message(
+    local_time: DateTime<Utc>,
+    server_time: Option<DateTime<Utc>>,
+) -> String {
+    let skew = match server_time {
+        Some(server_time) => {
+            let seconds = (local_time - server_time).num_seconds();
+            let direction = match seconds < 0 {
+                true => "behind",
+                false => "ahead of",
+            };
+
+            format!(" is {} seconds {} S3", seconds.abs(), direction)
+        }
+        None => String::from(" differs too much from S3"),
+    };
+
+    format!(
+        "S3 rejected a request because the system clock{}. Synchronize the clock, such as with NTP.",
+        skew
+    )
+}
+
+fn contains(body: &[u8], pattern: &[u8]) -> bool {
+    body.windows(pattern.len()).any(|window| window == pattern)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use chrono::TimeZone;
+    use rusoto_core::credential::StaticProvider;
+    use rusoto_core::{Region, RusotoError};
+    use rusoto_s3::{HeadBucketRequest, S3Client, S3};
+
+    /// Rejects every request with 403 Forbidden and the given error body.
+    struct ForbiddenDispatcher {
+        body: &'static str,
+    }
+
+    impl DispatchSignedRequest for ForbiddenDispatcher {
+        fn dispatch(
+            &self,
+            _request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let body = self.body;
+
+            Box::pin(async move {
+                let mut headers = hyper::HeaderMap::<String>::default();
+                headers.insert("date", String::from("Mon, 01 Jan 2001 00:00:00 GMT"));
+
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::FORBIDDEN,
+                    body: ByteStream::from(body.as_bytes().to_vec()),
+                    headers,
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_reports_request_time_too_skewed_as_clock_skew() {
+        let s3_client = S3Client::new_with(
+            ClockSkewDetectingDispatcher::new(ForbiddenDispatcher {
+                body: "<Error><Code>RequestTimeTooSkewed</Code>\
+                       <Message>The difference between the request time and the current \
+                       time is too large.</Message></Error>",
+            }),
+            StaticProvider::new_minimal(String::from("key"), String::from("secret")),
+            Region::UsEast1,
+        );
+
+        let error = s3_client
+            .head_bucket(HeadBucketRequest {
+                bucket: String::from("bucket"),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+
+        match error {
+            RusotoError::HttpDispatch(error) => assert!(error
+                .to_string()
+                .starts_with("S3 rejected a request because the system clock is ")),
+            error => panic!("Unexpected error: {:?}", error),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_passes_through_other_forbidden_responses() {
+        let s3_client = S3Client::new_with(
+            ClockSkewDetectingDispatcher::new(ForbiddenDispatcher {
+                body: "<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>",
+            }),
+            StaticProvider::new_minimal(String::from("key"), String::from("secret")),
+            Region::UsEast1,
+        );
+
+        let error = s3_client
+            .head_bucket(HeadBucketRequest {
+                bucket: String::from("bucket"),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+
+        match error {
+            RusotoError::Unknown(response) => {
+                assert_eq!(403, response.status.as_u16());
+                assert!(contains(&response.body, b"<Code>AccessDenied</Code>"));
+            }
+            error => panic!("Unexpected error: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_build_clock_skew_message_reports_skew() {
+        let server_time = Utc.timestamp(978307200, 0);
+
+        assert_eq!(
+            "S3 rejected a request because the system clock is 900 seconds ahead of S3. \
+             Synchronize the clock, such as with NTP.",
+            build_clock_skew_message(Utc.timestamp(978307200 + 900, 0), Some(server_time))
+        );
+        assert_eq!(
+            "S3 rejected a request because the system clock is 60 seconds behind S3. \
+             Synchronize the clock, such as with NTP.",
+            build_clock_skew_message(Utc.timestamp(978307200 - 60, 0), Some(server_time))
+        );
+    }
+
+    #[test]
+    fn test_build_clock_skew_message_without_server_time() {
+        assert_eq!(
+            "S3 rejected a request because the system clock differs too much from S3. \
+             Synchronize the clock, such as with NTP.",
+            build_clock_skew_message(Utc::now(), None)
+        );
+    }
+}
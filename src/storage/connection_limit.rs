@@ -0,0 +1,102 @@
+use rusoto_core::request::{DispatchSignedRequest, DispatchSignedRequestFuture, HttpDispatchError};
+use rusoto_core::signature::SignedRequest;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// Dispatches S3 requests while limiting how many are waiting on a response at
+/// once, so a burst of SFTP sessions cannot exhaust file descriptors by opening
+/// an unbounded number of connections.
+pub struct ConnectionLimitedDispatcher<D> {
+    dispatcher: Arc<D>,
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl<D> ConnectionLimitedDispatcher<D> {
+    pub fn new(dispatcher: D, max_connections: Option<usize>) -> ConnectionLimitedDispatcher<D> {
+        ConnectionLimitedDispatcher {
+            dispatcher: Arc::new(dispatcher),
+            permits: max_connections
+                .map(|max_connections| Arc::new(Semaphore::new(max_connections))),
+        }
+    }
+
+    /// Returns the number of requests that can currently be dispatched without
+    /// waiting, or `None` if requests are not limited.
+    #[allow(dead_code)]
+    pub fn available_connections(&self) -> Option<usize> {
+        self.permits
+            .as_ref()
+            .map(|permits| permits.available_permits())
+    }
+}
+
+impl<D: DispatchSignedRequest + Send + Sync + 'static> DispatchSignedRequest
+    for ConnectionLimitedDispatcher<D>
+{
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let dispatcher = self.dispatcher.clone();
+        let permits = self.permits.clone();
+
+        Box::pin(async move {
+            let _permit = match permits {
+                Some(permits) => Some(
+                    permits
+                        .acquire_owned()
+                        .await
+                        .map_err(|error| HttpDispatchError::new(error.to_string()))?,
+                ),
+                None => None,
+            };
+
+            dispatcher.dispatch(request, timeout).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rusoto_core::Region;
+
+    struct FailingDispatcher;
+
+    impl DispatchSignedRequest for FailingDispatcher {
+        fn dispatch(
+            &self,
+            _request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            Box::pin(async { Err(HttpDispatchError::new(String::from("failed"))) })
+        }
+    }
+
+    #[test]
+    fn test_new_limits_connections_to_max_connections() {
+        let dispatcher = ConnectionLimitedDispatcher::new(FailingDispatcher, Some(10));
+
+        assert_eq!(Some(10), dispatcher.available_connections());
+    }
+
+    #[test]
+    fn test_new_without_max_connections_is_unlimited() {
+        let dispatcher = ConnectionLimitedDispatcher::new(FailingDispatcher, None);
+
+        assert_eq!(None, dispatcher.available_connections());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_releases_connection_after_response() {
+        let dispatcher = ConnectionLimitedDispatcher::new(FailingDispatcher, Some(1));
+
+        let request = SignedRequest::new("GET", "s3", &Region::UsEast1, "/");
+        assert!(dispatcher.dispatch(request, None).await.is_err());
+
+        assert_eq!(Some(1), dispatcher.available_connections());
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// How long an interrupted listing can be resumed after its last page was
+/// read.
+const CHECKPOINT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Remembers where each unfinished directory listing left off, so a listing
+/// interrupted by a dropped connection can be resumed from a new SSH session.
+///
+/// Checkpoints are keyed by the handle of the interrupted listing and are
+/// removed once the listing reaches EOF or its handle is closed. Listings that
+/// are never finished or closed, such as those of clients that do not come
+/// back, expire after an hour.
+pub struct DirCheckpoints {
+    ttl: Duration,
+    checkpoints: RwLock<HashMap<String, DirCheckpoint>>,
+}
+
+struct DirCheckpoint {
+    dir_name: String,
+    continuation_token: String,
+    saved_at: Instant,
+}
+
+impl DirCheckpoints {
+    pub fn new() -> DirCheckpoints {
+        DirCheckpoints::with_ttl(CHECKPOINT_TTL)
+    }
+
+    fn with_ttl(ttl: Duration) -> DirCheckpoints {
+        DirCheckpoints {
+            ttl,
+            checkpoints: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn remove_expired(&self, checkpoints: &mut HashMap<String, DirCheckpoint>) {
+        checkpoints.retain(|_, checkpoint| checkpoint.saved_at.elapsed() < self.ttl);
+    }
+
+    /// Records the continuation token for the next page of a listing, or
+    /// forgets the listing if it has reached EOF.
+    pub async fn save(&self, handle: &str, dir_name: &str, continuation_token: Option<String>) {
+        let mut checkpoints = self.checkpoints.write().await;
+        self.remove_expired(&mut checkpoints);
+
+        match continuation_token {
+            Some(continuation_token) => {
+                checkpoints.insert(
+                    handle.to_owned(),
+                    DirCheckpoint {
+                        dir_name: dir_name.to_owned(),
+                        continuation_token,
+                        saved_at: Instant::now(),
+                    },
+                );
+            }
+            None => {
+                checkpoints.remove(handle);
+            }
+        }
+    }
+
+    /// Takes the continuation token of an interrupted listing. A token is only
+    /// returned if the listing was for the same directory.
+    pub async fn take(&self, handle: &str, dir_name: &str) -> Option<String> {
+        let mut checkpoints = self.checkpoints.write().await;
+        self.remove_expired(&mut checkpoints);
+
+        match checkpoints.get(handle) {
+            Some(checkpoint) if checkpoint.dir_name == dir_name => checkpoints
+                .remove(handle)
+                .map(|checkpoint| checkpoint.continuation_token),
+            _ => None,
+        }
+    }
+
+    pub async fn remove(&self, handle: &str) {
+        self.checkpoints.write().await.remove(handle);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_take_returns_saved_continuation_token() {
+        let dir_checkpoints = DirCheckpoints::new();
+
+        dir_checkpoints
+            .save("handle", "/home/test", Some(String::from("token")))
+            .await;
+
+        assert_eq!(
+            Some(String::from("token")),
+            dir_checkpoints.take("handle", "/home/test").await
+        );
+        assert_eq!(None, dir_checkpoints.take("handle", "/home/test").await);
+    }
+
+    #[tokio::test]
+    async fn test_take_ignores_checkpoint_for_other_dir() {
+        let dir_checkpoints = DirCheckpoints::new();
+
+        dir_checkpoints
+            .save("handle", "/home/test", Some(String::from("token")))
+            .await;
+
+        assert_eq!(None, dir_checkpoints.take("handle", "/home/other").await);
+        assert_eq!(
+            Some(String::from("token")),
+            dir_checkpoints.take("handle", "/home/test").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_without_continuation_token_removes_checkpoint() {
+        let dir_checkpoints = DirCheckpoints::new();
+
+        dir_checkpoints
+            .save("handle", "/home/test", Some(String::from("token")))
+            .await;
+        dir_checkpoints.save("handle", "/home/test", None).await;
+
+        assert_eq!(None, dir_checkpoints.take("handle", "/home/test").await);
+    }
+
+    #[tokio::test]
+    async fn test_remove_removes_checkpoint() {
+        let dir_checkpoints = DirCheckpoints::new();
+
+        dir_checkpoints
+            .save("handle", "/home/test", Some(String::from("token")))
+            .await;
+        dir_checkpoints.remove("handle").await;
+
+        assert_eq!(None, dir_checkpoints.take("handle", "/home/test").await);
+    }
+
+    #[tokio::test]
+    async fn test_expired_checkpoints_are_evicted() {
+        let dir_checkpoints = DirCheckpoints::with_ttl(Duration::ZERO);
+
+        dir_checkpoints
+            .save("handle1", "/home/test", Some(String::from("token1")))
+            .await;
+        dir_checkpoints
+            .save("handle2", "/home/test", Some(String::from("token2")))
+            .await;
+
+        assert_eq!(1, dir_checkpoints.checkpoints.read().await.len());
+        assert_eq!(None, dir_checkpoints.take("handle2", "/home/test").await);
+        assert!(dir_checkpoints.checkpoints.read().await.is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+use flate2::write::GzDecoder as GzWriteDecoder;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// The most compressed bytes read from the inner stream at once.
+const COMPRESSED_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Decompresses a gzip stream as it is read, so only the bytes decompressed
+/// from the latest compressed chunk are held in memory.
+pub struct GzipDecoder<R> {
+    inner: R,
+    decoder: GzWriteDecoder<Vec<u8>>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: AsyncRead + Unpin> GzipDecoder<R> {
+    pub fn new(inner: R) -> GzipDecoder<R> {
+        GzipDecoder {
+            inner,
+            decoder: GzWriteDecoder::new(Vec::new()),
+            position: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for GzipDecoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            let decompressed = this.decoder.get_mut();
+
+            if this.position < decompressed.len() {
+                let len = buf.remaining().min(decompressed.len() - this.position);
+                buf.put_slice(&decompressed[this.position..this.position + len]);
+                this.position += len;
+                return Poll::Ready(Ok(()));
+            }
+
+            decompressed.clear();
+            this.position = 0;
+
+            if this.finished {
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut chunk = [0u8; COMPRESSED_CHUNK_SIZE];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Ready(Ok(())) if chunk_buf.filled().is_empty() => {
+                    this.decoder.try_finish()?;
+                    this.finished = true;
+                }
+                Poll::Ready(Ok(())) => this.decoder.write_all(chunk_buf.filled())?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_read_decompresses_gzip_stream() {
+        let data = b"hello world ".repeat(4096);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decompressed = Vec::new();
+        GzipDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .await
+            .unwrap();
+
+        assert_eq!(data, decompressed);
+    }
+
+    #[tokio::test]
+    async fn test_read_fails_for_truncated_gzip_stream() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"hello world").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decompressed = Vec::new();
+        let result = GzipDecoder::new(&compressed[..compressed.len() / 2])
+            .read_to_end(&mut decompressed)
+            .await;
+
+        assert!(result.is_err());
+    }
+}
@@ -1,4 +1,10 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 use tokio::sync::{Mutex, RwLock};
 use uuid::Uuid;
 
@@ -6,6 +12,9 @@ pub struct HandleManager<ReadHandle, WriteHandle, DirHandle> {
     read_handles: RwLock<HashMap<String, Arc<Mutex<ReadHandle>>>>,
     write_handles: RwLock<HashMap<String, Arc<Mutex<WriteHandle>>>>,
     dir_handles: RwLock<HashMap<String, Arc<Mutex<DirHandle>>>>,
+    handle_paths: RwLock<HashMap<String, String>>,
+    invalidated_handles: RwLock<HashSet<String>>,
+    handle_sequence: AtomicU64,
 }
 
 impl<ReadHandle, WriteHandle, DirHandle> HandleManager<ReadHandle, WriteHandle, DirHandle> {
@@ -14,11 +23,14 @@ impl<ReadHandle, WriteHandle, DirHandle> HandleManager<ReadHandle, WriteHandle,
             read_handles: RwLock::new(HashMap::new()),
             write_handles: RwLock::new(HashMap::new()),
             dir_handles: RwLock::new(HashMap::new()),
+            handle_paths: RwLock::new(HashMap::new()),
+            invalidated_handles: RwLock::new(HashSet::new()),
+            handle_sequence: AtomicU64::new(0),
         }
     }
 
     pub async fn create_dir_handle(&self, dir_handle: DirHandle) -> String {
-        let handle_id = generate_handle_id();
+        let handle_id = self.generate_handle_id();
 
         self.dir_handles
             .write()
@@ -28,8 +40,13 @@ impl<ReadHandle, WriteHandle, DirHandle> HandleManager<ReadHandle, WriteHandle,
         handle_id
     }
 
-    pub async fn create_read_handle(&self, read_handle: ReadHandle) -> String {
-        let handle_id = generate_handle_id();
+    pub async fn create_read_handle(&self, path: &str, read_handle: ReadHandle) -> String {
+        let handle_id = self.generate_handle_id();
+
+        self.handle_paths
+            .write()
+            .await
+            .insert(handle_id.clone(), path.to_owned());
 
         self.read_handles
             .write()
@@ -39,8 +56,13 @@ impl<ReadHandle, WriteHandle, DirHandle> HandleManager<ReadHandle, WriteHandle,
         handle_id
     }
 
-    pub async fn create_write_handle(&self, write_handle: WriteHandle) -> String {
-        let handle_id = generate_handle_id();
+    pub async fn create_write_handle(&self, path: &str, write_handle: WriteHandle) -> String {
+        let handle_id = self.generate_handle_id();
+
+        self.handle_paths
+            .write()
+            .await
+            .insert(handle_id.clone(), path.to_owned());
 
         self.write_handles
             .write()
@@ -62,23 +84,55 @@ impl<ReadHandle, WriteHandle, DirHandle> HandleManager<ReadHandle, WriteHandle,
         self.write_handles.write().await.get(handle_id).cloned()
     }
 
-    pub async fn remove_handle(&self, handle: &str) {
-        self.dir_handles.write().await.remove(handle);
-        self.read_handles.write().await.remove(handle);
-        self.write_handles.write().await.remove(handle);
+    /// Closes the read and write handles of a path, and of everything under the
+    /// path if it is a directory, since the object behind them was removed or
+    /// renamed. The handles are remembered as invalidated until closed, so
+    /// requests on them can be distinguished from requests on unknown handles.
+    pub async fn invalidate_path(&self, path: &str) {
+        let dir_prefix = format!("{}/", path.trim_end_matches('/'));
+
+        let invalidated_handles: Vec<String> = self
+            .handle_paths
+            .read()
+            .await
+            .iter()
+            .filter(|(_, handle_path)| *handle_path == path || handle_path.starts_with(&dir_prefix))
+            .map(|(handle, _)| handle.clone())
+            .collect();
+
+        for handle in invalidated_handles {
+            self.read_handles.write().await.remove(&handle);
+            self.write_handles.write().await.remove(&handle);
+            self.handle_paths.write().await.remove(&handle);
+            self.invalidated_handles.write().await.insert(handle);
+        }
+    }
+
+    pub async fn is_invalidated(&self, handle: &str) -> bool {
+        self.invalidated_handles.read().await.contains(handle)
     }
-}
 
-pub trait Handle {
-    fn get_handle_id(&self) -> &str;
+    /// Removes a handle, returning false if the handle was never opened or was
+    /// already removed.
+    pub async fn remove_handle(&self, handle: &str) -> bool {
+        let is_dir_handle = self.dir_handles.write().await.remove(handle).is_some();
+        let is_read_handle = self.read_handles.write().await.remove(handle).is_some();
+        let is_write_handle = self.write_handles.write().await.remove(handle).is_some();
+        self.handle_paths.write().await.remove(handle);
+        let is_invalidated = self.invalidated_handles.write().await.remove(handle);
 
-    fn get_handle_id_string(&self) -> String {
-        self.get_handle_id().to_string()
+        is_dir_handle || is_read_handle || is_write_handle || is_invalidated
     }
-}
 
-fn generate_handle_id() -> String {
-    Uuid::new_v4().to_string()
+    /// Generates a handle id that is unique among the manager's handles, even
+    /// when handles are opened concurrently, by numbering each handle from an
+    /// atomic sequence. A random UUID keeps handles from different managers
+    /// apart, since directory checkpoints are shared between sessions.
+    fn generate_handle_id(&self) -> String {
+        let sequence = self.handle_sequence.fetch_add(1, Ordering::Relaxed);
+
+        format!("{}-{:x}", Uuid::new_v4(), sequence)
+    }
 }
 
 #[cfg(test)]
@@ -101,8 +155,9 @@ mod test {
         let handle_id = handle_manager.create_dir_handle(String::from("dir")).await;
         assert!(handle_manager.get_dir_handle(&handle_id).await.is_some());
 
-        handle_manager.remove_handle(&handle_id).await;
+        assert!(handle_manager.remove_handle(&handle_id).await);
         assert!(handle_manager.get_dir_handle(&handle_id).await.is_none());
+        assert!(!handle_manager.remove_handle(&handle_id).await);
     }
 
     #[tokio::test]
@@ -120,7 +175,7 @@ mod test {
         let handle_manager: HandleManager<String, String, String> = HandleManager::new();
 
         let handle_id = handle_manager
-            .create_read_handle(String::from("read"))
+            .create_read_handle("/file", String::from("read"))
             .await;
 
         let handle = handle_manager.get_read_handle(&handle_id).await.unwrap();
@@ -134,7 +189,7 @@ mod test {
         let handle_manager: HandleManager<String, String, String> = HandleManager::new();
 
         let handle_id = handle_manager
-            .create_read_handle(String::from("read"))
+            .create_read_handle("/file", String::from("read"))
             .await;
         assert!(handle_manager.get_read_handle(&handle_id).await.is_some());
 
@@ -157,7 +212,7 @@ mod test {
         let handle_manager: HandleManager<String, String, String> = HandleManager::new();
 
         let handle_id = handle_manager
-            .create_write_handle(String::from("write"))
+            .create_write_handle("/file", String::from("write"))
             .await;
 
         let handle = handle_manager.get_write_handle(&handle_id).await.unwrap();
@@ -171,7 +226,7 @@ mod test {
         let handle_manager: HandleManager<String, String, String> = HandleManager::new();
 
         let handle_id = handle_manager
-            .create_write_handle(String::from("write"))
+            .create_write_handle("/file", String::from("write"))
             .await;
         assert!(handle_manager.get_write_handle(&handle_id).await.is_some());
 
@@ -201,10 +256,72 @@ mod test {
             .is_none());
     }
 
+    #[tokio::test]
+    async fn test_handle_manager_invalidate_path_removes_handles_for_path() {
+        let handle_manager: HandleManager<String, String, String> = HandleManager::new();
+
+        let read_handle_id = handle_manager
+            .create_read_handle("/dir/file", String::from("read"))
+            .await;
+        let write_handle_id = handle_manager
+            .create_write_handle("/dir/file", String::from("write"))
+            .await;
+
+        handle_manager.invalidate_path("/dir/file").await;
+
+        assert!(handle_manager
+            .get_read_handle(&read_handle_id)
+            .await
+            .is_none());
+        assert!(handle_manager
+            .get_write_handle(&write_handle_id)
+            .await
+            .is_none());
+        assert!(handle_manager.is_invalidated(&read_handle_id).await);
+        assert!(handle_manager.is_invalidated(&write_handle_id).await);
+    }
+
+    #[tokio::test]
+    async fn test_handle_manager_invalidate_path_removes_handles_under_dir() {
+        let handle_manager: HandleManager<String, String, String> = HandleManager::new();
+
+        let nested_handle_id = handle_manager
+            .create_read_handle("/dir/file", String::from("read"))
+            .await;
+        let sibling_handle_id = handle_manager
+            .create_read_handle("/directory", String::from("read"))
+            .await;
+
+        handle_manager.invalidate_path("/dir").await;
+
+        assert!(handle_manager.is_invalidated(&nested_handle_id).await);
+        assert!(!handle_manager.is_invalidated(&sibling_handle_id).await);
+        assert!(handle_manager
+            .get_read_handle(&sibling_handle_id)
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_handle_manager_remove_handle_forgets_invalidated_handle() {
+        let handle_manager: HandleManager<String, String, String> = HandleManager::new();
+
+        let handle_id = handle_manager
+            .create_read_handle("/file", String::from("read"))
+            .await;
+
+        handle_manager.invalidate_path("/file").await;
+        handle_manager.remove_handle(&handle_id).await;
+
+        assert!(!handle_manager.is_invalidated(&handle_id).await);
+    }
+
     #[test]
     fn test_generate_handle_id_creates_uuid() {
-        let handle = generate_handle_id();
+        let handle_manager: HandleManager<String, String, String> = HandleManager::new();
+
+        let handle = handle_manager.generate_handle_id();
 
-        assert_eq!(true, handle.len() > 0);
+        assert!(Uuid::parse_str(&handle[..36]).is_ok());
     }
 }
@@ -0,0 +1,662 @@
+use super::dir_checkpoint::DirCheckpoints;
+use super::handle::HandleManager;
+use super::permissions::UserPermissions;
+use super::DirPage;
+use super::ReadData;
+use super::Storage;
+use super::StorageFactory;
+use super::VERSION_ID_ATTRIBUTE;
+use crate::error::Error;
+use crate::protocol::file_attributes::FileAttributes;
+use crate::protocol::response::name::File;
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Shares a single MemoryStorage between every SSH session, so tests can
+/// inspect what a session stored.
+pub struct MemoryStorageFactory {
+    object_storage: Arc<MemoryStorage>,
+}
+
+impl MemoryStorageFactory {
+    pub fn new(object_storage: Arc<MemoryStorage>) -> MemoryStorageFactory {
+        MemoryStorageFactory { object_storage }
+    }
+}
+
+impl StorageFactory for MemoryStorageFactory {
+    fn create_storage(&self) -> Arc<dyn Storage> {
+        self.object_storage.clone()
+    }
+}
+
+/// An in-memory Storage backend used to exercise the server in tests.
+pub struct MemoryStorage {
+    authorized_keys: Mutex<HashMap<String, Vec<String>>>,
+    user_permissions: Mutex<HashMap<String, UserPermissions>>,
+    visible_prefixes: Mutex<HashMap<String, Vec<String>>>,
+    quotas: Mutex<HashMap<String, u64>>,
+    files: Mutex<BTreeMap<String, Vec<u8>>>,
+    extended_attributes: Mutex<HashMap<String, BTreeMap<String, String>>>,
+    versions: Option<Mutex<HashMap<String, Vec<Vec<u8>>>>>,
+    mtimes: Mutex<HashMap<String, u32>>,
+    handle_manager: HandleManager<ReadHandle, WriteHandle, DirHandle>,
+    dir_checkpoints: DirCheckpoints,
+    page_size: usize,
+    rmdir_require_empty: bool,
+    read_delay: Option<Duration>,
+    read_chunk_length: Option<usize>,
+    read_dir_calls: AtomicUsize,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage {
+            authorized_keys: Mutex::new(HashMap::new()),
+            user_permissions: Mutex::new(HashMap::new()),
+            visible_prefixes: Mutex::new(HashMap::new()),
+            quotas: Mutex::new(HashMap::new()),
+            files: Mutex::new(BTreeMap::new()),
+            extended_attributes: Mutex::new(HashMap::new()),
+            versions: None,
+            mtimes: Mutex::new(HashMap::new()),
+            handle_manager: HandleManager::new(),
+            dir_checkpoints: DirCheckpoints::new(),
+            page_size: usize::MAX,
+            rmdir_require_empty: false,
+            read_delay: None,
+            read_chunk_length: None,
+            read_dir_calls: AtomicUsize::new(0),
+        }
+    }
+
+    /// Limits the number of entries returned by each read_dir call, so
+    /// listings span multiple pages like S3.
+    pub fn with_page_size(mut self, page_size: usize) -> MemoryStorage {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Keeps every version of each file, like a versioned S3 bucket. Version
+    /// ids are the version numbers of a file, starting from 1.
+    pub fn with_versioning(mut self) -> MemoryStorage {
+        self.versions = Some(Mutex::new(HashMap::new()));
+        self
+    }
+
+    /// Fails to remove directories that contain files, like S3 storage with
+    /// `DRAY_RMDIR_REQUIRE_EMPTY`.
+    pub fn with_rmdir_require_empty(mut self) -> MemoryStorage {
+        self.rmdir_require_empty = true;
+        self
+    }
+
+    /// Delays every read, like a slow connection to S3.
+    pub fn with_read_delay(mut self, read_delay: Duration) -> MemoryStorage {
+        self.read_delay = Some(read_delay);
+        self
+    }
+
+    /// Streams reads in chunks of the given length, like S3 streams large
+    /// reads of the object body.
+    pub fn with_streamed_reads(mut self, read_chunk_length: usize) -> MemoryStorage {
+        self.read_chunk_length = Some(read_chunk_length);
+        self
+    }
+
+    pub fn add_authorized_key(&self, user: &str, fingerprint: &str) {
+        self.authorized_keys
+            .lock()
+            .unwrap()
+            .entry(user.to_owned())
+            .or_default()
+            .push(fingerprint.to_owned());
+    }
+
+    pub fn set_user_permissions(&self, user: &str, user_permissions: UserPermissions) {
+        self.user_permissions
+            .lock()
+            .unwrap()
+            .insert(user.to_owned(), user_permissions);
+    }
+
+    pub fn set_visible_prefixes(&self, user: &str, visible_prefixes: Vec<String>) {
+        self.visible_prefixes
+            .lock()
+            .unwrap()
+            .insert(user.to_owned(), visible_prefixes);
+    }
+
+    /// Returns the number of directory pages that have been requested.
+    pub fn read_dir_calls(&self) -> usize {
+        self.read_dir_calls.load(Ordering::SeqCst)
+    }
+
+    pub fn set_user_quota(&self, user: &str, quota: u64) {
+        self.quotas.lock().unwrap().insert(user.to_owned(), quota);
+    }
+
+    /// Returns the error for a request on a handle that is not open, which is
+    /// SSH_FX_NO_SUCH_FILE if the file behind the handle was removed or renamed.
+    async fn missing_handle_error(&self, handle: &str, handle_type: &str) -> anyhow::Error {
+        match self.handle_manager.is_invalidated(handle).await {
+            true => Error::NoSuchFile.into(),
+            false => anyhow::anyhow!("Missing {} handle.", handle_type),
+        }
+    }
+
+    pub fn put_file(&self, file_name: &str, data: &[u8]) {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(file_name.to_owned(), data.to_vec());
+
+        if let Some(versions) = &self.versions {
+            versions
+                .lock()
+                .unwrap()
+                .entry(file_name.to_owned())
+                .or_default()
+                .push(data.to_vec());
+        }
+    }
+
+    pub fn get_file(&self, file_name: &str) -> Option<Vec<u8>> {
+        self.files.lock().unwrap().get(file_name).cloned()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    fn get_home(&self, user: &str) -> String {
+        format!("/home/{}", user)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_authorized_keys_fingerprints(&self, user: &str) -> Result<Vec<String>> {
+        Ok(self
+            .authorized_keys
+            .lock()
+            .unwrap()
+            .get(user)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn get_user_permissions(&self, user: &str) -> Result<UserPermissions> {
+        Ok(self
+            .user_permissions
+            .lock()
+            .unwrap()
+            .get(user)
+            .copied()
+            .unwrap_or_else(UserPermissions::read_write))
+    }
+
+    async fn get_visible_prefixes(&self, user: &str) -> Result<Option<Vec<String>>> {
+        Ok(self.visible_prefixes.lock().unwrap().get(user).cloned())
+    }
+
+    async fn get_user_quota(&self, user: &str) -> Result<Option<u64>> {
+        Ok(self.quotas.lock().unwrap().get(user).copied())
+    }
+
+    async fn get_used_bytes(&self, dir_name: String) -> Result<u64> {
+        let prefix = format!("{}/", dir_name.trim_end_matches('/'));
+
+        Ok(self
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(file_name, _)| file_name.starts_with(&prefix))
+            .map(|(_, data)| data.len() as u64)
+            .sum())
+    }
+
+    async fn open_dir_handle(&self, dir_name: String) -> Result<String> {
+        Ok(self
+            .handle_manager
+            .create_dir_handle(DirHandle {
+                prefix: dir_name,
+                position: 0,
+                is_eof: false,
+            })
+            .await)
+    }
+
+    async fn resume_dir_handle(&self, dir_name: String, previous_handle: &str) -> Result<String> {
+        let position = match self.dir_checkpoints.take(previous_handle, &dir_name).await {
+            Some(continuation_token) => continuation_token.parse()?,
+            None => return Err(anyhow::anyhow!("Missing directory checkpoint.")),
+        };
+
+        Ok(self
+            .handle_manager
+            .create_dir_handle(DirHandle {
+                prefix: dir_name,
+                position,
+                is_eof: false,
+            })
+            .await)
+    }
+
+    async fn create_dir(&self, _dir_name: String) -> Result<()> {
+        Ok(())
+    }
+
+    async fn create_home(&self, user: &str) -> Result<()> {
+        let home_marker = format!("{}/", self.get_home(user));
+        let mut files = self.files.lock().unwrap();
+
+        if !files
+            .keys()
+            .any(|file_name| file_name.starts_with(&home_marker))
+        {
+            files.insert(home_marker, Vec::new());
+        }
+
+        Ok(())
+    }
+
+    async fn read_dir(&self, handle: &str) -> Result<DirPage> {
+        self.read_dir_calls.fetch_add(1, Ordering::SeqCst);
+
+        let dir_handle = match self.handle_manager.get_dir_handle(handle).await {
+            Some(dir_handle) => dir_handle,
+            None => return Err(anyhow::anyhow!("Missing directory handle.")),
+        };
+
+        let mut dir_handle = dir_handle.lock().await;
+
+        if dir_handle.is_eof {
+            return Ok(DirPage {
+                files: Vec::new(),
+                has_more: false,
+            });
+        }
+
+        let prefix = format!("{}/", dir_handle.prefix.trim_end_matches('/'));
+
+        let mut dirs = BTreeSet::new();
+        let mut files = Vec::new();
+
+        for (file_name, data) in self.files.lock().unwrap().iter() {
+            let relative_name = match file_name.strip_prefix(&prefix) {
+                Some(relative_name) => relative_name,
+                None => continue,
+            };
+
+            match relative_name.split_once('/') {
+                Some((dir_name, _)) => {
+                    dirs.insert(dir_name.to_owned());
+                }
+                None => files.push(create_file(relative_name, data.len())),
+            }
+        }
+
+        let mut entries = dirs
+            .iter()
+            .map(|dir_name| create_dir(dir_name))
+            .chain(files)
+            .skip(dir_handle.position);
+
+        let page: Vec<File> = entries.by_ref().take(self.page_size).collect();
+
+        dir_handle.is_eof = entries.next().is_none();
+        dir_handle.position += page.len();
+
+        let continuation_token = match dir_handle.is_eof {
+            true => None,
+            false => Some(dir_handle.position.to_string()),
+        };
+
+        self.dir_checkpoints
+            .save(handle, &dir_handle.prefix, continuation_token)
+            .await;
+
+        Ok(DirPage {
+            files: page,
+            has_more: !dir_handle.is_eof,
+        })
+    }
+
+    async fn remove_dir(&self, dir_name: String) -> Result<()> {
+        let prefix = format!("{}/", dir_name.trim_end_matches('/'));
+
+        {
+            let mut files = self.files.lock().unwrap();
+
+            if self.rmdir_require_empty
+                && files.keys().any(|file_name| file_name.starts_with(&prefix))
+            {
+                return Err(Error::ServerError.into());
+            }
+
+            files.retain(|file_name, _| !file_name.starts_with(&prefix));
+        }
+
+        self.handle_manager.invalidate_path(&dir_name).await;
+
+        Ok(())
+    }
+
+    async fn get_file_metadata(&self, file_name: String) -> Result<File> {
+        let base_name = file_name.rsplit('/').next().unwrap_or("");
+
+        let mut file = match self.files.lock().unwrap().get(&file_name) {
+            Some(data) => create_file(base_name, data.len()),
+            None => return Ok(create_dir(base_name)),
+        };
+
+        file.file_attributes.mtime = self.mtimes.lock().unwrap().get(&file_name).copied();
+
+        if let Some(versions) = &self.versions {
+            if let Some(file_versions) = versions.lock().unwrap().get(&file_name) {
+                file.file_attributes.extended.insert(
+                    String::from(VERSION_ID_ATTRIBUTE),
+                    file_versions.len().to_string(),
+                );
+            }
+        }
+
+        Ok(file)
+    }
+
+    async fn get_extended_attributes(&self, file_name: String) -> Result<BTreeMap<String, String>> {
+        Ok(self
+            .extended_attributes
+            .lock()
+            .unwrap()
+            .get(&file_name)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_extended_attributes(
+        &self,
+        file_name: String,
+        extended: BTreeMap<String, String>,
+    ) -> Result<()> {
+        if !self.files.lock().unwrap().contains_key(&file_name) {
+            return Err(anyhow::anyhow!("Missing file."));
+        }
+
+        self.extended_attributes
+            .lock()
+            .unwrap()
+            .insert(file_name, extended);
+
+        Ok(())
+    }
+
+    async fn set_permissions(&self, _file_name: String, _permissions: u32) -> Result<()> {
+        Err(Error::Unimplemented.into())
+    }
+
+    async fn set_mtime(&self, file_name: String, mtime: u32) -> Result<()> {
+        if !self.files.lock().unwrap().contains_key(&file_name) {
+            return Err(Error::NoSuchFile.into());
+        }
+
+        self.mtimes.lock().unwrap().insert(file_name, mtime);
+
+        Ok(())
+    }
+
+    async fn dir_exists(&self, dir_name: String) -> Result<bool> {
+        let prefix = format!("{}/", dir_name.trim_end_matches('/'));
+
+        Ok(self
+            .files
+            .lock()
+            .unwrap()
+            .keys()
+            .any(|file_name| file_name.starts_with(&prefix)))
+    }
+
+    async fn file_exists(&self, file_name: String) -> Result<bool> {
+        Ok(self.files.lock().unwrap().contains_key(&file_name))
+    }
+
+    async fn open_read_handle(&self, file_name: String) -> Result<String> {
+        let data = match self.get_file(&file_name) {
+            Some(data) => data,
+            None => return Err(Error::NoSuchFile.into()),
+        };
+
+        Ok(self
+            .handle_manager
+            .create_read_handle(&file_name, ReadHandle { data, position: 0 })
+            .await)
+    }
+
+    async fn open_version_read_handle(
+        &self,
+        file_name: String,
+        version_id: String,
+    ) -> Result<String> {
+        let data = self
+            .versions
+            .as_ref()
+            .and_then(|versions| {
+                let version = version_id.parse::<usize>().ok()?.checked_sub(1)?;
+
+                versions
+                    .lock()
+                    .unwrap()
+                    .get(&file_name)?
+                    .get(version)
+                    .cloned()
+            })
+            .ok_or(Error::NoSuchFile)?;
+
+        Ok(self
+            .handle_manager
+            .create_read_handle(&file_name, ReadHandle { data, position: 0 })
+            .await)
+    }
+
+    async fn read_link(&self, _file_name: String) -> Result<String> {
+        Err(Error::Unimplemented.into())
+    }
+
+    /// Returns a `memory://` URL for the file, since files held in memory cannot
+    /// be downloaded from outside the server.
+    async fn presign_download(&self, file_name: String) -> Result<String> {
+        match self.files.lock().unwrap().contains_key(&file_name) {
+            true => Ok(format!("memory://{}", file_name)),
+            false => Err(Error::NoSuchFile.into()),
+        }
+    }
+
+    async fn read_data(&self, handle: &str, len: u32) -> Result<Vec<u8>> {
+        let read_handle = match self.handle_manager.get_read_handle(handle).await {
+            Some(read_handle) => read_handle,
+            None => return Err(self.missing_handle_error(handle, "read").await),
+        };
+
+        if let Some(read_delay) = self.read_delay {
+            tokio::time::sleep(read_delay).await;
+        }
+
+        let mut read_handle = read_handle.lock().await;
+
+        let start = read_handle.position;
+        let end = read_handle.data.len().min(start + len as usize);
+        read_handle.position = end;
+
+        Ok(read_handle.data[start..end].to_vec())
+    }
+
+    async fn read_data_stream(&self, handle: &str, len: u32) -> Result<ReadData> {
+        let data = self.read_data(handle, len).await?;
+
+        match self.read_chunk_length {
+            Some(read_chunk_length) if !data.is_empty() => {
+                let chunks: Vec<Result<Bytes>> = data
+                    .chunks(read_chunk_length)
+                    .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                    .collect();
+
+                Ok(ReadData::Streamed {
+                    len: data.len() as u32,
+                    chunks: Box::pin(futures::stream::iter(chunks)),
+                })
+            }
+            _ => Ok(ReadData::Buffered(data)),
+        }
+    }
+
+    async fn open_write_handle(
+        &self,
+        file_name: String,
+        file_attributes: FileAttributes,
+        _preserve_metadata: bool,
+        _uploader: &str,
+    ) -> Result<String> {
+        Ok(self
+            .handle_manager
+            .create_write_handle(
+                &file_name,
+                WriteHandle {
+                    file_name: file_name.clone(),
+                    buffer: Vec::new(),
+                    mtime: file_attributes.mtime,
+                },
+            )
+            .await)
+    }
+
+    async fn write_data(&self, handle: &str, data: Bytes) -> Result<()> {
+        let write_handle = match self.handle_manager.get_write_handle(handle).await {
+            Some(write_handle) => write_handle,
+            None => return Err(self.missing_handle_error(handle, "write").await),
+        };
+
+        write_handle.lock().await.buffer.extend_from_slice(&data);
+
+        Ok(())
+    }
+
+    async fn copy_data(&self, _read_handle: &str, _write_handle: &str) -> Result<u64> {
+        Err(Error::Unimplemented.into())
+    }
+
+    async fn flush_handle(&self, handle: &str) -> Result<()> {
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            let write_handle = write_handle.lock().await;
+
+            self.put_file(&write_handle.file_name, &write_handle.buffer);
+        }
+
+        Ok(())
+    }
+
+    async fn remove_file(&self, file_name: String) -> Result<()> {
+        self.files.lock().unwrap().remove(&file_name);
+        self.handle_manager.invalidate_path(&file_name).await;
+
+        Ok(())
+    }
+
+    async fn close_handle(&self, handle: &str) -> Result<()> {
+        if self.handle_manager.get_dir_handle(handle).await.is_some() {
+            self.dir_checkpoints.remove(handle).await;
+            self.handle_manager.remove_handle(handle).await;
+            return Ok(());
+        }
+
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            let write_handle = write_handle.lock().await;
+
+            self.put_file(&write_handle.file_name, &write_handle.buffer);
+
+            let mut mtimes = self.mtimes.lock().unwrap();
+
+            match write_handle.mtime {
+                Some(mtime) => mtimes.insert(write_handle.file_name.clone(), mtime),
+                None => mtimes.remove(&write_handle.file_name),
+            };
+        }
+
+        match self.handle_manager.remove_handle(handle).await {
+            true => Ok(()),
+            false => Err(Error::ServerError.into()),
+        }
+    }
+
+    async fn rename(&self, current: String, new: String) -> Result<()> {
+        self.handle_manager.invalidate_path(&current).await;
+
+        let mut files = self.files.lock().unwrap();
+
+        if let Some(data) = files.remove(&current) {
+            files.insert(new, data);
+            return Ok(());
+        }
+
+        let current_prefix = format!("{}/", current.trim_end_matches('/'));
+        let new_prefix = format!("{}/", new.trim_end_matches('/'));
+
+        let renamed_files: Vec<String> = files
+            .keys()
+            .filter(|file_name| file_name.starts_with(&current_prefix))
+            .cloned()
+            .collect();
+
+        for file_name in renamed_files {
+            if let Some(data) = files.remove(&file_name) {
+                files.insert(file_name.replacen(&current_prefix, &new_prefix, 1), data);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct ReadHandle {
+    data: Vec<u8>,
+    position: usize,
+}
+
+struct WriteHandle {
+    file_name: String,
+    buffer: Vec<u8>,
+    mtime: Option<u32>,
+}
+
+struct DirHandle {
+    prefix: String,
+    position: usize,
+    is_eof: bool,
+}
+
+fn create_file(file_name: &str, size: usize) -> File {
+    File {
+        file_name: file_name.to_owned(),
+        file_attributes: FileAttributes {
+            size: Some(size as u64),
+            permissions: Some(0o100777),
+            ..Default::default()
+        },
+    }
+}
+
+fn create_dir(dir_name: &str) -> File {
+    File {
+        file_name: dir_name.to_owned(),
+        file_attributes: FileAttributes {
+            permissions: Some(0o40777),
+            ..Default::default()
+        },
+    }
+}
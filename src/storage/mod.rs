@@ -1,13 +1,61 @@
+pub mod backend_calls;
+mod clock_skew;
+mod connection_limit;
+mod dir_checkpoint;
+mod gzip_decoder;
 mod handle;
+#[cfg(test)]
+pub mod memory;
+mod part_buffer;
+pub mod permissions;
 pub mod s3;
 
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
 use async_trait::async_trait;
 use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
 
+use crate::protocol::file_attributes::FileAttributes;
 use crate::protocol::response::name::File;
+use backend_calls::BackendCallCounts;
+use permissions::UserPermissions;
+
+/// The extended attribute that holds the version id of an object in a
+/// versioned bucket.
+pub const VERSION_ID_ATTRIBUTE: &str = "version-id";
+
+/// The extended attribute that holds the name of the user who uploaded a file,
+/// where the storage backend records it.
+pub const UPLOADER_ATTRIBUTE: &str = "uploader";
+
+/// The most metadata lookups `Storage::get_many_metadata` runs at once.
+pub const MAX_CONCURRENT_METADATA_REQUESTS: usize = 16;
+
+/// A page of entries from a directory listing.
+#[derive(Debug, PartialEq)]
+pub struct DirPage {
+    pub files: Vec<File>,
+    /// Whether the listing continues past this page, so callers can end it
+    /// without asking for an empty page.
+    pub has_more: bool,
+}
+
+/// The data of a read from a file.
+pub enum ReadData {
+    /// Data that was read whole, which is empty at the end of the file.
+    Buffered(Vec<u8>),
+    /// Data of a known length whose chunks are read while they are sent, so a
+    /// large read is not held in memory at once. The chunks add up to `len`.
+    Streamed {
+        len: u32,
+        chunks: BoxStream<'static, Result<Bytes>>,
+    },
+}
 
 /// Builds an instance of a Storage backend, such as AWS S3.
 ///
@@ -15,6 +63,13 @@ use crate::protocol::response::name::File;
 /// shared bewtween SSH sessions should be injected by the factory.
 pub trait StorageFactory: Send + Sync {
     fn create_storage(&self) -> Arc<dyn Storage>;
+
+    /// Builds the storage for the session of an authenticated user. Backends
+    /// that isolate users, such as S3 with per-user credentials, return a
+    /// storage that acts with the user's own credentials.
+    fn create_user_storage(&self, _user: &str) -> Arc<dyn Storage> {
+        self.create_storage()
+    }
 }
 
 /// An implementation of a Storage backend, such as AWS S3.
@@ -32,6 +87,19 @@ pub trait Storage: Send + Sync {
     /// operations cannot be performed.
     async fn health_check(&self) -> Result<()>;
 
+    /// Returns the backend calls made by this storage for each SFTP operation,
+    /// or `None` if the backend does not count them.
+    fn backend_calls(&self) -> Option<&BackendCallCounts> {
+        None
+    }
+
+    /// Aborts uploads that were started longer than `max_age` ago and never
+    /// finished, such as by a session that crashed, returning how many were
+    /// aborted. Backends without incomplete uploads have nothing to clean up.
+    async fn cleanup_orphaned_uploads(&self, _max_age: Duration) -> Result<usize> {
+        Ok(0)
+    }
+
     /// Retrieves the authorized key fingerprints for a user that will be compared
     /// against the fingerprint of the user-supplied key to determine if a user is
     /// allowed to log in.
@@ -41,14 +109,39 @@ pub trait Storage: Send + Sync {
     /// to prevent clients from determining whether or not a user exists.
     async fn get_authorized_keys_fingerprints(&self, user: &str) -> Result<Vec<String>>;
 
+    /// Retrieves the operations a user is allowed to perform. Users without
+    /// configured permissions are allowed to read and write.
+    async fn get_user_permissions(&self, user: &str) -> Result<UserPermissions>;
+
+    /// Retrieves the paths a user may see besides their home directory, such as
+    /// shared folders. Users without configured prefixes may see every path,
+    /// which is indicated by `None`.
+    async fn get_visible_prefixes(&self, user: &str) -> Result<Option<Vec<String>>>;
+
+    /// Retrieves the most bytes a user may store under their home directory, or
+    /// `None` if the user has no quota.
+    async fn get_user_quota(&self, user: &str) -> Result<Option<u64>>;
+
+    /// Sums the sizes of the files under a directory.
+    async fn get_used_bytes(&self, dir_name: String) -> Result<u64>;
+
     // Opens a directory handle for a prefix.
     async fn open_dir_handle(&self, dir_name: String) -> Result<String>;
 
+    /// Opens a directory handle that continues an unfinished listing of the same
+    /// prefix, which may have been started by a dropped SSH session.
+    async fn resume_dir_handle(&self, dir_name: String, previous_handle: &str) -> Result<String>;
+
     /// Creates a directory.
     async fn create_dir(&self, dir_name: String) -> Result<()>;
 
-    // Reads a file listing from the prefix associated with a given handle.
-    async fn read_dir(&self, handle: &str) -> Result<Vec<File>>;
+    /// Creates a zero-byte marker for a user's home directory if nothing is
+    /// stored under it yet, so clients that expect the home to exist can list it.
+    async fn create_home(&self, user: &str) -> Result<()>;
+
+    /// Reads the next page of the listing of the prefix associated with a given
+    /// handle. A finished listing returns empty pages.
+    async fn read_dir(&self, handle: &str) -> Result<DirPage>;
 
     /// Removes a directory.
     async fn remove_dir(&self, dir_name: String) -> Result<()>;
@@ -56,22 +149,113 @@ pub trait Storage: Send + Sync {
     /// Retrieves an file's metadata.
     async fn get_file_metadata(&self, file_name: String) -> Result<File>;
 
-    /// Creates a read handle for a file.
+    /// Retrieves the metadata of several files concurrently, in the order of
+    /// the file names, for listings that need attributes their entries lack.
+    async fn get_many_metadata(&self, file_names: Vec<String>) -> Result<Vec<File>> {
+        futures::stream::iter(file_names)
+            .map(|file_name| self.get_file_metadata(file_name))
+            .buffered(MAX_CONCURRENT_METADATA_REQUESTS)
+            .try_collect()
+            .await
+    }
+
+    /// Reads a file's extended attributes, such as S3 object tags. They are
+    /// not part of `get_file_metadata`, since most backends store them
+    /// separately from the rest of a file's metadata.
+    async fn get_extended_attributes(&self, file_name: String) -> Result<BTreeMap<String, String>>;
+
+    /// Replaces a file's extended attributes, such as S3 object tags.
+    async fn set_extended_attributes(
+        &self,
+        file_name: String,
+        extended: BTreeMap<String, String>,
+    ) -> Result<()>;
+
+    /// Applies SFTP permission bits to a file. An `Unimplemented` error is
+    /// returned if the storage backend has no equivalent of the permissions.
+    async fn set_permissions(&self, file_name: String, permissions: u32) -> Result<()>;
+
+    /// Sets the modification time of a file in seconds since the epoch. An
+    /// `Unimplemented` error is returned if modification times are not stored.
+    async fn set_mtime(&self, file_name: String, mtime: u32) -> Result<()>;
+
+    /// Checks if anything is stored under a directory's prefix, including a
+    /// directory marker.
+    async fn dir_exists(&self, dir_name: String) -> Result<bool>;
+
+    /// Checks if a file exists. Directories are not files.
+    async fn file_exists(&self, file_name: String) -> Result<bool>;
+
+    /// Creates a read handle for a file. A `NoSuchFile` error is returned if
+    /// the file does not exist.
     async fn open_read_handle(&self, file_name: String) -> Result<String>;
 
+    /// Creates a read handle for a specific version of a file in a versioned
+    /// bucket.
+    async fn open_version_read_handle(
+        &self,
+        file_name: String,
+        version_id: String,
+    ) -> Result<String>;
+
+    /// Reads the target of a file that links to another, such as an S3 object
+    /// with a website redirect location. A `ServerError` error is returned if the
+    /// file is not a link, and `Unimplemented` if the storage backend has no
+    /// links.
+    async fn read_link(&self, file_name: String) -> Result<String>;
+
+    /// Creates a URL that downloads a file directly from the storage backend
+    /// until it expires. An `Unimplemented` error is returned if the storage
+    /// backend cannot presign URLs.
+    async fn presign_download(&self, file_name: String) -> Result<String>;
+
     /// Reads up to len bytes of data data from a file associated with a given handle.
     async fn read_data(&self, handle: &str, len: u32) -> Result<Vec<u8>>;
 
-    /// Creates a write handle for a file.
-    async fn open_write_handle(&self, file_name: String) -> Result<String>;
+    /// Reads up to len bytes like `read_data`, but backends that know how much
+    /// of the file is left may stream the data instead of buffering it.
+    async fn read_data_stream(&self, handle: &str, len: u32) -> Result<ReadData> {
+        Ok(ReadData::Buffered(self.read_data(handle, len).await?))
+    }
+
+    /// Reads up to len bytes like `read_data_stream`, starting at the given
+    /// offset. Backends that can only read in order ignore the offset.
+    async fn read_data_at(&self, handle: &str, _offset: u64, len: u32) -> Result<ReadData> {
+        self.read_data_stream(handle, len).await
+    }
+
+    /// Creates a write handle for a file, applying the attributes the client
+    /// requested, such as permissions, where the storage backend supports them.
+    /// The metadata of an existing file, such as its content type, is kept for
+    /// the new contents if `preserve_metadata` is set. The `uploader` is the
+    /// user writing the file, which backends may record for auditing.
+    async fn open_write_handle(
+        &self,
+        file_name: String,
+        file_attributes: FileAttributes,
+        preserve_metadata: bool,
+        uploader: &str,
+    ) -> Result<String>;
 
     /// Writes data to a file associated with a given handle.
     async fn write_data(&self, handle: &str, data: Bytes) -> Result<()>;
 
+    /// Copies all of the file behind a read handle into a write handle that
+    /// nothing has been written to, without passing the data through the
+    /// server, and returns the bytes copied. An `Unimplemented` error is
+    /// returned if the backend cannot copy the file this way, in which case the
+    /// data has to be read and written instead.
+    async fn copy_data(&self, read_handle: &str, write_handle: &str) -> Result<u64>;
+
+    /// Persists the data written to a handle so far without closing it.
+    async fn flush_handle(&self, handle: &str) -> Result<()>;
+
     /// Removes a file.
     async fn remove_file(&self, key: String) -> Result<()>;
 
-    // Closes a handle.
+    /// Closes a file or directory handle. Closing a directory handle discards
+    /// the state of its listing. A `ServerError` error is returned for unknown
+    /// handles.
     async fn close_handle(&self, handle: &str) -> Result<()>;
 
     /// Renames a file or directory.
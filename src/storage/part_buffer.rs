@@ -0,0 +1,89 @@
+/// The smallest part S3 accepts for any part of a multipart upload but the last.
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Coalesces the arbitrarily sized chunks written by a client into parts of a
+/// fixed size, since S3 rejects multipart uploads with undersized parts.
+pub struct PartBuffer {
+    part_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl PartBuffer {
+    pub fn new(part_size: usize) -> PartBuffer {
+        PartBuffer {
+            part_size,
+            buffer: Vec::with_capacity(part_size),
+        }
+    }
+
+    /// Buffers the data and returns any parts that are now full.
+    pub fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        let mut parts = Vec::new();
+
+        while self.buffer.len() >= self.part_size {
+            let remaining = self.buffer.split_off(self.part_size);
+            parts.push(std::mem::replace(&mut self.buffer, remaining));
+        }
+
+        parts
+    }
+
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns the final partial part, which may be smaller than the part size.
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_push_coalesces_writes_into_parts() {
+        let mut part_buffer = PartBuffer::new(MIN_PART_SIZE);
+        let mut parts = Vec::new();
+
+        for _ in 0..200 {
+            parts.extend(part_buffer.push(&[0x01; 64 * 1024]));
+        }
+        parts.push(part_buffer.finish());
+
+        let part_sizes: Vec<usize> = parts.iter().map(|part| part.len()).collect();
+
+        assert_eq!(
+            vec![
+                MIN_PART_SIZE,
+                MIN_PART_SIZE,
+                200 * 64 * 1024 - 2 * MIN_PART_SIZE
+            ],
+            part_sizes
+        );
+    }
+
+    #[test]
+    fn test_push_splits_write_larger_than_part_size() {
+        let mut part_buffer = PartBuffer::new(4);
+
+        assert_eq!(
+            vec![vec![0x01; 4], vec![0x01; 4]],
+            part_buffer.push(&[0x01; 10])
+        );
+        assert_eq!(vec![0x01; 2], part_buffer.finish());
+    }
+
+    #[test]
+    fn test_finish_clears_buffer() {
+        let mut part_buffer = PartBuffer::new(4);
+
+        part_buffer.push(&[0x01; 2]);
+        part_buffer.finish();
+
+        assert!(part_buffer.finish().is_empty());
+    }
+}
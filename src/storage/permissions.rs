@@ -0,0 +1,125 @@
+use anyhow::Result;
+
+use crate::protocol::request::path::normalize_path;
+
+/// The operations a user is allowed to perform, which allows download-only
+/// users and full-access users to share a server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UserPermissions {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl UserPermissions {
+    pub fn read_write() -> UserPermissions {
+        UserPermissions {
+            read: true,
+            write: true,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn read_only() -> UserPermissions {
+        UserPermissions {
+            read: true,
+            write: false,
+        }
+    }
+}
+
+/// Parses a list of permissions separated by commas or whitespace, such as
+/// `read` or `read,write`.
+pub fn parse_user_permissions(user_permissions: &str) -> Result<UserPermissions> {
+    let mut parsed_user_permissions = UserPermissions {
+        read: false,
+        write: false,
+    };
+
+    let permissions = user_permissions
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|permission| !permission.is_empty());
+
+    for permission in permissions {
+        match permission.to_ascii_lowercase().as_str() {
+            "read" => parsed_user_permissions.read = true,
+            "write" => parsed_user_permissions.write = true,
+            _ => return Err(anyhow::anyhow!("Unknown permission {}.", permission)),
+        }
+    }
+
+    Ok(parsed_user_permissions)
+}
+
+/// Parses a list of visible paths with one path per line, such as `/shared`.
+/// Blank lines are ignored.
+pub fn parse_visible_prefixes(visible_prefixes: &str) -> Vec<String> {
+    visible_prefixes
+        .lines()
+        .map(|visible_prefix| visible_prefix.trim())
+        .filter(|visible_prefix| !visible_prefix.is_empty())
+        .map(normalize_path)
+        .collect()
+}
+
+/// Parses a quota in bytes, such as `1073741824`.
+pub fn parse_quota(quota: &str) -> Result<u64> {
+    quota
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid quota {}.", quota.trim()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_visible_prefixes_normalizes_paths() {
+        assert_eq!(
+            vec![String::from("/shared"), String::from("/reports/2024")],
+            parse_visible_prefixes("/shared/\n\n  reports//2024  \n")
+        );
+    }
+
+    #[test]
+    fn test_parse_quota() {
+        assert_eq!(1073741824, parse_quota("1073741824\n").unwrap());
+    }
+
+    #[test]
+    fn test_parse_quota_with_invalid_quota() {
+        assert!(parse_quota("1 GiB").is_err());
+    }
+
+    #[test]
+    fn test_parse_user_permissions_with_read_only() {
+        assert_eq!(
+            UserPermissions::read_only(),
+            parse_user_permissions("read\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_user_permissions_with_read_write() {
+        assert_eq!(
+            UserPermissions::read_write(),
+            parse_user_permissions("read, write").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_user_permissions_with_no_permissions() {
+        assert_eq!(
+            UserPermissions {
+                read: false,
+                write: false,
+            },
+            parse_user_permissions("").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_user_permissions_with_unknown_permission() {
+        assert!(parse_user_permissions("read,delete").is_err());
+    }
+}
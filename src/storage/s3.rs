@@ -1,16 +1,35 @@
+use super::backend_calls::{BackendCall, BackendCallCounts};
+use super::clock_skew::ClockSkewDetectingDispatcher;
+use super::connection_limit::ConnectionLimitedDispatcher;
+use super::dir_checkpoint::DirCheckpoints;
+use super::gzip_decoder::GzipDecoder;
 use super::handle::HandleManager;
+use super::part_buffer::{PartBuffer, MIN_PART_SIZE};
+use super::permissions::{self, UserPermissions};
+use super::DirPage;
+use super::ReadData;
 use super::Storage;
 use super::StorageFactory;
+use super::{UPLOADER_ATTRIBUTE, VERSION_ID_ATTRIBUTE};
 use crate::error::Error;
 use crate::protocol::file_attributes::FileAttributes;
 use crate::protocol::response::name::File;
 use crate::ssh_keys;
 use anyhow::Result;
 use async_trait::async_trait;
-use bytes::BufMut;
 use chrono::{DateTime, TimeZone, Utc};
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use log::{error, info, warn};
+use rusoto_core::credential::{DefaultCredentialsProvider, ProfileProvider, ProvideAwsCredentials};
+use rusoto_core::request::DispatchSignedRequest;
+use rusoto_core::signature::encode_uri_path;
 use rusoto_core::ByteStream;
+use rusoto_core::HttpClient;
 use rusoto_core::Region;
+use rusoto_core::RusotoError;
+use rusoto_s3::util::{PreSignedRequest, PreSignedRequestOption};
+use rusoto_s3::AbortMultipartUploadRequest;
 use rusoto_s3::CompleteMultipartUploadRequest;
 use rusoto_s3::CompletedMultipartUpload;
 use rusoto_s3::CompletedPart;
@@ -18,18 +37,69 @@ use rusoto_s3::CopyObjectRequest;
 use rusoto_s3::CreateMultipartUploadOutput;
 use rusoto_s3::CreateMultipartUploadRequest;
 use rusoto_s3::DeleteObjectRequest;
+use rusoto_s3::GetBucketLocationRequest;
+use rusoto_s3::GetObjectError;
+use rusoto_s3::GetObjectOutput;
+use rusoto_s3::GetObjectTaggingRequest;
 use rusoto_s3::HeadBucketRequest;
+use rusoto_s3::ListMultipartUploadsRequest;
+use rusoto_s3::ListObjectVersionsRequest;
+use rusoto_s3::PutObjectAclRequest;
+use rusoto_s3::PutObjectRequest;
+use rusoto_s3::PutObjectTaggingRequest;
+use rusoto_s3::UploadPartCopyRequest;
 use rusoto_s3::UploadPartRequest;
 use rusoto_s3::{
     CommonPrefix, GetObjectRequest, HeadObjectOutput, ListObjectsV2Output, ListObjectsV2Request,
     Object, S3Client, S3,
 };
+use rusoto_s3::{Delete, DeleteObjectsRequest, ObjectIdentifier, S3Error};
 use rusoto_s3::{HeadObjectError, HeadObjectRequest};
+use rusoto_s3::{Tag, Tagging};
+use serde::de;
 use serde::Deserialize;
+use serde::Deserializer;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// The most keys S3 returns for a single list request.
+const MAX_LIST_PAGE_SIZE: i64 = 1000;
+
+/// The object metadata key that holds a client-supplied modification time, in
+/// seconds since the epoch.
+const MTIME_METADATA: &str = "mtime";
+
+/// The wait before the first retry of a read of a just-written object, which
+/// doubles with each further retry.
+const READ_AFTER_WRITE_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The largest object that S3 can copy into a multipart upload as one part.
+const MAX_COPY_PART_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+
+/// The shortest read that is streamed from the object body rather than read
+/// into memory first, below which streaming saves little.
+const MIN_STREAMED_READ_LENGTH: u32 = 64 * 1024;
+
+/// The most data of a streamed read that is held in memory at once.
+const READ_CHUNK_LENGTH: usize = 32 * 1024;
+
+/// The wait before the first retry of objects that S3 failed to delete, which
+/// doubles with each further retry.
+const DELETE_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The codes of the errors that S3 may report for an object in a batch delete
+/// that could succeed if the object is retried.
+const TRANSIENT_DELETE_ERROR_CODES: [&str; 3] = ["InternalError", "SlowDown", "ServiceUnavailable"];
 
 #[derive(Deserialize, Debug)]
 pub struct S3Config {
@@ -39,278 +109,805 @@ pub struct S3Config {
     #[serde(default = "get_default_endpoint_region")]
     pub endpoint_region: String,
 
+    /// The AWS region of the bucket. The region is detected from the bucket's
+    /// location at startup if unset.
+    #[serde(
+        rename(deserialize = "s3_region"),
+        default,
+        deserialize_with = "deserialize_region"
+    )]
+    pub region: Option<Region>,
+
     #[serde(rename(deserialize = "s3_bucket"))]
     pub bucket: String,
+
+    /// Stores the directories given as `path=bucket` pairs, such as
+    /// `/archive=archive-bucket`, in their own buckets instead of the default
+    /// bucket, which allows tiered storage. The longest matching path wins.
+    /// Each bucket must be in the same region as the default bucket, and the
+    /// directories are only listed in their parent if it also exists in the
+    /// parent's bucket.
+    #[serde(
+        rename(deserialize = "s3_bucket_prefixes"),
+        default,
+        deserialize_with = "deserialize_bucket_prefixes"
+    )]
+    pub bucket_prefixes: Vec<(String, String)>,
+
+    /// Signs the requests of the users given as `user=profile` pairs, such as
+    /// `alice=tenant-a`, with the credentials of that profile in the AWS
+    /// credentials file, so each user only reaches what their profile allows.
+    /// Other users are signed with the default credentials.
+    #[serde(
+        rename(deserialize = "s3_user_profiles"),
+        default,
+        deserialize_with = "deserialize_user_profiles"
+    )]
+    pub user_profiles: Vec<(String, String)>,
+
+    #[serde(rename(deserialize = "s3_storage_class"))]
+    pub storage_class: Option<StorageClass>,
+
+    /// The most S3 requests that may wait on a response at once.
+    #[serde(rename(deserialize = "s3_max_connections"))]
+    pub max_connections: Option<usize>,
+
+    /// The number of seconds to wait for a connection to S3 to be established.
+    #[serde(rename(deserialize = "s3_connect_timeout"))]
+    pub connect_timeout: Option<u64>,
+
+    /// The size in bytes of each part of a multipart upload but the last.
+    #[serde(
+        default = "get_default_part_size",
+        deserialize_with = "deserialize_part_size"
+    )]
+    pub part_size: usize,
+
+    /// The most entries to request from S3 for each page of a directory
+    /// listing, which is capped at the S3 limit of 1000.
+    pub list_page_size: Option<i64>,
+
+    /// The most bytes of each upload held in memory while waiting for parts to
+    /// upload, which defaults to twice the part size. The limit is raised to fit
+    /// a full part if it is smaller.
+    pub max_write_buffer: Option<usize>,
+
+    /// Grants the `public-read` ACL to objects that clients make readable by
+    /// others, such as with mode 0644, and the `private` ACL otherwise.
+    ///
+    /// # Warning
+    /// Objects with the `public-read` ACL can be read by anyone on the internet
+    /// without credentials, and most SFTP clients upload files as 0644 by
+    /// default, so this is off by default. The bucket must also allow ACLs.
+    #[serde(rename(deserialize = "s3_public_read_permissions"), default)]
+    pub public_read_permissions: bool,
+
+    /// Fails to remove directories that contain objects, like POSIX rmdir,
+    /// instead of deleting every object under the directory.
+    #[serde(default)]
+    pub rmdir_require_empty: bool,
+
+    /// Stores the modification times that clients send in object metadata and
+    /// reports them on stat instead of the upload time.
+    #[serde(default)]
+    pub preserve_mtime: bool,
+
+    /// Looks up the metadata of each listed file, so listings report the same
+    /// attributes as stat, such as preserved modification times and tags. Each
+    /// listed file then takes extra requests to S3.
+    #[serde(rename(deserialize = "s3_list_metadata"), default)]
+    pub list_metadata: bool,
+
+    /// Decompresses objects stored with `Content-Encoding: gzip` as they are
+    /// read, so clients receive the original bytes. The stored objects are left
+    /// compressed, and stat still reports their compressed size.
+    #[serde(default)]
+    pub decompress_gzip: bool,
+
+    /// Resolves stat and open requests for keys that do not exist to a stored
+    /// key that differs only in case, for clients that expect case-insensitive
+    /// paths.
+    ///
+    /// # Performance
+    /// S3 can only look up keys by exact case, so each miss lists the whole
+    /// parent directory to find a match. This adds a list request per page of
+    /// the directory to every lookup of a missing file, such as the stat that
+    /// many clients send before an upload.
+    #[serde(default)]
+    pub case_insensitive_keys: bool,
+
+    /// The number of seconds that URLs returned for `presign-download@dray`
+    /// requests stay valid.
+    #[serde(default = "get_default_presigned_url_expiry")]
+    pub presigned_url_expiry: u64,
+
+    /// The number of times to retry a stat or read that finds no object at a
+    /// key the session just wrote, for S3-compatible stores that are only
+    /// eventually consistent. No retries are made by default.
+    #[serde(default)]
+    pub read_after_write_retries: u32,
+
+    /// The number of times to retry deleting the objects that S3 reports it
+    /// failed to delete while removing a directory.
+    #[serde(default = "get_default_delete_retries")]
+    pub delete_retries: u32,
+
+    /// The fewest bytes fetched with a range request when a client reads out
+    /// of order, such as a media player seeking. Later reads that fall in the
+    /// fetched range are answered without another request, so many small
+    /// scattered reads take one request per window instead of one each. A
+    /// window of 0 fetches only the bytes each read asks for.
+    #[serde(default = "get_default_read_coalesce_window")]
+    pub read_coalesce_window: usize,
+
+    /// Opens the object that an object's website redirect location points to,
+    /// like following a symlink. Only one redirect is followed. Redirects are
+    /// followed regardless of the user's visible prefixes, so only trusted
+    /// tools should be able to set them.
+    #[serde(default)]
+    pub follow_redirects: bool,
+
+    /// The user metadata key to record the uploading user's name under, such
+    /// as `dray-user`, which S3 stores as the `x-amz-meta-dray-user` header.
+    /// The name is reported as the `uploader` extended attribute on stat.
+    /// Uploads are not stamped if unset.
+    pub uploader_metadata: Option<String>,
+
+    /// A read-only directory, such as `/.snapshots`, that shows the bucket as it
+    /// was at a point in time for recovery. Reading
+    /// `/.snapshots/2024-01-01T00:00:00Z/home/user/file.txt` returns the version
+    /// of `/home/user/file.txt` that was current at that time. The bucket must
+    /// be versioned. Snapshots are disabled if unset.
+    pub snapshot_dir: Option<String>,
+
+    /// A directory that uploads are written to before any of their parts are
+    /// sent to S3. Each upload is sent from its file when the handle is closed,
+    /// so an upload that is never closed sends nothing, and parts are not held
+    /// in memory while the client writes. The directory needs room for every
+    /// upload in progress. Uploads are buffered in memory if unset.
+    pub write_spool_dir: Option<String>,
+}
+
+/// The S3 storage class applied to uploaded objects. Deserializing an unknown
+/// storage class is an error, so a misconfigured deployment fails on startup.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum StorageClass {
+    Standard,
+    StandardIa,
+    IntelligentTiering,
+    GlacierIr,
+}
+
+impl StorageClass {
+    fn as_str(&self) -> &'static str {
+        match self {
+            StorageClass::Standard => "STANDARD",
+            StorageClass::StandardIa => "STANDARD_IA",
+            StorageClass::IntelligentTiering => "INTELLIGENT_TIERING",
+            StorageClass::GlacierIr => "GLACIER_IR",
+        }
+    }
 }
 
 pub struct S3StorageFactory {
     s3_client: S3Client,
     bucket: String,
+    bucket_prefixes: Vec<(String, String)>,
+    storage_class: Option<StorageClass>,
+    part_size: usize,
+    list_page_size: Option<i64>,
+    max_write_buffer: usize,
+    public_read_permissions: bool,
+    rmdir_require_empty: bool,
+    preserve_mtime: bool,
+    list_metadata: bool,
+    decompress_gzip: bool,
+    case_insensitive_keys: bool,
+    read_after_write_retries: u32,
+    delete_retries: u32,
+    read_coalesce_window: usize,
+    follow_redirects: bool,
+    uploader_metadata: Option<String>,
+    snapshot_dir: Option<String>,
+    write_spool_dir: Option<PathBuf>,
+    presigner: Presigner,
+    user_clients: HashMap<String, (S3Client, Presigner)>,
+    dir_checkpoints: Arc<DirCheckpoints>,
 }
 
 impl S3StorageFactory {
-    pub fn new(s3_config: &S3Config) -> S3StorageFactory {
-        let region = match &s3_config.endpoint_name {
-            Some(endpoint_name) => Region::Custom {
-                name: s3_config.endpoint_region.clone(),
-                endpoint: endpoint_name.clone(),
-            },
-            None => Region::default(),
+    /// Creates the factory, failing if the credential providers cannot be
+    /// created, such as when a user's profile is missing.
+    pub async fn new(s3_config: &S3Config) -> Result<S3StorageFactory> {
+        let credentials_provider = DefaultCredentialsProvider::new()
+            .map_err(|error| anyhow::anyhow!("Failed to create credentials provider: {}", error))?;
+
+        // The region is resolved once, so every storage created by the factory
+        // shares the detected region.
+        let region = resolve_region(
+            s3_config,
+            build_request_dispatcher(s3_config),
+            credentials_provider.clone(),
+        )
+        .await;
+
+        let presigner = Presigner {
+            region: region.clone(),
+            credentials_provider: Arc::new(credentials_provider.clone()),
+            expiry: Duration::from_secs(s3_config.presigned_url_expiry),
         };
 
-        S3StorageFactory {
-            s3_client: S3Client::new(region),
+        let user_clients = s3_config
+            .user_profiles
+            .iter()
+            .map(|(user, profile)| {
+                let credentials_provider = ProfileProvider::with_default_credentials(profile)
+                    .map_err(|error| {
+                        anyhow::anyhow!(
+                            "Failed to create credentials provider for profile {}: {}",
+                            profile,
+                            error
+                        )
+                    })?;
+
+                let user_presigner = Presigner {
+                    credentials_provider: Arc::new(credentials_provider.clone()),
+                    ..presigner.clone()
+                };
+
+                let s3_client = S3Client::new_with(
+                    build_request_dispatcher(s3_config),
+                    credentials_provider,
+                    region.clone(),
+                );
+
+                Ok((user.clone(), (s3_client, user_presigner)))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(S3StorageFactory {
+            s3_client: S3Client::new_with(
+                build_request_dispatcher(s3_config),
+                credentials_provider,
+                region,
+            ),
             bucket: s3_config.bucket.clone(),
-        }
+            bucket_prefixes: s3_config.bucket_prefixes.clone(),
+            storage_class: s3_config.storage_class,
+            part_size: s3_config.part_size,
+            list_page_size: s3_config.list_page_size.map(get_list_page_size),
+            max_write_buffer: s3_config
+                .max_write_buffer
+                .unwrap_or(2 * s3_config.part_size),
+            public_read_permissions: s3_config.public_read_permissions,
+            rmdir_require_empty: s3_config.rmdir_require_empty,
+            preserve_mtime: s3_config.preserve_mtime,
+            list_metadata: s3_config.list_metadata,
+            decompress_gzip: s3_config.decompress_gzip,
+            case_insensitive_keys: s3_config.case_insensitive_keys,
+            read_after_write_retries: s3_config.read_after_write_retries,
+            delete_retries: s3_config.delete_retries,
+            read_coalesce_window: s3_config.read_coalesce_window,
+            follow_redirects: s3_config.follow_redirects,
+            uploader_metadata: s3_config.uploader_metadata.clone(),
+            snapshot_dir: s3_config.snapshot_dir.clone(),
+            write_spool_dir: s3_config.write_spool_dir.as_ref().map(PathBuf::from),
+            presigner,
+            user_clients,
+            dir_checkpoints: Arc::new(DirCheckpoints::new()),
+        })
+    }
+
+    fn build_storage(&self, s3_client: S3Client, presigner: Presigner) -> Arc<dyn Storage> {
+        Arc::new(
+            S3Storage::new(
+                s3_client,
+                self.bucket.clone(),
+                self.storage_class,
+                self.part_size,
+                self.list_page_size,
+                self.max_write_buffer,
+                self.dir_checkpoints.clone(),
+            )
+            .with_bucket_prefixes(self.bucket_prefixes.clone())
+            .with_public_read_permissions(self.public_read_permissions)
+            .with_rmdir_require_empty(self.rmdir_require_empty)
+            .with_preserve_mtime(self.preserve_mtime)
+            .with_list_metadata(self.list_metadata)
+            .with_decompress_gzip(self.decompress_gzip)
+            .with_case_insensitive_keys(self.case_insensitive_keys)
+            .with_read_after_write_retries(self.read_after_write_retries)
+            .with_delete_retries(self.delete_retries)
+            .with_read_coalesce_window(self.read_coalesce_window)
+            .with_follow_redirects(self.follow_redirects)
+            .with_uploader_metadata(self.uploader_metadata.clone())
+            .with_snapshot_dir(self.snapshot_dir.clone())
+            .with_write_spool_dir(self.write_spool_dir.clone())
+            .with_presigner(presigner),
+        )
     }
 }
 
 #[async_trait]
 impl StorageFactory for S3StorageFactory {
     fn create_storage(&self) -> Arc<dyn Storage> {
-        Arc::new(S3Storage::new(self.s3_client.clone(), self.bucket.clone()))
+        self.build_storage(self.s3_client.clone(), self.presigner.clone())
+    }
+
+    fn create_user_storage(&self, user: &str) -> Arc<dyn Storage> {
+        match self.user_clients.get(user) {
+            Some((s3_client, presigner)) => {
+                self.build_storage(s3_client.clone(), presigner.clone())
+            }
+            None => self.create_storage(),
+        }
     }
 }
 
+/// Signs GET URLs for objects with the credentials the S3 client uses, which
+/// lets clients download objects directly from S3.
+#[derive(Clone)]
+pub struct Presigner {
+    pub region: Region,
+    pub credentials_provider: Arc<dyn ProvideAwsCredentials + Send + Sync>,
+    pub expiry: Duration,
+}
+
 pub struct S3Storage {
     s3_client: S3Client,
     bucket: String,
-    handle_manager: HandleManager<Pin<Box<dyn AsyncRead + Send>>, WriteHandle, DirHandle>,
+    bucket_prefixes: Vec<(String, String)>,
+    storage_class: Option<StorageClass>,
+    part_size: usize,
+    list_page_size: Option<i64>,
+    max_write_buffer: usize,
+    public_read_permissions: bool,
+    rmdir_require_empty: bool,
+    preserve_mtime: bool,
+    list_metadata: bool,
+    decompress_gzip: bool,
+    case_insensitive_keys: bool,
+    read_after_write_retries: u32,
+    recent_writes: std::sync::Mutex<HashSet<String>>,
+    delete_retries: u32,
+    read_coalesce_window: usize,
+    follow_redirects: bool,
+    uploader_metadata: Option<String>,
+    snapshot_dir: Option<String>,
+    write_spool_dir: Option<PathBuf>,
+    presigner: Option<Presigner>,
+    handle_manager: HandleManager<ReadHandle, WriteHandle, DirHandle>,
+    dir_checkpoints: Arc<DirCheckpoints>,
+    backend_calls: BackendCallCounts,
 }
 
 impl S3Storage {
-    pub fn new(s3_client: S3Client, bucket: String) -> S3Storage {
+    pub fn new(
+        s3_client: S3Client,
+        bucket: String,
+        storage_class: Option<StorageClass>,
+        part_size: usize,
+        list_page_size: Option<i64>,
+        max_write_buffer: usize,
+        dir_checkpoints: Arc<DirCheckpoints>,
+    ) -> S3Storage {
         S3Storage {
             s3_client,
             bucket,
+            bucket_prefixes: Vec::new(),
+            storage_class,
+            part_size,
+            list_page_size,
+            max_write_buffer,
+            public_read_permissions: false,
+            rmdir_require_empty: false,
+            preserve_mtime: false,
+            list_metadata: false,
+            decompress_gzip: false,
+            case_insensitive_keys: false,
+            read_after_write_retries: 0,
+            recent_writes: std::sync::Mutex::new(HashSet::new()),
+            delete_retries: 0,
+            read_coalesce_window: 0,
+            follow_redirects: false,
+            uploader_metadata: None,
+            snapshot_dir: None,
+            write_spool_dir: None,
+            presigner: None,
             handle_manager: HandleManager::new(),
+            dir_checkpoints,
+            backend_calls: BackendCallCounts::new(),
         }
     }
 
-    async fn complete_part_upload(
-        &self,
-        write_handle: &mut tokio::sync::MutexGuard<'_, WriteHandle>,
-    ) -> Result<()> {
-        let part_number = (write_handle.completed_parts.len() as i64) + 1;
+    /// Routes the keys under each prefix, such as `archive/`, to its bucket.
+    pub fn with_bucket_prefixes(mut self, mut bucket_prefixes: Vec<(String, String)>) -> S3Storage {
+        // The longest prefixes are checked first, so a nested directory can be
+        // mapped to a different bucket than its parent.
+        bucket_prefixes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        self.bucket_prefixes = bucket_prefixes;
+        self
+    }
 
-        let upload_part_response = self
-            .s3_client
-            .upload_part(UploadPartRequest {
-                bucket: self.bucket.clone(),
-                key: write_handle.key.clone(),
-                upload_id: write_handle.upload_id.clone(),
-                part_number,
-                body: Some(ByteStream::from(write_handle.buffer.clone())),
-                ..Default::default()
+    /// Returns the bucket that stores a key or prefix, which is the default
+    /// bucket unless the key is under a mapped prefix.
+    fn get_bucket(&self, key: &str) -> &str {
+        self.bucket_prefixes
+            .iter()
+            .find(|(prefix, _)| {
+                key.starts_with(prefix.as_str()) || prefix.strip_suffix('/') == Some(key)
             })
-            .await?;
+            .map(|(_, bucket)| bucket.as_str())
+            .unwrap_or(&self.bucket)
+    }
 
-        write_handle.completed_parts.push(CompletedPart {
-            e_tag: upload_part_response.e_tag,
-            part_number: Some(part_number),
-        });
+    pub fn with_public_read_permissions(mut self, public_read_permissions: bool) -> S3Storage {
+        self.public_read_permissions = public_read_permissions;
+        self
+    }
 
-        write_handle.buffer.clear();
+    pub fn with_rmdir_require_empty(mut self, rmdir_require_empty: bool) -> S3Storage {
+        self.rmdir_require_empty = rmdir_require_empty;
+        self
+    }
 
-        Ok(())
+    pub fn with_preserve_mtime(mut self, preserve_mtime: bool) -> S3Storage {
+        self.preserve_mtime = preserve_mtime;
+        self
     }
 
-    async fn rename_file(&self, current: String, new: String) -> Result<()> {
-        self.s3_client
-            .copy_object(CopyObjectRequest {
-                bucket: self.bucket.clone(),
-                copy_source: get_s3_copy_source(&self.bucket, &current),
-                key: new,
-                ..Default::default()
-            })
-            .await?;
+    pub fn with_list_metadata(mut self, list_metadata: bool) -> S3Storage {
+        self.list_metadata = list_metadata;
+        self
+    }
 
-        self.remove_file(current).await?;
+    pub fn with_decompress_gzip(mut self, decompress_gzip: bool) -> S3Storage {
+        self.decompress_gzip = decompress_gzip;
+        self
+    }
 
-        Ok(())
+    pub fn with_case_insensitive_keys(mut self, case_insensitive_keys: bool) -> S3Storage {
+        self.case_insensitive_keys = case_insensitive_keys;
+        self
     }
 
-    async fn rename_dir(&self, current: String, new: String) -> Result<()> {
-        let current_prefix = get_s3_prefix(current);
-        let new_prefix = get_s3_prefix(new);
+    pub fn with_read_after_write_retries(mut self, read_after_write_retries: u32) -> S3Storage {
+        self.read_after_write_retries = read_after_write_retries;
+        self
+    }
 
-        let mut continuation_token = None;
+    pub fn with_delete_retries(mut self, delete_retries: u32) -> S3Storage {
+        self.delete_retries = delete_retries;
+        self
+    }
+
+    pub fn with_read_coalesce_window(mut self, read_coalesce_window: usize) -> S3Storage {
+        self.read_coalesce_window = read_coalesce_window;
+        self
+    }
+
+    pub fn with_follow_redirects(mut self, follow_redirects: bool) -> S3Storage {
+        self.follow_redirects = follow_redirects;
+        self
+    }
+
+    pub fn with_uploader_metadata(mut self, uploader_metadata: Option<String>) -> S3Storage {
+        self.uploader_metadata = uploader_metadata;
+        self
+    }
+
+    pub fn with_snapshot_dir(mut self, snapshot_dir: Option<String>) -> S3Storage {
+        self.snapshot_dir =
+            snapshot_dir.map(|snapshot_dir| snapshot_dir.trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Writes uploads to files in the directory until their handles are
+    /// closed, instead of sending parts to S3 as they fill.
+    pub fn with_write_spool_dir(mut self, write_spool_dir: Option<PathBuf>) -> S3Storage {
+        self.write_spool_dir = write_spool_dir;
+        self
+    }
+
+    /// Splits a path under the snapshot directory into the time of the snapshot
+    /// and the path of the file in the bucket, or returns `None` for any other
+    /// path. A snapshot time that is not RFC 3339 names no file.
+    fn parse_snapshot_path(&self, path: &str) -> Result<Option<(DateTime<Utc>, String)>> {
+        let snapshot_path = match self
+            .snapshot_dir
+            .as_deref()
+            .and_then(|snapshot_dir| path.strip_prefix(snapshot_dir))
+            .and_then(|snapshot_path| snapshot_path.strip_prefix('/'))
+        {
+            Some(snapshot_path) => snapshot_path,
+            None => return Ok(None),
+        };
+
+        let (snapshot_time, file_name) = snapshot_path.split_once('/').ok_or(Error::NoSuchFile)?;
+        let snapshot_time = snapshot_time
+            .parse::<DateTime<Utc>>()
+            .map_err(|_| Error::NoSuchFile)?;
+
+        Ok(Some((snapshot_time, format!("/{}", file_name))))
+    }
+
+    /// Refuses changes under the snapshot directory, which is read-only,
+    /// including paths with invalid snapshot times.
+    fn check_not_snapshot_path(&self, path: &str) -> Result<()> {
+        match self.parse_snapshot_path(path) {
+            Ok(None) => Ok(()),
+            _ => Err(Error::PermissionDenied.into()),
+        }
+    }
+
+    /// Finds the version of a key that was current at a point in time, or
+    /// `None` if the object did not exist or had been deleted at that time.
+    async fn find_version_at(
+        &self,
+        key: &str,
+        snapshot_time: DateTime<Utc>,
+    ) -> Result<Option<String>> {
+        let mut key_marker = None;
+        let mut version_id_marker = None;
+        // The newest version or delete marker at or before the snapshot time,
+        // where a delete marker has no version to read.
+        let mut current: Option<(DateTime<Utc>, Option<String>)> = None;
 
         loop {
-            let objects = self
+            self.backend_calls.record(BackendCall::List);
+            let versions = self
                 .s3_client
-                .list_objects_v2(ListObjectsV2Request {
-                    bucket: self.bucket.clone(),
-                    prefix: Some(current_prefix.clone()),
-                    continuation_token: continuation_token.clone(),
-                    delimiter: None,
+                .list_object_versions(ListObjectVersionsRequest {
+                    bucket: self.get_bucket(key).to_owned(),
+                    prefix: Some(key.to_owned()),
+                    key_marker: key_marker.clone(),
+                    version_id_marker: version_id_marker.clone(),
                     ..Default::default()
                 })
                 .await?;
 
-            continuation_token = objects.continuation_token;
-
-            if let Some(contents) = objects.contents {
-                let keys = contents.into_iter().filter_map(|content| content.key);
-
-                for key in keys {
-                    let destination = key.replace(&current_prefix, &new_prefix);
+            let object_versions = versions
+                .versions
+                .unwrap_or_default()
+                .into_iter()
+                .map(|version| (version.key, version.last_modified, version.version_id));
+            let delete_markers = versions
+                .delete_markers
+                .unwrap_or_default()
+                .into_iter()
+                .map(|delete_marker| (delete_marker.key, delete_marker.last_modified, None));
+
+            for (version_key, last_modified, version_id) in object_versions.chain(delete_markers) {
+                if version_key.as_deref() != Some(key) {
+                    continue;
+                }
 
-                    self.rename_file(key, destination).await?;
+                let last_modified = match last_modified
+                    .and_then(|last_modified| last_modified.parse::<DateTime<Utc>>().ok())
+                {
+                    Some(last_modified) if last_modified <= snapshot_time => last_modified,
+                    _ => continue,
+                };
+
+                if current
+                    .as_ref()
+                    .is_none_or(|(current_time, _)| last_modified > *current_time)
+                {
+                    current = Some((last_modified, version_id));
                 }
             }
 
-            if continuation_token.is_none() {
-                break;
+            if versions.is_truncated != Some(true) {
+                return Ok(current.and_then(|(_, version_id)| version_id));
             }
-        }
 
-        Ok(())
+            key_marker = versions.next_key_marker;
+            version_id_marker = versions.next_version_id_marker;
+        }
     }
-}
 
-#[async_trait]
-impl Storage for S3Storage {
-    fn get_home(&self, user: &str) -> String {
-        get_home(user)
-    }
+    /// Waits before retrying a lookup that found no object at a key written by
+    /// this storage and not yet seen since, returning false once the retries
+    /// are used up or if the key was not just written.
+    async fn should_retry_read_after_write(&self, key: &str, attempt: u32) -> bool {
+        if attempt >= self.read_after_write_retries
+            || !self.recent_writes.lock().unwrap().contains(key)
+        {
+            return false;
+        }
 
-    async fn health_check(&self) -> Result<()> {
-        self.s3_client
-            .head_bucket(HeadBucketRequest {
-                bucket: self.bucket.clone(),
-                ..Default::default()
-            })
-            .await?;
+        tokio::time::sleep(READ_AFTER_WRITE_RETRY_DELAY * 2u32.saturating_pow(attempt)).await;
 
-        Ok(())
+        true
     }
 
-    async fn get_authorized_keys_fingerprints(&self, user: &str) -> Result<Vec<String>> {
-        let authorized_keys_key = format!(".ssh/{}/authorized_keys", user);
-
-        let object = self
-            .s3_client
-            .get_object(GetObjectRequest {
-                bucket: self.bucket.clone(),
-                key: authorized_keys_key,
-                ..Default::default()
-            })
-            .await?;
-
-        let body = match object.body {
-            Some(body) => body,
-            None => return Ok(vec![]),
-        };
-
-        let mut buffer = String::new();
-        body.into_async_read().read_to_string(&mut buffer).await?;
-
-        Ok(ssh_keys::parse_authorized_keys(&buffer))
+    /// Stops retrying reads of a key, since it was seen or no longer exists.
+    fn forget_recent_write(&self, key: &str) {
+        self.recent_writes.lock().unwrap().remove(key);
     }
 
-    async fn open_dir_handle(&self, dir_name: String) -> Result<String> {
-        Ok(self
-            .handle_manager
-            .create_dir_handle(DirHandle {
-                prefix: dir_name,
-                continuation_token: None,
-                is_eof: false,
-            })
-            .await)
+    pub fn with_presigner(mut self, presigner: Presigner) -> S3Storage {
+        self.presigner = Some(presigner);
+        self
     }
 
-    async fn read_dir(&self, handle: &str) -> Result<Vec<File>> {
-        let dir_handle = match self.handle_manager.get_dir_handle(&handle).await {
-            Some(dir_handle) => dir_handle,
-            None => return Err(anyhow::anyhow!("Missing directory handle.")),
+    /// Finds a stored key that matches a key when both are case-folded by
+    /// listing the key's parent directory.
+    async fn find_case_insensitive_key(&self, key: &str) -> Result<Option<String>> {
+        let prefix = match key.rsplit_once('/') {
+            Some((parent, _)) => format!("{}/", parent),
+            None => String::new(),
         };
+        let folded_key = key.to_lowercase();
+        let mut continuation_token = None;
 
-        let mut dir_handle = dir_handle.lock().await;
-
-        if dir_handle.is_eof {
-            return Ok(Vec::new());
-        }
-
-        let prefix = get_s3_prefix(dir_handle.prefix.clone());
+        loop {
+            self.backend_calls.record(BackendCall::List);
+            let objects = self
+                .s3_client
+                .list_objects_v2(build_list_dir_request(
+                    self.get_bucket(&prefix),
+                    &prefix,
+                    continuation_token,
+                    self.list_page_size,
+                ))
+                .await?;
 
-        let objects = self
-            .s3_client
-            .list_objects_v2(ListObjectsV2Request {
-                bucket: self.bucket.clone(),
-                prefix: Some(prefix),
-                continuation_token: dir_handle.continuation_token.clone(),
-                delimiter: Some("/".to_owned()),
-                ..Default::default()
-            })
-            .await?;
+            let canonical_key = objects
+                .contents
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|object| object.key)
+                .find(|object_key| object_key.to_lowercase() == folded_key);
 
-        dir_handle.continuation_token = objects.next_continuation_token.clone();
-        dir_handle.is_eof = objects.next_continuation_token.is_none();
+            if canonical_key.is_some() {
+                return Ok(canonical_key);
+            }
 
-        Ok(map_list_objects_to_files(objects))
-    }
+            continuation_token = objects.next_continuation_token;
 
-    async fn create_dir(&self, _prefix: String) -> Result<()> {
-        /*
-            S3 does not support creating empty prefixes. The prefix is created when the
-            first object is added to it. This operation is a NO-OP to allow GUI-based
-            SFTP clients to make it appear that a directory has been created.
-        */
-        Ok(())
+            if continuation_token.is_none() {
+                return Ok(None);
+            }
+        }
     }
 
-    async fn remove_dir(&self, prefix: String) -> Result<()> {
-        let mut continuation_token = None;
+    async fn get_object(
+        &self,
+        key: &str,
+        version_id: Option<String>,
+    ) -> Result<GetObjectOutput, RusotoError<GetObjectError>> {
+        let mut attempt = 0;
 
         loop {
-            let objects = self
+            self.backend_calls.record(BackendCall::Get);
+            let get_object_response = self
                 .s3_client
-                .list_objects_v2(ListObjectsV2Request {
-                    bucket: self.bucket.clone(),
-                    prefix: Some(prefix.clone()),
-                    continuation_token: continuation_token.clone(),
-                    delimiter: None,
+                .get_object(GetObjectRequest {
+                    bucket: self.get_bucket(key).to_owned(),
+                    key: key.to_owned(),
+                    version_id: version_id.clone(),
                     ..Default::default()
                 })
-                .await?;
-
-            continuation_token = objects.continuation_token;
+                .await;
 
-            if let Some(contents) = objects.contents {
-                let keys = contents.into_iter().filter_map(|content| content.key);
+            match get_object_response {
+                Err(RusotoError::Service(GetObjectError::NoSuchKey(_)))
+                    if self.should_retry_read_after_write(key, attempt).await =>
+                {
+                    attempt += 1;
+                }
+                get_object_response => {
+                    if get_object_response.is_ok() {
+                        self.forget_recent_write(key);
+                    }
 
-                for key in keys {
-                    self.remove_file(key).await?;
+                    return get_object_response;
                 }
             }
+        }
+    }
 
-            if continuation_token.is_none() {
-                break;
+    /// Reads a user's configuration object under `.ssh/<user>/`, or `None` if
+    /// it does not exist or is empty.
+    async fn get_user_config(&self, user: &str, name: &str) -> Result<Option<String>> {
+        let object = match self
+            .get_object(&format!(".ssh/{}/{}", user, name), None)
+            .await
+        {
+            Ok(object) => object,
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+            Err(error) => return Err(anyhow::Error::from(error)),
+        };
+
+        let body = match object.body {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+
+        let mut buffer = String::new();
+        body.into_async_read().read_to_string(&mut buffer).await?;
+
+        Ok(Some(buffer))
+    }
+
+    /// Retrieves an object's metadata, or `None` if the object does not exist.
+    async fn head_file(&self, key: &str) -> Result<Option<File>> {
+        match self.head_object(key).await? {
+            Some(head_object_response) => {
+                let mut file = map_head_object_to_file(key, &head_object_response);
+
+                if let Some(uploader) = self.get_uploader(&head_object_response) {
+                    file.file_attributes
+                        .extended
+                        .insert(String::from(UPLOADER_ATTRIBUTE), uploader);
+                }
+
+                Ok(Some(file))
             }
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the attributes of the files in a listing with their metadata.
+    /// The listed modification time is kept for files without a stored one.
+    async fn add_listed_file_metadata(&self, prefix: &str, files: Vec<File>) -> Result<Vec<File>> {
+        let (dirs, mut files): (Vec<File>, Vec<File>) = files
+            .into_iter()
+            .partition(|file| file.file_attributes.is_dir());
+
+        let file_names = files
+            .iter()
+            .map(|file| format!("/{}{}", prefix, file.file_name))
+            .collect();
+
+        for (file, metadata) in files
+            .iter_mut()
+            .zip(self.get_many_metadata(file_names).await?)
+        {
+            let mtime = metadata
+                .file_attributes
+                .mtime
+                .or(file.file_attributes.mtime);
+            file.file_attributes = FileAttributes {
+                mtime,
+                ..metadata.file_attributes
+            };
         }
 
-        Ok(())
+        Ok(dirs.into_iter().chain(files).collect())
     }
 
-    async fn get_file_metadata(&self, file_name: String) -> Result<File> {
+    /// Retrieves an object's S3 headers, or `None` if the object does not exist.
+    async fn head_object(&self, key: &str) -> Result<Option<HeadObjectOutput>> {
+        self.head_object_version(key, None).await
+    }
+
+    /// Retrieves the S3 headers of a version of an object, or of its current
+    /// version if no version is given, or `None` if it does not exist.
+    async fn head_object_version(
+        &self,
+        key: &str,
+        version_id: Option<String>,
+    ) -> Result<Option<HeadObjectOutput>> {
+        self.backend_calls.record(BackendCall::Head);
         let head_object_response = self
             .s3_client
             .head_object(HeadObjectRequest {
-                bucket: self.bucket.clone(),
-                key: file_name.clone(),
+                bucket: self.get_bucket(key).to_owned(),
+                key: key.to_owned(),
+                version_id,
                 ..Default::default()
             })
             .await;
 
         match head_object_response {
-            Ok(head_object_response) => {
-                Ok(map_head_object_to_file(&file_name, &head_object_response))
-            }
+            Ok(head_object_response) => Ok(Some(head_object_response)),
             Err(error) => match error {
                 rusoto_core::RusotoError::Unknown(http_response) => {
                     if 404 == http_response.status.as_u16() {
-                        Ok(create_file_with_directory_bit(&file_name))
+                        Ok(None)
                     } else {
                         Err(anyhow::Error::from(rusoto_core::RusotoError::<
                             HeadObjectError,
@@ -324,302 +921,5021 @@ impl Storage for S3Storage {
         }
     }
 
-    async fn open_read_handle(&self, file_name: String) -> Result<String> {
-        let read_response = self
-            .s3_client
-            .get_object(GetObjectRequest {
-                bucket: self.bucket.clone(),
-                key: file_name,
-                ..Default::default()
-            })
-            .await?;
+    /// Reads the name of the user who uploaded an object from its metadata, if
+    /// uploads are stamped with it.
+    fn get_uploader(&self, head_object: &HeadObjectOutput) -> Option<String> {
+        let uploader_metadata = self.uploader_metadata.as_ref()?;
 
-        let read_stream = read_response
-            .body
-            .ok_or(Error::ServerError)?
-            .into_async_read();
+        head_object
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get(uploader_metadata))
+            .cloned()
+    }
 
-        Ok(self
-            .handle_manager
-            .create_read_handle(Box::pin(read_stream))
-            .await)
+    /// Builds the object metadata that holds a client-supplied modification
+    /// time, if modification times are preserved.
+    fn build_mtime_metadata(&self, mtime: Option<u32>) -> Option<HashMap<String, String>> {
+        match (self.preserve_mtime, mtime) {
+            (true, Some(mtime)) => Some(
+                vec![(String::from(MTIME_METADATA), mtime.to_string())]
+                    .into_iter()
+                    .collect(),
+            ),
+            _ => None,
+        }
     }
 
-    async fn read_data(&self, handle: &str, len: u32) -> Result<Vec<u8>> {
-        let read_handle = match self.handle_manager.get_read_handle(&handle).await {
-            Some(dir_handle) => dir_handle,
-            None => return Err(anyhow::anyhow!("Missing read handle.")),
+    /// Starts uploading a part in the background, so the client can keep
+    /// sending data while S3 receives the part.
+    fn start_part_upload(&self, write_handle: &mut WriteHandle, part: Vec<u8>) {
+        let part_number =
+            (write_handle.completed_parts.len() + write_handle.pending_uploads.len() + 1) as i64;
+        let length = part.len();
+
+        self.backend_calls.record(BackendCall::Put);
+        let s3_client = self.s3_client.clone();
+        let upload_part_request = UploadPartRequest {
+            bucket: self.get_bucket(&write_handle.key).to_owned(),
+            key: write_handle.key.clone(),
+            upload_id: write_handle.upload_id.clone(),
+            part_number,
+            body: Some(ByteStream::from(part)),
+            ..Default::default()
         };
 
-        let mut buffer = Vec::with_capacity(len as usize);
-
-        read_handle
-            .lock()
-            .await
-            .as_mut()
-            .take(len as u64)
-            .read_to_end(&mut buffer)
-            .await?;
-
-        Ok(buffer)
-    }
+        let upload = tokio::spawn(async move {
+            let upload_part_response = s3_client.upload_part(upload_part_request).await?;
 
-    async fn open_write_handle(&self, file_name: String) -> Result<String> {
-        let multipart_response = self
-            .s3_client
-            .create_multipart_upload(CreateMultipartUploadRequest {
-                bucket: self.bucket.clone(),
-                key: file_name,
-                ..Default::default()
+            Ok(CompletedPart {
+                e_tag: upload_part_response.e_tag,
+                part_number: Some(part_number),
             })
-            .await?;
-
-        let write_handle = map_create_multipart_response_to_write_handle(multipart_response)?;
+        });
 
-        Ok(self.handle_manager.create_write_handle(write_handle).await)
+        write_handle
+            .pending_uploads
+            .push_back(PendingUpload { length, upload });
     }
 
-    async fn write_data(&self, handle: &str, data: bytes::Bytes) -> Result<()> {
-        let write_handle = match self.handle_manager.get_write_handle(&handle).await {
-            Some(dir_handle) => dir_handle,
-            None => return Err(anyhow::anyhow!("Missing write handle.")),
+    /// Waits for the oldest part upload to finish.
+    async fn finish_part_upload(&self, write_handle: &mut WriteHandle) -> Result<()> {
+        let pending_upload = match write_handle.pending_uploads.pop_front() {
+            Some(pending_upload) => pending_upload,
+            None => return Ok(()),
         };
 
-        let mut write_handle = write_handle.lock().await;
-
-        write_handle.buffer.put(data);
-
-        if write_handle.buffer.len() > 10000000 {
-            self.complete_part_upload(&mut write_handle).await?;
-            write_handle.buffer.clear();
+        let upload_result = match pending_upload.upload.await {
+            Ok(upload_result) => upload_result,
+            Err(error) => Err(error.into()),
         };
 
-        Ok(())
+        match upload_result {
+            Ok(completed_part) => {
+                write_handle.completed_parts.push(completed_part);
+                Ok(())
+            }
+            Err(error) => {
+                // A file missing a part must never be completed, so the upload is
+                // aborted and the handle refuses any further writes.
+                write_handle.is_poisoned = true;
+                write_handle.pending_uploads.clear();
+                self.abort_upload(write_handle).await;
+                Err(error)
+            }
+        }
     }
 
-    async fn close_handle(&self, handle: &str) -> Result<()> {
-        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
-            let mut write_handle = write_handle.lock().await;
+    /// Adds data to the part being filled, uploading each part once it is full.
+    async fn buffer_data(&self, write_handle: &mut WriteHandle, data: &[u8]) -> Result<()> {
+        // Wait for parts to finish uploading rather than buffering without
+        // bound when the client sends data faster than S3 accepts it.
+        while write_handle.in_flight_bytes() + data.len() > self.max_write_buffer
+            && !write_handle.pending_uploads.is_empty()
+        {
+            self.finish_part_upload(write_handle).await?;
+        }
 
-            self.complete_part_upload(&mut write_handle).await?;
+        let parts = write_handle.buffer.push(data);
 
-            self.s3_client
-                .complete_multipart_upload(CompleteMultipartUploadRequest {
-                    bucket: self.bucket.clone(),
-                    key: write_handle.key.clone(),
-                    upload_id: write_handle.upload_id.clone(),
-                    multipart_upload: Some(CompletedMultipartUpload {
-                        parts: Some(write_handle.completed_parts.clone()),
-                    }),
-                    ..Default::default()
-                })
-                .await?;
+        for part in parts {
+            self.start_part_upload(write_handle, part);
         }
 
-        self.handle_manager.remove_handle(handle).await;
         Ok(())
     }
 
-    async fn remove_file(&self, file_name: String) -> Result<()> {
+    /// Uploads the data written to the handle's spool file one part at a time.
+    /// The file is removed once the spool is dropped.
+    async fn upload_spool(&self, write_handle: &mut WriteHandle) -> Result<()> {
+        let mut write_spool = match write_handle.spool.take() {
+            Some(write_spool) => write_spool,
+            None => return Ok(()),
+        };
+
+        write_spool.file.seek(SeekFrom::Start(0)).await?;
+
+        loop {
+            let mut part = Vec::with_capacity(self.part_size);
+            (&mut write_spool.file)
+                .take(self.part_size as u64)
+                .read_to_end(&mut part)
+                .await?;
+
+            if part.is_empty() {
+                return Ok(());
+            }
+
+            self.buffer_data(write_handle, &part).await?;
+        }
+    }
+
+    async fn complete_upload(&self, write_handle: &mut WriteHandle) -> Result<()> {
+        if write_handle.is_poisoned {
+            return Err(build_poisoned_write_handle_error());
+        }
+
+        self.upload_spool(write_handle).await?;
+
+        let part = write_handle.buffer.finish();
+
+        // S3 requires at least one part, so an empty file is uploaded as a
+        // single empty part.
+        if !part.is_empty()
+            || (write_handle.completed_parts.is_empty() && write_handle.pending_uploads.is_empty())
+        {
+            self.start_part_upload(write_handle, part);
+        }
+
+        while !write_handle.pending_uploads.is_empty() {
+            self.finish_part_upload(write_handle).await?;
+        }
+
+        self.backend_calls.record(BackendCall::Put);
+        self.s3_client
+            .complete_multipart_upload(CompleteMultipartUploadRequest {
+                bucket: self.get_bucket(&write_handle.key).to_owned(),
+                key: write_handle.key.clone(),
+                upload_id: write_handle.upload_id.clone(),
+                multipart_upload: Some(CompletedMultipartUpload {
+                    parts: Some(write_handle.completed_parts.clone()),
+                }),
+                ..Default::default()
+            })
+            .await?;
+
+        if !write_handle.preserved_tags.is_empty() {
+            self.backend_calls.record(BackendCall::Put);
+            self.s3_client
+                .put_object_tagging(PutObjectTaggingRequest {
+                    bucket: self.get_bucket(&write_handle.key).to_owned(),
+                    key: write_handle.key.clone(),
+                    tagging: Tagging {
+                        tag_set: write_handle.preserved_tags.clone(),
+                    },
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        if self.read_after_write_retries > 0 {
+            self.recent_writes
+                .lock()
+                .unwrap()
+                .insert(write_handle.key.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn abort_upload(&self, write_handle: &WriteHandle) {
+        self.backend_calls.record(BackendCall::Delete);
+        let abort_response = self
+            .s3_client
+            .abort_multipart_upload(AbortMultipartUploadRequest {
+                bucket: self.get_bucket(&write_handle.key).to_owned(),
+                key: write_handle.key.clone(),
+                upload_id: write_handle.upload_id.clone(),
+                ..Default::default()
+            })
+            .await;
+
+        if let Err(error) = abort_response {
+            error!(
+                "Failed to abort upload {} for {}: {}",
+                write_handle.upload_id, write_handle.key, error
+            );
+        }
+    }
+
+    /// Reads the bytes of an object from start up to but excluding end.
+    async fn get_object_range(&self, source: &CopySource, start: u64, end: u64) -> Result<Vec<u8>> {
+        self.backend_calls.record(BackendCall::Get);
+        let range_response = self
+            .s3_client
+            .get_object(GetObjectRequest {
+                bucket: self.get_bucket(&source.key).to_owned(),
+                key: source.key.clone(),
+                version_id: source.version_id.clone(),
+                range: Some(format!("bytes={}-{}", start, end - 1)),
+                ..Default::default()
+            })
+            .await?;
+
+        let mut data = Vec::with_capacity((end - start) as usize);
+        range_response
+            .body
+            .ok_or(Error::ServerError)?
+            .into_async_read()
+            .read_to_end(&mut data)
+            .await?;
+
+        Ok(data)
+    }
+
+    /// Aborts the multipart uploads under a prefix of a bucket that were
+    /// initiated at or before the cutoff, returning how many were aborted.
+    /// Failing to abort one upload is logged and does not stop the others.
+    async fn abort_uploads_initiated_before(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+        cutoff: DateTime<Utc>,
+    ) -> Result<usize> {
+        let mut key_marker = None;
+        let mut upload_id_marker = None;
+        let mut aborted = 0;
+
+        loop {
+            self.backend_calls.record(BackendCall::List);
+            let uploads = self
+                .s3_client
+                .list_multipart_uploads(ListMultipartUploadsRequest {
+                    bucket: bucket.to_owned(),
+                    prefix: prefix.map(str::to_owned),
+                    key_marker: key_marker.clone(),
+                    upload_id_marker: upload_id_marker.clone(),
+                    ..Default::default()
+                })
+                .await?;
+
+            for upload in uploads.uploads.unwrap_or_default() {
+                let (key, upload_id) = match (upload.key, upload.upload_id) {
+                    (Some(key), Some(upload_id)) => (key, upload_id),
+                    _ => continue,
+                };
+
+                match upload
+                    .initiated
+                    .and_then(|initiated| initiated.parse::<DateTime<Utc>>().ok())
+                {
+                    Some(initiated) if initiated <= cutoff => {}
+                    _ => continue,
+                }
+
+                self.backend_calls.record(BackendCall::Delete);
+                let abort_response = self
+                    .s3_client
+                    .abort_multipart_upload(AbortMultipartUploadRequest {
+                        bucket: bucket.to_owned(),
+                        key: key.clone(),
+                        upload_id: upload_id.clone(),
+                        ..Default::default()
+                    })
+                    .await;
+
+                match abort_response {
+                    Ok(_) => {
+                        info!("Aborted orphaned upload {} for {}", upload_id, key);
+                        aborted += 1;
+                    }
+                    Err(error) => error!(
+                        "Failed to abort orphaned upload {} for {}: {}",
+                        upload_id, key, error
+                    ),
+                }
+            }
+
+            if uploads.is_truncated != Some(true) {
+                return Ok(aborted);
+            }
+
+            key_marker = uploads.next_key_marker;
+            upload_id_marker = uploads.next_upload_id_marker;
+        }
+    }
+
+    /// Closes a directory handle along with the continuation token of its
+    /// listing, which is no longer needed to resume the listing.
+    async fn close_dir_handle(&self, handle: &str) -> Result<()> {
+        self.dir_checkpoints.remove(handle).await;
+        self.handle_manager.remove_handle(handle).await;
+        Ok(())
+    }
+
+    /// Returns the error for a request on a handle that is not open, which is
+    /// SSH_FX_NO_SUCH_FILE if the object behind the handle was removed or renamed.
+    async fn missing_handle_error(&self, handle: &str, handle_type: &str) -> anyhow::Error {
+        match self.handle_manager.is_invalidated(handle).await {
+            true => Error::NoSuchFile.into(),
+            false => anyhow::anyhow!("Missing {} handle.", handle_type),
+        }
+    }
+
+    async fn open_object_read_handle(
+        &self,
+        file_name: String,
+        version_id: Option<String>,
+    ) -> Result<String> {
+        let key = resolve_key(&file_name)?;
+
+        let (source_key, read_response) = match self.get_object(&key, version_id.clone()).await {
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_)))
+                if self.case_insensitive_keys =>
+            {
+                match self.find_case_insensitive_key(&key).await? {
+                    Some(canonical_key) => {
+                        let read_response =
+                            self.get_object(&canonical_key, version_id.clone()).await?;
+                        (canonical_key, read_response)
+                    }
+                    None => return Err(Error::NoSuchFile.into()),
+                }
+            }
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => {
+                return Err(Error::NoSuchFile.into())
+            }
+            read_response => (key.clone(), read_response?),
+        };
+
+        let mut copy_source = Some(CopySource {
+            key: source_key,
+            version_id,
+            size: read_response.content_length.unwrap_or_default(),
+        });
+
+        let read_response = match (
+            self.follow_redirects,
+            &read_response.website_redirect_location,
+        ) {
+            (true, Some(location)) => {
+                copy_source = None;
+                self.get_redirect_target(&key, location).await?
+            }
+            _ => read_response,
+        };
+
+        let is_gzip_encoded = read_response.content_encoding.as_deref() == Some("gzip");
+        let mut remaining = read_response
+            .content_length
+            .and_then(|content_length| u64::try_from(content_length).ok());
+        let read_stream = read_response
+            .body
+            .ok_or(Error::ServerError)?
+            .into_async_read();
+
+        let stream: Pin<Box<dyn AsyncRead + Send>> = match self.decompress_gzip && is_gzip_encoded {
+            true => {
+                // The decoded stream no longer matches the stored bytes.
+                copy_source = None;
+                remaining = None;
+                Box::pin(GzipDecoder::new(read_stream))
+            }
+            false => Box::pin(read_stream),
+        };
+
+        Ok(self
+            .handle_manager
+            .create_read_handle(
+                &file_name,
+                ReadHandle {
+                    stream,
+                    remaining,
+                    position: 0,
+                    copy_source,
+                    fetched_range: None,
+                },
+            )
+            .await)
+    }
+
+    /// Reads the object that a website redirect location points to. Only one
+    /// redirect is followed, so a target that redirects again, including back
+    /// to the original object, is refused rather than followed in a loop.
+    async fn get_redirect_target(&self, key: &str, location: &str) -> Result<GetObjectOutput> {
+        // Redirects to other websites cannot be read from the bucket.
+        if !location.starts_with('/') {
+            warn!("Not following redirect from {} to {}", key, location);
+            return Err(Error::ServerError.into());
+        }
+
+        let target_key = resolve_key(location)?;
+        let target = self.get_object(&target_key, None).await?;
+
+        if target.website_redirect_location.is_some() {
+            warn!(
+                "Not following redirect from {} to {}, which redirects again",
+                key, target_key
+            );
+            return Err(Error::ServerError.into());
+        }
+
+        Ok(target)
+    }
+
+    async fn rename_object(&self, current_key: String, new_key: String) -> Result<()> {
+        self.backend_calls.record(BackendCall::Put);
+        self.s3_client
+            .copy_object(CopyObjectRequest {
+                bucket: self.get_bucket(&new_key).to_owned(),
+                copy_source: get_s3_copy_source(self.get_bucket(&current_key), &current_key),
+                key: new_key,
+                storage_class: map_storage_class(self.storage_class),
+                ..Default::default()
+            })
+            .await?;
+
+        self.delete_object(current_key).await?;
+
+        Ok(())
+    }
+
+    async fn delete_object(&self, key: String) -> Result<()> {
+        self.backend_calls.record(BackendCall::Delete);
         self.s3_client
             .delete_object(DeleteObjectRequest {
-                bucket: self.bucket.clone(),
-                key: file_name,
+                bucket: self.get_bucket(&key).to_owned(),
+                key,
                 ..Default::default()
             })
             .await?;
 
-        Ok(())
+        Ok(())
+    }
+
+    /// Deletes a page of up to 1000 objects, retrying the objects that S3
+    /// fails to delete with a transient error. The keys that still fail after
+    /// the retries, or that fail with any other error, are logged, and the
+    /// error tells the client how many objects remain.
+    async fn delete_objects(&self, bucket: &str, keys: Vec<String>) -> Result<()> {
+        let total_keys = keys.len();
+        let mut keys = keys;
+        let mut attempt = 0;
+
+        loop {
+            let delete_errors = self.try_delete_objects(bucket, keys).await?;
+
+            if delete_errors.is_empty() {
+                return Ok(());
+            }
+
+            let is_retryable = delete_errors.iter().all(|delete_error| {
+                delete_error.key.is_some()
+                    && delete_error
+                        .code
+                        .as_deref()
+                        .is_some_and(|code| TRANSIENT_DELETE_ERROR_CODES.contains(&code))
+            });
+
+            if !is_retryable || attempt >= self.delete_retries {
+                error!(
+                    "Failed to delete objects after {} retries: {}",
+                    attempt,
+                    delete_errors
+                        .iter()
+                        .map(|delete_error| format!(
+                            "{} ({})",
+                            delete_error.key.as_deref().unwrap_or(""),
+                            delete_error.message.as_deref().unwrap_or("")
+                        ))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+
+                return Err(anyhow::Error::new(Error::ServerError).context(format!(
+                    "Failed to delete {} of {} objects.",
+                    delete_errors.len(),
+                    total_keys
+                )));
+            }
+
+            warn!(
+                "Retrying {} objects that failed to delete",
+                delete_errors.len()
+            );
+            tokio::time::sleep(DELETE_RETRY_DELAY * 2u32.saturating_pow(attempt)).await;
+
+            keys = delete_errors
+                .into_iter()
+                .filter_map(|delete_error| delete_error.key)
+                .collect();
+            attempt += 1;
+        }
+    }
+
+    /// Deletes up to 1000 objects in a single request, returning an error for
+    /// each object that S3 failed to delete.
+    async fn try_delete_objects(&self, bucket: &str, keys: Vec<String>) -> Result<Vec<S3Error>> {
+        self.backend_calls.record(BackendCall::Delete);
+        let delete_response = self
+            .s3_client
+            .delete_objects(DeleteObjectsRequest {
+                bucket: bucket.to_owned(),
+                delete: Delete {
+                    objects: keys
+                        .into_iter()
+                        .map(|key| ObjectIdentifier {
+                            key,
+                            version_id: None,
+                        })
+                        .collect(),
+                    quiet: Some(true),
+                },
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(delete_response.errors.unwrap_or_default())
+    }
+
+    async fn get_object_tags(&self, key: &str) -> Result<BTreeMap<String, String>> {
+        self.backend_calls.record(BackendCall::Get);
+        let tagging = self
+            .s3_client
+            .get_object_tagging(GetObjectTaggingRequest {
+                bucket: self.get_bucket(key).to_owned(),
+                key: key.to_owned(),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(map_tags_to_extended_attributes(tagging.tag_set))
+    }
+
+    async fn rename_dir(&self, current: &str, new: &str) -> Result<()> {
+        let current_prefix = get_s3_prefix(current)?;
+        let new_prefix = get_s3_prefix(new)?;
+
+        let mut continuation_token = None;
+
+        loop {
+            self.backend_calls.record(BackendCall::List);
+            let objects = self
+                .s3_client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.get_bucket(&current_prefix).to_owned(),
+                    prefix: Some(current_prefix.clone()),
+                    continuation_token: continuation_token.clone(),
+                    delimiter: None,
+                    ..Default::default()
+                })
+                .await?;
+
+            continuation_token = objects.continuation_token;
+
+            if let Some(contents) = objects.contents {
+                let keys = contents.into_iter().filter_map(|content| content.key);
+
+                for key in keys {
+                    let destination = key.replacen(&current_prefix, &new_prefix, 1);
+
+                    self.rename_object(key, destination).await?;
+                }
+            }
+
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    fn get_home(&self, user: &str) -> String {
+        get_home(user)
+    }
+
+    fn backend_calls(&self) -> Option<&BackendCallCounts> {
+        Some(&self.backend_calls)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        let buckets = std::iter::once(&self.bucket)
+            .chain(self.bucket_prefixes.iter().map(|(_, bucket)| bucket));
+
+        for bucket in buckets {
+            self.backend_calls.record(BackendCall::Head);
+            self.s3_client
+                .head_bucket(HeadBucketRequest {
+                    bucket: bucket.clone(),
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn cleanup_orphaned_uploads(&self, max_age: Duration) -> Result<usize> {
+        let cutoff = Utc::now() - chrono::Duration::from_std(max_age)?;
+        let mut aborted = self
+            .abort_uploads_initiated_before(&self.bucket, None, cutoff)
+            .await?;
+
+        // Mapped prefixes in the default bucket were already covered above.
+        for (prefix, bucket) in &self.bucket_prefixes {
+            if *bucket != self.bucket {
+                aborted += self
+                    .abort_uploads_initiated_before(bucket, Some(prefix), cutoff)
+                    .await?;
+            }
+        }
+
+        Ok(aborted)
+    }
+
+    async fn get_authorized_keys_fingerprints(&self, user: &str) -> Result<Vec<String>> {
+        let authorized_keys_key = format!(".ssh/{}/authorized_keys", user);
+
+        self.backend_calls.record(BackendCall::Get);
+        let object = self
+            .s3_client
+            .get_object(GetObjectRequest {
+                bucket: self.get_bucket(&authorized_keys_key).to_owned(),
+                key: authorized_keys_key,
+                ..Default::default()
+            })
+            .await?;
+
+        let body = match object.body {
+            Some(body) => body,
+            None => return Ok(vec![]),
+        };
+
+        let mut buffer = String::new();
+        body.into_async_read().read_to_string(&mut buffer).await?;
+
+        Ok(ssh_keys::parse_authorized_keys(&buffer))
+    }
+
+    async fn get_user_permissions(&self, user: &str) -> Result<UserPermissions> {
+        match self.get_user_config(user, "permissions").await? {
+            Some(user_permissions) => permissions::parse_user_permissions(&user_permissions),
+            None => Ok(UserPermissions::read_write()),
+        }
+    }
+
+    async fn get_visible_prefixes(&self, user: &str) -> Result<Option<Vec<String>>> {
+        Ok(self
+            .get_user_config(user, "visible_prefixes")
+            .await?
+            .map(|visible_prefixes| permissions::parse_visible_prefixes(&visible_prefixes)))
+    }
+
+    async fn get_user_quota(&self, user: &str) -> Result<Option<u64>> {
+        match self.get_user_config(user, "quota").await? {
+            Some(quota) => Ok(Some(permissions::parse_quota(&quota)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_used_bytes(&self, dir_name: String) -> Result<u64> {
+        let prefix = get_s3_prefix(&dir_name)?;
+        let mut continuation_token = None;
+        let mut used_bytes = 0;
+
+        loop {
+            self.backend_calls.record(BackendCall::List);
+            let objects = self
+                .s3_client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.get_bucket(&prefix).to_owned(),
+                    prefix: Some(prefix.clone()),
+                    continuation_token: continuation_token.clone(),
+                    ..Default::default()
+                })
+                .await?;
+
+            used_bytes += objects
+                .contents
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|object| object.size)
+                .map(|size| size as u64)
+                .sum::<u64>();
+
+            continuation_token = objects.next_continuation_token;
+
+            if continuation_token.is_none() {
+                return Ok(used_bytes);
+            }
+        }
+    }
+
+    async fn open_dir_handle(&self, dir_name: String) -> Result<String> {
+        Ok(self
+            .handle_manager
+            .create_dir_handle(DirHandle {
+                prefix: get_s3_prefix(&dir_name)?,
+                continuation_token: None,
+                is_eof: false,
+            })
+            .await)
+    }
+
+    async fn resume_dir_handle(&self, dir_name: String, previous_handle: &str) -> Result<String> {
+        let prefix = get_s3_prefix(&dir_name)?;
+
+        let continuation_token = match self.dir_checkpoints.take(previous_handle, &prefix).await {
+            Some(continuation_token) => continuation_token,
+            None => return Err(anyhow::anyhow!("Missing directory checkpoint.")),
+        };
+
+        Ok(self
+            .handle_manager
+            .create_dir_handle(DirHandle {
+                prefix,
+                continuation_token: Some(continuation_token),
+                is_eof: false,
+            })
+            .await)
+    }
+
+    async fn read_dir(&self, handle: &str) -> Result<DirPage> {
+        let dir_handle = match self.handle_manager.get_dir_handle(handle).await {
+            Some(dir_handle) => dir_handle,
+            None => return Err(anyhow::anyhow!("Missing directory handle.")),
+        };
+
+        let mut dir_handle = dir_handle.lock().await;
+
+        if dir_handle.is_eof {
+            return Ok(DirPage {
+                files: Vec::new(),
+                has_more: false,
+            });
+        }
+
+        let prefix = dir_handle.prefix.clone();
+
+        // Keep listing until entries are found or the listing ends, so an empty
+        // directory is reported as EOF instead of an empty page of entries.
+        loop {
+            self.backend_calls.record(BackendCall::List);
+            let objects = self
+                .s3_client
+                .list_objects_v2(build_list_dir_request(
+                    self.get_bucket(&prefix),
+                    &prefix,
+                    dir_handle.continuation_token.clone(),
+                    self.list_page_size,
+                ))
+                .await?;
+
+            dir_handle.continuation_token = objects.next_continuation_token.clone();
+            dir_handle.is_eof = objects.next_continuation_token.is_none();
+
+            self.dir_checkpoints
+                .save(
+                    handle,
+                    &dir_handle.prefix,
+                    dir_handle.continuation_token.clone(),
+                )
+                .await;
+
+            let mut files = map_list_objects_to_files(&prefix, objects);
+
+            if self.list_metadata {
+                files = self.add_listed_file_metadata(&prefix, files).await?;
+            }
+
+            if !files.is_empty() || dir_handle.is_eof {
+                return Ok(DirPage {
+                    files,
+                    has_more: !dir_handle.is_eof,
+                });
+            }
+        }
+    }
+
+    async fn create_dir(&self, _prefix: String) -> Result<()> {
+        /*
+            S3 does not support creating empty prefixes. The prefix is created when the
+            first object is added to it. This operation is a NO-OP to allow GUI-based
+            SFTP clients to make it appear that a directory has been created.
+        */
+        Ok(())
+    }
+
+    async fn create_home(&self, user: &str) -> Result<()> {
+        let prefix = get_s3_prefix(&get_home(user))?;
+
+        self.backend_calls.record(BackendCall::List);
+        let objects = self
+            .s3_client
+            .list_objects_v2(ListObjectsV2Request {
+                bucket: self.get_bucket(&prefix).to_owned(),
+                prefix: Some(prefix.clone()),
+                max_keys: Some(1),
+                ..Default::default()
+            })
+            .await?;
+
+        if objects
+            .contents
+            .is_some_and(|contents| !contents.is_empty())
+        {
+            return Ok(());
+        }
+
+        self.backend_calls.record(BackendCall::Put);
+        self.s3_client
+            .put_object(PutObjectRequest {
+                bucket: self.get_bucket(&prefix).to_owned(),
+                key: prefix,
+                body: Some(ByteStream::from(Vec::new())),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn remove_dir(&self, dir_name: String) -> Result<()> {
+        let prefix = get_s3_prefix(&dir_name)?;
+        let bucket = self.get_bucket(&prefix);
+        let mut continuation_token = None;
+
+        loop {
+            self.backend_calls.record(BackendCall::List);
+            let objects = self
+                .s3_client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: bucket.to_owned(),
+                    prefix: Some(prefix.clone()),
+                    continuation_token: continuation_token.clone(),
+                    delimiter: None,
+                    ..Default::default()
+                })
+                .await?;
+
+            continuation_token = objects.next_continuation_token;
+
+            let keys: Vec<String> = objects
+                .contents
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|content| content.key)
+                .collect();
+
+            if self.rmdir_require_empty && !keys.is_empty() {
+                return Err(Error::ServerError.into());
+            }
+
+            if !keys.is_empty() {
+                self.delete_objects(bucket, keys).await?;
+            }
+
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        self.handle_manager.invalidate_path(&dir_name).await;
+
+        Ok(())
+    }
+
+    async fn get_file_metadata(&self, file_name: String) -> Result<File> {
+        if let Some((snapshot_time, file_name)) = self.parse_snapshot_path(&file_name)? {
+            let key = resolve_key(&file_name)?;
+
+            // Paths without a version at the snapshot time are directories,
+            // like paths without an object outside of snapshots.
+            let version_id = match self.find_version_at(&key, snapshot_time).await? {
+                Some(version_id) => version_id,
+                None => return Ok(create_file_with_directory_bit(&key)),
+            };
+
+            return match self.head_object_version(&key, Some(version_id)).await? {
+                Some(head_object) => Ok(map_head_object_to_file(&key, &head_object)),
+                None => Err(Error::NoSuchFile.into()),
+            };
+        }
+
+        let key = resolve_key(&file_name)?;
+        let mut attempt = 0;
+
+        loop {
+            if let Some(file) = self.head_file(&key).await? {
+                self.forget_recent_write(&key);
+                return Ok(file);
+            }
+
+            if !self.should_retry_read_after_write(&key, attempt).await {
+                break;
+            }
+
+            attempt += 1;
+        }
+
+        if self.case_insensitive_keys {
+            if let Some(canonical_key) = self.find_case_insensitive_key(&key).await? {
+                if let Some(file) = self.head_file(&canonical_key).await? {
+                    return Ok(file);
+                }
+            }
+        }
+
+        Ok(create_file_with_directory_bit(&key))
+    }
+
+    async fn get_extended_attributes(&self, file_name: String) -> Result<BTreeMap<String, String>> {
+        // Tags are not kept for snapshots, which are read from old versions.
+        if self.parse_snapshot_path(&file_name)?.is_some() {
+            return Ok(BTreeMap::new());
+        }
+
+        let mut key = resolve_key(&file_name)?;
+
+        if self.case_insensitive_keys && self.head_object(&key).await?.is_none() {
+            if let Some(canonical_key) = self.find_case_insensitive_key(&key).await? {
+                key = canonical_key;
+            }
+        }
+
+        self.get_object_tags(&key).await
+    }
+
+    async fn set_extended_attributes(
+        &self,
+        file_name: String,
+        mut extended: BTreeMap<String, String>,
+    ) -> Result<()> {
+        // The version id and uploader are reported from S3 headers rather than
+        // stored as tags, so they are dropped when clients send back the
+        // attributes they read.
+        extended.remove(VERSION_ID_ATTRIBUTE);
+        extended.remove(UPLOADER_ATTRIBUTE);
+
+        self.check_not_snapshot_path(&file_name)?;
+        let key = resolve_key(&file_name)?;
+
+        self.backend_calls.record(BackendCall::Put);
+        self.s3_client
+            .put_object_tagging(PutObjectTaggingRequest {
+                bucket: self.get_bucket(&key).to_owned(),
+                key,
+                tagging: Tagging {
+                    tag_set: map_extended_attributes_to_tags(extended),
+                },
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_permissions(&self, file_name: String, permissions: u32) -> Result<()> {
+        self.check_not_snapshot_path(&file_name)?;
+
+        let acl = match map_permissions_to_acl(Some(permissions), self.public_read_permissions) {
+            Some(acl) => acl,
+            None => return Err(Error::Unimplemented.into()),
+        };
+
+        let key = resolve_key(&file_name)?;
+
+        self.backend_calls.record(BackendCall::Put);
+        self.s3_client
+            .put_object_acl(PutObjectAclRequest {
+                bucket: self.get_bucket(&key).to_owned(),
+                key,
+                acl: Some(acl),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// S3 only allows metadata to be changed by copying an object onto itself,
+    /// which also resets its other metadata.
+    async fn set_mtime(&self, file_name: String, mtime: u32) -> Result<()> {
+        self.check_not_snapshot_path(&file_name)?;
+
+        let metadata = match self.build_mtime_metadata(Some(mtime)) {
+            Some(metadata) => metadata,
+            None => return Err(Error::Unimplemented.into()),
+        };
+
+        let key = resolve_key(&file_name)?;
+
+        self.backend_calls.record(BackendCall::Put);
+        self.s3_client
+            .copy_object(CopyObjectRequest {
+                bucket: self.get_bucket(&key).to_owned(),
+                copy_source: get_s3_copy_source(self.get_bucket(&key), &key),
+                key,
+                metadata: Some(metadata),
+                metadata_directive: Some(String::from("REPLACE")),
+                storage_class: map_storage_class(self.storage_class),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn dir_exists(&self, dir_name: String) -> Result<bool> {
+        let prefix = get_s3_prefix(&dir_name)?;
+
+        if prefix.is_empty() {
+            return Ok(true);
+        }
+
+        self.backend_calls.record(BackendCall::List);
+        let objects = self
+            .s3_client
+            .list_objects_v2(ListObjectsV2Request {
+                bucket: self.get_bucket(&prefix).to_owned(),
+                prefix: Some(prefix.clone()),
+                max_keys: Some(1),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(objects
+            .contents
+            .is_some_and(|contents| !contents.is_empty()))
+    }
+
+    async fn file_exists(&self, file_name: String) -> Result<bool> {
+        match self.parse_snapshot_path(&file_name)? {
+            Some((snapshot_time, file_name)) => Ok(self
+                .find_version_at(&resolve_key(&file_name)?, snapshot_time)
+                .await?
+                .is_some()),
+            None => Ok(self.head_object(&resolve_key(&file_name)?).await?.is_some()),
+        }
+    }
+
+    async fn open_read_handle(&self, file_name: String) -> Result<String> {
+        let (snapshot_time, file_name) = match self.parse_snapshot_path(&file_name)? {
+            Some(snapshot) => snapshot,
+            None => return self.open_object_read_handle(file_name, None).await,
+        };
+
+        match self
+            .find_version_at(&resolve_key(&file_name)?, snapshot_time)
+            .await?
+        {
+            Some(version_id) => {
+                self.open_object_read_handle(file_name, Some(version_id))
+                    .await
+            }
+            None => Err(Error::NoSuchFile.into()),
+        }
+    }
+
+    async fn open_version_read_handle(
+        &self,
+        file_name: String,
+        version_id: String,
+    ) -> Result<String> {
+        self.open_object_read_handle(file_name, Some(version_id))
+            .await
+    }
+
+    async fn read_link(&self, file_name: String) -> Result<String> {
+        match self.head_object(&resolve_key(&file_name)?).await? {
+            Some(head_object) => head_object
+                .website_redirect_location
+                .ok_or_else(|| Error::ServerError.into()),
+            None => Err(Error::NoSuchFile.into()),
+        }
+    }
+
+    async fn presign_download(&self, file_name: String) -> Result<String> {
+        let presigner = self.presigner.as_ref().ok_or(Error::Unimplemented)?;
+        let credentials = presigner.credentials_provider.credentials().await?;
+
+        let key = resolve_key(&file_name)?;
+
+        let get_object_request = GetObjectRequest {
+            bucket: self.get_bucket(&key).to_owned(),
+            key,
+            ..Default::default()
+        };
+
+        Ok(get_object_request.get_presigned_url(
+            &presigner.region,
+            &credentials,
+            &PreSignedRequestOption {
+                expires_in: presigner.expiry,
+            },
+        ))
+    }
+
+    async fn read_data(&self, handle: &str, len: u32) -> Result<Vec<u8>> {
+        let read_handle = match self.handle_manager.get_read_handle(handle).await {
+            Some(dir_handle) => dir_handle,
+            None => return Err(self.missing_handle_error(handle, "read").await),
+        };
+
+        let mut read_handle = read_handle.lock().await;
+
+        read_buffered(&mut read_handle, len).await
+    }
+
+    async fn read_data_stream(&self, handle: &str, len: u32) -> Result<ReadData> {
+        let read_handle = match self.handle_manager.get_read_handle(handle).await {
+            Some(read_handle) => read_handle,
+            None => return Err(self.missing_handle_error(handle, "read").await),
+        };
+
+        // The handle stays locked until the stream ends, so later reads of the
+        // handle continue where it ends.
+        let mut read_handle = read_handle.lock_owned().await;
+
+        let streamed_len = match read_handle.remaining {
+            Some(remaining) => remaining.min(len as u64) as u32,
+            None => 0,
+        };
+
+        if streamed_len < MIN_STREAMED_READ_LENGTH {
+            return Ok(ReadData::Buffered(
+                read_buffered(&mut read_handle, len).await?,
+            ));
+        }
+
+        read_handle.remaining = read_handle
+            .remaining
+            .map(|remaining| remaining - streamed_len as u64);
+        read_handle.position += streamed_len as u64;
+
+        let chunks = futures::stream::try_unfold(
+            (read_handle, streamed_len as usize),
+            |(mut read_handle, unread_len)| async move {
+                if unread_len == 0 {
+                    return Ok(None);
+                }
+
+                let mut chunk = vec![0; unread_len.min(READ_CHUNK_LENGTH)];
+                let chunk_len = read_handle.stream.as_mut().read(&mut chunk).await?;
+
+                // The length was already sent, so the stream cannot end early.
+                if chunk_len == 0 {
+                    return Err(anyhow::anyhow!(
+                        "Object ended {} bytes before its content length.",
+                        unread_len
+                    ));
+                }
+
+                chunk.truncate(chunk_len);
+
+                Ok(Some((
+                    bytes::Bytes::from(chunk),
+                    (read_handle, unread_len - chunk_len),
+                )))
+            },
+        );
+
+        Ok(ReadData::Streamed {
+            len: streamed_len,
+            chunks: Box::pin(chunks),
+        })
+    }
+
+    async fn read_data_at(&self, handle: &str, offset: u64, len: u32) -> Result<ReadData> {
+        let read_handle = match self.handle_manager.get_read_handle(handle).await {
+            Some(read_handle) => read_handle,
+            None => return Err(self.missing_handle_error(handle, "read").await),
+        };
+
+        let mut read_handle = read_handle.lock().await;
+
+        // Reads in order continue the stream, as do reads of streams that can
+        // only be read in order.
+        let copy_source = match &read_handle.copy_source {
+            Some(copy_source) if offset != read_handle.position => copy_source,
+            _ => {
+                drop(read_handle);
+                return self.read_data_stream(handle, len).await;
+            }
+        };
+
+        let size = u64::try_from(copy_source.size).unwrap_or_default();
+        let end = offset.saturating_add(len as u64).min(size);
+
+        if offset >= end {
+            return Ok(ReadData::Buffered(Vec::new()));
+        }
+
+        let is_fetched = matches!(
+            &read_handle.fetched_range,
+            Some((start, data)) if *start <= offset && end <= start + data.len() as u64
+        );
+
+        if !is_fetched {
+            let fetch_end = offset
+                .saturating_add(self.read_coalesce_window as u64)
+                .max(end)
+                .min(size);
+            let data = self
+                .get_object_range(copy_source, offset, fetch_end)
+                .await?;
+            read_handle.fetched_range = Some((offset, data));
+        }
+
+        let data = match &read_handle.fetched_range {
+            Some((start, data)) => {
+                let from = ((offset - start) as usize).min(data.len());
+                let to = ((end - start) as usize).min(data.len());
+                data[from..to].to_vec()
+            }
+            None => Vec::new(),
+        };
+
+        Ok(ReadData::Buffered(data))
+    }
+
+    async fn open_write_handle(
+        &self,
+        file_name: String,
+        file_attributes: FileAttributes,
+        preserve_metadata: bool,
+        uploader: &str,
+    ) -> Result<String> {
+        self.check_not_snapshot_path(&file_name)?;
+
+        let key = resolve_key(&file_name)?;
+
+        let mut metadata = self.build_mtime_metadata(file_attributes.mtime);
+
+        if let Some(uploader_metadata) = &self.uploader_metadata {
+            metadata
+                .get_or_insert_with(HashMap::new)
+                .insert(uploader_metadata.clone(), uploader.to_owned());
+        }
+
+        let mut create_multipart_upload_request = build_create_multipart_upload_request(
+            self.get_bucket(&key),
+            key.clone(),
+            self.storage_class,
+            map_permissions_to_acl(file_attributes.permissions, self.public_read_permissions),
+            metadata,
+        );
+
+        // S3 objects cannot be modified in place, so the rewritten object would
+        // lose the headers and tags of the original unless they are copied.
+        let existing_object = match preserve_metadata {
+            true => self.head_object(&key).await?,
+            false => None,
+        };
+
+        let preserved_tags = match existing_object {
+            Some(existing_object) => {
+                apply_existing_object_headers(
+                    &mut create_multipart_upload_request,
+                    existing_object,
+                );
+                map_extended_attributes_to_tags(self.get_object_tags(&key).await?)
+            }
+            None => Vec::new(),
+        };
+
+        self.backend_calls.record(BackendCall::Put);
+        let multipart_response = self
+            .s3_client
+            .create_multipart_upload(create_multipart_upload_request)
+            .await?;
+
+        let mut write_handle =
+            map_create_multipart_response_to_write_handle(multipart_response, self.part_size)?;
+        write_handle.preserved_tags = preserved_tags;
+
+        if let Some(write_spool_dir) = &self.write_spool_dir {
+            match WriteSpool::create(write_spool_dir).await {
+                Ok(write_spool) => write_handle.spool = Some(write_spool),
+                Err(error) => {
+                    self.abort_upload(&write_handle).await;
+                    return Err(error);
+                }
+            }
+        }
+
+        Ok(self
+            .handle_manager
+            .create_write_handle(&file_name, write_handle)
+            .await)
+    }
+
+    async fn write_data(&self, handle: &str, data: bytes::Bytes) -> Result<()> {
+        let write_handle = match self.handle_manager.get_write_handle(handle).await {
+            Some(dir_handle) => dir_handle,
+            None => return Err(self.missing_handle_error(handle, "write").await),
+        };
+
+        let mut write_handle = write_handle.lock().await;
+
+        if write_handle.is_poisoned {
+            return Err(build_poisoned_write_handle_error());
+        }
+
+        if let Some(write_spool) = &mut write_handle.spool {
+            return write_spool.write(&data).await;
+        }
+
+        self.buffer_data(&mut write_handle, &data).await
+    }
+
+    /// Copies the object as the first part of the upload with UploadPartCopy.
+    /// Objects smaller than a part can only be the last part of an upload, and
+    /// S3 copies at most 5 GiB as one part, so other sizes are left to the
+    /// caller to read and write.
+    async fn copy_data(&self, read_handle: &str, write_handle: &str) -> Result<u64> {
+        let read_handle = match self.handle_manager.get_read_handle(read_handle).await {
+            Some(read_handle) => read_handle,
+            None => return Err(self.missing_handle_error(read_handle, "read").await),
+        };
+        let write_handle = match self.handle_manager.get_write_handle(write_handle).await {
+            Some(write_handle) => write_handle,
+            None => return Err(self.missing_handle_error(write_handle, "write").await),
+        };
+
+        let read_handle = read_handle.lock().await;
+        let mut write_handle = write_handle.lock().await;
+
+        let copy_source = match &read_handle.copy_source {
+            Some(copy_source)
+                if (MIN_PART_SIZE as i64..=MAX_COPY_PART_SIZE).contains(&copy_source.size) =>
+            {
+                copy_source
+            }
+            _ => return Err(Error::Unimplemented.into()),
+        };
+
+        if write_handle.is_poisoned
+            || !write_handle.completed_parts.is_empty()
+            || !write_handle.pending_uploads.is_empty()
+            || write_handle.buffer.buffered_len() > 0
+            || write_handle
+                .spool
+                .as_ref()
+                .is_some_and(|write_spool| write_spool.len > 0)
+        {
+            return Err(Error::Unimplemented.into());
+        }
+
+        let mut source = get_s3_copy_source(self.get_bucket(&copy_source.key), &copy_source.key);
+
+        if let Some(version_id) = &copy_source.version_id {
+            source = format!("{}?versionId={}", source, version_id);
+        }
+
+        self.backend_calls.record(BackendCall::Put);
+        let upload_part_copy_response = self
+            .s3_client
+            .upload_part_copy(UploadPartCopyRequest {
+                bucket: self.get_bucket(&write_handle.key).to_owned(),
+                key: write_handle.key.clone(),
+                upload_id: write_handle.upload_id.clone(),
+                part_number: 1,
+                copy_source: source,
+                ..Default::default()
+            })
+            .await?;
+
+        write_handle.completed_parts.push(CompletedPart {
+            e_tag: upload_part_copy_response
+                .copy_part_result
+                .and_then(|copy_part_result| copy_part_result.e_tag),
+            part_number: Some(1),
+        });
+
+        Ok(copy_source.size as u64)
+    }
+
+    async fn close_handle(&self, handle: &str) -> Result<()> {
+        if self.handle_manager.get_dir_handle(handle).await.is_some() {
+            return self.close_dir_handle(handle).await;
+        }
+
+        let result = match self.handle_manager.get_write_handle(handle).await {
+            Some(write_handle) => self.complete_upload(&mut *write_handle.lock().await).await,
+            None => Ok(()),
+        };
+
+        match self.handle_manager.remove_handle(handle).await {
+            true => result,
+            false => Err(Error::ServerError.into()),
+        }
+    }
+
+    /// Parts are uploaded as soon as they are full, but a multipart upload only
+    /// becomes visible once it is completed, which cannot be undone to accept
+    /// more parts. The remaining data is therefore persisted when the handle
+    /// is closed, and flushing only checks that the handle is open.
+    async fn flush_handle(&self, handle: &str) -> Result<()> {
+        if self.handle_manager.get_write_handle(handle).await.is_none()
+            && self.handle_manager.get_read_handle(handle).await.is_none()
+        {
+            return Err(self.missing_handle_error(handle, "file").await);
+        }
+
+        Ok(())
+    }
+
+    async fn remove_file(&self, file_name: String) -> Result<()> {
+        self.check_not_snapshot_path(&file_name)?;
+        let key = resolve_key(&file_name)?;
+
+        self.forget_recent_write(&key);
+        self.delete_object(key).await?;
+        self.handle_manager.invalidate_path(&file_name).await;
+
+        Ok(())
+    }
+
+    async fn rename(&self, current: String, new: String) -> Result<()> {
+        self.check_not_snapshot_path(&current)?;
+        self.check_not_snapshot_path(&new)?;
+
+        let file = self.get_file_metadata(current.clone()).await?;
+
+        match file.file_attributes.is_dir() {
+            true => self.rename_dir(&current, &new).await,
+            false => {
+                self.rename_object(resolve_key(&current)?, resolve_key(&new)?)
+                    .await
+            }
+        }?;
+
+        self.handle_manager.invalidate_path(&current).await;
+
+        Ok(())
+    }
+}
+
+struct DirHandle {
+    prefix: String,
+    continuation_token: Option<String>,
+    is_eof: bool,
+}
+
+struct ReadHandle {
+    stream: Pin<Box<dyn AsyncRead + Send>>,
+    /// The length of the stream that is left to read, if it is known, which
+    /// lets reads be streamed with their length sent first.
+    remaining: Option<u64>,
+    /// The offset of the next byte of the stream.
+    position: u64,
+    /// The object the stream reads unchanged, which can be copied on S3 rather
+    /// than through the server, or read at other offsets with range requests.
+    /// `None` if the stream was decoded or redirected.
+    copy_source: Option<CopySource>,
+    /// The offset and bytes of the last range fetched for a read out of order,
+    /// which later reads in the range are answered from.
+    fetched_range: Option<(u64, Vec<u8>)>,
+}
+
+struct CopySource {
+    key: String,
+    version_id: Option<String>,
+    size: i64,
+}
+
+struct WriteHandle {
+    key: String,
+    upload_id: String,
+    completed_parts: Vec<CompletedPart>,
+    buffer: PartBuffer,
+    pending_uploads: VecDeque<PendingUpload>,
+    is_poisoned: bool,
+    /// The tags of the object being rewritten, which are applied once the
+    /// upload completes.
+    preserved_tags: Vec<Tag>,
+    /// The file the upload is written to until the handle is closed, if
+    /// uploads are spooled.
+    spool: Option<WriteSpool>,
+}
+
+impl WriteHandle {
+    /// Returns the bytes held in memory, which are the buffered bytes and the
+    /// parts that are still uploading.
+    fn in_flight_bytes(&self) -> usize {
+        self.buffer.buffered_len()
+            + self
+                .pending_uploads
+                .iter()
+                .map(|pending_upload| pending_upload.length)
+                .sum::<usize>()
+    }
+}
+
+struct PendingUpload {
+    length: usize,
+    upload: JoinHandle<Result<CompletedPart>>,
+}
+
+/// A file in the spool directory that holds an upload until its handle is
+/// closed. The file is removed when the spool is dropped, whether or not the
+/// upload completed.
+struct WriteSpool {
+    path: PathBuf,
+    file: tokio::fs::File,
+    len: u64,
+}
+
+impl WriteSpool {
+    async fn create(write_spool_dir: &Path) -> Result<WriteSpool> {
+        let path = write_spool_dir.join(Uuid::new_v4().to_string());
+        let file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+            .map_err(|error| {
+                anyhow::anyhow!("Failed to create spool file {}: {}", path.display(), error)
+            })?;
+
+        Ok(WriteSpool { path, file, len: 0 })
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.file.write_all(data).await?;
+        self.len += data.len() as u64;
+
+        Ok(())
+    }
+}
+
+impl Drop for WriteSpool {
+    fn drop(&mut self) {
+        if let Err(error) = std::fs::remove_file(&self.path) {
+            warn!(
+                "Failed to remove spool file {}: {}",
+                self.path.display(),
+                error
+            );
+        }
+    }
+}
+
+fn get_home(user: &str) -> String {
+    format!("/home/{}", user)
+}
+
+/// Converts an absolute SFTP path into an S3 key.
+///
+/// Paths are normalized by the session before reaching storage, so `..`, empty
+/// components and null bytes are rejected here rather than resolved, since a
+/// malformed key could silently target an unexpected object.
+fn resolve_key(path: &str) -> Result<String> {
+    if path.contains('\0') {
+        return Err(anyhow::anyhow!("Invalid path {:?}: null byte.", path));
+    }
+
+    let relative_path = path.strip_prefix('/').unwrap_or(path);
+    let relative_path = relative_path.strip_suffix('/').unwrap_or(relative_path);
+
+    if relative_path.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut components = Vec::new();
+
+    for component in relative_path.split('/') {
+        match component {
+            "" => return Err(anyhow::anyhow!("Invalid path {:?}: empty component.", path)),
+            ".." => {
+                return Err(anyhow::anyhow!(
+                    "Invalid path {:?}: parent component.",
+                    path
+                ))
+            }
+            "." => {}
+            _ => components.push(component),
+        }
+    }
+
+    Ok(components.join("/"))
+}
+
+/// Reads up to len bytes from a read handle into memory.
+async fn read_buffered(read_handle: &mut ReadHandle, len: u32) -> Result<Vec<u8>> {
+    let mut buffer = Vec::with_capacity(len as usize);
+
+    read_handle
+        .stream
+        .as_mut()
+        .take(len as u64)
+        .read_to_end(&mut buffer)
+        .await?;
+
+    if let Some(remaining) = &mut read_handle.remaining {
+        *remaining = remaining.saturating_sub(buffer.len() as u64);
+    }
+
+    read_handle.position += buffer.len() as u64;
+
+    Ok(buffer)
+}
+
+fn get_s3_prefix(dir_name: &str) -> Result<String> {
+    let key = resolve_key(dir_name)?;
+
+    match key.is_empty() {
+        true => Ok(key),
+        false => Ok(format!("{}/", key)),
+    }
+}
+
+/// Builds the source of an S3 copy, whose key must be percent-encoded since it
+/// is sent as a header.
+fn get_s3_copy_source(bucket: &str, key: &str) -> String {
+    format!("{}/{}", bucket, encode_uri_path(key))
+}
+
+fn map_list_objects_to_files(prefix: &str, list_objects: ListObjectsV2Output) -> Vec<File> {
+    let files = list_objects.contents.unwrap_or_default();
+
+    // Skip the zero-byte marker object some clients create for the directory itself
+    let files = files
+        .iter()
+        .filter(|object| object.key.as_deref() != Some(prefix));
+
+    let directories = list_objects.common_prefixes.unwrap_or_default();
+
+    let mapped_files = files.map(map_object_to_file);
+
+    let mapped_dirs = directories.iter().map(map_prefix_to_file);
+
+    mapped_dirs.chain(mapped_files).collect()
+}
+
+fn map_object_to_file(object: &Object) -> File {
+    let key = match &object.key {
+        Some(key) => key,
+        None => "",
+    };
+
+    let mut key_pieces = key.rsplit('/');
+    let file_name = key_pieces.next().unwrap_or("");
+
+    File {
+        file_name: file_name.to_string(),
+        file_attributes: FileAttributes {
+            size: object.size.map(|size| size as u64),
+            uid: None,
+            gid: None,
+            permissions: Some(0o100777),
+            atime: None,
+            mtime: map_rfc3339_to_epoch(object.last_modified.as_ref()),
+            extended: BTreeMap::new(),
+        },
+    }
+}
+
+fn map_prefix_to_file(prefix: &CommonPrefix) -> File {
+    let prefix = match prefix.prefix {
+        Some(ref prefix) => {
+            let mut prefix = prefix.to_string();
+            prefix.pop(); // strip trailing /
+            format!("/{}", prefix)
+        }
+        None => "".to_owned(),
+    };
+
+    let mut prefix_pieces = prefix.rsplit('/');
+    let file_name = prefix_pieces.next().unwrap_or("");
+
+    File {
+        file_name: file_name.to_string(),
+        file_attributes: FileAttributes {
+            size: None,
+            uid: None,
+            gid: None,
+            permissions: Some(0o40777),
+            atime: None,
+            mtime: None,
+            extended: BTreeMap::new(),
+        },
+    }
+}
+
+fn map_head_object_to_file(key: &str, head_object: &HeadObjectOutput) -> File {
+    let mut key_pieces = key.rsplit('/');
+    let file_name = key_pieces.next().unwrap_or("");
+
+    File {
+        file_name: file_name.to_string(),
+        file_attributes: FileAttributes {
+            size: head_object
+                .content_length
+                .map(|content_length| content_length as u64),
+            uid: None,
+            gid: None,
+            permissions: Some(0o100777),
+            atime: None,
+            mtime: head_object
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.get(MTIME_METADATA))
+                .and_then(|mtime| mtime.parse().ok()),
+            extended: head_object
+                .version_id
+                .iter()
+                .map(|version_id| (String::from(VERSION_ID_ATTRIBUTE), version_id.clone()))
+                .collect(),
+        },
+    }
+}
+
+fn create_file_with_directory_bit(key: &str) -> File {
+    let mut key_pieces = key.rsplit('/');
+    let file_name = key_pieces.next().unwrap_or("");
+
+    File {
+        file_name: file_name.to_string(),
+        file_attributes: FileAttributes {
+            size: None,
+            uid: None,
+            gid: None,
+            permissions: Some(0o40777),
+            atime: None,
+            mtime: None,
+            extended: BTreeMap::new(),
+        },
+    }
+}
+
+fn map_tags_to_extended_attributes(tags: Vec<Tag>) -> BTreeMap<String, String> {
+    tags.into_iter().map(|tag| (tag.key, tag.value)).collect()
+}
+
+fn map_extended_attributes_to_tags(extended: BTreeMap<String, String>) -> Vec<Tag> {
+    extended
+        .into_iter()
+        .map(|(key, value)| Tag { key, value })
+        .collect()
+}
+
+fn map_rfc3339_to_epoch(rfc3339: Option<&String>) -> Option<u32> {
+    rfc3339.map(|last_modified| {
+        last_modified
+            .parse::<DateTime<Utc>>()
+            .unwrap_or_else(|_e| Utc.timestamp(0, 0))
+            .timestamp() as u32
+    })
+}
+
+/// Resolves the region to send requests to, looking up the bucket's location
+/// if no endpoint or region is configured. The default region is used if the
+/// lookup fails, so a missing permission does not prevent startup.
+async fn resolve_region<D, P>(
+    s3_config: &S3Config,
+    dispatcher: D,
+    credentials_provider: P,
+) -> Region
+where
+    D: DispatchSignedRequest + Send + Sync + 'static,
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+{
+    if let Some(endpoint_name) = &s3_config.endpoint_name {
+        return Region::Custom {
+            name: s3_config.endpoint_region.clone(),
+            endpoint: endpoint_name.clone(),
+        };
+    }
+
+    if let Some(region) = &s3_config.region {
+        return region.clone();
+    }
+
+    let s3_client = S3Client::new_with(dispatcher, credentials_provider, Region::default());
+
+    match detect_bucket_region(&s3_client, &s3_config.bucket).await {
+        Ok(region) => {
+            info!(
+                "Detected region {} for bucket {}",
+                region.name(),
+                s3_config.bucket
+            );
+            region
+        }
+        Err(error) => {
+            let region = Region::default();
+            warn!(
+                "Failed to detect region for bucket {}, using {}: {}",
+                s3_config.bucket,
+                region.name(),
+                error
+            );
+            region
+        }
+    }
+}
+
+/// Looks up the region a bucket was created in. S3 reports an empty location
+/// for us-east-1, and `EU` for buckets created in eu-west-1 by old clients.
+async fn detect_bucket_region(s3_client: &S3Client, bucket: &str) -> Result<Region> {
+    let bucket_location = s3_client
+        .get_bucket_location(GetBucketLocationRequest {
+            bucket: bucket.to_owned(),
+            ..Default::default()
+        })
+        .await?;
+
+    match bucket_location.location_constraint.as_deref() {
+        None | Some("") => Ok(Region::UsEast1),
+        Some("EU") => Ok(Region::EuWest1),
+        Some(location_constraint) => Ok(location_constraint.parse()?),
+    }
+}
+
+fn build_request_dispatcher(
+    s3_config: &S3Config,
+) -> ConnectionLimitedDispatcher<
+    ClockSkewDetectingDispatcher<HttpClient<HttpsConnector<HttpConnector>>>,
+> {
+    let mut http_connector = HttpConnector::new();
+    http_connector.enforce_http(false);
+    http_connector.set_connect_timeout(s3_config.connect_timeout.map(Duration::from_secs));
+
+    let mut builder = hyper::Client::builder();
+
+    if let Some(max_connections) = s3_config.max_connections {
+        builder.pool_max_idle_per_host(max_connections);
+    }
+
+    let http_client =
+        HttpClient::from_builder(builder, HttpsConnector::new_with_connector(http_connector));
+
+    ConnectionLimitedDispatcher::new(
+        ClockSkewDetectingDispatcher::new(http_client),
+        s3_config.max_connections,
+    )
+}
+
+fn build_create_multipart_upload_request(
+    bucket: &str,
+    key: String,
+    storage_class: Option<StorageClass>,
+    acl: Option<String>,
+    metadata: Option<HashMap<String, String>>,
+) -> CreateMultipartUploadRequest {
+    CreateMultipartUploadRequest {
+        bucket: bucket.to_owned(),
+        key,
+        storage_class: map_storage_class(storage_class),
+        acl,
+        metadata,
+        ..Default::default()
+    }
+}
+
+/// Maps SFTP permission bits to a canned ACL when permission mapping is
+/// enabled. Objects readable by others are `public-read`, and all other
+/// objects are `private`.
+fn map_permissions_to_acl(
+    permissions: Option<u32>,
+    public_read_permissions: bool,
+) -> Option<String> {
+    const OTHER_READ: u32 = 0o004;
+
+    match (permissions, public_read_permissions) {
+        (Some(permissions), true) if permissions & OTHER_READ != 0 => {
+            Some(String::from("public-read"))
+        }
+        (Some(_), true) => Some(String::from("private")),
+        _ => None,
+    }
+}
+
+fn map_storage_class(storage_class: Option<StorageClass>) -> Option<String> {
+    storage_class.map(|storage_class| storage_class.as_str().to_owned())
+}
+
+fn map_create_multipart_response_to_write_handle(
+    create_multipart_response: CreateMultipartUploadOutput,
+    part_size: usize,
+) -> Result<WriteHandle> {
+    let upload_id = match create_multipart_response.upload_id {
+        Some(upload_id) => Ok(upload_id),
+        None => Err(anyhow::anyhow!("Missing upload id.")),
+    }?;
+
+    let key = match create_multipart_response.key {
+        Some(key) => Ok(key),
+        None => Err(anyhow::anyhow!("Missing key.")),
+    }?;
+
+    Ok(WriteHandle {
+        key,
+        upload_id,
+        completed_parts: Vec::new(),
+        buffer: PartBuffer::new(part_size),
+        pending_uploads: VecDeque::new(),
+        is_poisoned: false,
+        preserved_tags: Vec::new(),
+        spool: None,
+    })
+}
+
+/// Copies the content headers and user metadata of an object being rewritten
+/// onto the upload that replaces it. Metadata set by the upload itself, such
+/// as the modification time, takes precedence.
+fn apply_existing_object_headers(
+    create_multipart_upload_request: &mut CreateMultipartUploadRequest,
+    existing_object: HeadObjectOutput,
+) {
+    create_multipart_upload_request.content_type = existing_object.content_type;
+    create_multipart_upload_request.content_encoding = existing_object.content_encoding;
+    create_multipart_upload_request.content_disposition = existing_object.content_disposition;
+    create_multipart_upload_request.content_language = existing_object.content_language;
+    create_multipart_upload_request.cache_control = existing_object.cache_control;
+
+    if let Some(mut metadata) = existing_object.metadata {
+        metadata.extend(
+            create_multipart_upload_request
+                .metadata
+                .take()
+                .unwrap_or_default(),
+        );
+        create_multipart_upload_request.metadata = Some(metadata);
+    }
+}
+
+fn get_list_page_size(list_page_size: i64) -> i64 {
+    list_page_size.clamp(1, MAX_LIST_PAGE_SIZE)
+}
+
+fn build_list_dir_request(
+    bucket: &str,
+    prefix: &str,
+    continuation_token: Option<String>,
+    max_keys: Option<i64>,
+) -> ListObjectsV2Request {
+    ListObjectsV2Request {
+        bucket: bucket.to_owned(),
+        prefix: Some(prefix.to_owned()),
+        continuation_token,
+        delimiter: Some("/".to_owned()),
+        max_keys,
+        ..Default::default()
+    }
+}
+
+fn build_poisoned_write_handle_error() -> anyhow::Error {
+    anyhow::anyhow!("The upload was aborted because a part failed to upload.")
+}
+
+fn get_default_endpoint_region() -> String {
+    String::from("custom")
+}
+
+fn get_default_delete_retries() -> u32 {
+    3
+}
+
+fn get_default_read_coalesce_window() -> usize {
+    256 * 1024
+}
+
+fn get_default_presigned_url_expiry() -> u64 {
+    15 * 60
+}
+
+fn get_default_part_size() -> usize {
+    10 * 1024 * 1024
+}
+
+fn deserialize_region<'de, D>(deserializer: D) -> std::result::Result<Option<Region>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let region = String::deserialize(deserializer)?;
+
+    region.parse().map(Some).map_err(de::Error::custom)
+}
+
+fn deserialize_bucket_prefixes<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Vec<(String, String)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|bucket_prefix| {
+            let (path, bucket) = bucket_prefix.split_once('=').ok_or_else(|| {
+                de::Error::custom(format!(
+                    "bucket prefix {} must be a path=bucket pair",
+                    bucket_prefix
+                ))
+            })?;
+
+            let prefix = get_s3_prefix(path).map_err(de::Error::custom)?;
+
+            if prefix.is_empty() || bucket.is_empty() {
+                return Err(de::Error::custom(format!(
+                    "bucket prefix {} must map a directory to a bucket",
+                    bucket_prefix
+                )));
+            }
+
+            Ok((prefix, bucket.to_owned()))
+        })
+        .collect()
+}
+
+fn deserialize_user_profiles<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Vec<(String, String)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|user_profile| match user_profile.split_once('=') {
+            Some((user, profile)) if !user.is_empty() && !profile.is_empty() => {
+                Ok((user.to_owned(), profile.to_owned()))
+            }
+            _ => Err(de::Error::custom(format!(
+                "user profile {} must be a user=profile pair",
+                user_profile
+            ))),
+        })
+        .collect()
+}
+
+fn deserialize_part_size<'de, D>(deserializer: D) -> std::result::Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let part_size = usize::deserialize(deserializer)?;
+
+    if part_size < MIN_PART_SIZE {
+        return Err(de::Error::custom(format!(
+            "part size must be at least {} bytes",
+            MIN_PART_SIZE
+        )));
+    }
+
+    Ok(part_size)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::storage::backend_calls;
+    use crate::storage::MAX_CONCURRENT_METADATA_REQUESTS;
+    use futures::{StreamExt, TryStreamExt};
+    use rusoto_core::credential::StaticProvider;
+    use rusoto_core::request::{DispatchSignedRequest, DispatchSignedRequestFuture, HttpResponse};
+    use rusoto_core::signature::{SignedRequest, SignedRequestPayload};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    /// Fails every part upload and records the method and query parameters of
+    /// each request.
+    struct PartFailingDispatcher {
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DispatchSignedRequest for PartFailingDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let params: Vec<&str> = request.params.keys().map(String::as_str).collect();
+            let is_upload_part = request.method == "PUT" && params.contains(&"partNumber");
+
+            self.requests
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", request.method, params.join(",")));
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: match is_upload_part {
+                        true => hyper::StatusCode::INTERNAL_SERVER_ERROR,
+                        false => hyper::StatusCode::NO_CONTENT,
+                    },
+                    body: ByteStream::from(Vec::new()),
+                    headers: hyper::HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    /// Lists a fixed set of keys and records the keys of every batch delete and
+    /// the path of every upload.
+    struct ListingDispatcher {
+        keys: Vec<String>,
+        deleted_keys: Arc<Mutex<Vec<String>>>,
+        put_paths: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DispatchSignedRequest for ListingDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let body = match (request.method.as_str(), &request.payload) {
+                ("POST", Some(SignedRequestPayload::Buffer(payload))) => {
+                    let payload = String::from_utf8(payload.to_vec()).unwrap();
+                    let keys = payload
+                        .split("<Key>")
+                        .skip(1)
+                        .map(|key| key.split("</Key>").next().unwrap().to_owned());
+
+                    self.deleted_keys.lock().unwrap().extend(keys);
+                    String::from("<DeleteResult></DeleteResult>")
+                }
+                ("PUT", _) => {
+                    self.put_paths.lock().unwrap().push(request.path.clone());
+                    String::new()
+                }
+                _ => format!(
+                    "<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                    self.keys
+                        .iter()
+                        .map(|key| format!("<Contents><Key>{}</Key></Contents>", key))
+                        .collect::<String>()
+                ),
+            };
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(body.into_bytes()),
+                    headers: hyper::HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    /// Lists the given in-progress multipart uploads as key, upload ID and
+    /// initiation time, and records the upload IDs that are aborted.
+    struct MultipartUploadsDispatcher {
+        uploads: Vec<(String, String, DateTime<Utc>)>,
+        aborted_upload_ids: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DispatchSignedRequest for MultipartUploadsDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let body = match request.method.as_str() {
+                "DELETE" => {
+                    let upload_id = request.params.get("uploadId").cloned().flatten();
+                    self.aborted_upload_ids.lock().unwrap().extend(upload_id);
+                    String::new()
+                }
+                _ => format!(
+                    "<ListMultipartUploadsResult><IsTruncated>false</IsTruncated>{}\
+                     </ListMultipartUploadsResult>",
+                    self.uploads
+                        .iter()
+                        .map(|(key, upload_id, initiated)| format!(
+                            "<Upload><Key>{}</Key><UploadId>{}</UploadId>\
+                             <Initiated>{}</Initiated></Upload>",
+                            key,
+                            upload_id,
+                            initiated.to_rfc3339()
+                        ))
+                        .collect::<String>()
+                ),
+            };
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(body.into_bytes()),
+                    headers: hyper::HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    /// Lists the given keys and accepts deletes, but fails to delete
+    /// `failing_key` the given number of times first.
+    struct FlakyDeleteDispatcher {
+        keys: Vec<String>,
+        failing_key: String,
+        failure_code: &'static str,
+        failures: Arc<Mutex<u32>>,
+        deleted_keys: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DispatchSignedRequest for FlakyDeleteDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let body = match (request.method.as_str(), &request.payload) {
+                ("POST", Some(SignedRequestPayload::Buffer(payload))) => {
+                    let payload = String::from_utf8(payload.to_vec()).unwrap();
+                    let mut failures = self.failures.lock().unwrap();
+                    let mut errors = String::new();
+
+                    for key in payload
+                        .split("<Key>")
+                        .skip(1)
+                        .map(|key| key.split("</Key>").next().unwrap().to_owned())
+                    {
+                        if key == self.failing_key && *failures > 0 {
+                            *failures -= 1;
+                            errors.push_str(&format!(
+                                "<Error><Key>{}</Key><Code>{}</Code>\
+                                 <Message>Failed.</Message></Error>",
+                                key, self.failure_code
+                            ));
+                        } else {
+                            self.deleted_keys.lock().unwrap().push(key);
+                        }
+                    }
+
+                    format!("<DeleteResult>{}</DeleteResult>", errors)
+                }
+                _ => format!(
+                    "<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                    self.keys
+                        .iter()
+                        .map(|key| format!("<Contents><Key>{}</Key></Contents>", key))
+                        .collect::<String>()
+                ),
+            };
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(body.into_bytes()),
+                    headers: hyper::HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    /// Records the canned ACL of each request that sets one.
+    struct AclRecordingDispatcher {
+        acls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DispatchSignedRequest for AclRecordingDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            if let Some(acl) = request.headers.get("x-amz-acl") {
+                self.acls
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(acl[0].clone()).unwrap());
+            }
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(Vec::new()),
+                    headers: hyper::HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    /// Serves an existing CSV object with user metadata and a tag, accepts
+    /// uploads, and records the method, query parameters and headers of each
+    /// request.
+    struct ExistingObjectDispatcher {
+        requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    }
+
+    struct RecordedRequest {
+        method: String,
+        params: Vec<String>,
+        headers: BTreeMap<String, String>,
+    }
+
+    impl DispatchSignedRequest for ExistingObjectDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let params: Vec<String> = request.params.keys().cloned().collect();
+            let headers = request
+                .headers
+                .iter()
+                .map(|(name, values)| (name.clone(), String::from_utf8(values[0].clone()).unwrap()))
+                .collect();
+
+            let mut response_headers = hyper::HeaderMap::<String>::default();
+
+            let body = match (request.method.as_str(), params.first().map(String::as_str)) {
+                ("HEAD", _) => {
+                    response_headers.insert("Content-Type", String::from("text/csv"));
+                    response_headers.insert("x-amz-meta-owner", String::from("reports"));
+                    String::new()
+                }
+                ("GET", Some("tagging")) => String::from(
+                    "<Tagging><TagSet><Tag><Key>project</Key><Value>alpha</Value></Tag>\
+                     </TagSet></Tagging>",
+                ),
+                ("POST", Some("uploads")) => String::from(
+                    "<InitiateMultipartUploadResult><Key>file</Key><UploadId>id</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                ),
+                _ => String::new(),
+            };
+
+            self.requests.lock().unwrap().push(RecordedRequest {
+                method: request.method.clone(),
+                params,
+                headers,
+            });
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(body.into_bytes()),
+                    headers: response_headers,
+                })
+            })
+        }
+    }
+
+    /// Serves every object with the given size and accepts multipart uploads,
+    /// recording the copy source of each request that copies a part.
+    struct CopyDispatcher {
+        size: usize,
+        copy_sources: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DispatchSignedRequest for CopyDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let mut headers = hyper::HeaderMap::<String>::default();
+
+            let body = match (
+                request.method.as_str(),
+                request.headers.get("x-amz-copy-source"),
+            ) {
+                ("GET", _) => {
+                    headers.insert("Content-Length", self.size.to_string());
+                    String::new()
+                }
+                ("POST", _) if request.params.contains_key("uploads") => String::from(
+                    "<InitiateMultipartUploadResult><Key>file</Key><UploadId>id</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                ),
+                ("PUT", Some(copy_source)) => {
+                    self.copy_sources
+                        .lock()
+                        .unwrap()
+                        .push(String::from_utf8(copy_source[0].clone()).unwrap());
+                    String::from("<CopyPartResult><ETag>etag</ETag></CopyPartResult>")
+                }
+                _ => String::new(),
+            };
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(body.into_bytes()),
+                    headers,
+                })
+            })
+        }
+    }
+
+    /// Serves the same object, stored with `Content-Encoding: gzip`, for every
+    /// request.
+    struct GzipObjectDispatcher {
+        body: Vec<u8>,
+    }
+
+    impl DispatchSignedRequest for GzipObjectDispatcher {
+        fn dispatch(
+            &self,
+            _request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let body = self.body.clone();
+
+            Box::pin(async move {
+                let mut headers = hyper::HeaderMap::default();
+                headers.insert("Content-Encoding", "gzip".parse().unwrap());
+
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(body),
+                    headers,
+                })
+            })
+        }
+    }
+
+    /// Serves an object whose bytes are their offsets modulo 256, answering
+    /// range requests with just the range, and records the range header of
+    /// every request, or `None` for requests of the whole object.
+    struct RangeObjectDispatcher {
+        length: usize,
+        ranges: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl DispatchSignedRequest for RangeObjectDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let range = request
+                .headers
+                .get("range")
+                .map(|range| String::from_utf8(range[0].clone()).unwrap());
+            self.ranges.lock().unwrap().push(range.clone());
+
+            let object: Vec<u8> = (0..self.length).map(|offset| offset as u8).collect();
+
+            let (status, body) = match range {
+                Some(range) => {
+                    let (start, end) = range.trim_start_matches("bytes=").split_once('-').unwrap();
+                    let start: usize = start.parse().unwrap();
+                    let end: usize = end.parse().unwrap();
+
+                    (
+                        hyper::StatusCode::PARTIAL_CONTENT,
+                        object[start..=end].to_vec(),
+                    )
+                }
+                None => (hyper::StatusCode::OK, object),
+            };
+
+            Box::pin(async move {
+                let mut headers = hyper::HeaderMap::default();
+                headers.insert("Content-Length", body.len().to_string());
+
+                Ok(HttpResponse {
+                    status,
+                    body: ByteStream::from(body),
+                    headers,
+                })
+            })
+        }
+    }
+
+    /// Serves an object whose body arrives in chunks, counting the chunks that
+    /// have been pulled from the body.
+    struct ChunkedObjectDispatcher {
+        chunk_count: usize,
+        chunk_length: usize,
+        pulled_chunks: Arc<AtomicUsize>,
+    }
+
+    impl DispatchSignedRequest for ChunkedObjectDispatcher {
+        fn dispatch(
+            &self,
+            _request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let chunk_length = self.chunk_length;
+            let content_length = self.chunk_count * chunk_length;
+            let pulled_chunks = self.pulled_chunks.clone();
+            let chunks = futures::stream::iter(
+                (0..self.chunk_count).map(move |index| vec![index as u8; chunk_length]),
+            )
+            .map(move |chunk| {
+                pulled_chunks.fetch_add(1, Ordering::SeqCst);
+                Ok(bytes::Bytes::from(chunk))
+            });
+
+            Box::pin(async move {
+                let mut headers = hyper::HeaderMap::default();
+                headers.insert("Content-Length", content_length.to_string());
+
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::new(chunks),
+                    headers,
+                })
+            })
+        }
+    }
+
+    /// Serves objects that each hold their key as content and may carry a
+    /// website redirect location.
+    struct RedirectDispatcher {
+        redirects: Vec<(String, Option<String>)>,
+    }
+
+    impl DispatchSignedRequest for RedirectDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let key = request.path.trim_start_matches("/bucket/").to_owned();
+            let mut headers = hyper::HeaderMap::<String>::default();
+
+            let status = match self
+                .redirects
+                .iter()
+                .find(|(stored_key, _)| *stored_key == key)
+            {
+                Some((_, redirect)) => {
+                    if let Some(redirect) = redirect {
+                        headers.insert("x-amz-website-redirect-location", redirect.clone());
+                    }
+                    hyper::StatusCode::OK
+                }
+                None => hyper::StatusCode::NOT_FOUND,
+            };
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status,
+                    body: ByteStream::from(key.into_bytes()),
+                    headers,
+                })
+            })
+        }
+    }
+
+    /// Serves a fixed set of objects, which all contain `data`, answering
+    /// requests for any other key like S3 does for missing keys.
+    struct ObjectDispatcher {
+        keys: Vec<String>,
+    }
+
+    impl DispatchSignedRequest for ObjectDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let key = request.path.trim_start_matches("/bucket/");
+            let is_stored = self.keys.iter().any(|stored_key| stored_key == key);
+
+            let (status, body) = if request.params.contains_key("list-type") {
+                (
+                    hyper::StatusCode::OK,
+                    format!(
+                        "<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                        self.keys
+                            .iter()
+                            .map(|key| format!("<Contents><Key>{}</Key></Contents>", key))
+                            .collect::<String>()
+                    ),
+                )
+            } else if request.params.contains_key("tagging") {
+                (
+                    hyper::StatusCode::OK,
+                    String::from("<Tagging><TagSet></TagSet></Tagging>"),
+                )
+            } else if is_stored {
+                (hyper::StatusCode::OK, String::from("data"))
+            } else if request.method == "HEAD" {
+                (hyper::StatusCode::NOT_FOUND, String::new())
+            } else {
+                (
+                    hyper::StatusCode::NOT_FOUND,
+                    String::from("<Error><Code>NoSuchKey</Code><Message></Message></Error>"),
+                )
+            };
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status,
+                    body: ByteStream::from(body.into_bytes()),
+                    headers: hyper::HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    /// Records the path of every request, which starts with the bucket, and
+    /// answers each with an empty listing.
+    struct PathRecordingDispatcher {
+        paths: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DispatchSignedRequest for PathRecordingDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            self.paths.lock().unwrap().push(request.path.clone());
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(
+                        b"<ListBucketResult><IsTruncated>false</IsTruncated></ListBucketResult>"
+                            .to_vec(),
+                    ),
+                    headers: hyper::HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    /// Records the access key that signed each request, and answers each with
+    /// an empty listing.
+    #[derive(Clone)]
+    struct CredentialRecordingDispatcher {
+        access_keys: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DispatchSignedRequest for CredentialRecordingDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let authorization =
+                String::from_utf8(request.headers["authorization"][0].clone()).unwrap();
+            let (_, credential) = authorization.split_once("Credential=").unwrap();
+            let (access_key, _) = credential.split_once('/').unwrap();
+            self.access_keys.lock().unwrap().push(access_key.to_owned());
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(
+                        b"<ListBucketResult><IsTruncated>false</IsTruncated></ListBucketResult>"
+                            .to_vec(),
+                    ),
+                    headers: hyper::HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    /// Lists the given versions of `home/test/file.txt` as `(version id, last
+    /// modified)` pairs, where a version id of `None` is a delete marker, and
+    /// answers each versioned read with the version id as the object data.
+    struct VersionsDispatcher {
+        versions: Vec<(Option<&'static str>, &'static str)>,
+    }
+
+    impl DispatchSignedRequest for VersionsDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let body = if request.params.contains_key("versions") {
+                format!(
+                    "<ListVersionsResult><IsTruncated>false</IsTruncated>{}</ListVersionsResult>",
+                    self.versions
+                        .iter()
+                        .map(|(version_id, last_modified)| match version_id {
+                            Some(version_id) => format!(
+                                "<Version><Key>home/test/file.txt</Key><VersionId>{}</VersionId>\
+                                 <LastModified>{}</LastModified></Version>",
+                                version_id, last_modified
+                            ),
+                            None => format!(
+                                "<DeleteMarker><Key>home/test/file.txt</Key><VersionId>marker\
+                                 </VersionId><LastModified>{}</LastModified></DeleteMarker>",
+                                last_modified
+                            ),
+                        })
+                        .collect::<String>()
+                )
+            } else {
+                request
+                    .params
+                    .get("versionId")
+                    .cloned()
+                    .flatten()
+                    .unwrap_or_default()
+            };
+
+            Box::pin(async move {
+                let mut headers = hyper::HeaderMap::default();
+                headers.insert("Content-Length", body.len().to_string());
+
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(body.into_bytes()),
+                    headers,
+                })
+            })
+        }
+    }
+
+    /// Answers bucket location requests with a fixed location and records the
+    /// query parameters of each request.
+    struct LocationDispatcher {
+        location_constraint: &'static str,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DispatchSignedRequest for LocationDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let params: Vec<&str> = request.params.keys().map(String::as_str).collect();
+            self.requests.lock().unwrap().push(params.join(","));
+
+            let body = format!(
+                "<LocationConstraint>{}</LocationConstraint>",
+                self.location_constraint
+            );
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(body.into_bytes()),
+                    headers: hyper::HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    /// Answers each HEAD request for `fileN.txt` with a size of N after a
+    /// delay, and records the most HEAD requests that were in flight at once.
+    /// Listings hold `file1.txt`, `file2.txt` and the `dir` directory.
+    struct MetadataDispatcher {
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl DispatchSignedRequest for MetadataDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let in_flight = self.in_flight.clone();
+            let max_in_flight = self.max_in_flight.clone();
+
+            Box::pin(async move {
+                let mut headers = hyper::HeaderMap::<String>::default();
+
+                let body = match request.method.as_str() {
+                    "HEAD" => {
+                        let count = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_in_flight.fetch_max(count, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                        let size = request
+                            .path
+                            .trim_start_matches("/bucket/home/test/file")
+                            .trim_end_matches(".txt")
+                            .to_owned();
+                        headers.insert("Content-Length", size);
+                        Vec::new()
+                    }
+                    _ if request.params.contains_key("tagging") => {
+                        b"<Tagging><TagSet></TagSet></Tagging>".to_vec()
+                    }
+                    _ => b"<ListBucketResult><IsTruncated>false</IsTruncated>\
+                           <Contents><Key>home/test/file1.txt</Key></Contents>\
+                           <Contents><Key>home/test/file2.txt</Key></Contents>\
+                           <CommonPrefixes><Prefix>home/test/dir/</Prefix></CommonPrefixes>\
+                           </ListBucketResult>"
+                        .to_vec(),
+                };
+
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(body),
+                    headers,
+                })
+            })
+        }
+    }
+
+    /// Delays every part upload, like a slow connection to S3.
+    struct SlowDispatcher;
+
+    impl DispatchSignedRequest for SlowDispatcher {
+        fn dispatch(
+            &self,
+            _request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+
+                Ok(HttpResponse {
+                    status: hyper::StatusCode::OK,
+                    body: ByteStream::from(Vec::new()),
+                    headers: hyper::HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    /// Accepts uploads and reports the uploaded object as missing for a number
+    /// of reads after it is written, like an eventually consistent store.
+    struct EventuallyConsistentDispatcher {
+        misses: Arc<Mutex<usize>>,
+    }
+
+    impl DispatchSignedRequest for EventuallyConsistentDispatcher {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let is_read = (request.method == "GET" || request.method == "HEAD")
+                && !request.params.contains_key("tagging");
+
+            let (status, body) = if is_read && *self.misses.lock().unwrap() > 0 {
+                *self.misses.lock().unwrap() -= 1;
+
+                match request.method.as_str() {
+                    "HEAD" => (hyper::StatusCode::NOT_FOUND, String::new()),
+                    _ => (
+                        hyper::StatusCode::NOT_FOUND,
+                        String::from("<Error><Code>NoSuchKey</Code><Message></Message></Error>"),
+                    ),
+                }
+            } else if request.params.contains_key("tagging") {
+                (
+                    hyper::StatusCode::OK,
+                    String::from("<Tagging><TagSet></TagSet></Tagging>"),
+                )
+            } else if is_read {
+                (hyper::StatusCode::OK, String::from("data"))
+            } else {
+                (hyper::StatusCode::OK, String::new())
+            };
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status,
+                    body: ByteStream::from(body.into_bytes()),
+                    headers: hyper::HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_after_write_retries_missing_object() {
+        let misses = Arc::new(Mutex::new(1));
+        let s3_storage = create_s3_storage(
+            EventuallyConsistentDispatcher {
+                misses: misses.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_read_after_write_retries(2);
+
+        let handle = create_write_handle(&s3_storage).await;
+        s3_storage
+            .write_data(&handle, bytes::Bytes::from("data"))
+            .await
+            .unwrap();
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        let handle = s3_storage
+            .open_read_handle(String::from("/file"))
+            .await
+            .unwrap();
+
+        assert_eq!(0, *misses.lock().unwrap());
+        assert_eq!(
+            b"data".to_vec(),
+            s3_storage.read_data(&handle, 1024).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_after_write_retries_stat() {
+        let misses = Arc::new(Mutex::new(1));
+        let s3_storage = create_s3_storage(
+            EventuallyConsistentDispatcher {
+                misses: misses.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_read_after_write_retries(2);
+
+        let handle = create_write_handle(&s3_storage).await;
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        let file = s3_storage
+            .get_file_metadata(String::from("/file"))
+            .await
+            .unwrap();
+
+        assert_eq!(0, *misses.lock().unwrap());
+        assert!(!file.file_attributes.is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_read_without_recent_write_is_not_retried() {
+        let misses = Arc::new(Mutex::new(2));
+        let s3_storage = create_s3_storage(
+            EventuallyConsistentDispatcher {
+                misses: misses.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_read_after_write_retries(2);
+
+        assert!(s3_storage
+            .open_read_handle(String::from("/file"))
+            .await
+            .is_err());
+        assert_eq!(1, *misses.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_write_data_with_failed_part_poisons_handle_and_aborts_upload() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            PartFailingDispatcher {
+                requests: requests.clone(),
+            },
+            MIN_PART_SIZE,
+        );
+        let handle = create_write_handle(&s3_storage).await;
+
+        let part = bytes::Bytes::from(vec![0x01; MIN_PART_SIZE]);
+
+        // The first part uploads in the background, so its failure is reported
+        // once the next write waits for it.
+        s3_storage.write_data(&handle, part.clone()).await.unwrap();
+        assert!(s3_storage.write_data(&handle, part.clone()).await.is_err());
+        assert!(requests
+            .lock()
+            .unwrap()
+            .contains(&String::from("DELETE uploadId")));
+
+        assert!(s3_storage.write_data(&handle, part).await.is_err());
+        assert!(s3_storage.close_handle(&handle).await.is_err());
+        assert!(s3_storage
+            .handle_manager
+            .get_write_handle(&handle)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_presign_download_signs_get_url_for_key() {
+        let s3_storage =
+            create_s3_storage(SlowDispatcher, 2 * MIN_PART_SIZE).with_presigner(Presigner {
+                region: Region::UsEast1,
+                credentials_provider: Arc::new(StaticProvider::new_minimal(
+                    String::from("key"),
+                    String::from("secret"),
+                )),
+                expiry: Duration::from_secs(600),
+            });
+
+        let url = s3_storage
+            .presign_download(String::from("/home/test/file.txt"))
+            .await
+            .unwrap();
+
+        let (path, query) = url.split_once('?').unwrap();
+        let params: Vec<&str> = query.split('&').collect();
+
+        assert_eq!(
+            "https://s3.us-east-1.amazonaws.com/bucket/home/test/file.txt",
+            path
+        );
+        assert!(params.contains(&"X-Amz-Algorithm=AWS4-HMAC-SHA256"));
+        assert!(params.contains(&"X-Amz-Expires=600"));
+        assert!(params.contains(&"X-Amz-SignedHeaders=host"));
+        assert!(params
+            .iter()
+            .any(|param| param.starts_with("X-Amz-Credential=key%2F")
+                && param.ends_with("%2Fus-east-1%2Fs3%2Faws4_request")));
+        assert!(params.iter().any(|param| {
+            param
+                .strip_prefix("X-Amz-Signature=")
+                .is_some_and(|signature| signature.len() == 64 && hex::decode(signature).is_ok())
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_presign_download_without_presigner_is_unimplemented() {
+        let s3_storage = create_s3_storage(SlowDispatcher, 2 * MIN_PART_SIZE);
+
+        let error = s3_storage
+            .presign_download(String::from("/home/test/file.txt"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(Some(&Error::Unimplemented), error.downcast_ref::<Error>());
+    }
+
+    #[tokio::test]
+    async fn test_write_data_limits_in_flight_bytes_with_slow_uploads() {
+        let max_write_buffer = 2 * MIN_PART_SIZE;
+        let s3_storage = create_s3_storage(SlowDispatcher, max_write_buffer);
+        let handle = create_write_handle(&s3_storage).await;
+
+        let write_handle = s3_storage
+            .handle_manager
+            .get_write_handle(&handle)
+            .await
+            .unwrap();
+
+        let mut max_in_flight_bytes = 0;
+
+        for _ in 0..(4 * MIN_PART_SIZE / (64 * 1024)) {
+            s3_storage
+                .write_data(&handle, bytes::Bytes::from(vec![0x01; 64 * 1024]))
+                .await
+                .unwrap();
+
+            let in_flight_bytes = write_handle.lock().await.in_flight_bytes();
+            assert!(in_flight_bytes <= max_write_buffer);
+            max_in_flight_bytes = max_in_flight_bytes.max(in_flight_bytes);
+        }
+
+        // Uploads overlap with writes, so more than one part is held at once.
+        assert!(max_in_flight_bytes > MIN_PART_SIZE);
+    }
+
+    fn create_s3_storage<D: DispatchSignedRequest + Send + Sync + 'static>(
+        dispatcher: D,
+        max_write_buffer: usize,
+    ) -> S3Storage {
+        let s3_client = S3Client::new_with(
+            dispatcher,
+            StaticProvider::new_minimal(String::from("key"), String::from("secret")),
+            Region::UsEast1,
+        );
+
+        S3Storage::new(
+            s3_client,
+            String::from("bucket"),
+            None,
+            MIN_PART_SIZE,
+            None,
+            max_write_buffer,
+            Arc::new(DirCheckpoints::new()),
+        )
+    }
+
+    /// Creates a factory that signs with the `key` access key by default, and
+    /// with each of the `user_access_keys` for its user.
+    fn create_s3_storage_factory<D: DispatchSignedRequest + Clone + Send + Sync + 'static>(
+        dispatcher: D,
+        user_access_keys: Vec<(&str, &str)>,
+    ) -> S3StorageFactory {
+        let create_client = |access_key: &str| {
+            let credentials_provider =
+                StaticProvider::new_minimal(access_key.to_owned(), String::from("secret"));
+
+            let presigner = Presigner {
+                region: Region::UsEast1,
+                credentials_provider: Arc::new(credentials_provider.clone()),
+                expiry: Duration::from_secs(600),
+            };
+
+            let s3_client =
+                S3Client::new_with(dispatcher.clone(), credentials_provider, Region::UsEast1);
+
+            (s3_client, presigner)
+        };
+
+        let (s3_client, presigner) = create_client("key");
+
+        S3StorageFactory {
+            s3_client,
+            bucket: String::from("bucket"),
+            bucket_prefixes: Vec::new(),
+            storage_class: None,
+            part_size: MIN_PART_SIZE,
+            list_page_size: None,
+            max_write_buffer: 2 * MIN_PART_SIZE,
+            public_read_permissions: false,
+            rmdir_require_empty: false,
+            preserve_mtime: false,
+            list_metadata: false,
+            decompress_gzip: false,
+            case_insensitive_keys: false,
+            read_after_write_retries: 0,
+            delete_retries: 0,
+            read_coalesce_window: 0,
+            follow_redirects: false,
+            uploader_metadata: None,
+            snapshot_dir: None,
+            write_spool_dir: None,
+            presigner,
+            user_clients: user_access_keys
+                .into_iter()
+                .map(|(user, access_key)| (user.to_owned(), create_client(access_key)))
+                .collect(),
+            dir_checkpoints: Arc::new(DirCheckpoints::new()),
+        }
+    }
+
+    async fn create_write_handle(s3_storage: &S3Storage) -> String {
+        s3_storage
+            .handle_manager
+            .create_write_handle(
+                "/file",
+                map_create_multipart_response_to_write_handle(
+                    CreateMultipartUploadOutput {
+                        upload_id: Some(String::from("id")),
+                        key: Some(String::from("file")),
+                        ..Default::default()
+                    },
+                    MIN_PART_SIZE,
+                )
+                .unwrap(),
+            )
+            .await
+    }
+
+    #[test]
+    fn test_get_home_returns_users_home_directory() {
+        assert_eq!("/home/test", get_home("test"));
+    }
+
+    #[test]
+    fn test_get_default_endpoint_region() {
+        assert_eq!("custom", get_default_endpoint_region());
+    }
+
+    #[test]
+    fn test_s3_config_deserializes_storage_class() {
+        let s3_config: S3Config = envy::from_iter(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (String::from("S3_STORAGE_CLASS"), String::from("GLACIER_IR")),
+        ])
+        .unwrap();
+
+        assert_eq!(Some(StorageClass::GlacierIr), s3_config.storage_class);
+    }
+
+    #[test]
+    fn test_s3_config_deserializes_bucket_prefixes() {
+        let s3_config: S3Config = envy::from_iter(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (
+                String::from("S3_BUCKET_PREFIXES"),
+                String::from("/archive=archive-bucket,/data/cold=cold-bucket"),
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                (String::from("archive/"), String::from("archive-bucket")),
+                (String::from("data/cold/"), String::from("cold-bucket")),
+            ],
+            s3_config.bucket_prefixes
+        );
+    }
+
+    #[test]
+    fn test_s3_config_deserializes_user_profiles() {
+        let s3_config: S3Config = envy::from_iter(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (
+                String::from("S3_USER_PROFILES"),
+                String::from("alice=tenant-a,bob=tenant-b"),
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                (String::from("alice"), String::from("tenant-a")),
+                (String::from("bob"), String::from("tenant-b")),
+            ],
+            s3_config.user_profiles
+        );
+    }
+
+    #[test]
+    fn test_s3_config_rejects_user_profile_without_profile() {
+        let s3_config: Result<S3Config, envy::Error> = envy::from_iter(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (String::from("S3_USER_PROFILES"), String::from("alice=")),
+        ]);
+
+        assert!(s3_config.is_err());
+    }
+
+    #[test]
+    fn test_s3_config_rejects_bucket_prefix_without_bucket() {
+        let s3_config: Result<S3Config, envy::Error> = envy::from_iter(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (String::from("S3_BUCKET_PREFIXES"), String::from("/archive")),
+        ]);
+
+        assert!(s3_config.is_err());
+    }
+
+    #[test]
+    fn test_s3_config_deserializes_region() {
+        let s3_config: S3Config = envy::from_iter(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (String::from("S3_REGION"), String::from("eu-west-2")),
+        ])
+        .unwrap();
+
+        assert_eq!(Some(Region::EuWest2), s3_config.region);
+    }
+
+    #[test]
+    fn test_s3_config_rejects_invalid_region() {
+        let s3_config: Result<S3Config, envy::Error> = envy::from_iter(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (String::from("S3_REGION"), String::from("not-a-region")),
+        ]);
+
+        assert!(s3_config.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_region_detects_bucket_location_when_region_unset() {
+        let s3_config: S3Config =
+            envy::from_iter(vec![(String::from("S3_BUCKET"), String::from("bucket"))]).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let region = resolve_region(
+            &s3_config,
+            LocationDispatcher {
+                location_constraint: "eu-west-2",
+                requests: requests.clone(),
+            },
+            StaticProvider::new_minimal(String::from("key"), String::from("secret")),
+        )
+        .await;
+
+        assert_eq!(Region::EuWest2, region);
+        assert_eq!(vec![String::from("location")], *requests.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_region_uses_configured_region() {
+        let s3_config: S3Config = envy::from_iter(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (String::from("S3_REGION"), String::from("ap-south-1")),
+        ])
+        .unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let region = resolve_region(
+            &s3_config,
+            LocationDispatcher {
+                location_constraint: "eu-west-2",
+                requests: requests.clone(),
+            },
+            StaticProvider::new_minimal(String::from("key"), String::from("secret")),
+        )
+        .await;
+
+        assert_eq!(Region::ApSouth1, region);
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_detect_bucket_region_maps_empty_location_to_us_east_1() {
+        let s3_client = S3Client::new_with(
+            LocationDispatcher {
+                location_constraint: "",
+                requests: Arc::new(Mutex::new(Vec::new())),
+            },
+            StaticProvider::new_minimal(String::from("key"), String::from("secret")),
+            Region::UsEast1,
+        );
+
+        assert_eq!(
+            Region::UsEast1,
+            detect_bucket_region(&s3_client, "bucket").await.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_s3_config_deserializes_part_size() {
+        let s3_config: S3Config = envy::from_iter(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (String::from("PART_SIZE"), String::from("8388608")),
+        ])
+        .unwrap();
+
+        assert_eq!(8 * 1024 * 1024, s3_config.part_size);
+    }
+
+    #[test]
+    fn test_s3_config_defaults_part_size() {
+        let s3_config: S3Config =
+            envy::from_iter(vec![(String::from("S3_BUCKET"), String::from("bucket"))]).unwrap();
+
+        assert_eq!(get_default_part_size(), s3_config.part_size);
+    }
+
+    #[test]
+    fn test_s3_config_rejects_part_size_below_minimum() {
+        let s3_config = envy::from_iter::<_, S3Config>(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (String::from("PART_SIZE"), (MIN_PART_SIZE - 1).to_string()),
+        ]);
+
+        assert!(s3_config.is_err());
+    }
+
+    #[test]
+    fn test_s3_config_deserializes_list_page_size() {
+        let s3_config: S3Config = envy::from_iter(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (String::from("LIST_PAGE_SIZE"), String::from("100")),
+        ])
+        .unwrap();
+
+        assert_eq!(Some(100), s3_config.list_page_size);
+    }
+
+    #[test]
+    fn test_get_list_page_size_caps_at_s3_limit() {
+        assert_eq!(1000, get_list_page_size(5000));
+        assert_eq!(100, get_list_page_size(100));
+        assert_eq!(1, get_list_page_size(0));
+    }
+
+    #[test]
+    fn test_build_list_dir_request_uses_page_size() {
+        let list_dir_request = build_list_dir_request(
+            "bucket",
+            "home/test/",
+            Some(String::from("token")),
+            Some(100),
+        );
+
+        assert_eq!(Some(100), list_dir_request.max_keys);
+        assert_eq!(Some(String::from("home/test/")), list_dir_request.prefix);
+        assert_eq!(
+            Some(String::from("token")),
+            list_dir_request.continuation_token
+        );
+        assert_eq!(Some(String::from("/")), list_dir_request.delimiter);
+    }
+
+    #[test]
+    fn test_s3_config_rejects_invalid_storage_class() {
+        let s3_config = envy::from_iter::<_, S3Config>(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (
+                String::from("S3_STORAGE_CLASS"),
+                String::from("DEEP_FREEZE"),
+            ),
+        ]);
+
+        assert!(s3_config.is_err());
+    }
+
+    #[test]
+    fn test_build_request_dispatcher_limits_connections() {
+        let s3_config: S3Config = envy::from_iter(vec![
+            (String::from("S3_BUCKET"), String::from("bucket")),
+            (String::from("S3_MAX_CONNECTIONS"), String::from("25")),
+            (String::from("S3_CONNECT_TIMEOUT"), String::from("5")),
+        ])
+        .unwrap();
+
+        assert_eq!(Some(5), s3_config.connect_timeout);
+        assert_eq!(
+            Some(25),
+            build_request_dispatcher(&s3_config).available_connections()
+        );
+    }
+
+    #[test]
+    fn test_build_create_multipart_upload_request_sets_storage_class() {
+        let request = build_create_multipart_upload_request(
+            "bucket",
+            String::from("key"),
+            Some(StorageClass::StandardIa),
+            None,
+            None,
+        );
+
+        assert_eq!("bucket", request.bucket);
+        assert_eq!("key", request.key);
+        assert_eq!(Some(String::from("STANDARD_IA")), request.storage_class);
+    }
+
+    #[test]
+    fn test_build_create_multipart_upload_request_without_storage_class() {
+        let request =
+            build_create_multipart_upload_request("bucket", String::from("key"), None, None, None);
+
+        assert_eq!(None, request.storage_class);
+    }
+
+    #[test]
+    fn test_build_create_multipart_upload_request_sets_acl() {
+        let request = build_create_multipart_upload_request(
+            "bucket",
+            String::from("key"),
+            None,
+            map_permissions_to_acl(Some(0o644), true),
+            None,
+        );
+
+        assert_eq!(Some(String::from("public-read")), request.acl);
+    }
+
+    #[test]
+    fn test_map_permissions_to_acl() {
+        assert_eq!(
+            Some(String::from("public-read")),
+            map_permissions_to_acl(Some(0o644), true)
+        );
+        assert_eq!(
+            Some(String::from("private")),
+            map_permissions_to_acl(Some(0o600), true)
+        );
+        assert_eq!(None, map_permissions_to_acl(Some(0o644), false));
+        assert_eq!(None, map_permissions_to_acl(None, true));
+    }
+
+    #[tokio::test]
+    async fn test_read_dir_reports_single_page_has_no_more_pages() {
+        let s3_storage = create_s3_storage(
+            ListingDispatcher {
+                keys: vec![String::from("dir/a.txt"), String::from("dir/b.txt")],
+                deleted_keys: Arc::new(Mutex::new(Vec::new())),
+                put_paths: Arc::new(Mutex::new(Vec::new())),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let handle = s3_storage
+            .open_dir_handle(String::from("/dir"))
+            .await
+            .unwrap();
+        let dir_page = s3_storage.read_dir(&handle).await.unwrap();
+
+        assert_eq!(2, dir_page.files.len());
+        assert!(!dir_page.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_remove_dir_deletes_objects_under_prefix_in_batch() {
+        let deleted_keys = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            ListingDispatcher {
+                keys: vec![String::from("dir/a.txt"), String::from("dir/sub/b.txt")],
+                deleted_keys: deleted_keys.clone(),
+                put_paths: Arc::new(Mutex::new(Vec::new())),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        s3_storage.remove_dir(String::from("/dir")).await.unwrap();
+
+        assert_eq!(
+            vec![String::from("dir/a.txt"), String::from("dir/sub/b.txt")],
+            *deleted_keys.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_dir_retries_objects_that_fail_to_delete() {
+        let deleted_keys = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            FlakyDeleteDispatcher {
+                keys: vec![String::from("dir/a.txt"), String::from("dir/b.txt")],
+                failing_key: String::from("dir/a.txt"),
+                failure_code: "InternalError",
+                failures: Arc::new(Mutex::new(2)),
+                deleted_keys: deleted_keys.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_delete_retries(2);
+
+        s3_storage.remove_dir(String::from("/dir")).await.unwrap();
+
+        assert_eq!(
+            vec![String::from("dir/b.txt"), String::from("dir/a.txt")],
+            *deleted_keys.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_dir_reports_objects_that_fail_to_delete_after_retries() {
+        let deleted_keys = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            FlakyDeleteDispatcher {
+                keys: vec![String::from("dir/a.txt"), String::from("dir/b.txt")],
+                failing_key: String::from("dir/a.txt"),
+                failure_code: "InternalError",
+                failures: Arc::new(Mutex::new(2)),
+                deleted_keys: deleted_keys.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_delete_retries(1);
+
+        let error = s3_storage
+            .remove_dir(String::from("/dir"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(Some(&Error::ServerError), error.downcast_ref::<Error>());
+        assert_eq!("Failed to delete 1 of 2 objects.", error.to_string());
+        assert_eq!(
+            vec![String::from("dir/b.txt")],
+            *deleted_keys.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_dir_does_not_retry_objects_denied_deletion() {
+        let deleted_keys = Arc::new(Mutex::new(Vec::new()));
+        let failures = Arc::new(Mutex::new(2));
+        let s3_storage = create_s3_storage(
+            FlakyDeleteDispatcher {
+                keys: vec![String::from("dir/a.txt"), String::from("dir/b.txt")],
+                failing_key: String::from("dir/a.txt"),
+                failure_code: "AccessDenied",
+                failures: failures.clone(),
+                deleted_keys: deleted_keys.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_delete_retries(2);
+
+        let error = s3_storage
+            .remove_dir(String::from("/dir"))
+            .await
+            .unwrap_err();
+
+        assert_eq!("Failed to delete 1 of 2 objects.", error.to_string());
+        assert_eq!(1, *failures.lock().unwrap());
+        assert_eq!(
+            vec![String::from("dir/b.txt")],
+            *deleted_keys.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_dir_requiring_empty_dir_fails_for_non_empty_dir() {
+        let deleted_keys = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            ListingDispatcher {
+                keys: vec![String::from("dir/a.txt")],
+                deleted_keys: deleted_keys.clone(),
+                put_paths: Arc::new(Mutex::new(Vec::new())),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_rmdir_require_empty(true);
+
+        let error = s3_storage
+            .remove_dir(String::from("/dir"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(Some(&Error::ServerError), error.downcast_ref::<Error>());
+        assert!(deleted_keys.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_remove_dir_requiring_empty_dir_succeeds_for_empty_dir() {
+        let deleted_keys = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            ListingDispatcher {
+                keys: Vec::new(),
+                deleted_keys: deleted_keys.clone(),
+                put_paths: Arc::new(Mutex::new(Vec::new())),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_rmdir_require_empty(true);
+
+        s3_storage.remove_dir(String::from("/dir")).await.unwrap();
+
+        assert!(deleted_keys.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_visible_prefixes_reads_user_config() {
+        let s3_storage = create_s3_storage(
+            ObjectDispatcher {
+                keys: vec![String::from(".ssh/test/visible_prefixes")],
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        assert_eq!(
+            Some(vec![String::from("/data")]),
+            s3_storage.get_visible_prefixes("test").await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_visible_prefixes_without_user_config() {
+        let s3_storage =
+            create_s3_storage(ObjectDispatcher { keys: Vec::new() }, 2 * MIN_PART_SIZE);
+
+        assert_eq!(None, s3_storage.get_visible_prefixes("test").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_get_file_metadata_resolves_key_case_insensitively_when_enabled() {
+        let s3_storage = create_s3_storage(
+            ObjectDispatcher {
+                keys: vec![String::from("home/test/file.txt")],
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_case_insensitive_keys(true);
+
+        let file = s3_storage
+            .get_file_metadata(String::from("/home/test/File.TXT"))
+            .await
+            .unwrap();
+
+        assert_eq!("file.txt", file.file_name);
+        assert_eq!(Some(0o100777), file.file_attributes.permissions);
+    }
+
+    #[tokio::test]
+    async fn test_get_file_metadata_matches_key_case_when_disabled() {
+        let s3_storage = create_s3_storage(
+            ObjectDispatcher {
+                keys: vec![String::from("home/test/file.txt")],
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let file = s3_storage
+            .get_file_metadata(String::from("/home/test/File.TXT"))
+            .await
+            .unwrap();
+
+        assert_eq!(Some(0o40777), file.file_attributes.permissions);
+    }
+
+    #[tokio::test]
+    async fn test_download_counts_backend_calls_per_operation() {
+        let s3_storage = create_s3_storage(
+            ObjectDispatcher {
+                keys: vec![String::from("home/test/file.txt")],
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let handle = backend_calls::with_operation(
+            "open",
+            s3_storage.open_read_handle(String::from("/home/test/file.txt")),
+        )
+        .await
+        .unwrap();
+        let data = backend_calls::with_operation("read", s3_storage.read_data(&handle, 1024))
+            .await
+            .unwrap();
+        backend_calls::with_operation("close", s3_storage.close_handle(&handle))
+            .await
+            .unwrap();
+
+        let backend_calls = s3_storage.backend_calls().unwrap();
+
+        assert_eq!(b"data".to_vec(), data);
+        assert_eq!(1, backend_calls.get("open", BackendCall::Get));
+        assert_eq!(0, backend_calls.get("read", BackendCall::Get));
+        assert_eq!(0, backend_calls.get("close", BackendCall::Get));
+        assert_eq!("backend_calls open.GET=1", backend_calls.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_open_read_handle_resolves_key_case_insensitively_when_enabled() {
+        let s3_storage = create_s3_storage(
+            ObjectDispatcher {
+                keys: vec![String::from("home/test/file.txt")],
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_case_insensitive_keys(true);
+
+        let handle = s3_storage
+            .open_read_handle(String::from("/home/test/File.TXT"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"data".to_vec(),
+            s3_storage.read_data(&handle, 1024).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_read_handle_fails_for_missing_key_when_case_insensitive() {
+        let s3_storage = create_s3_storage(
+            ObjectDispatcher {
+                keys: vec![String::from("home/test/file.txt")],
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_case_insensitive_keys(true);
+
+        assert!(s3_storage
+            .open_read_handle(String::from("/home/test/other.txt"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_many_metadata_heads_files_concurrently_in_order() {
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let s3_storage = create_s3_storage(
+            MetadataDispatcher {
+                in_flight: Arc::new(AtomicUsize::new(0)),
+                max_in_flight: max_in_flight.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let file_count = 2 * MAX_CONCURRENT_METADATA_REQUESTS;
+        let files = s3_storage
+            .get_many_metadata(
+                (0..file_count)
+                    .map(|i| format!("/home/test/file{}.txt", i))
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(file_count, files.len());
+
+        for (i, file) in files.iter().enumerate() {
+            assert_eq!(format!("file{}.txt", i), file.file_name);
+            assert_eq!(Some(i as u64), file.file_attributes.size);
+        }
+
+        assert_eq!(
+            MAX_CONCURRENT_METADATA_REQUESTS,
+            max_in_flight.load(Ordering::SeqCst)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_dir_with_list_metadata_reports_file_metadata() {
+        let s3_storage = create_s3_storage(
+            MetadataDispatcher {
+                in_flight: Arc::new(AtomicUsize::new(0)),
+                max_in_flight: Arc::new(AtomicUsize::new(0)),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_list_metadata(true);
+
+        let handle = s3_storage
+            .open_dir_handle(String::from("/home/test"))
+            .await
+            .unwrap();
+        let files = s3_storage.read_dir(&handle).await.unwrap().files;
+
+        let entries: Vec<(&str, Option<u64>)> = files
+            .iter()
+            .map(|file| (file.file_name.as_str(), file.file_attributes.size))
+            .collect();
+        assert_eq!(
+            vec![
+                ("dir", None),
+                ("file1.txt", Some(1)),
+                ("file2.txt", Some(2))
+            ],
+            entries
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_user_storage_signs_with_each_users_credentials() {
+        let access_keys = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage_factory = create_s3_storage_factory(
+            CredentialRecordingDispatcher {
+                access_keys: access_keys.clone(),
+            },
+            vec![("alice", "alice-key"), ("bob", "bob-key")],
+        );
+
+        for user in ["alice", "bob", "carol"] {
+            s3_storage_factory
+                .create_user_storage(user)
+                .file_exists(format!("/home/{}/file.txt", user))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            vec!["alice-key", "bob-key", "key"],
+            *access_keys.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bucket_prefixes_route_keys_to_mapped_bucket() {
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            PathRecordingDispatcher {
+                paths: paths.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_bucket_prefixes(vec![
+            (String::from("archive/"), String::from("archive-bucket")),
+            (String::from("archive/cold/"), String::from("cold-bucket")),
+        ]);
+
+        for file_name in [
+            "/archive/file.txt",
+            "/archive/cold/file.txt",
+            "/home/test/file.txt",
+            "/archived.txt",
+        ] {
+            s3_storage
+                .file_exists(String::from(file_name))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            vec![
+                "/archive-bucket/archive/file.txt",
+                "/cold-bucket/archive/cold/file.txt",
+                "/bucket/home/test/file.txt",
+                "/bucket/archived.txt",
+            ],
+            *paths.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bucket_prefixes_list_mapped_directory_from_its_bucket() {
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            PathRecordingDispatcher {
+                paths: paths.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_bucket_prefixes(vec![(
+            String::from("archive/"),
+            String::from("archive-bucket"),
+        )]);
+
+        s3_storage
+            .get_used_bytes(String::from("/archive"))
+            .await
+            .unwrap();
+        s3_storage
+            .get_used_bytes(String::from("/home/test"))
+            .await
+            .unwrap();
+
+        assert_eq!(vec!["/archive-bucket", "/bucket"], *paths.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_open_read_handle_through_snapshot_dir_reads_older_version() {
+        let s3_storage = create_s3_storage(
+            VersionsDispatcher {
+                versions: vec![
+                    (Some("v2"), "2024-02-01T00:00:00.000Z"),
+                    (Some("v1"), "2024-01-01T00:00:00.000Z"),
+                ],
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_snapshot_dir(Some(String::from("/.snapshots/")));
+
+        let handle = s3_storage
+            .open_read_handle(String::from(
+                "/.snapshots/2024-01-15T00:00:00Z/home/test/file.txt",
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"v1".to_vec(),
+            s3_storage.read_data(&handle, 1024).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_read_handle_through_snapshot_dir_before_first_version() {
+        let s3_storage = create_s3_storage(
+            VersionsDispatcher {
+                versions: vec![(Some("v1"), "2024-01-01T00:00:00.000Z")],
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_snapshot_dir(Some(String::from("/.snapshots")));
+
+        let error = s3_storage
+            .open_read_handle(String::from(
+                "/.snapshots/2023-12-31T00:00:00Z/home/test/file.txt",
+            ))
+            .await
+            .unwrap_err();
+
+        assert_eq!(Some(&Error::NoSuchFile), error.downcast_ref::<Error>());
+    }
+
+    #[tokio::test]
+    async fn test_open_read_handle_through_snapshot_dir_after_delete() {
+        let s3_storage = create_s3_storage(
+            VersionsDispatcher {
+                versions: vec![
+                    (None, "2024-02-01T00:00:00.000Z"),
+                    (Some("v1"), "2024-01-01T00:00:00.000Z"),
+                ],
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_snapshot_dir(Some(String::from("/.snapshots")));
+
+        let error = s3_storage
+            .open_read_handle(String::from(
+                "/.snapshots/2024-03-01T00:00:00Z/home/test/file.txt",
+            ))
+            .await
+            .unwrap_err();
+
+        assert_eq!(Some(&Error::NoSuchFile), error.downcast_ref::<Error>());
+    }
+
+    #[tokio::test]
+    async fn test_open_write_handle_under_snapshot_dir_is_denied() {
+        let s3_storage = create_s3_storage(
+            VersionsDispatcher {
+                versions: Vec::new(),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_snapshot_dir(Some(String::from("/.snapshots")));
+
+        let error = s3_storage
+            .open_write_handle(
+                String::from("/.snapshots/2024-01-01T00:00:00Z/home/test/file.txt"),
+                FileAttributes::default(),
+                false,
+                "test",
+            )
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            Some(&Error::PermissionDenied),
+            error.downcast_ref::<Error>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_file_metadata_through_snapshot_dir_returns_older_version() {
+        let s3_storage = create_s3_storage(
+            VersionsDispatcher {
+                versions: vec![
+                    (Some("version-2"), "2024-02-01T00:00:00.000Z"),
+                    (Some("v1"), "2024-01-01T00:00:00.000Z"),
+                ],
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_snapshot_dir(Some(String::from("/.snapshots")));
+
+        let file = s3_storage
+            .get_file_metadata(String::from(
+                "/.snapshots/2024-01-15T00:00:00Z/home/test/file.txt",
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!("file.txt", file.file_name);
+        assert_eq!(Some(2), file.file_attributes.size);
+        assert!(!file.file_attributes.is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_file_exists_through_snapshot_dir() {
+        let s3_storage = create_s3_storage(
+            VersionsDispatcher {
+                versions: vec![(Some("v1"), "2024-01-01T00:00:00.000Z")],
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_snapshot_dir(Some(String::from("/.snapshots")));
+
+        assert!(s3_storage
+            .file_exists(String::from(
+                "/.snapshots/2024-01-15T00:00:00Z/home/test/file.txt"
+            ))
+            .await
+            .unwrap());
+        assert!(!s3_storage
+            .file_exists(String::from(
+                "/.snapshots/2023-12-31T00:00:00Z/home/test/file.txt"
+            ))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_changes_under_snapshot_dir_are_denied() {
+        let s3_storage = create_s3_storage(
+            VersionsDispatcher {
+                versions: vec![(Some("v1"), "2024-01-01T00:00:00.000Z")],
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_snapshot_dir(Some(String::from("/.snapshots")))
+        .with_preserve_mtime(true);
+        let file_name = String::from("/.snapshots/2024-01-15T00:00:00Z/home/test/file.txt");
+
+        let errors = vec![
+            s3_storage.remove_file(file_name.clone()).await.unwrap_err(),
+            s3_storage
+                .rename(file_name.clone(), String::from("/home/test/file.txt"))
+                .await
+                .unwrap_err(),
+            s3_storage
+                .rename(String::from("/home/test/file.txt"), file_name.clone())
+                .await
+                .unwrap_err(),
+            s3_storage
+                .set_mtime(file_name.clone(), 0)
+                .await
+                .unwrap_err(),
+            s3_storage
+                .set_permissions(file_name.clone(), 0o644)
+                .await
+                .unwrap_err(),
+            s3_storage
+                .set_extended_attributes(file_name, BTreeMap::new())
+                .await
+                .unwrap_err(),
+        ];
+
+        for error in errors {
+            assert_eq!(
+                Some(&Error::PermissionDenied),
+                error.downcast_ref::<Error>()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_copy_data_copies_object_as_first_part() {
+        let copy_sources = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            CopyDispatcher {
+                size: MIN_PART_SIZE,
+                copy_sources: copy_sources.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let read_handle = s3_storage
+            .open_read_handle(String::from("/home/test/source.txt"))
+            .await
+            .unwrap();
+        let write_handle = s3_storage
+            .open_write_handle(
+                String::from("/home/test/copy.txt"),
+                FileAttributes::default(),
+                false,
+                "test",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            MIN_PART_SIZE as u64,
+            s3_storage
+                .copy_data(&read_handle, &write_handle)
+                .await
+                .unwrap()
+        );
+        s3_storage.close_handle(&write_handle).await.unwrap();
+
+        assert_eq!(
+            vec![String::from("bucket/home/test/source.txt")],
+            *copy_sources.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_copy_data_leaves_object_smaller_than_part_to_caller() {
+        let copy_sources = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            CopyDispatcher {
+                size: 10,
+                copy_sources: copy_sources.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let read_handle = s3_storage
+            .open_read_handle(String::from("/home/test/source.txt"))
+            .await
+            .unwrap();
+        let write_handle = s3_storage
+            .open_write_handle(
+                String::from("/home/test/copy.txt"),
+                FileAttributes::default(),
+                false,
+                "test",
+            )
+            .await
+            .unwrap();
+
+        let error = s3_storage
+            .copy_data(&read_handle, &write_handle)
+            .await
+            .unwrap_err();
+
+        assert_eq!(Some(&Error::Unimplemented), error.downcast_ref::<Error>());
+        assert!(copy_sources.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_open_read_handle_returns_no_such_file_for_missing_key() {
+        let s3_storage =
+            create_s3_storage(ObjectDispatcher { keys: Vec::new() }, 2 * MIN_PART_SIZE);
+
+        let error = s3_storage
+            .open_read_handle(String::from("/home/test/file.txt"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(Some(&Error::NoSuchFile), error.downcast_ref::<Error>());
+    }
+
+    #[tokio::test]
+    async fn test_file_exists_for_stored_and_missing_keys() {
+        let s3_storage = create_s3_storage(
+            ObjectDispatcher {
+                keys: vec![String::from("home/test/file.txt")],
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        assert!(s3_storage
+            .file_exists(String::from("/home/test/file.txt"))
+            .await
+            .unwrap());
+        assert!(!s3_storage
+            .file_exists(String::from("/home/test/other.txt"))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_orphaned_uploads_aborts_only_old_uploads() {
+        let aborted_upload_ids = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            MultipartUploadsDispatcher {
+                uploads: vec![
+                    (
+                        String::from("home/test/crashed.txt"),
+                        String::from("old-upload"),
+                        Utc::now() - chrono::Duration::days(2),
+                    ),
+                    (
+                        String::from("home/test/uploading.txt"),
+                        String::from("recent-upload"),
+                        Utc::now() - chrono::Duration::minutes(5),
+                    ),
+                    (
+                        String::from("home/other/crashed.txt"),
+                        String::from("older-upload"),
+                        Utc::now() - chrono::Duration::days(30),
+                    ),
+                ],
+                aborted_upload_ids: aborted_upload_ids.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let aborted = s3_storage
+            .cleanup_orphaned_uploads(Duration::from_secs(24 * 60 * 60))
+            .await
+            .unwrap();
+
+        assert_eq!(2, aborted);
+        assert_eq!(
+            vec![String::from("old-upload"), String::from("older-upload")],
+            *aborted_upload_ids.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dir_exists_for_listed_and_empty_prefixes() {
+        let existing_storage = create_s3_storage(
+            ListingDispatcher {
+                keys: vec![String::from("home/test/dir/file.txt")],
+                deleted_keys: Arc::new(Mutex::new(Vec::new())),
+                put_paths: Arc::new(Mutex::new(Vec::new())),
+            },
+            2 * MIN_PART_SIZE,
+        );
+        let missing_storage = create_s3_storage(
+            ListingDispatcher {
+                keys: Vec::new(),
+                deleted_keys: Arc::new(Mutex::new(Vec::new())),
+                put_paths: Arc::new(Mutex::new(Vec::new())),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        assert!(existing_storage
+            .dir_exists(String::from("/home/test/dir"))
+            .await
+            .unwrap());
+        assert!(!missing_storage
+            .dir_exists(String::from("/home/test/dir"))
+            .await
+            .unwrap());
+        assert!(missing_storage.dir_exists(String::from("/")).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_create_home_puts_marker_for_missing_home() {
+        let put_paths = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            ListingDispatcher {
+                keys: Vec::new(),
+                deleted_keys: Arc::new(Mutex::new(Vec::new())),
+                put_paths: put_paths.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        s3_storage.create_home("test").await.unwrap();
+
+        assert_eq!(
+            vec![String::from("/bucket/home/test/")],
+            *put_paths.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_home_skips_existing_home() {
+        let put_paths = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            ListingDispatcher {
+                keys: vec![String::from("home/test/file.txt")],
+                deleted_keys: Arc::new(Mutex::new(Vec::new())),
+                put_paths: put_paths.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        s3_storage.create_home("test").await.unwrap();
+
+        assert!(put_paths.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_open_write_handle_preserving_metadata_keeps_content_type_and_tags() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            ExistingObjectDispatcher {
+                requests: requests.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let handle = s3_storage
+            .open_write_handle(
+                String::from("/file"),
+                FileAttributes::default(),
+                true,
+                "test",
+            )
+            .await
+            .unwrap();
+        s3_storage
+            .write_data(&handle, bytes::Bytes::from("data"))
+            .await
+            .unwrap();
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+
+        let create_request = requests
+            .iter()
+            .find(|request| {
+                request.method == "POST" && request.params.contains(&String::from("uploads"))
+            })
+            .unwrap();
+        assert_eq!(
+            Some("text/csv"),
+            create_request
+                .headers
+                .get("content-type")
+                .map(String::as_str)
+        );
+        assert_eq!(
+            Some("reports"),
+            create_request
+                .headers
+                .get("x-amz-meta-owner")
+                .map(String::as_str)
+        );
+
+        assert!(requests
+            .iter()
+            .any(|request| request.method == "PUT"
+                && request.params.contains(&String::from("tagging"))));
+    }
+
+    #[tokio::test]
+    async fn test_spooled_write_is_only_uploaded_when_closed() {
+        let write_spool_dir =
+            std::env::temp_dir().join(format!("dray-spool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&write_spool_dir).unwrap();
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            ExistingObjectDispatcher {
+                requests: requests.clone(),
+            },
+            MIN_PART_SIZE,
+        )
+        .with_write_spool_dir(Some(write_spool_dir.clone()));
+
+        let handle = s3_storage
+            .open_write_handle(
+                String::from("/file"),
+                FileAttributes::default(),
+                false,
+                "test",
+            )
+            .await
+            .unwrap();
+
+        // Two full parts and a partial one, which would upload the full parts
+        // before the handle is closed without spooling.
+        for _ in 0..5 {
+            s3_storage
+                .write_data(&handle, bytes::Bytes::from(vec![0; MIN_PART_SIZE / 2]))
+                .await
+                .unwrap();
+        }
+
+        let count_requests = |method: &str, param: &str| {
+            requests
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|request| {
+                    request.method == method && request.params.contains(&String::from(param))
+                })
+                .count()
+        };
+
+        assert_eq!(0, count_requests("PUT", "partNumber"));
+        assert_eq!(0, count_requests("POST", "uploadId"));
+        assert_eq!(1, std::fs::read_dir(&write_spool_dir).unwrap().count());
+
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!(3, count_requests("PUT", "partNumber"));
+        assert_eq!(1, count_requests("POST", "uploadId"));
+        assert_eq!(0, std::fs::read_dir(&write_spool_dir).unwrap().count());
+
+        std::fs::remove_dir(&write_spool_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_spool_file_is_removed_if_handle_is_never_closed() {
+        let write_spool_dir =
+            std::env::temp_dir().join(format!("dray-spool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&write_spool_dir).unwrap();
+
+        let s3_storage = create_s3_storage(
+            ExistingObjectDispatcher {
+                requests: Arc::new(Mutex::new(Vec::new())),
+            },
+            MIN_PART_SIZE,
+        )
+        .with_write_spool_dir(Some(write_spool_dir.clone()));
+
+        let handle = s3_storage
+            .open_write_handle(
+                String::from("/file"),
+                FileAttributes::default(),
+                false,
+                "test",
+            )
+            .await
+            .unwrap();
+        s3_storage
+            .write_data(&handle, bytes::Bytes::from("data"))
+            .await
+            .unwrap();
+
+        drop(s3_storage);
+
+        assert_eq!(0, std::fs::read_dir(&write_spool_dir).unwrap().count());
+
+        std::fs::remove_dir(&write_spool_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_file_metadata_does_not_read_tags() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            ExistingObjectDispatcher {
+                requests: requests.clone(),
+            },
+            MIN_PART_SIZE,
+        );
+
+        s3_storage
+            .get_file_metadata(String::from("/file"))
+            .await
+            .unwrap();
+
+        assert!(!requests
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|request| request.params.contains(&String::from("tagging"))));
+    }
+
+    #[tokio::test]
+    async fn test_get_extended_attributes_reads_tags() {
+        let s3_storage = create_s3_storage(
+            ExistingObjectDispatcher {
+                requests: Arc::new(Mutex::new(Vec::new())),
+            },
+            MIN_PART_SIZE,
+        );
+
+        let mut expected = BTreeMap::new();
+        expected.insert(String::from("project"), String::from("alpha"));
+
+        assert_eq!(
+            expected,
+            s3_storage
+                .get_extended_attributes(String::from("/file"))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_write_handle_without_preserving_metadata_skips_existing_object() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            ExistingObjectDispatcher {
+                requests: requests.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let handle = s3_storage
+            .open_write_handle(
+                String::from("/file"),
+                FileAttributes::default(),
+                false,
+                "test",
+            )
+            .await
+            .unwrap();
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+
+        assert!(!requests.iter().any(|request| request.method == "HEAD"));
+        assert!(!requests
+            .iter()
+            .any(|request| request.params.contains(&String::from("tagging"))));
+        assert!(!requests.iter().any(|request| {
+            request.headers.get("content-type").map(String::as_str) == Some("text/csv")
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_open_write_handle_records_uploader_metadata_when_enabled() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            ExistingObjectDispatcher {
+                requests: requests.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_uploader_metadata(Some(String::from("dray-user")));
+
+        let handle = s3_storage
+            .open_write_handle(
+                String::from("/file"),
+                FileAttributes::default(),
+                false,
+                "alice",
+            )
+            .await
+            .unwrap();
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+
+        let create_request = requests
+            .iter()
+            .find(|request| {
+                request.method == "POST" && request.params.contains(&String::from("uploads"))
+            })
+            .unwrap();
+        assert_eq!(
+            Some("alice"),
+            create_request
+                .headers
+                .get("x-amz-meta-dray-user")
+                .map(String::as_str)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_file_metadata_reports_uploader_when_enabled() {
+        let s3_storage = create_s3_storage(
+            ExistingObjectDispatcher {
+                requests: Arc::new(Mutex::new(Vec::new())),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_uploader_metadata(Some(String::from("owner")));
+
+        let file = s3_storage
+            .get_file_metadata(String::from("/file"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Some(&String::from("reports")),
+            file.file_attributes.extended.get(UPLOADER_ATTRIBUTE)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_permissions_applies_public_read_acl_when_enabled() {
+        let acls = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            AclRecordingDispatcher { acls: acls.clone() },
+            2 * MIN_PART_SIZE,
+        )
+        .with_public_read_permissions(true);
+
+        s3_storage
+            .set_permissions(String::from("/home/test/file.txt"), 0o644)
+            .await
+            .unwrap();
+
+        assert_eq!(vec![String::from("public-read")], *acls.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_set_permissions_applies_no_acl_when_disabled() {
+        let acls = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            AclRecordingDispatcher { acls: acls.clone() },
+            2 * MIN_PART_SIZE,
+        );
+
+        let error = s3_storage
+            .set_permissions(String::from("/home/test/file.txt"), 0o644)
+            .await
+            .unwrap_err();
+
+        assert_eq!(Some(&Error::Unimplemented), error.downcast_ref::<Error>());
+        assert!(acls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_data_decompresses_gzip_object_when_enabled() {
+        let s3_storage = create_s3_storage(
+            GzipObjectDispatcher {
+                body: gzip(b"data"),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_decompress_gzip(true);
+
+        let handle = s3_storage
+            .open_read_handle(String::from("/home/test/file.txt"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"data".to_vec(),
+            s3_storage.read_data(&handle, 1024).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_data_returns_gzip_object_unchanged_when_disabled() {
+        let s3_storage = create_s3_storage(
+            GzipObjectDispatcher {
+                body: gzip(b"data"),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let handle = s3_storage
+            .open_read_handle(String::from("/home/test/file.txt"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            gzip(b"data"),
+            s3_storage.read_data(&handle, 1024).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_data_stream_reads_body_as_chunks_are_sent() {
+        let pulled_chunks = Arc::new(AtomicUsize::new(0));
+        let s3_storage = create_s3_storage(
+            ChunkedObjectDispatcher {
+                chunk_count: 8,
+                chunk_length: 32 * 1024,
+                pulled_chunks: pulled_chunks.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let handle = s3_storage
+            .open_read_handle(String::from("/home/test/file.txt"))
+            .await
+            .unwrap();
+
+        let (len, mut chunks) = match s3_storage
+            .read_data_stream(&handle, 256 * 1024)
+            .await
+            .unwrap()
+        {
+            ReadData::Streamed { len, chunks } => (len, chunks),
+            ReadData::Buffered(_) => panic!("Expected a streamed read"),
+        };
+
+        assert_eq!(256 * 1024, len);
+
+        let first_chunk = chunks.next().await.unwrap().unwrap();
+
+        assert_eq!(vec![0; 32 * 1024], first_chunk.to_vec());
+        assert!(pulled_chunks.load(Ordering::SeqCst) < 8);
+
+        let mut data = first_chunk.to_vec();
+        while let Some(chunk) = chunks.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+
+        assert_eq!(256 * 1024, data.len());
+        assert_eq!(vec![7; 32 * 1024], data[224 * 1024..].to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_read_data_stream_stops_at_requested_length() {
+        let s3_storage = create_s3_storage(
+            ChunkedObjectDispatcher {
+                chunk_count: 4,
+                chunk_length: 64 * 1024,
+                pulled_chunks: Arc::new(AtomicUsize::new(0)),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let handle = s3_storage
+            .open_read_handle(String::from("/home/test/file.txt"))
+            .await
+            .unwrap();
+
+        let mut lens = Vec::new();
+        let mut data = Vec::new();
+
+        loop {
+            match s3_storage
+                .read_data_stream(&handle, 100 * 1024)
+                .await
+                .unwrap()
+            {
+                ReadData::Streamed { len, chunks } => {
+                    let chunks: Vec<bytes::Bytes> = chunks.try_collect().await.unwrap();
+                    let chunks = chunks.concat();
+
+                    assert_eq!(len as usize, chunks.len());
+                    lens.push(len);
+                    data.extend(chunks);
+                }
+                ReadData::Buffered(buffer) if buffer.is_empty() => break,
+                ReadData::Buffered(buffer) => {
+                    lens.push(buffer.len() as u32);
+                    data.extend(buffer);
+                }
+            }
+        }
+
+        assert_eq!(vec![100 * 1024, 100 * 1024, 56 * 1024], lens);
+        assert_eq!(vec![1; 64 * 1024], data[64 * 1024..128 * 1024].to_vec());
+        assert_eq!(256 * 1024, data.len());
+    }
+
+    #[tokio::test]
+    async fn test_read_data_stream_buffers_short_reads() {
+        let s3_storage = create_s3_storage(
+            ChunkedObjectDispatcher {
+                chunk_count: 1,
+                chunk_length: 1024,
+                pulled_chunks: Arc::new(AtomicUsize::new(0)),
+            },
+            2 * MIN_PART_SIZE,
+        );
+
+        let handle = s3_storage
+            .open_read_handle(String::from("/home/test/file.txt"))
+            .await
+            .unwrap();
+
+        match s3_storage
+            .read_data_stream(&handle, 256 * 1024)
+            .await
+            .unwrap()
+        {
+            ReadData::Buffered(data) => assert_eq!(vec![0; 1024], data),
+            ReadData::Streamed { .. } => panic!("Expected a buffered read"),
+        }
     }
 
-    async fn rename(&self, current: String, new: String) -> Result<()> {
-        let file = self.get_file_metadata(current.clone()).await?;
+    #[tokio::test]
+    async fn test_read_data_at_coalesces_scattered_reads_in_window() {
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            RangeObjectDispatcher {
+                length: 64 * 1024,
+                ranges: ranges.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_read_coalesce_window(16 * 1024);
 
-        match file.file_attributes.is_dir() {
-            true => self.rename_dir(current, new).await,
-            false => self.rename_file(current, new).await,
-        }?;
+        let handle = s3_storage
+            .open_read_handle(String::from("/home/test/file.txt"))
+            .await
+            .unwrap();
+
+        for offset in [4096, 1024 * 12, 8192, 4200] {
+            match s3_storage.read_data_at(&handle, offset, 100).await.unwrap() {
+                ReadData::Buffered(data) => assert_eq!(
+                    (offset..offset + 100)
+                        .map(|offset| offset as u8)
+                        .collect::<Vec<u8>>(),
+                    data
+                ),
+                ReadData::Streamed { .. } => panic!("Expected a buffered read"),
+            }
+        }
 
-        Ok(())
+        assert_eq!(
+            vec![None, Some(String::from("bytes=4096-20479"))],
+            *ranges.lock().unwrap()
+        );
     }
-}
-
-struct DirHandle {
-    prefix: String,
-    continuation_token: Option<String>,
-    is_eof: bool,
-}
-
-struct WriteHandle {
-    key: String,
-    upload_id: String,
-    completed_parts: Vec<CompletedPart>,
-    buffer: Vec<u8>,
-}
 
-fn get_home(user: &str) -> String {
-    format!("/home/{}", user)
-}
+    #[tokio::test]
+    async fn test_read_data_at_fetches_reads_outside_window_separately() {
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let s3_storage = create_s3_storage(
+            RangeObjectDispatcher {
+                length: 64 * 1024,
+                ranges: ranges.clone(),
+            },
+            2 * MIN_PART_SIZE,
+        );
 
-fn get_s3_prefix(dir_name: String) -> String {
-    let prefix = match "".eq(&dir_name) {
-        true => String::from("/"),
-        false => format!("{}/", dir_name[1..dir_name.len()].to_string()),
-    };
-    prefix
-}
+        let handle = s3_storage
+            .open_read_handle(String::from("/home/test/file.txt"))
+            .await
+            .unwrap();
 
-fn get_s3_copy_source(bucket: &str, key: &str) -> String {
-    format!("{}/{}", bucket, key)
-}
+        s3_storage.read_data_at(&handle, 4096, 100).await.unwrap();
+        s3_storage.read_data_at(&handle, 8192, 100).await.unwrap();
 
-fn map_list_objects_to_files(list_objects: ListObjectsV2Output) -> Vec<File> {
-    let files = list_objects.contents.unwrap_or_default();
+        match s3_storage.read_data_at(&handle, 0, 4).await.unwrap() {
+            ReadData::Buffered(data) => assert_eq!(vec![0, 1, 2, 3], data),
+            ReadData::Streamed { .. } => panic!("Expected a buffered read"),
+        }
+        match s3_storage
+            .read_data_at(&handle, 64 * 1024, 4)
+            .await
+            .unwrap()
+        {
+            ReadData::Buffered(data) => assert!(data.is_empty()),
+            ReadData::Streamed { .. } => panic!("Expected a buffered read"),
+        }
 
-    let directories = list_objects.common_prefixes.unwrap_or_default();
+        assert_eq!(
+            vec![
+                None,
+                Some(String::from("bytes=4096-4195")),
+                Some(String::from("bytes=8192-8291")),
+            ],
+            *ranges.lock().unwrap()
+        );
+    }
 
-    let mapped_files = files.iter().map(|object| map_object_to_file(object));
+    #[tokio::test]
+    async fn test_open_read_handle_follows_redirect_when_enabled() {
+        let s3_storage = create_s3_storage(
+            RedirectDispatcher {
+                redirects: vec![
+                    (String::from("link"), Some(String::from("/target"))),
+                    (String::from("target"), None),
+                ],
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_follow_redirects(true);
 
-    let mapped_dirs = directories.iter().map(|prefix| map_prefix_to_file(prefix));
+        let handle = s3_storage
+            .open_read_handle(String::from("/link"))
+            .await
+            .unwrap();
 
-    mapped_dirs.chain(mapped_files).collect()
-}
+        assert_eq!(
+            b"target".to_vec(),
+            s3_storage.read_data(&handle, 1024).await.unwrap()
+        );
+    }
 
-fn map_object_to_file(object: &Object) -> File {
-    let key = match &object.key {
-        Some(key) => key,
-        None => "",
-    };
+    #[tokio::test]
+    async fn test_open_read_handle_ignores_redirect_when_disabled() {
+        let s3_storage = create_s3_storage(
+            RedirectDispatcher {
+                redirects: vec![
+                    (String::from("link"), Some(String::from("/target"))),
+                    (String::from("target"), None),
+                ],
+            },
+            2 * MIN_PART_SIZE,
+        );
 
-    let mut key_pieces = key.rsplit('/');
-    let file_name = key_pieces.next().unwrap_or("");
+        let handle = s3_storage
+            .open_read_handle(String::from("/link"))
+            .await
+            .unwrap();
 
-    File {
-        file_name: file_name.to_string(),
-        file_attributes: FileAttributes {
-            size: object.size.map(|size| size as u64),
-            uid: None,
-            gid: None,
-            permissions: Some(0o100777),
-            atime: None,
-            mtime: map_rfc3339_to_epoch(object.last_modified.as_ref()),
-        },
+        assert_eq!(
+            b"link".to_vec(),
+            s3_storage.read_data(&handle, 1024).await.unwrap()
+        );
     }
-}
 
-fn map_prefix_to_file(prefix: &CommonPrefix) -> File {
-    let prefix = match prefix.prefix {
-        Some(ref prefix) => {
-            let mut prefix = prefix.to_string();
-            prefix.pop(); // strip trailing /
-            format!("/{}", prefix)
-        }
-        None => "".to_owned(),
-    };
+    #[tokio::test]
+    async fn test_open_read_handle_refuses_redirect_loop() {
+        let s3_storage = create_s3_storage(
+            RedirectDispatcher {
+                redirects: vec![
+                    (String::from("a"), Some(String::from("/b"))),
+                    (String::from("b"), Some(String::from("/a"))),
+                ],
+            },
+            2 * MIN_PART_SIZE,
+        )
+        .with_follow_redirects(true);
 
-    let mut prefix_pieces = prefix.rsplit('/');
-    let file_name = prefix_pieces.next().unwrap_or("");
+        let error = s3_storage
+            .open_read_handle(String::from("/a"))
+            .await
+            .unwrap_err();
 
-    File {
-        file_name: file_name.to_string(),
-        file_attributes: FileAttributes {
-            size: None,
-            uid: None,
-            gid: None,
-            permissions: Some(0o40777),
-            atime: None,
-            mtime: None,
-        },
+        assert_eq!(Some(&Error::ServerError), error.downcast_ref::<Error>());
     }
-}
 
-fn map_head_object_to_file(key: &str, head_object: &HeadObjectOutput) -> File {
-    let mut key_pieces = key.rsplit('/');
-    let file_name = key_pieces.next().unwrap_or("");
+    #[tokio::test]
+    async fn test_read_link_returns_redirect_location() {
+        let s3_storage = create_s3_storage(
+            RedirectDispatcher {
+                redirects: vec![
+                    (String::from("link"), Some(String::from("/target"))),
+                    (String::from("target"), None),
+                ],
+            },
+            2 * MIN_PART_SIZE,
+        );
 
-    File {
-        file_name: file_name.to_string(),
-        file_attributes: FileAttributes {
-            size: head_object
-                .content_length
-                .map(|content_length| content_length as u64),
-            uid: None,
-            gid: None,
-            permissions: Some(0o100777),
-            atime: None,
-            mtime: None,
-        },
+        assert_eq!(
+            "/target",
+            s3_storage.read_link(String::from("/link")).await.unwrap()
+        );
+        assert_eq!(
+            Some(&Error::ServerError),
+            s3_storage
+                .read_link(String::from("/target"))
+                .await
+                .unwrap_err()
+                .downcast_ref::<Error>()
+        );
+        assert_eq!(
+            Some(&Error::NoSuchFile),
+            s3_storage
+                .read_link(String::from("/missing"))
+                .await
+                .unwrap_err()
+                .downcast_ref::<Error>()
+        );
     }
-}
 
-fn create_file_with_directory_bit(key: &str) -> File {
-    let mut key_pieces = key.rsplit('/');
-    let file_name = key_pieces.next().unwrap_or("");
+    #[tokio::test]
+    async fn test_read_data_near_end_of_object_returns_tail() {
+        let s3_storage = create_s3_storage(
+            ObjectDispatcher {
+                keys: vec![String::from("home/test/file.txt")],
+            },
+            2 * MIN_PART_SIZE,
+        );
 
-    File {
-        file_name: file_name.to_string(),
-        file_attributes: FileAttributes {
-            size: None,
-            uid: None,
-            gid: None,
-            permissions: Some(0o40777),
-            atime: None,
-            mtime: None,
-        },
+        let handle = s3_storage
+            .open_read_handle(String::from("/home/test/file.txt"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"da".to_vec(),
+            s3_storage.read_data(&handle, 2).await.unwrap()
+        );
+        assert_eq!(
+            b"ta".to_vec(),
+            s3_storage.read_data(&handle, 10).await.unwrap()
+        );
+        assert!(s3_storage.read_data(&handle, 10).await.unwrap().is_empty());
     }
-}
 
-fn map_rfc3339_to_epoch(rfc3339: Option<&String>) -> Option<u32> {
-    rfc3339.map(|last_modified| {
-        last_modified
-            .parse::<DateTime<Utc>>()
-            .unwrap_or_else(|_e| Utc.timestamp(0, 0))
-            .timestamp() as u32
-    })
-}
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
 
-fn map_create_multipart_response_to_write_handle(
-    create_multipart_response: CreateMultipartUploadOutput,
-) -> Result<WriteHandle> {
-    let upload_id = match create_multipart_response.upload_id {
-        Some(upload_id) => Ok(upload_id),
-        None => Err(anyhow::anyhow!("Missing upload id.")),
-    }?;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
 
-    let key = match create_multipart_response.key {
-        Some(key) => Ok(key),
-        None => Err(anyhow::anyhow!("Missing key.")),
-    }?;
+    #[test]
+    fn test_resolve_key_strips_leading_slash() {
+        assert_eq!(
+            "home/test/file.txt",
+            resolve_key("/home/test/file.txt").unwrap()
+        );
+    }
 
-    Ok(WriteHandle {
-        key,
-        upload_id,
-        completed_parts: Vec::new(),
-        buffer: Vec::with_capacity(5000000),
-    })
-}
+    #[test]
+    fn test_resolve_key_strips_trailing_slash_and_current_dir() {
+        assert_eq!("home/test", resolve_key("/home/./test/").unwrap());
+    }
 
-fn get_default_endpoint_region() -> String {
-    String::from("custom")
-}
+    #[test]
+    fn test_resolve_key_converts_root_to_empty_key() {
+        assert_eq!("", resolve_key("/").unwrap());
+    }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    #[test]
+    fn test_resolve_key_rejects_parent_components() {
+        assert!(resolve_key("a/../../etc").is_err());
+    }
 
     #[test]
-    fn test_get_home_returns_users_home_directory() {
-        assert_eq!("/home/test", get_home("test"));
+    fn test_resolve_key_rejects_double_slashes() {
+        assert!(resolve_key("a//b").is_err());
     }
 
     #[test]
-    fn test_get_default_endpoint_region() {
-        assert_eq!("custom", get_default_endpoint_region());
+    fn test_resolve_key_rejects_null_bytes() {
+        assert!(resolve_key("a\0b").is_err());
     }
 
     #[test]
     fn test_get_s3_prefix_converts_unix_absolute_directory() {
-        assert_eq!(String::from("test/"), get_s3_prefix(String::from("/test")))
+        assert_eq!(String::from("test/"), get_s3_prefix("/test").unwrap())
     }
 
     #[test]
@@ -627,9 +5943,17 @@ mod test {
         assert_eq!("bucket/key", get_s3_copy_source("bucket", "key"))
     }
 
+    #[test]
+    fn test_get_s3_copy_source_percent_encodes_key() {
+        assert_eq!(
+            "bucket/home/test/a%20b%2Bc%25%3F.txt",
+            get_s3_copy_source("bucket", "home/test/a b+c%?.txt")
+        )
+    }
+
     #[test]
     fn test_get_s3_prefix_converts_blank_directory() {
-        assert_eq!(String::from("/"), get_s3_prefix(String::from("")))
+        assert_eq!(String::from(""), get_s3_prefix("").unwrap())
     }
 
     #[test]
@@ -637,7 +5961,6 @@ mod test {
         let list_objects = ListObjectsV2Output {
             common_prefixes: Some(vec![CommonPrefix {
                 prefix: Some("users/test/subfolder/".to_owned()),
-                ..Default::default()
             }]),
             contents: Some(vec![Object {
                 key: Some("users/test/file.txt".to_owned()),
@@ -649,7 +5972,7 @@ mod test {
             ..Default::default()
         };
 
-        let result = map_list_objects_to_files(list_objects);
+        let result = map_list_objects_to_files("users/test/", list_objects);
 
         assert_eq!(2, result.len());
         assert_eq!(
@@ -662,6 +5985,7 @@ mod test {
                     permissions: Some(0o40777),
                     atime: None,
                     mtime: None,
+                    extended: BTreeMap::new(),
                 }
             },
             result[0]
@@ -676,6 +6000,7 @@ mod test {
                     permissions: Some(0o100777),
                     atime: None,
                     mtime: Some(1417176009),
+                    extended: BTreeMap::new(),
                 }
             },
             result[1]
@@ -688,7 +6013,23 @@ mod test {
             ..Default::default()
         };
 
-        let result = map_list_objects_to_files(list_objects);
+        let result = map_list_objects_to_files("users/test/", list_objects);
+
+        assert_eq!(0, result.len());
+    }
+
+    #[test]
+    fn test_map_list_objects_to_files_skips_directory_marker() {
+        let list_objects = ListObjectsV2Output {
+            contents: Some(vec![Object {
+                key: Some("users/test/".to_owned()),
+                size: Some(0),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        let result = map_list_objects_to_files("users/test/", list_objects);
 
         assert_eq!(0, result.len());
     }
@@ -709,6 +6050,7 @@ mod test {
                     permissions: Some(0o100777),
                     atime: None,
                     mtime: None,
+                    extended: BTreeMap::new(),
                 }
             },
             map_object_to_file(&object)
@@ -729,6 +6071,7 @@ mod test {
                     permissions: Some(0o40777),
                     atime: None,
                     mtime: None,
+                    extended: BTreeMap::new(),
                 }
             },
             map_prefix_to_file(&prefix)
@@ -751,12 +6094,69 @@ mod test {
                     permissions: Some(0o100777),
                     atime: None,
                     mtime: None,
+                    extended: BTreeMap::new(),
                 }
             },
             map_head_object_to_file("file", &head_object)
         );
     }
 
+    #[test]
+    fn test_map_head_object_to_file_with_version_id() {
+        let head_object = HeadObjectOutput {
+            version_id: Some(String::from("version")),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Some(&String::from("version")),
+            map_head_object_to_file("file", &head_object)
+                .file_attributes
+                .extended
+                .get(VERSION_ID_ATTRIBUTE)
+        );
+    }
+
+    #[test]
+    fn test_map_head_object_to_file_with_mtime_metadata() {
+        let head_object = HeadObjectOutput {
+            metadata: Some(
+                vec![(String::from(MTIME_METADATA), String::from("1608671341"))]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Some(1608671341),
+            map_head_object_to_file("file", &head_object)
+                .file_attributes
+                .mtime
+        );
+    }
+
+    #[test]
+    fn test_build_mtime_metadata_when_preserved() {
+        let s3_storage =
+            create_s3_storage(SlowDispatcher, 2 * MIN_PART_SIZE).with_preserve_mtime(true);
+
+        assert_eq!(
+            Some(String::from("1608671341")),
+            s3_storage
+                .build_mtime_metadata(Some(1608671341))
+                .unwrap()
+                .remove(MTIME_METADATA)
+        );
+    }
+
+    #[test]
+    fn test_build_mtime_metadata_when_not_preserved() {
+        let s3_storage = create_s3_storage(SlowDispatcher, 2 * MIN_PART_SIZE);
+
+        assert_eq!(None, s3_storage.build_mtime_metadata(Some(1608671341)));
+    }
+
     #[test]
     fn test_create_file_with_directory_bit() {
         assert_eq!(
@@ -769,16 +6169,44 @@ mod test {
                     permissions: Some(0o40777),
                     atime: None,
                     mtime: None,
+                    extended: BTreeMap::new(),
                 }
             },
             create_file_with_directory_bit("file")
         )
     }
 
+    #[test]
+    fn test_map_tags_to_extended_attributes() {
+        let tags = vec![Tag {
+            key: String::from("env"),
+            value: String::from("prod"),
+        }];
+
+        let mut extended = BTreeMap::new();
+        extended.insert(String::from("env"), String::from("prod"));
+
+        assert_eq!(extended, map_tags_to_extended_attributes(tags));
+    }
+
+    #[test]
+    fn test_map_extended_attributes_to_tags() {
+        let mut extended = BTreeMap::new();
+        extended.insert(String::from("env"), String::from("prod"));
+
+        assert_eq!(
+            vec![Tag {
+                key: String::from("env"),
+                value: String::from("prod"),
+            }],
+            map_extended_attributes_to_tags(extended)
+        );
+    }
+
     #[test]
     fn test_map_rfc3339_to_epoch_maps_valid_date() {
         assert_eq!(
-            Some(1417176009 as u32),
+            Some(1417176009_u32),
             map_rfc3339_to_epoch(Some(String::from("2014-11-28T12:00:09Z")).as_ref())
         );
     }
@@ -791,7 +6219,7 @@ mod test {
     #[test]
     fn test_map_rfc3339_to_epoch_maps_invalid_date_to_unix_epoch() {
         assert_eq!(
-            Some(0 as u32),
+            Some(0_u32),
             map_rfc3339_to_epoch(Some(String::from("invalid")).as_ref())
         );
     }
@@ -804,13 +6232,14 @@ mod test {
             ..Default::default()
         };
 
-        let write_handle =
-            map_create_multipart_response_to_write_handle(multipart_response).unwrap();
+        let mut write_handle =
+            map_create_multipart_response_to_write_handle(multipart_response, MIN_PART_SIZE)
+                .unwrap();
 
         assert_eq!("id", &write_handle.upload_id);
         assert_eq!("key", &write_handle.key);
         assert_eq!(0, write_handle.completed_parts.len());
-        assert_eq!(5000000, write_handle.buffer.capacity());
+        assert!(write_handle.buffer.finish().is_empty());
     }
 
     #[test]
@@ -820,7 +6249,10 @@ mod test {
             ..Default::default()
         };
 
-        assert!(map_create_multipart_response_to_write_handle(multipart_response).is_err());
+        assert!(
+            map_create_multipart_response_to_write_handle(multipart_response, MIN_PART_SIZE)
+                .is_err()
+        );
     }
 
     #[test]
@@ -830,6 +6262,9 @@ mod test {
             ..Default::default()
         };
 
-        assert!(map_create_multipart_response_to_write_handle(multipart_response).is_err());
+        assert!(
+            map_create_multipart_response_to_write_handle(multipart_response, MIN_PART_SIZE)
+                .is_err()
+        );
     }
 }
@@ -0,0 +1,121 @@
+use std::fmt::{Display, Formatter, Result};
+use std::time::Duration;
+
+const BYTE_UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// The data moved through a file handle during its life, logged as a single
+/// line of `key=value` fields when the handle is closed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferSummary {
+    pub path: String,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub duration: Duration,
+    /// Whether the file was opened in text mode. The data is moved unchanged
+    /// either way, but the mode is logged for text transfers.
+    pub text: bool,
+}
+
+impl TransferSummary {
+    /// Returns the average bytes per second over the life of the handle.
+    pub fn throughput(&self) -> f64 {
+        let bytes = (self.bytes_read + self.bytes_written) as f64;
+
+        match self.duration.as_secs_f64() {
+            seconds if seconds > 0.0 => bytes / seconds,
+            _ => bytes,
+        }
+    }
+}
+
+impl Display for TransferSummary {
+    fn fmt(&self, formatter: &mut Formatter) -> Result {
+        write!(
+            formatter,
+            "transfer_summary path={:?} bytes_read={} bytes_written={} duration={:.3}s throughput={}/s",
+            self.path,
+            self.bytes_read,
+            self.bytes_written,
+            self.duration.as_secs_f64(),
+            format_bytes(self.throughput())
+        )?;
+
+        if self.text {
+            write!(formatter, " mode=text")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a byte count with the largest binary unit that keeps it at or above
+/// one, such as `1.5 MiB`.
+pub fn format_bytes(bytes: f64) -> String {
+    let mut value = bytes;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < BYTE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} {}", value.round(), BYTE_UNITS[unit]),
+        _ => format!("{:.1} {}", value, BYTE_UNITS[unit]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display_formats_transfer_summary() {
+        let transfer_summary = TransferSummary {
+            path: String::from("/home/test/file.txt"),
+            bytes_read: 0,
+            bytes_written: 3 * 1024 * 1024,
+            duration: Duration::from_secs(2),
+            text: false,
+        };
+
+        assert_eq!(
+            "transfer_summary path=\"/home/test/file.txt\" bytes_read=0 bytes_written=3145728 \
+             duration=2.000s throughput=1.5 MiB/s",
+            transfer_summary.to_string()
+        );
+    }
+
+    #[test]
+    fn test_display_marks_text_mode_transfer() {
+        let transfer_summary = TransferSummary {
+            path: String::from("/home/test/file.txt"),
+            bytes_read: 4,
+            bytes_written: 0,
+            duration: Duration::from_secs(1),
+            text: true,
+        };
+
+        assert!(transfer_summary.to_string().ends_with(" mode=text"));
+    }
+
+    #[test]
+    fn test_throughput_with_zero_duration() {
+        let transfer_summary = TransferSummary {
+            path: String::from("/home/test/file.txt"),
+            bytes_read: 10,
+            bytes_written: 0,
+            duration: Duration::ZERO,
+            text: false,
+        };
+
+        assert_eq!(10.0, transfer_summary.throughput());
+    }
+
+    #[test]
+    fn test_format_bytes_picks_unit() {
+        assert_eq!("512 B", format_bytes(512.0));
+        assert_eq!("1.0 KiB", format_bytes(1024.0));
+        assert_eq!("2.5 GiB", format_bytes(2.5 * 1024.0 * 1024.0 * 1024.0));
+    }
+}
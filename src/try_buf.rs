@@ -10,11 +10,25 @@ pub trait TryBuf: Buf {
 
     fn try_get_string(&mut self) -> Result<String, Error>;
 
+    /// Reads a string like `try_get_string`, but rejects strings longer than
+    /// `max_len` bytes before reading them.
+    fn try_get_string_max(&mut self, max_len: u32) -> Result<String, Error>;
+
     fn try_get_u8(&mut self) -> Result<u8, Error>;
 
+    /// Reads an SSH `boolean`, a single byte where any nonzero value is true.
+    #[allow(dead_code)]
+    fn try_get_bool(&mut self) -> Result<bool, Error>;
+
     fn try_get_u32(&mut self) -> Result<u32, Error>;
 
     fn try_get_u64(&mut self) -> Result<u64, Error>;
+
+    fn try_get_i64(&mut self) -> Result<i64, Error>;
+
+    fn peek_u8(&self) -> Result<u8, Error>;
+
+    fn peek_u32(&self) -> Result<u32, Error>;
 }
 
 impl<T: Buf> TryBuf for T {
@@ -26,6 +40,10 @@ impl<T: Buf> TryBuf for T {
         Ok(self.get_u8())
     }
 
+    fn try_get_bool(&mut self) -> Result<bool, Error> {
+        Ok(self.try_get_u8()? != 0)
+    }
+
     fn try_get_u32(&mut self) -> Result<u32, Error> {
         if self.remaining() < std::mem::size_of::<u32>() {
             return Err(Error::BadMessage);
@@ -42,6 +60,30 @@ impl<T: Buf> TryBuf for T {
         Ok(self.get_u64())
     }
 
+    fn try_get_i64(&mut self) -> Result<i64, Error> {
+        if self.remaining() < std::mem::size_of::<i64>() {
+            return Err(Error::BadMessage);
+        }
+
+        Ok(self.get_i64())
+    }
+
+    fn peek_u8(&self) -> Result<u8, Error> {
+        match self.chunk().first() {
+            Some(value) => Ok(*value),
+            None => Err(Error::BadMessage),
+        }
+    }
+
+    fn peek_u32(&self) -> Result<u32, Error> {
+        // Only the current chunk can be inspected without advancing, which is
+        // the whole buffer for the contiguous buffers used by the parsers.
+        match self.chunk().get(..std::mem::size_of::<u32>()) {
+            Some(value) => Ok(u32::from_be_bytes(value.try_into().unwrap())),
+            None => Err(Error::BadMessage),
+        }
+    }
+
     fn try_get_bytes(&mut self, len: u32) -> Result<Bytes, Error> {
         let len = match len.try_into() {
             Ok(len) => len,
@@ -56,29 +98,52 @@ impl<T: Buf> TryBuf for T {
     }
 
     fn try_get_string(&mut self) -> Result<String, Error> {
+        self.try_get_string_max(u32::MAX)
+    }
+
+    fn try_get_string_max(&mut self, max_len: u32) -> Result<String, Error> {
         let len = self.try_get_u32()?;
+
+        if len > max_len {
+            return Err(Error::BadMessage);
+        }
+
         let string_bytes = self.try_get_bytes(len)?;
 
-        let string = match String::from_utf8((&string_bytes).to_vec()) {
+        let string = match String::from_utf8(string_bytes.to_vec()) {
             Ok(string) => string,
-            Err(_) => return Err(Error::BadMessage),
+            Err(_) => return Err(Error::InvalidUtf8),
         };
 
         Ok(string)
     }
 }
 
+#[allow(dead_code)]
 pub trait TryBufMut: BufMut {
     fn try_put_str(&mut self, str: &str) -> Result<(), Error>;
+
+    /// Writes a string like `try_put_str`, but rejects strings longer than
+    /// `max_len` bytes without writing anything, so a pathological value cannot
+    /// produce an oversized packet.
+    fn try_put_string_max(&mut self, str: &str, max_len: u32) -> Result<(), Error>;
+
+    fn try_put_i64(&mut self, value: i64) -> Result<(), Error>;
+
+    /// Writes the `u32` length and `u8` type that frame every SFTP packet, where
+    /// the length counts the type byte and a body of `body_len` bytes.
+    fn try_put_sftp_header(&mut self, data_type: u8, body_len: usize) -> Result<(), Error>;
 }
 
 impl<T: BufMut> TryBufMut for T {
     fn try_put_str(&mut self, str: &str) -> Result<(), Error> {
-        let len = str.len();
+        self.try_put_string_max(str, u32::MAX)
+    }
 
-        let len = match u32::try_from(len) {
-            Ok(len) => len,
-            Err(_) => return Err(Error::BadMessage),
+    fn try_put_string_max(&mut self, str: &str, max_len: u32) -> Result<(), Error> {
+        let len = match u32::try_from(str.len()) {
+            Ok(len) if len <= max_len => len,
+            _ => return Err(Error::BadMessage),
         };
 
         self.put_u32(len);
@@ -86,12 +151,76 @@ impl<T: BufMut> TryBufMut for T {
 
         Ok(())
     }
+
+    fn try_put_i64(&mut self, value: i64) -> Result<(), Error> {
+        if self.remaining_mut() < std::mem::size_of::<i64>() {
+            return Err(Error::BadMessage);
+        }
+
+        self.put_i64(value);
+
+        Ok(())
+    }
+
+    fn try_put_sftp_header(&mut self, data_type: u8, body_len: usize) -> Result<(), Error> {
+        let len = match u32::try_from(body_len)
+            .ok()
+            .and_then(|body_len| body_len.checked_add(std::mem::size_of::<u8>() as u32))
+        {
+            Some(len) => len,
+            None => return Err(Error::BadMessage),
+        };
+
+        self.put_u32(len);
+        self.put_u8(data_type);
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_try_put_sftp_header() {
+        let mut header_bytes: Vec<u8> = Vec::new();
+
+        header_bytes.try_put_sftp_header(101, 4).unwrap();
+
+        assert_eq!(vec![0x00, 0x00, 0x00, 0x05, 101], header_bytes);
+    }
+
+    #[test]
+    fn test_try_put_sftp_header_with_oversized_body() {
+        let mut header_bytes: Vec<u8> = Vec::new();
+
+        assert_eq!(
+            Err(Error::BadMessage),
+            header_bytes.try_put_sftp_header(101, u32::MAX as usize)
+        );
+    }
+
+    #[test]
+    fn test_try_get_string_max_at_max_length() {
+        let string_bytes: Vec<u8> = vec![0x00, 0x00, 0x00, 0x03, b'a', b'b', b'c'];
+
+        assert_eq!(
+            string_bytes.as_slice().try_get_string_max(3),
+            Ok(String::from("abc"))
+        );
+    }
+
+    #[test]
+    fn test_try_get_string_max_over_max_length() {
+        let string_bytes: Vec<u8> = vec![0x00, 0x00, 0x00, 0x04, b'a', b'b', b'c', b'd'];
+
+        assert_eq!(
+            string_bytes.as_slice().try_get_string_max(3),
+            Err(Error::BadMessage)
+        );
+    }
+
     #[test]
     fn test_try_get_u8() {
         let u8_bytes: Vec<u8> = vec![0x01, 0x02];
@@ -104,6 +233,26 @@ mod tests {
         assert_eq!(vec![].as_slice().try_get_u8(), Err(Error::BadMessage));
     }
 
+    #[test]
+    fn test_try_get_bool_with_false() {
+        assert_eq!(vec![0x00].as_slice().try_get_bool(), Ok(false));
+    }
+
+    #[test]
+    fn test_try_get_bool_with_true() {
+        assert_eq!(vec![0x01].as_slice().try_get_bool(), Ok(true));
+    }
+
+    #[test]
+    fn test_try_get_bool_treats_nonzero_as_true() {
+        assert_eq!(vec![0xFF].as_slice().try_get_bool(), Ok(true));
+    }
+
+    #[test]
+    fn test_try_get_bool_with_invalid_data() {
+        assert_eq!(vec![].as_slice().try_get_bool(), Err(Error::BadMessage));
+    }
+
     #[test]
     fn test_try_get_u32() {
         let u32_bytes: Vec<u8> = vec![0x00, 0x00, 0x00, 0x01];
@@ -132,6 +281,56 @@ mod tests {
         assert_eq!(u64_bytes.as_slice().try_get_u64(), Err(Error::BadMessage));
     }
 
+    #[test]
+    fn test_try_get_i64() {
+        let i64_bytes: Vec<u8> = vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01];
+
+        assert_eq!(i64_bytes.as_slice().try_get_i64(), Ok(0x01));
+    }
+
+    #[test]
+    fn test_try_get_i64_with_negative_value() {
+        let i64_bytes: Vec<u8> = vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+        assert_eq!(i64_bytes.as_slice().try_get_i64(), Ok(-1));
+    }
+
+    #[test]
+    fn test_try_get_i64_with_invalid_data() {
+        let i64_bytes: Vec<u8> = vec![0x00, 0x00, 0x00, 0x00];
+
+        assert_eq!(i64_bytes.as_slice().try_get_i64(), Err(Error::BadMessage));
+    }
+
+    #[test]
+    fn test_peek_u8() {
+        let mut u8_bytes: &[u8] = &[0x01, 0x02];
+
+        assert_eq!(u8_bytes.peek_u8(), Ok(0x01));
+        assert_eq!(u8_bytes.try_get_u8(), Ok(0x01));
+    }
+
+    #[test]
+    fn test_peek_u8_with_invalid_data() {
+        assert_eq!(vec![].as_slice().peek_u8(), Err(Error::BadMessage));
+    }
+
+    #[test]
+    fn test_peek_u32() {
+        let mut u32_bytes: &[u8] = &[0x00, 0x00, 0x00, 0x01, 0x02];
+
+        assert_eq!(u32_bytes.peek_u32(), Ok(0x01));
+        assert_eq!(u32_bytes.remaining(), 5);
+        assert_eq!(u32_bytes.try_get_u32(), Ok(0x01));
+    }
+
+    #[test]
+    fn test_peek_u32_with_invalid_data() {
+        let u32_bytes: Vec<u8> = vec![0x00, 0x00, 0x01];
+
+        assert_eq!(u32_bytes.as_slice().peek_u32(), Err(Error::BadMessage));
+    }
+
     #[test]
     fn test_try_get_bytes() {
         let bytes: Vec<u8> = vec![0x00, 0x01];
@@ -174,7 +373,7 @@ mod tests {
     fn test_try_get_string_with_invalid_utf8() {
         let string: Vec<u8> = vec![0x00, 0x00, 0x00, 0x01, 0xFF];
 
-        assert_eq!(string.as_slice().try_get_string(), Err(Error::BadMessage))
+        assert_eq!(string.as_slice().try_get_string(), Err(Error::InvalidUtf8))
     }
 
     #[test]
@@ -190,4 +389,44 @@ mod tests {
             &[0x00, 0x00, 0x00, 0x04, 0x54, 0x45, 0x53, 0x54]
         ); // TEST with length 4
     }
+
+    #[test]
+    fn test_try_put_string_max_within_max_length() {
+        let mut bytes: Vec<u8> = Vec::new();
+
+        assert_eq!(bytes.try_put_string_max("TEST", 4), Ok(()));
+        assert_eq!(
+            bytes.as_slice(),
+            &[0x00, 0x00, 0x00, 0x04, 0x54, 0x45, 0x53, 0x54]
+        ); // TEST with length 4
+    }
+
+    #[test]
+    fn test_try_put_string_max_over_max_length() {
+        let mut bytes: Vec<u8> = Vec::new();
+
+        assert_eq!(bytes.try_put_string_max("TEST", 3), Err(Error::BadMessage));
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn test_try_put_i64() {
+        let mut bytes: Vec<u8> = Vec::new();
+        let result = bytes.try_put_i64(-2);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            bytes.as_slice(),
+            &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]
+        );
+    }
+
+    #[test]
+    fn test_try_put_i64_with_insufficient_capacity() {
+        let mut buffer = [0u8; 4];
+        let result = (&mut buffer[..]).try_put_i64(1);
+
+        assert_eq!(result, Err(Error::BadMessage));
+        assert_eq!(buffer, [0x00, 0x00, 0x00, 0x00]);
+    }
 }